@@ -0,0 +1,17 @@
+// Generates include/confidis.h for the C API when the ffi feature is enabled,
+// and sets up linking for the Node.js addon when the node feature is
+
+fn main() {
+    #[cfg(feature = "node")]
+    napi_build::setup();
+
+    #[cfg(feature = "ffi")]
+    {
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        cbindgen::generate(&crate_dir)
+            .expect("Couldn't generate the C header")
+            .write_to_file(format!("{}/include/confidis.h", crate_dir));
+    }
+}
@@ -0,0 +1,20 @@
+// Parse arbitrary input as a text and a JSON command and execute whatever
+// parses on an empty graph. Neither may panic, only return errors.
+#![no_main]
+
+use confidis::command::Command;
+use confidis::graph::Graph;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let line = String::from_utf8_lossy(data);
+    let mut g = Graph::new();
+    if let Ok(cmd) = Command::from(&line) {
+        // a parsed command can be displayed and executed
+        let _ = cmd.to_string();
+        let _ = g.execute_command(&cmd);
+    }
+    if let Ok(cmd) = Command::from_json(&line) {
+        let _ = g.execute_command(&cmd);
+    }
+});
@@ -0,0 +1,85 @@
+// Compare two arbitrary answers with every built-in comparison method and
+// question type. This must not panic and distances must be within 0..=1, the
+// range the clustering relies on.
+#![no_main]
+
+use confidis::equalifier::{
+    parse_datetime, parse_location, Answer, DatetimeEqualifier, Equalifier, EqualifierConfig,
+    GeoEqualifier, Language, VecDistAlgo,
+};
+use libfuzzer_sys::fuzz_target;
+
+fn configs() -> Vec<EqualifierConfig> {
+    let mut configs = vec![
+        EqualifierConfig::Exact,
+        EqualifierConfig::Numeric { max_distance: 1.0 },
+        EqualifierConfig::Numeric { max_distance: 0.0 },
+    ];
+    for diff_fn in &[
+        VecDistAlgo::L1Norm,
+        VecDistAlgo::L2Norm,
+        VecDistAlgo::PercentNotEqual,
+        VecDistAlgo::IntersectionOverUnion,
+    ] {
+        configs.push(EqualifierConfig::NumericVec {
+            allowed_difference: 1.0,
+            vec_length: 3,
+            diff_fn: diff_fn.clone(),
+        });
+    }
+    for language in Language::ALL {
+        configs.push(EqualifierConfig::Text {
+            max_distance: 0.5,
+            fold_width: true,
+            language,
+            stem: true,
+            stopwords: true,
+        });
+    }
+    configs.push(EqualifierConfig::Text {
+        max_distance: 0.0,
+        fold_width: false,
+        language: Language::English,
+        stem: false,
+        stopwords: false,
+    });
+    configs
+}
+
+// The equalifiers of the geo and datetime question types, which have no
+// comparison method. TYPE only accepts a positive max_distance.
+fn typed() -> Vec<(&'static str, Box<dyn Equalifier>)> {
+    vec![
+        ("geo", Box::new(GeoEqualifier::new(0.1))),
+        ("geo", Box::new(GeoEqualifier::new(f64::MIN_POSITIVE))),
+        ("datetime", Box::new(DatetimeEqualifier::new(60.0))),
+        (
+            "datetime",
+            Box::new(DatetimeEqualifier::new(f64::MIN_POSITIVE)),
+        ),
+    ]
+}
+
+fuzz_target!(|data: (String, String)| {
+    parse_location(&data.0);
+    parse_datetime(&data.0);
+    let a = Answer::new(data.0, String::from("s1"));
+    let b = Answer::new(data.1, String::from("s2"));
+    let equalifiers = configs()
+        .into_iter()
+        .map(|config| (format!("{:?}", config), config.build().unwrap()))
+        .chain(typed().into_iter().map(|(name, e)| (name.to_string(), e)));
+    for (name, equalifier) in equalifiers {
+        equalifier.is_valid_answer(&a);
+        equalifier.is_valid_answer(&b);
+        let distance = equalifier.get_distance(&a, &b);
+        assert!(
+            (0.0..=1.0).contains(&distance),
+            "{} distance of {:?} and {:?} is {}",
+            name,
+            a.content,
+            b.content,
+            distance
+        );
+    }
+});
@@ -0,0 +1,50 @@
+#ifndef CONFIDIS_H
+#define CONFIDIS_H
+
+/* Generated by cbindgen from src/ffi.rs, don't edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef struct ConfidisGraph ConfidisGraph;
+
+/**
+ * Create an empty graph, free it with confidis_graph_free
+ */
+struct ConfidisGraph *confidis_graph_new(void);
+
+/**
+ * # Safety
+ * graph must come from confidis_graph_new and not be used afterwards
+ */
+void confidis_graph_free(struct ConfidisGraph *graph);
+
+/**
+ * Execute a command in the text grammar, e.g. "SET q1 a FROM s1"
+ *
+ * # Safety
+ * graph must come from confidis_graph_new, command must be a NUL terminated
+ * string
+ */
+int confidis_execute(struct ConfidisGraph *graph, const char *command);
+
+/**
+ * Execute a JSON command envelope, e.g. {"cmd": "get_answer", "question": "q1"}
+ *
+ * # Safety
+ * graph must come from confidis_graph_new, json must be a NUL terminated
+ * string
+ */
+int confidis_execute_json(struct ConfidisGraph *graph, const char *json);
+
+/**
+ * The JSON response of the last executed command, "" before the first
+ *
+ * # Safety
+ * graph must come from confidis_graph_new
+ */
+const char *confidis_last_response(const struct ConfidisGraph *graph);
+
+#endif  /* CONFIDIS_H */
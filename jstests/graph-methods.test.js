@@ -0,0 +1,28 @@
+const test = require("ava")
+const { GraphJS } = require("../pkg/node")
+
+test("graph methods without the text grammar", (t) => {
+    const g = GraphJS.new()
+    g.set("what color?", "light blue", "source 1")
+    g.set("what color?", "light blue", "source 2")
+
+    t.is(g.get_answer("what color?").answer, "light blue")
+    t.true(g.get_source_quality("source 1") > 0)
+
+    const copy = GraphJS.import_jsonl(g.export_jsonl())
+    t.is(copy.get_answer("what color?").answer, "light blue")
+})
+
+// SET reads the clock, which wasm32 only has through Date.now
+test("SET with the settings that read the clock", (t) => {
+    const g = GraphJS.new()
+    g.execute_command("CONFIGURE confidence_decay_after 3600")
+    g.execute_command("CONFIGURE evidence_expires_after 3600")
+    g.execute_command("CONFIGURE rate_limit_answers 10")
+    g.execute_command("SET color blue source1")
+    g.execute_command("SET color blue source2")
+    g.set("what color?", "light blue", "source 1")
+
+    t.is(g.get_answer("color").answer, "blue")
+    t.is(g.get_answer("what color?").answer, "light blue")
+})
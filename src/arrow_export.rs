@@ -0,0 +1,162 @@
+// Arrow record batch and Parquet export of questions and sources
+//
+// questions: question (utf8), answer (utf8, null if unanswered),
+//            confidence (f64), answer_count (u64)
+// sources:   source (utf8), quality (f64), strength (f64)
+//
+// Rows are sorted by name. The answer and confidence are computed from the
+// current source qualities, the same as GET ANSWER. Honeypot questions aren't
+// exported, their answers are known already.
+
+use crate::graph::Graph;
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, UInt64Array};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+use std::io::Write;
+use std::sync::Arc;
+
+fn arrow_err(e: arrow_schema::ArrowError) -> String {
+    format!("Couldn't build record batch: {}", e)
+}
+
+fn write_parquet<W: Write + Send>(writer: W, batch: &RecordBatch) -> Result<(), String> {
+    let parquet_err = |e: parquet::errors::ParquetError| format!("Couldn't write Parquet: {}", e);
+    let mut writer = ArrowWriter::try_new(writer, batch.schema(), None).map_err(parquet_err)?;
+    writer.write(batch).map_err(parquet_err)?;
+    writer.close().map_err(parquet_err)?;
+    Ok(())
+}
+
+impl Graph {
+    pub fn questions_record_batch(&self) -> Result<RecordBatch, String> {
+        let mut question_names: Vec<&String> = self
+            .questions
+            .iter()
+            .filter(|(_, question)| question.honeypot.is_none())
+            .map(|(name, _)| name)
+            .collect();
+        question_names.sort();
+
+        let mut answers: Vec<Option<String>> = Vec::with_capacity(question_names.len());
+        let mut confidences: Vec<f64> = Vec::with_capacity(question_names.len());
+        let mut answer_counts: Vec<u64> = Vec::with_capacity(question_names.len());
+        for question_name in &question_names {
+            let question = &self.questions[*question_name];
+            if question.answers.is_empty() {
+                answers.push(None);
+                confidences.push(0.0);
+            } else {
+                let (answer, confidence) = self.compute_answer(question_name)?;
+                answers.push(Some(answer));
+                confidences.push(confidence);
+            }
+            answer_counts.push(question.answers.len() as u64);
+        }
+
+        let schema = Schema::new(vec![
+            Field::new("question", DataType::Utf8, false),
+            Field::new("answer", DataType::Utf8, true),
+            Field::new("confidence", DataType::Float64, false),
+            Field::new("answer_count", DataType::UInt64, false),
+        ]);
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(question_names)),
+            Arc::new(StringArray::from(answers)),
+            Arc::new(Float64Array::from(confidences)),
+            Arc::new(UInt64Array::from(answer_counts)),
+        ];
+        RecordBatch::try_new(Arc::new(schema), columns).map_err(arrow_err)
+    }
+
+    pub fn sources_record_batch(&self) -> Result<RecordBatch, String> {
+        let mut sources: Vec<_> = self.sources.values().collect();
+        sources.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let schema = Schema::new(vec![
+            Field::new("source", DataType::Utf8, false),
+            Field::new("quality", DataType::Float64, false),
+            Field::new("strength", DataType::Float64, false),
+        ]);
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(
+                sources.iter().map(|s| &s.name),
+            )),
+            Arc::new(Float64Array::from_iter_values(
+                sources.iter().map(|s| s.quality),
+            )),
+            Arc::new(Float64Array::from_iter_values(
+                sources.iter().map(|s| s.strength),
+            )),
+        ];
+        RecordBatch::try_new(Arc::new(schema), columns).map_err(arrow_err)
+    }
+
+    pub fn write_questions_parquet<W: Write + Send>(&self, writer: W) -> Result<(), String> {
+        write_parquet(writer, &self.questions_record_batch()?)
+    }
+
+    pub fn write_sources_parquet<W: Write + Send>(&self, writer: W) -> Result<(), String> {
+        write_parquet(writer, &self.sources_record_batch()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::fs::{self, File};
+
+    #[test]
+    fn test_arrow_export() {
+        let mut g = Graph::new();
+        for line in &[
+            "SET q2 a FROM s1",
+            "SET q2 a FROM s2",
+            "SET q1 b FROM s3",
+            "BELIEVE s3",
+            "HONEYPOT q3 c",
+            "SET q3 c FROM s1",
+        ] {
+            g.execute_command(&Command::from(line).unwrap()).unwrap();
+        }
+
+        // without the honeypot q3
+        let questions = g.questions_record_batch().unwrap();
+        assert_eq!(questions.num_rows(), 2);
+        let names = questions
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(names.value(0), "q1");
+        let answers = questions
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(answers.value(0), "b");
+        assert!(!answers.is_null(1));
+
+        let sources = g.sources_record_batch().unwrap();
+        assert_eq!(sources.num_rows(), 3);
+        let quality = sources
+            .column(1)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(quality.value(2), g.config().quality_of_believed_sources);
+
+        let path = std::env::temp_dir().join(format!("confidis-{}.parquet", std::process::id()));
+        g.write_questions_parquet(File::create(&path).unwrap())
+            .unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|batch| batch.unwrap()).collect();
+        assert_eq!(batches, vec![questions]);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+// A graph for async (e.g. tokio based) services
+//
+// AsyncGraph owns a graph on a dedicated thread and receives commands through
+// a bounded mpsc channel, replying to each over a oneshot channel. Awaiting
+// execute never blocks the caller's executor, even while a command recomputes
+// a large question, and a full channel makes callers wait instead of queueing
+// without limit. Commands are executed in the order they arrive.
+//
+//   let g = AsyncGraph::spawn(Graph::new());
+//   g.execute(Command::from("SET q1 a FROM s1")?).await?;
+//   let q1 = QuestionId::new("q1")?;
+//   let result = g.with_graph(move |g| g.get_answer(&q1)).await??;
+//
+// The channels don't depend on a runtime, so AsyncGraph works with any
+// executor. The thread exits once every AsyncGraph handle is dropped.
+
+use crate::command::{Command, CommandResponse};
+use crate::error::ConfidisError;
+use crate::graph::Graph;
+use std::thread;
+use tokio::sync::{mpsc, oneshot};
+
+// Number of requests that can wait for the graph before senders have to wait
+const REQUEST_QUEUE_CAPACITY: usize = 1024;
+
+enum Request {
+    // boxed so queued requests stay small
+    Execute(
+        Box<Command<'static>>,
+        oneshot::Sender<Result<CommandResponse, ConfidisError>>,
+    ),
+    // Runs a closure with the graph, see with_graph
+    Run(Box<dyn FnOnce(&mut Graph) + Send>),
+}
+
+#[derive(Clone)]
+pub struct AsyncGraph {
+    requests: mpsc::Sender<Request>,
+}
+
+fn stopped() -> ConfidisError {
+    ConfidisError::Internal(String::from("Graph worker has stopped"))
+}
+
+impl AsyncGraph {
+    pub fn spawn(mut g: Graph) -> AsyncGraph {
+        let (requests, mut incoming) = mpsc::channel::<Request>(REQUEST_QUEUE_CAPACITY);
+        thread::spawn(move || {
+            while let Some(request) = incoming.blocking_recv() {
+                match request {
+                    Request::Execute(cmd, reply) => {
+                        // The requester may have gone away, nothing to do then
+                        let _ = reply.send(g.execute_command(&cmd));
+                    }
+                    Request::Run(f) => f(&mut g),
+                }
+            }
+        });
+        AsyncGraph { requests }
+    }
+
+    pub async fn execute(&self, cmd: Command<'static>) -> Result<CommandResponse, ConfidisError> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.requests
+            .send(Request::Execute(Box::new(cmd), reply_tx))
+            .await
+            .map_err(|_| stopped())?;
+        reply_rx.await.map_err(|_| stopped())?
+    }
+
+    // Run f on the graph's thread with exclusive access to the graph, e.g. to
+    // use the typed API
+    pub async fn with_graph<F, R>(&self, f: F) -> Result<R, ConfidisError>
+    where
+        F: FnOnce(&mut Graph) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let run = move |g: &mut Graph| {
+            let _ = reply_tx.send(f(g));
+        };
+        self.requests
+            .send(Request::Run(Box::new(run)))
+            .await
+            .map_err(|_| stopped())?;
+        reply_rx.await.map_err(|_| stopped())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::id::QuestionId;
+    use futures::executor::block_on;
+    use futures::future::join_all;
+
+    #[test]
+    fn test_async_graph() {
+        let g = AsyncGraph::spawn(Graph::new());
+        block_on(async {
+            let sets = ["s1", "s2"].iter().map(|source| {
+                let line = format!("SET q1 a FROM {}", source);
+                g.execute(Command::from(&line).unwrap().into_owned())
+            });
+            for result in join_all(sets).await {
+                result.unwrap();
+            }
+            match g
+                .execute(Command::from("GET ANSWER TO q1").unwrap())
+                .await
+                .unwrap()
+            {
+                CommandResponse::Answer { content, .. } => assert_eq!(content, "a"),
+                response => panic!("Unexpected response {:?}", response),
+            }
+            let sources = g
+                .with_graph(|g| {
+                    let q1 = QuestionId::new("q1").unwrap();
+                    g.get_answer(&q1).map(|result| result.sources)
+                })
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(sources, vec!["s1", "s2"]);
+        });
+    }
+}
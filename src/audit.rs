@@ -0,0 +1,138 @@
+// Bounded log of source quality and strength adjustments
+//
+// Every time a question changes what a source's quality says about it (the
+// question's effect is added or reverted while answers are recomputed) or a
+// source is believed, a graph with an audit log records the old and new
+// quality and strength. Only the most recent `capacity` changes are kept.
+//
+//   g.set_audit_log(AuditLog::new(10_000));
+//   g.execute_command(&Command::from("GET AUDIT FOR s1")?)?;
+//
+// Changes that leave both quality and strength as they were (e.g. a question
+// with a single answer has no weight) aren't recorded. Timestamps come from the
+// system clock, which wasm32-unknown-unknown doesn't have, so the audit log
+// isn't available there.
+
+use crate::journal::now_millis;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QualityChange {
+    // unix timestamp in ms
+    pub timestamp: u64,
+    pub source: String,
+    // the question whose effect changed the source, None for BELIEVE
+    pub question: Option<String>,
+    // whether the question's effect was reverted, before its answers are
+    // recomputed
+    pub reverted: bool,
+    pub old_quality: f64,
+    pub new_quality: f64,
+    pub old_strength: f64,
+    pub new_strength: f64,
+}
+
+impl fmt::Display for QualityChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.question {
+            Some(question) if self.reverted => {
+                write!(f, "{} (revert) {}", self.timestamp, question)?
+            }
+            Some(question) => write!(f, "{} {}", self.timestamp, question)?,
+            None => write!(f, "{} BELIEVE", self.timestamp)?,
+        }
+        write!(
+            f,
+            ": quality {:.3} -> {:.3}, strength {:.3} -> {:.3}",
+            self.old_quality, self.new_quality, self.old_strength, self.new_strength
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    capacity: usize,
+    changes: VecDeque<QualityChange>,
+}
+
+impl AuditLog {
+    pub fn new(capacity: usize) -> AuditLog {
+        AuditLog {
+            capacity,
+            changes: VecDeque::with_capacity(capacity.min(1024)),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    // Record a change of source from (old_quality, old_strength) to
+    // (new_quality, new_strength), dropping the oldest change when full
+    pub(crate) fn record(
+        &mut self,
+        source: &str,
+        question: Option<&str>,
+        reverted: bool,
+        old: (f64, f64),
+        new: (f64, f64),
+    ) {
+        if old == new || self.capacity == 0 {
+            return;
+        }
+        if self.changes.len() == self.capacity {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(QualityChange {
+            timestamp: now_millis(),
+            source: source.to_string(),
+            question: question.map(String::from),
+            reverted,
+            old_quality: old.0,
+            new_quality: new.0,
+            old_strength: old.1,
+            new_strength: new.1,
+        });
+    }
+
+    // Every change still in the log, oldest first
+    pub fn changes(&self) -> impl Iterator<Item = &QualityChange> {
+        self.changes.iter()
+    }
+
+    // The changes of one source, oldest first
+    pub fn changes_for<'a>(&'a self, source: &'a str) -> impl Iterator<Item = &'a QualityChange> {
+        self.changes
+            .iter()
+            .filter(move |change| change.source == source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_log_is_bounded() {
+        let mut log = AuditLog::new(2);
+        log.record("s1", Some("q1"), false, (0.5, 1.0), (0.6, 2.0));
+        log.record("s1", Some("q1"), false, (0.6, 2.0), (0.6, 2.0));
+        log.record("s2", None, false, (0.5, 1.0), (0.999, 100.0));
+        log.record("s1", Some("q2"), true, (0.6, 2.0), (0.5, 1.0));
+        assert_eq!(log.len(), 2);
+        let s1: Vec<_> = log.changes_for("s1").collect();
+        assert_eq!(s1.len(), 1);
+        assert_eq!(s1[0].question.as_deref(), Some("q2"));
+        assert!(s1[0].reverted);
+    }
+}
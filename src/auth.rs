@@ -0,0 +1,267 @@
+// Authentication of server clients
+//
+// A server given tokens (see Server::set_tokens and confidis-server --tokens)
+// only executes the commands of connections that sent AUTH <token> first, and
+// only those the token's role allows:
+//   read    the read-only commands, e.g. GET ANSWER TO, GET SOURCE, STATS
+//   write   also the commands adding answers or changing how a question is
+//           judged, e.g. SET, SCHEMA, TYPE
+//   admin   also the commands that rewrite source qualities or the
+//           configuration: CONFIGURE, BELIEVE, HONEYPOT, EXCLUDE, REBUILD,
+//           UNDO and REDO
+// A connection can AUTH again to switch tokens. CONFIGURE output_format only
+// changes the connection's own replies and needs no role.
+//
+// The other servers check the same tokens and roles when given them with
+// set_tokens: the HTTP server (and its GraphQL endpoint) takes the token of
+// each request from an "Authorization: Bearer <token>" header, WebSocket
+// clients send {"auth": "<token>"} before subscribing, which needs the read
+// role, and replicas send AUTH <token> when they connect to a
+// ReplicationServer, which streams them everything and needs the admin role.
+//
+// Tokens are read from text with one "<token> <role>" per line, blank lines
+// and lines starting with "#" are skipped.
+//
+// On a replica (see Server::set_replica) connections can only run read-only
+// commands, and only while the replica is within its max staleness, see
+// replication.rs.
+
+use crate::command::CommandType;
+#[cfg(feature = "server")]
+use crate::replication::ReplicaStatus;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use subtle::{ConditionallySelectable, ConstantTimeEq};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Read,
+    Write,
+    Admin,
+}
+
+impl Role {
+    // The least role that can run commands of type cmd
+    pub fn required(cmd: CommandType) -> Role {
+        match cmd {
+            CommandType::Configure
+            | CommandType::Believe
+            | CommandType::Honeypot
+            | CommandType::Exclude
+            | CommandType::Rebuild
+            | CommandType::Undo
+            | CommandType::Redo => Role::Admin,
+            cmd if cmd.is_read_only() => Role::Read,
+            _ => Role::Write,
+        }
+    }
+
+    pub fn allows(&self, cmd: CommandType) -> bool {
+        *self >= Role::required(cmd)
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Role::Read => write!(f, "read"),
+            Role::Write => write!(f, "write"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Role::Read),
+            "write" => Ok(Role::Write),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("unknown role \"{}\". Try read, write, admin", s)),
+        }
+    }
+}
+
+// The tokens clients can authenticate with and their roles
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tokens {
+    roles: HashMap<String, Role>,
+}
+
+impl Tokens {
+    pub fn insert(&mut self, token: &str, role: Role) {
+        self.roles.insert(token.to_string(), role);
+    }
+
+    // Compares token with every known token in constant time, so how long
+    // the lookup takes doesn't tell a client how much of a token it guessed
+    pub fn role(&self, token: &str) -> Option<Role> {
+        let mut found = 0u8;
+        for (known, role) in &self.roles {
+            let matches = known.as_bytes().ct_eq(token.as_bytes());
+            found.conditional_assign(&(*role as u8 + 1), matches);
+        }
+        [Role::Read, Role::Write, Role::Admin]
+            .iter()
+            .copied()
+            .find(|role| *role as u8 + 1 == found)
+    }
+
+    // Tokens from "<token> <role>" lines
+    pub fn parse(text: &str) -> Result<Tokens, String> {
+        let mut tokens = Tokens::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut items = line.split_whitespace();
+            match (items.next(), items.next(), items.next()) {
+                (Some(token), Some(role), None) => {
+                    let role = role
+                        .parse()
+                        .map_err(|e| format!("Invalid token on line {}: {}", i + 1, e))?;
+                    tokens.insert(token, role);
+                }
+                _ => {
+                    return Err(format!(
+                        "Invalid token on line {}, expected <token> <role>",
+                        i + 1
+                    ))
+                }
+            }
+        }
+        Ok(tokens)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Tokens, String> {
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("Couldn't read {}: {}", path.as_ref().display(), e))?;
+        Tokens::parse(&text)
+    }
+}
+
+// What a connection is allowed to do. Without tokens every connection can run
+// every command.
+#[derive(Debug, Clone, Default)]
+pub struct AuthSession {
+    tokens: Option<Arc<Tokens>>,
+    role: Option<Role>,
+    #[cfg(feature = "server")]
+    replica: Option<Arc<ReplicaStatus>>,
+}
+
+impl AuthSession {
+    pub fn new(tokens: Option<Arc<Tokens>>) -> AuthSession {
+        AuthSession {
+            tokens,
+            role: None,
+            #[cfg(feature = "server")]
+            replica: None,
+        }
+    }
+
+    // Only allow the reads replica can serve
+    #[cfg(feature = "server")]
+    pub fn set_replica(&mut self, replica: Arc<ReplicaStatus>) {
+        self.replica = Some(replica);
+    }
+
+    // AUTH <token>, the token's role from now on. A token that isn't known
+    // leaves the connection unauthenticated.
+    pub fn authenticate(&mut self, token: &str) -> Result<Role, String> {
+        let role = match self.tokens.as_ref() {
+            Some(tokens) => tokens.role(token),
+            None => return Err(String::from("AUTH called without any tokens configured")),
+        };
+        self.role = role;
+        role.ok_or_else(|| String::from("Invalid token"))
+    }
+
+    // The role of the token the connection authenticated with
+    pub fn role(&self) -> Option<Role> {
+        self.role
+    }
+
+    pub fn has_tokens(&self) -> bool {
+        self.tokens.is_some()
+    }
+
+    // Ok if the connection may run commands of type cmd
+    pub fn authorize(&self, cmd: CommandType) -> Result<(), String> {
+        if self.tokens.is_some() {
+            match self.role {
+                None => {
+                    return Err(String::from(
+                        "Authentication required, send AUTH <token> first",
+                    ))
+                }
+                Some(role) if !role.allows(cmd) => {
+                    return Err(format!(
+                        "Permission denied, {:?} needs the {} role",
+                        cmd,
+                        Role::required(cmd)
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+        #[cfg(feature = "server")]
+        if let Some(replica) = self.replica.as_ref() {
+            return replica.check(cmd);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_session() {
+        let tokens = Tokens::parse("# clients\nr1 read\n\nw1 write\na1 admin\n").unwrap();
+        assert_eq!(tokens.role("w1"), Some(Role::Write));
+        assert_eq!(tokens.role("a1"), Some(Role::Admin));
+        assert_eq!(tokens.role("w"), None);
+        assert_eq!(tokens.role("w12"), None);
+        assert!(Tokens::parse("r1 reader").is_err());
+        assert!(Tokens::parse("r1").is_err());
+
+        let mut session = AuthSession::new(Some(Arc::new(tokens)));
+        assert!(session.authorize(CommandType::GetAnswer).is_err());
+        assert_eq!(session.authenticate("r1"), Ok(Role::Read));
+        assert!(session.authorize(CommandType::GetAnswer).is_ok());
+        assert_eq!(
+            session.authorize(CommandType::Set),
+            Err(String::from("Permission denied, Set needs the write role"))
+        );
+        session.authenticate("w1").unwrap();
+        assert!(session.authorize(CommandType::Set).is_ok());
+        assert!(session.authorize(CommandType::Believe).is_err());
+        // honeypots and exclusions change source qualities too
+        assert_eq!(
+            session.authorize(CommandType::Honeypot),
+            Err(String::from(
+                "Permission denied, Honeypot needs the admin role"
+            ))
+        );
+        assert!(session.authorize(CommandType::Exclude).is_err());
+        session.authenticate("a1").unwrap();
+        assert!(session.authorize(CommandType::Configure).is_ok());
+        assert!(session.authorize(CommandType::Honeypot).is_ok());
+        // a wrong token drops the previous role
+        assert!(session.authenticate("x").is_err());
+        assert!(session.authorize(CommandType::GetAnswer).is_err());
+
+        let mut open = AuthSession::default();
+        assert!(open.authorize(CommandType::Configure).is_ok());
+        assert!(open.authenticate("a1").is_err());
+    }
+}
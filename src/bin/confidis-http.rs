@@ -0,0 +1,85 @@
+// Serve a graph over HTTP, see confidis::http for the routes
+use confidis::audit::AuditLog;
+use confidis::auth::Tokens;
+use confidis::graph::Graph;
+use confidis::history::AnswerHistory;
+use confidis::http::HttpServer;
+use confidis::journal::Journal;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+struct Cli {
+    // address to listen on
+    #[structopt(long, default_value = "127.0.0.1:7380")]
+    addr: String,
+
+    // journal to restore the graph from and append mutating commands to
+    #[structopt(long, parse(from_os_str))]
+    journal: Option<std::path::PathBuf>,
+
+    // number of recent source quality changes to keep for GET AUDIT FOR
+    #[structopt(long)]
+    audit_capacity: Option<usize>,
+
+    // number of recent answer changes to keep per question for GET HISTORY OF
+    #[structopt(long)]
+    history_capacity: Option<usize>,
+
+    // break ties by answer and order output by name, see Graph::set_deterministic
+    #[structopt(long)]
+    deterministic: bool,
+
+    // fail GET SOURCE and GET ANSWER TO of unknown sources and questions, see
+    // Graph::set_strict
+    #[structopt(long)]
+    strict: bool,
+
+    // file of "<token> <role>" lines, one of which requests have to carry in
+    // an Authorization: Bearer header, see confidis::auth
+    #[structopt(long, parse(from_os_str))]
+    tokens: Option<std::path::PathBuf>,
+
+    // OTLP/HTTP endpoint to export a span per command to, e.g.
+    // http://localhost:4318/v1/traces
+    #[cfg(feature = "otel")]
+    #[structopt(long)]
+    otlp_endpoint: Option<String>,
+}
+
+fn main() {
+    pretty_env_logger::init();
+    let args = Cli::from_args();
+    #[cfg(feature = "otel")]
+    let _tracer_provider = args.otlp_endpoint.as_ref().map(|endpoint| {
+        confidis::telemetry::init_otlp(endpoint).expect("Couldn't set up OTLP export")
+    });
+    let audit_capacity = args.audit_capacity;
+    let history_capacity = args.history_capacity;
+    let deterministic = args.deterministic;
+    let strict = args.strict;
+    let journal_path = args.journal;
+    let mut server = HttpServer::bind(&args.addr, move || {
+        let mut g = Graph::new();
+        if let Some(journal_path) = journal_path {
+            if journal_path.exists() {
+                g = Graph::replay(&journal_path).expect("Couldn't replay journal");
+            }
+            g.set_journal(Journal::open(&journal_path).expect("Couldn't open journal"));
+        }
+        if let Some(capacity) = audit_capacity {
+            g.set_audit_log(AuditLog::new(capacity));
+        }
+        if let Some(capacity) = history_capacity {
+            g.set_answer_history(AnswerHistory::new(capacity));
+        }
+        g.set_deterministic(deterministic);
+        g.set_strict(strict);
+        g
+    })
+    .expect("Couldn't start server");
+    if let Some(path) = &args.tokens {
+        server.set_tokens(Tokens::load(path).expect("Couldn't load tokens"));
+    }
+    println!("Listening on {}", server.local_addr().unwrap());
+    server.run().expect("Server failed");
+}
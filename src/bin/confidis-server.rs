@@ -0,0 +1,183 @@
+// Serve a graph over TCP, see confidis::server for the protocol
+use confidis::audit::AuditLog;
+use confidis::auth::Tokens;
+use confidis::command::OutputFormat;
+use confidis::graph::Graph;
+use confidis::history::AnswerHistory;
+use confidis::journal::Journal;
+use confidis::replication::{Replica, ReplicationServer};
+use confidis::server::Server;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+struct Cli {
+    // address to listen on
+    #[structopt(long, default_value = "127.0.0.1:7370")]
+    addr: String,
+
+    // journal to restore the graph from and append mutating commands to
+    #[structopt(long, parse(from_os_str))]
+    journal: Option<std::path::PathBuf>,
+
+    // file holding the 64 hex digit key the journal is encrypted with, see
+    // confidis::encryption
+    #[cfg(feature = "encryption")]
+    #[structopt(long, requires = "journal", parse(from_os_str))]
+    encryption_key: Option<std::path::PathBuf>,
+
+    // address to stream the journal to replicas on, needs --journal
+    #[structopt(long, requires = "journal")]
+    replication_addr: Option<String>,
+
+    // replication address of a primary to serve a read-only replica of
+    #[structopt(long, conflicts_with = "journal")]
+    replica_of: Option<String>,
+
+    // admin token to authenticate with the primary, if it has --tokens
+    #[structopt(long, requires = "replica-of")]
+    primary_token: Option<String>,
+
+    // how long a replica can go without hearing from its primary before it
+    // refuses reads, in ms
+    #[structopt(long, default_value = "5000")]
+    max_staleness_ms: u64,
+
+    // number of recent source quality changes to keep for GET AUDIT FOR
+    #[structopt(long)]
+    audit_capacity: Option<usize>,
+
+    // number of recent answer changes to keep per question for GET HISTORY OF
+    #[structopt(long)]
+    history_capacity: Option<usize>,
+
+    // break ties by answer and order output by name, see Graph::set_deterministic
+    #[structopt(long)]
+    deterministic: bool,
+
+    // fail GET SOURCE and GET ANSWER TO of unknown sources and questions, see
+    // Graph::set_strict
+    #[structopt(long)]
+    strict: bool,
+
+    // answer text commands as text or json, see CONFIGURE output_format
+    #[structopt(long, default_value = "text")]
+    output_format: OutputFormat,
+
+    // file of "<token> <role>" lines clients (including replicas and
+    // WebSocket clients) have to AUTH with, see confidis::auth
+    #[structopt(long, parse(from_os_str))]
+    tokens: Option<std::path::PathBuf>,
+
+    // address to stream answer changes over WebSocket on
+    #[cfg(feature = "websocket")]
+    #[structopt(long)]
+    websocket: Option<String>,
+
+    // OTLP/HTTP endpoint to export a span per command to, e.g.
+    // http://localhost:4318/v1/traces
+    #[cfg(feature = "otel")]
+    #[structopt(long)]
+    otlp_endpoint: Option<String>,
+}
+
+fn main() {
+    pretty_env_logger::init();
+    let args = Cli::from_args();
+    #[cfg(feature = "otel")]
+    let _tracer_provider = args.otlp_endpoint.as_ref().map(|endpoint| {
+        confidis::telemetry::init_otlp(endpoint).expect("Couldn't set up OTLP export")
+    });
+    let audit_capacity = args.audit_capacity;
+    let history_capacity = args.history_capacity;
+    let deterministic = args.deterministic;
+    let strict = args.strict;
+    let journal_path = args.journal.clone();
+    #[cfg(feature = "encryption")]
+    let encryption_key = args.encryption_key.as_ref().map(|path| {
+        confidis::encryption::EncryptionKey::load(path).expect("Couldn't load encryption key")
+    });
+    let mut server = Server::bind(&args.addr, move || {
+        let mut g = Graph::new();
+        if let Some(journal_path) = journal_path {
+            let exists = journal_path.exists();
+            let plain = || {
+                let replayed = if exists {
+                    Graph::replay(&journal_path)
+                } else {
+                    Ok(Graph::new())
+                };
+                (replayed, Journal::open(&journal_path))
+            };
+            #[cfg(feature = "encryption")]
+            let (replayed, journal) = match encryption_key {
+                Some(key) => {
+                    let replayed = if exists {
+                        Graph::replay_encrypted(&journal_path, &key)
+                    } else {
+                        Ok(Graph::new())
+                    };
+                    (replayed, Journal::open_encrypted(&journal_path, key))
+                }
+                None => plain(),
+            };
+            #[cfg(not(feature = "encryption"))]
+            let (replayed, journal) = plain();
+            g = replayed.expect("Couldn't replay journal");
+            g.set_journal(journal.expect("Couldn't open journal"));
+        }
+        if let Some(capacity) = audit_capacity {
+            g.set_audit_log(AuditLog::new(capacity));
+        }
+        if let Some(capacity) = history_capacity {
+            g.set_answer_history(AnswerHistory::new(capacity));
+        }
+        g.set_deterministic(deterministic);
+        g.set_strict(strict);
+        g
+    })
+    .expect("Couldn't start server");
+    server.set_output_format(args.output_format);
+    let tokens = args
+        .tokens
+        .as_ref()
+        .map(|path| Tokens::load(path).expect("Couldn't load tokens"));
+    if let Some(tokens) = &tokens {
+        server.set_tokens(tokens.clone());
+    }
+    if let Some(primary) = &args.replica_of {
+        let mut replica = Replica::new(
+            primary,
+            server.worker(),
+            std::time::Duration::from_millis(args.max_staleness_ms),
+        );
+        if let Some(token) = &args.primary_token {
+            replica.set_token(token);
+        }
+        server.set_replica(replica.status());
+        std::thread::spawn(move || replica.run());
+    }
+    println!("Listening on {}", server.local_addr().unwrap());
+    if let Some(addr) = &args.replication_addr {
+        let mut replication_server = ReplicationServer::bind(addr, server.worker())
+            .expect("Couldn't start replication server");
+        if let Some(tokens) = &tokens {
+            replication_server.set_tokens(tokens.clone());
+        }
+        println!(
+            "Replication listening on {}",
+            replication_server.local_addr().unwrap()
+        );
+        std::thread::spawn(move || replication_server.run().expect("Replication server failed"));
+    }
+    #[cfg(feature = "websocket")]
+    if let Some(addr) = &args.websocket {
+        let mut ws_server = confidis::websocket::WebSocketServer::bind(addr, server.worker())
+            .expect("Couldn't start WebSocket server");
+        if let Some(tokens) = &tokens {
+            ws_server.set_tokens(tokens.clone());
+        }
+        println!("WebSocket listening on {}", ws_server.local_addr().unwrap());
+        std::thread::spawn(move || ws_server.run().expect("WebSocket server failed"));
+    }
+    server.run().expect("Server failed");
+}
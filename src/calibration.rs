@@ -0,0 +1,161 @@
+// Seeding source qualities from a gold calibration set
+//
+// Every source starts at default_source_quality, so a graph needs a lot of
+// answers before it tells good sources from bad ones. When the true answers
+// to some questions are known (gold) along with answers sources gave to them
+// earlier, calibrate estimates each source's quality from those instead:
+//
+//   g.calibrate(&[("q1", "paris")], &[("q1", "paris", "s1"), ("q1", "rome", "s2")])
+//
+// An answer is correct if it matches the gold answer under the comparison
+// method (a distance below 1), after the normalize steps. Only a source's
+// latest answer to each gold question counts, and each counts like a question
+// of weight 1, so the quality is the share of correct answers smoothed
+// towards the default by initial_source_strength:
+//
+//   quality  = (default_source_quality * initial_source_strength + correct)
+//              / (initial_source_strength + answered)
+//   strength = initial_source_strength + answered
+//
+// The calibration answers aren't added to the graph, the qualities replace
+// those of the sources that answered gold questions, so calibrate before
+// live traffic starts. Sources are seeded with SEED SOURCE journal records.
+
+use crate::command::Answer;
+use crate::error::ConfidisError;
+use crate::graph::Graph;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+// How a source did on the calibration set and the quality it was seeded with
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceCalibration {
+    pub source: String,
+    // gold questions the source answered
+    pub answered: usize,
+    // of those, how many it answered correctly
+    pub correct: usize,
+    pub quality: f64,
+    pub strength: f64,
+}
+
+impl fmt::Display for SourceCalibration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} of {} correct, quality {:.3}",
+            self.source, self.correct, self.answered, self.quality
+        )
+    }
+}
+
+impl Graph {
+    // Seed the qualities of the sources in answers, (question, answer, source)
+    // entries like set_many's, from how well they answered the gold
+    // (question, answer) pairs. Answers to questions without a gold answer are
+    // ignored. Returns every seeded source, sorted by name.
+    pub fn calibrate(
+        &mut self,
+        gold: &[(&str, &str)],
+        answers: &[(&str, &str, &str)],
+    ) -> Result<Vec<SourceCalibration>, ConfidisError> {
+        if self.is_bulk_loading() {
+            return Err(ConfidisError::BulkLoadInProgress);
+        }
+        let normalize = &self.config().normalize;
+        let gold: HashMap<&str, Answer> = gold
+            .iter()
+            .map(|&(question, answer)| {
+                let answer = normalize.apply(answer).into_owned();
+                (question, Answer::new(answer, String::new()))
+            })
+            .collect();
+        // each source's latest answer to each gold question
+        let mut latest: HashMap<(&str, &str), &str> = HashMap::new();
+        for &(question, answer, source) in answers {
+            if gold.contains_key(question) {
+                latest.insert((source, question), answer);
+            }
+        }
+        let mut counts: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+        for ((source, question), answer) in latest {
+            let answer = Answer::new(normalize.apply(answer).into_owned(), source.to_string());
+            let correct = self.answer_distance(question, &answer, &gold[question]) < 1.0;
+            let (answered, correct_count) = counts.entry(source).or_default();
+            *answered += 1;
+            *correct_count += correct as usize;
+        }
+
+        let prior = self.config().default_source_quality;
+        let prior_strength = self.config().initial_source_strength;
+        let calibrations: Vec<SourceCalibration> = counts
+            .into_iter()
+            .map(|(source, (answered, correct))| SourceCalibration {
+                source: source.to_string(),
+                answered,
+                correct,
+                quality: (prior * prior_strength + correct as f64)
+                    / (prior_strength + answered as f64),
+                strength: prior_strength + answered as f64,
+            })
+            .collect();
+        for calibration in &calibrations {
+            self.seed_source(
+                &calibration.source,
+                calibration.quality,
+                calibration.strength,
+            );
+        }
+        if let Some(journal) = self.journal_mut() {
+            journal
+                .append_seed_sources(&calibrations)
+                .map_err(ConfidisError::Journal)?;
+        }
+        self.snapshot_if_due();
+        Ok(calibrations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+
+    #[test]
+    fn test_calibrate() {
+        let mut g = Graph::new();
+        g.execute_command(&Command::from("CONFIGURE normalize lowercase").unwrap())
+            .unwrap();
+        let gold = [("g1", "paris"), ("g2", "4"), ("g3", "blue")];
+        let answers = [
+            ("g1", "Paris", "s1"),
+            ("g2", "4", "s1"),
+            ("g3", "blue", "s1"),
+            ("g1", "rome", "s2"),
+            ("g2", "5", "s2"),
+            // s2 corrected itself, only the latest answer counts
+            ("g3", "red", "s2"),
+            ("g3", "blue", "s2"),
+            ("q9", "x", "s3"),
+        ];
+        let calibrations = g.calibrate(&gold, &answers).unwrap();
+        assert_eq!(calibrations.len(), 2);
+        assert_eq!((calibrations[0].answered, calibrations[0].correct), (3, 3));
+        assert_eq!(calibrations[0].quality, 3.5 / 4.0);
+        assert_eq!(
+            calibrations[1].to_string(),
+            "s2: 1 of 3 correct, quality 0.375"
+        );
+
+        let s1 = g.source("s1").unwrap();
+        assert_eq!((s1.quality, s1.strength), (0.875, 4.0));
+        // Nothing is added to the graph
+        assert!(!g.has_question("g1"));
+        assert!(g.source("s3").is_none());
+
+        // s1 now outweighs two default sources
+        g.set_many(&[("q1", "a", "s1"), ("q1", "b", "s4"), ("q1", "b", "s5")])
+            .unwrap();
+        assert_eq!(g.compute_answer("q1").unwrap().0, "a");
+    }
+}
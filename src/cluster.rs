@@ -1,32 +1,161 @@
 use crate::command::Answer;
-use crate::equalifier::{Equalifier, ExactEqualifier, NumericEqualifier};
+use crate::equalifier::Equalifier;
+use crate::hash::AnswerHash;
+use std::collections::HashMap;
+use std::sync::Mutex;
 
-pub fn equal_distance_fn(a: &Answer, b: &Answer) -> f64 {
+// Once the cache holds this many distances it is emptied and starts refilling
+const DEFAULT_DISTANCE_CACHE_CAPACITY: usize = 1_000_000;
+
+// Memoizes pairwise answer distances so re-clustering a question after each SET
+// only computes distances for the newly added answer.
+//
+// Entries are keyed by (hash_a, hash_b, version). The version must be bumped
+// with invalidate() whenever the equalifier (or its configuration) changes.
+// Each hash stands for the first content seen with it, answers whose content
+// differs (a hash collision) are compared without the cache.
+pub struct DistanceCache<A = String> {
+    version: u64,
+    capacity: usize,
+    entries: Mutex<CacheEntries<A>>,
+}
+
+struct CacheEntries<A> {
+    // the content each hash stands for
+    contents: HashMap<AnswerHash, A>,
+    distances: HashMap<(AnswerHash, AnswerHash, u64), f64>,
+}
+
+impl<A> CacheEntries<A> {
+    fn clear(&mut self) {
+        self.contents.clear();
+        self.distances.clear();
+    }
+}
+
+impl<A: Clone + PartialEq> CacheEntries<A> {
+    // Whether the answer's hash stands for its content
+    fn knows(&mut self, answer: &Answer<A>) -> bool {
+        let content = self
+            .contents
+            .entry(answer.hash)
+            .or_insert_with(|| answer.content.clone());
+        *content == answer.content
+    }
+}
+
+impl<A> Default for DistanceCache<A> {
+    fn default() -> Self {
+        DistanceCache::new(DEFAULT_DISTANCE_CACHE_CAPACITY)
+    }
+}
+
+impl<A> DistanceCache<A> {
+    pub fn new(capacity: usize) -> Self {
+        DistanceCache {
+            version: 0,
+            capacity,
+            entries: Mutex::new(CacheEntries {
+                contents: HashMap::new(),
+                distances: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().distances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Drop every cached distance, called when the equalifier changes
+    pub fn invalidate(&mut self) {
+        self.version += 1;
+        self.entries.get_mut().unwrap().clear();
+    }
+}
+
+impl<A: Clone + PartialEq> DistanceCache<A> {
+    pub fn get_distance(
+        &self,
+        a: &Answer<A>,
+        b: &Answer<A>,
+        equalifier: &dyn Equalifier<A>,
+    ) -> f64 {
+        // distances are assumed to be symmetric, so store each pair once
+        let key = if a.hash <= b.hash {
+            (a.hash, b.hash, self.version)
+        } else {
+            (b.hash, a.hash, self.version)
+        };
+        {
+            let mut entries = self.entries.lock().unwrap();
+            if !(entries.knows(a) && entries.knows(b)) {
+                drop(entries);
+                return equalifier.get_distance(a, b);
+            }
+            if let Some(distance) = entries.distances.get(&key) {
+                return *distance;
+            }
+        }
+        let distance = equalifier.get_distance(a, b);
+        let mut entries = self.entries.lock().unwrap();
+        if entries.distances.len() >= self.capacity {
+            entries.clear();
+        }
+        entries.distances.insert(key, distance);
+        distance
+    }
+}
+
+pub fn equal_distance_fn<A: PartialEq>(a: &Answer<A>, b: &Answer<A>) -> f64 {
     if a.content == b.content {
-        return 0.0;
+        0.0
     } else {
-        return 1.0;
+        1.0
     }
 }
 
-pub fn compute_clusters(
-    answers: &Vec<Answer>,
-    equalifier: &dyn Equalifier,
+pub fn compute_clusters<A>(
+    answers: &[Answer<A>],
+    equalifier: &dyn Equalifier<A>,
+) -> Result<Vec<Vec<usize>>, String> {
+    cluster_by_distance(answers.len(), |i, u| {
+        equalifier.get_distance(&answers[i], &answers[u])
+    })
+}
+
+// Same as compute_clusters, but distances are looked up in (and added to) cache
+pub fn compute_clusters_cached<A: Clone + PartialEq>(
+    answers: &[Answer<A>],
+    equalifier: &dyn Equalifier<A>,
+    cache: &DistanceCache<A>,
+) -> Result<Vec<Vec<usize>>, String> {
+    cluster_by_distance(answers.len(), |i, u| {
+        cache.get_distance(&answers[i], &answers[u], equalifier)
+    })
+}
+
+#[allow(clippy::needless_range_loop)]
+fn cluster_by_distance(
+    n: usize,
+    distance_fn: impl Fn(usize, usize) -> f64,
 ) -> Result<Vec<Vec<usize>>, String> {
     // Compute answer distances
-    let N = answers.len();
     // TODO (not important, probably) the distance function doesn't need to have duplicates since all distances
     // are assumed to be symmetric.
-    let mut distances: Vec<Vec<f64>> = (0..N).map(|_| vec![0.0; N]).collect();
-    for i in 0..N {
-        for u in i..N {
-            if i == u {
-                distances[i][u] = 0.0;
-            } else {
-                let iudist: f64 = equalifier.get_distance(&answers[i], &answers[u]);
-                distances[i][u] = iudist;
-                distances[u][i] = iudist;
-            }
+    let mut distances: Vec<Vec<f64>> = (0..n).map(|_| vec![0.0; n]).collect();
+    for i in 0..n {
+        for u in (i + 1)..n {
+            let iudist: f64 = distance_fn(i, u);
+            distances[i][u] = iudist;
+            distances[u][i] = iudist;
         }
     }
 
@@ -52,7 +181,7 @@ pub fn compute_clusters(
 
     let mut clustered_answers: Vec<Vec<usize>> = vec![Vec::new(); number_of_clusters];
 
-    for (i, location) in (&dbscan_output).iter().enumerate() {
+    for (i, location) in dbscan_output.iter().enumerate() {
         match location {
             dbscan::Classification::Core(cluster) | dbscan::Classification::Edge(cluster) => {
                 clustered_answers[*cluster].push(i);
@@ -70,12 +199,28 @@ pub fn compute_clusters(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::equalifier::{ExactEqualifier, NumericEqualifier};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingEqualifier {
+        calls: AtomicUsize,
+    }
+
+    impl Equalifier for CountingEqualifier {
+        fn is_valid_answer(&self, _a: &Answer) -> bool {
+            true
+        }
+        fn get_distance(&self, a: &Answer, b: &Answer) -> f64 {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            ExactEqualifier::new().get_distance(a, b)
+        }
+    }
 
     #[test]
     fn test_compute_clusters_exact() {
         assert_eq!(
             compute_clusters(
-                &vec![
+                &[
                     Answer::new(String::from("a"), String::from("s1")),
                     Answer::new(String::from("b"), String::from("s2")),
                 ],
@@ -90,7 +235,7 @@ mod tests {
     fn test_compute_clusters_nums() {
         assert_eq!(
             compute_clusters(
-                &vec![
+                &[
                     Answer::new(String::from("0.2"), String::from("s1")),
                     Answer::new(String::from("0.5"), String::from("s2")),
                     Answer::new(String::from("2.4"), String::from("s3")),
@@ -101,4 +246,53 @@ mod tests {
             vec![vec![0, 1], vec![2]],
         );
     }
+
+    #[test]
+    fn test_compute_clusters_cached() {
+        let equalifier = CountingEqualifier {
+            calls: AtomicUsize::new(0),
+        };
+        let mut cache = DistanceCache::default();
+        let mut answers = vec![
+            Answer::new(String::from("a"), String::from("s1")),
+            Answer::new(String::from("b"), String::from("s2")),
+        ];
+        compute_clusters_cached(&answers, &equalifier, &cache).unwrap();
+        assert_eq!(equalifier.calls.load(Ordering::SeqCst), 1);
+
+        // Only the pairs involving the new answer need to be computed
+        answers.push(Answer::new(String::from("a"), String::from("s3")));
+        assert_eq!(
+            compute_clusters_cached(&answers, &equalifier, &cache).unwrap(),
+            vec![vec![0, 2], vec![1]],
+        );
+        assert_eq!(equalifier.calls.load(Ordering::SeqCst), 2);
+
+        cache.invalidate();
+        assert!(cache.is_empty());
+        compute_clusters_cached(&answers, &equalifier, &cache).unwrap();
+        assert_eq!(equalifier.calls.load(Ordering::SeqCst), 4);
+
+        // "c" collides with "a", so it's compared rather than taking a's
+        // cached distances
+        let mut colliding = Answer::new(String::from("c"), String::from("s4"));
+        colliding.hash = answers[0].hash;
+        answers.push(colliding);
+        assert_eq!(
+            compute_clusters_cached(&answers, &equalifier, &cache).unwrap(),
+            vec![vec![0, 2], vec![1], vec![3]],
+        );
+        assert_eq!(
+            cache.get_distance(&answers[3], &answers[1], &equalifier),
+            1.0
+        );
+        assert_eq!(
+            cache.get_distance(&answers[0], &answers[3], &equalifier),
+            1.0
+        );
+        assert_eq!(
+            cache.get_distance(&answers[0], &answers[2], &equalifier),
+            0.0
+        );
+    }
 }
@@ -1,223 +1,1772 @@
+use crate::audit::QualityChange;
+use crate::config::ConfigKey;
+use crate::equalifier::parse_numeric_vec;
+use crate::error::ConfidisError;
+use crate::hash::{AnswerHash, AnswerHasher};
+use crate::history::AnswerChange;
+use crate::id::validate_command_ids;
+use crate::shadow::ShadowStats;
 use serde::{Deserialize, Serialize};
-use std::collections::hash_map::DefaultHasher;
+use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::fmt;
-use std::hash::{Hash, Hasher};
+use std::hash::Hash;
+use std::str::FromStr;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+// The snake_case aliases are the names used by the JSON command envelope
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum CommandType {
+    #[default]
+    #[serde(alias = "invalid")]
     Invalid,
+    #[serde(alias = "set")]
     Set,
+    #[serde(alias = "get_answer")]
     GetAnswer,
+    #[serde(alias = "get_answers")]
     GetAnswers,
+    #[serde(alias = "get_source")]
     GetSource,
+    #[serde(alias = "believe")]
     Believe,
+    #[serde(alias = "configure")]
     Configure,
+    #[serde(alias = "test_equality")]
     TestEquality,
+    #[serde(alias = "stats")]
+    Stats,
+    #[serde(alias = "explain")]
+    Explain,
+    #[serde(alias = "get_audit")]
+    GetAudit,
+    #[serde(alias = "get_history")]
+    GetHistory,
+    #[serde(alias = "debug_clusters")]
+    DebugClusters,
+    #[serde(alias = "compare_sources")]
+    CompareSources,
+    #[serde(alias = "mget")]
+    MGet,
+    #[serde(alias = "exclude")]
+    Exclude,
+    #[serde(alias = "honeypot")]
+    Honeypot,
+    #[serde(alias = "schema")]
+    Schema,
+    #[serde(alias = "type")]
+    Type,
+    #[serde(alias = "rebuild")]
+    Rebuild,
+    #[serde(alias = "undo")]
+    Undo,
+    #[serde(alias = "redo")]
+    Redo,
+    #[serde(alias = "get_shadow")]
+    GetShadow,
 }
 
-impl Default for CommandType {
-    fn default() -> CommandType {
-        CommandType::Invalid
+impl CommandType {
+    // Whether the command can be executed without modifying the graph
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            CommandType::GetAnswer
+                | CommandType::GetAnswers
+                | CommandType::GetSource
+                | CommandType::TestEquality
+                | CommandType::Stats
+                | CommandType::Explain
+                | CommandType::GetAudit
+                | CommandType::GetHistory
+                | CommandType::DebugClusters
+                | CommandType::CompareSources
+                | CommandType::MGet
+                | CommandType::GetShadow
+        )
+    }
+
+    // The Command fields a command of this type needs
+    pub fn required_fields(&self) -> &'static [&'static str] {
+        match self {
+            CommandType::Set => &["question", "answer", "source"],
+            CommandType::Exclude => &["source", "question"],
+            CommandType::Honeypot => &["question", "answer"],
+            CommandType::Schema => &["question", "schema"],
+            CommandType::Type => &["question", "question_type"],
+            CommandType::GetAnswer
+            | CommandType::GetAnswers
+            | CommandType::Explain
+            | CommandType::GetHistory
+            | CommandType::DebugClusters => &["question"],
+            CommandType::GetSource | CommandType::Believe | CommandType::GetAudit => &["source"],
+            CommandType::Configure => &["config_key", "config_val"],
+            CommandType::TestEquality => &["answer1", "answer2"],
+            CommandType::CompareSources => &["sources"],
+            CommandType::MGet => &["questions"],
+            CommandType::Stats
+            | CommandType::Rebuild
+            | CommandType::Undo
+            | CommandType::Redo
+            | CommandType::GetShadow
+            | CommandType::Invalid => &[],
+        }
     }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
-pub struct Command {
+// Fields that don't apply to a command are None and left out of its JSON.
+// Commands parsed from the text grammar borrow their fields from the line, so
+// replaying a journal doesn't allocate per field, JSON commands own them. Use
+// into_owned to keep a command beyond the line it was parsed from.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Command<'a> {
     pub cmd: CommandType,
-    pub source: Option<String>,
-    pub question: Option<String>,
-    pub answer: Option<String>,
-    pub config_key: Option<String>,
-    pub config_val: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub question: Option<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub answer: Option<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_key: Option<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_val: Option<Cow<'a, str>>,
+
+    // SCHEMA, the question's answer schema in text form, see schema.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Cow<'a, str>>,
+
+    // TYPE, the question's type in text form, see question_type.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub question_type: Option<Cow<'a, str>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub answer1: Option<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub answer2: Option<Cow<'a, str>>,
+
+    // COMPARE SOURCES, the sources to compare
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sources: Option<Vec<Cow<'a, str>>>,
+
+    // MGET, the questions to answer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub questions: Option<Vec<Cow<'a, str>>>,
+
+    // UNDO and REDO, how many commands to take back or redo, 1 if None
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<usize>,
+
+    // GET ANSWER TO ... AS OF, the unix time in ms to answer as of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub as_of: Option<u64>,
+
+    // GET ANSWER TO ... RUNNERS UP, how many competing answers to report
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runners_up: Option<usize>,
+}
+
+// A value as one item of the text grammar: as is if it has no whitespace,
+// quotes or backslashes, otherwise in double quotes with '"', '\\' and
+// control characters escaped, e.g. "New York" or "a\nb". See split_items.
+pub fn quote(value: &str) -> Cow<'_, str> {
+    let plain = !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == '"' || c == '\\');
+    if plain {
+        return Cow::Borrowed(value);
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    Cow::Owned(quoted)
+}
 
-    pub answer1: Option<String>,
-    pub answer2: Option<String>,
+// Split a line of the text grammar into its items at whitespace. An item
+// starting with a double quote runs to the closing quote and is unescaped,
+// see quote.
+pub(crate) fn split_items(line: &str) -> Result<Vec<Cow<'_, str>>, ConfidisError> {
+    let (items, _) = split_items_with_rest(line, usize::MAX)?;
+    Ok(items)
 }
 
-impl fmt::Display for Command {
+// The first count items of a line and the rest of it as written, for values
+// made up of several items that their own parser splits, e.g. the schema
+// enum "New York" Boston or json {"type": "string"}
+pub(crate) fn split_items_with_rest(
+    line: &str,
+    count: usize,
+) -> Result<(Vec<Cow<'_, str>>, &str), ConfidisError> {
+    let unterminated = || ConfidisError::ParseError(format!("Unterminated quote in \"{}\"", line));
+    let mut items = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() && items.len() < count {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let end = loop {
+                match chars.next().ok_or_else(unterminated)? {
+                    (i, '"') => break i + 1,
+                    (_, '\\') => match chars.next().ok_or_else(unterminated)?.1 {
+                        'n' => value.push('\n'),
+                        'r' => value.push('\r'),
+                        't' => value.push('\t'),
+                        'u' => {
+                            let invalid = || {
+                                ConfidisError::ParseError(format!(
+                                    "Invalid \\u escape in \"{}\"",
+                                    line
+                                ))
+                            };
+                            if chars.next().map(|(_, c)| c) != Some('{') {
+                                return Err(invalid());
+                            }
+                            let code: String = chars
+                                .by_ref()
+                                .map(|(_, c)| c)
+                                .take_while(|&c| c != '}')
+                                .collect();
+                            let c = u32::from_str_radix(&code, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(invalid)?;
+                            value.push(c);
+                        }
+                        c => value.push(c),
+                    },
+                    (_, c) => value.push(c),
+                }
+            };
+            rest = &quoted[end..];
+            if rest.starts_with(|c: char| !c.is_whitespace()) {
+                return Err(ConfidisError::ParseError(format!(
+                    "Expected whitespace after a quoted item in \"{}\"",
+                    line
+                )));
+            }
+            items.push(Cow::Owned(value));
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            items.push(Cow::Borrowed(&rest[..end]));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok((items, rest.trim_end()))
+}
+
+impl fmt::Display for Command<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Missing fields are left blank rather than failing, the others are
+        // quoted so the line parses back to the same command
+        let field = |value: &Option<Cow<str>>| match value.as_deref() {
+            Some(value) => quote(value).into_owned(),
+            None => String::new(),
+        };
+        // Multi-item values, e.g. CONFIGURE's, are written as given
+        let raw = |value: &Option<Cow<str>>| value.as_deref().unwrap_or_default().to_string();
         match self.cmd {
             CommandType::Set => write!(
                 f,
                 "SET {} {} FROM {}",
-                &self.question.as_ref().unwrap(),
-                &self.answer.as_ref().unwrap(),
-                &self.source.as_ref().unwrap()
+                field(&self.question),
+                field(&self.answer),
+                field(&self.source)
             ),
             CommandType::GetAnswer => {
-                write!(f, "GET ANSWER TO {}", &self.question.as_ref().unwrap())
+                write!(f, "GET ANSWER TO {}", field(&self.question))?;
+                if let Some(timestamp) = self.as_of {
+                    write!(f, " AS OF {}", timestamp)?;
+                }
+                match self.runners_up {
+                    Some(count) => write!(f, " RUNNERS UP {}", count),
+                    None => Ok(()),
+                }
             }
-            CommandType::GetSource => write!(f, "GET SOURCE {}", &self.question.as_ref().unwrap()),
-            CommandType::Believe => write!(f, "BELIEVE {}", &self.source.as_ref().unwrap()),
+            CommandType::GetSource => write!(f, "GET SOURCE {}", field(&self.source)),
+            CommandType::Believe => write!(f, "BELIEVE {}", field(&self.source)),
+            CommandType::Exclude => write!(
+                f,
+                "EXCLUDE {} FROM {}",
+                field(&self.source),
+                field(&self.question)
+            ),
+            CommandType::Honeypot => write!(
+                f,
+                "HONEYPOT {} {}",
+                field(&self.question),
+                field(&self.answer)
+            ),
+            CommandType::Schema => {
+                write!(f, "SCHEMA {} {}", field(&self.question), raw(&self.schema))
+            }
+            CommandType::Type => write!(
+                f,
+                "TYPE {} {}",
+                field(&self.question),
+                raw(&self.question_type)
+            ),
             CommandType::Configure => write!(
                 f,
                 "CONFIGURE {} {}",
-                &self.config_key.as_ref().unwrap(),
-                &self.config_val.as_ref().unwrap()
+                field(&self.config_key),
+                raw(&self.config_val)
             ),
             CommandType::Invalid => write!(f, "INVALID"),
             CommandType::TestEquality => write!(
                 f,
                 "TEST EQUALITY {} {}",
-                &self.answer1.as_ref().unwrap(),
-                &self.answer2.as_ref().unwrap(),
+                field(&self.answer1),
+                field(&self.answer2),
             ),
-            CommandType::GetAnswers => {
-                write!(f, "GET ANSWERS TO {}", &self.question.as_ref().unwrap())
+            CommandType::GetAnswers => write!(f, "GET ANSWERS TO {}", field(&self.question)),
+            CommandType::Stats => write!(f, "STATS"),
+            CommandType::GetShadow => write!(f, "GET SHADOW"),
+            CommandType::Rebuild => write!(f, "REBUILD"),
+            CommandType::Undo | CommandType::Redo => {
+                let keyword = if self.cmd == CommandType::Undo {
+                    "UNDO"
+                } else {
+                    "REDO"
+                };
+                match self.count {
+                    Some(count) => write!(f, "{} {}", keyword, count),
+                    None => write!(f, "{}", keyword),
+                }
+            }
+            CommandType::Explain => write!(f, "EXPLAIN {}", field(&self.question)),
+            CommandType::GetAudit => write!(f, "GET AUDIT FOR {}", field(&self.source)),
+            CommandType::GetHistory => write!(f, "GET HISTORY OF {}", field(&self.question)),
+            CommandType::DebugClusters => write!(f, "DEBUG CLUSTERS {}", field(&self.question)),
+            CommandType::CompareSources => {
+                write!(f, "COMPARE SOURCES {}", quote_list(self.sources.as_deref()))
             }
+            CommandType::MGet => write!(f, "MGET {}", quote_list(self.questions.as_deref())),
         }
     }
 }
 
-impl Command {
-    pub fn from(line: &str) -> Result<Command, String> {
-        // TODO shouldn't split up quoted strings
-        let items: Vec<&str> = line.split_whitespace().collect();
-        if items.len() == 0 {
-            return Err("Blank command".into());
+fn quote_list(values: Option<&[Cow<str>]>) -> String {
+    values
+        .unwrap_or_default()
+        .iter()
+        .map(|value| quote(value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Command<'_> {
+    // Parse a line of the text grammar. Items containing whitespace, e.g. an
+    // answer, are written in double quotes: SET q1 "New York" FROM s1
+    pub fn from(line: &str) -> Result<Command<'_>, ConfidisError> {
+        // The values of SCHEMA, TYPE and CONFIGURE are the rest of the line as
+        // written, their parsers split it
+        let (items, value) = match line.split_whitespace().next() {
+            Some("SCHEMA" | "schema" | "TYPE" | "type" | "CONFIGURE" | "configure") => {
+                split_items_with_rest(line, 2)?
+            }
+            _ => (split_items(line)?, ""),
+        };
+        if items.is_empty() {
+            return Err(ConfidisError::ParseError("Blank command".into()));
         }
-        match items[0] {
+        let item = |i: usize| {
+            items
+                .get(i)
+                .cloned()
+                .ok_or_else(|| ConfidisError::ParseError(format!("Missing items in \"{}\"", line)))
+        };
+        let is = |i: usize, keyword: &str| items.get(i).is_some_and(|item| item == keyword);
+        match items[0].as_ref() {
             "SET" | "set" => {
                 if items.len() != 5 {
-                    return Err(
+                    return Err(ConfidisError::ParseError(
                         "Missing items, syntax is SET <question> <answer> FROM <source>".into(),
-                    );
+                    ));
                 }
                 // SET <question> <answer> FROM <source>
                 Ok(Command {
                     cmd: CommandType::Set,
-                    question: Some(String::from(items[1])),
-                    answer: Some(String::from(items[2])),
-                    source: Some(String::from(items[4])),
+                    question: Some(item(1)?),
+                    answer: Some(item(2)?),
+                    source: Some(item(4)?),
                     ..Default::default()
                 })
             }
             "GET" | "get" => {
-                if items[1] == "ANSWER" && items[2] == "TO" {
-                    // GET ANSWER TO <question>
+                if is(1, "ANSWER") && is(2, "TO") {
+                    // GET ANSWER TO <question> [AS OF <timestamp>] [RUNNERS UP <count>]
+                    let mut i = 4;
+                    let as_of = if is(i, "AS") && is(i + 1, "OF") {
+                        i += 3;
+                        Some(item(i - 1)?.parse::<u64>().map_err(|_| {
+                            ConfidisError::ParseError(format!(
+                                "Invalid timestamp in \"{}\", expected unix time in ms",
+                                line
+                            ))
+                        })?)
+                    } else {
+                        None
+                    };
+                    let runners_up = if is(i, "RUNNERS") && is(i + 1, "UP") {
+                        match item(i + 2)?.parse::<usize>() {
+                            Ok(count) if count > 0 => Some(count),
+                            _ => {
+                                return Err(ConfidisError::ParseError(format!(
+                                    "Invalid count in \"{}\", expected RUNNERS UP <count> with a count of at least 1",
+                                    line
+                                )))
+                            }
+                        }
+                    } else {
+                        None
+                    };
                     Ok(Command {
                         cmd: CommandType::GetAnswer,
-                        question: Some(String::from(items[3])),
+                        question: Some(item(3)?),
+                        as_of,
+                        runners_up,
                         ..Default::default()
                     })
-                } else if items[1] == "SOURCE" {
+                } else if is(1, "SOURCE") {
                     // GET SOURCE <source>
                     Ok(Command {
                         cmd: CommandType::GetSource,
-                        source: Some(String::from(items[2])),
+                        source: Some(item(2)?),
                         ..Default::default()
                     })
-                } else if items[1] == "ANSWERS" && items[2] == "TO" {
+                } else if is(1, "ANSWERS") && is(2, "TO") {
                     Ok(Command {
                         cmd: CommandType::GetAnswers,
-                        question: Some(String::from(items[3])),
+                        question: Some(item(3)?),
+                        ..Default::default()
+                    })
+                } else if is(1, "HISTORY") && is(2, "OF") {
+                    // GET HISTORY OF <question>
+                    Ok(Command {
+                        cmd: CommandType::GetHistory,
+                        question: Some(item(3)?),
+                        ..Default::default()
+                    })
+                } else if is(1, "SHADOW") && items.len() == 2 {
+                    // GET SHADOW
+                    Ok(Command {
+                        cmd: CommandType::GetShadow,
+                        ..Default::default()
+                    })
+                } else if is(1, "AUDIT") && is(2, "FOR") {
+                    // GET AUDIT FOR <source>
+                    Ok(Command {
+                        cmd: CommandType::GetAudit,
+                        source: Some(item(3)?),
                         ..Default::default()
                     })
                 } else {
-                    Err(format!("Invalid GET command: \"{}\"", line))
+                    Err(ConfidisError::ParseError(format!(
+                        "Invalid GET command: \"{}\"",
+                        line
+                    )))
                 }
             }
             "BELIEVE" | "believe" => {
                 // BELIEVE <source>
                 Ok(Command {
                     cmd: CommandType::Believe,
-                    source: Some(String::from(items[1])),
+                    source: Some(item(1)?),
+                    ..Default::default()
+                })
+            }
+            "EXCLUDE" | "exclude" => {
+                if items.len() != 4 || !is(2, "FROM") {
+                    return Err(ConfidisError::ParseError(
+                        "Missing items, syntax is EXCLUDE <source> FROM <question>".into(),
+                    ));
+                }
+                // EXCLUDE <source> FROM <question>
+                Ok(Command {
+                    cmd: CommandType::Exclude,
+                    source: Some(item(1)?),
+                    question: Some(item(3)?),
+                    ..Default::default()
+                })
+            }
+            "HONEYPOT" | "honeypot" => {
+                if items.len() != 3 {
+                    return Err(ConfidisError::ParseError(
+                        "Missing items, syntax is HONEYPOT <question> <answer>".into(),
+                    ));
+                }
+                // HONEYPOT <question> <answer>
+                Ok(Command {
+                    cmd: CommandType::Honeypot,
+                    question: Some(item(1)?),
+                    answer: Some(item(2)?),
+                    ..Default::default()
+                })
+            }
+            "SCHEMA" | "schema" => {
+                if items.len() < 2 || value.is_empty() {
+                    return Err(ConfidisError::ParseError(
+                        "Missing items, syntax is SCHEMA <question> <schema>".into(),
+                    ));
+                }
+                // SCHEMA <question> <schema> [param ...]
+                Ok(Command {
+                    cmd: CommandType::Schema,
+                    question: Some(item(1)?),
+                    schema: Some(value.into()),
+                    ..Default::default()
+                })
+            }
+            "TYPE" | "type" => {
+                if items.len() < 2 || value.is_empty() {
+                    return Err(ConfidisError::ParseError(
+                        "Missing items, syntax is TYPE <question> <type>".into(),
+                    ));
+                }
+                // TYPE <question> <type> [param=value ...]
+                Ok(Command {
+                    cmd: CommandType::Type,
+                    question: Some(item(1)?),
+                    question_type: Some(value.into()),
                     ..Default::default()
                 })
             }
             "CONFIGURE" | "configure" => {
-                // CONFIGURE <key> <value>
+                // CONFIGURE <key> <value> [param=value ...]
                 Ok(Command {
                     cmd: CommandType::Configure,
-                    config_key: Some(String::from(items[1])),
-                    config_val: Some(String::from(items[2])),
+                    config_key: Some(item(1)?),
+                    config_val: Some(value.into()),
                     ..Default::default()
                 })
             }
             "TEST" | "test" => {
-                if items[1] == "EQUALITY" {
+                if is(1, "EQUALITY") {
                     Ok(Command {
                         cmd: CommandType::TestEquality,
-                        answer1: Some(String::from(items[2])),
-                        answer2: Some(String::from(items[3])),
+                        answer1: Some(item(2)?),
+                        answer2: Some(item(3)?),
                         ..Default::default()
                     })
                 } else {
-                    Err(format!("Invalid TEST command: \"{}\"", line))
+                    Err(ConfidisError::ParseError(format!(
+                        "Invalid TEST command: \"{}\"",
+                        line
+                    )))
                 }
             }
-            _ => Err(format!("Invalid command starting token: {}", items[0])),
+            "STATS" | "stats" => Ok(Command {
+                cmd: CommandType::Stats,
+                ..Default::default()
+            }),
+            "REBUILD" | "rebuild" => Ok(Command {
+                cmd: CommandType::Rebuild,
+                ..Default::default()
+            }),
+            "UNDO" | "undo" | "REDO" | "redo" => {
+                // UNDO [<count>] / REDO [<count>]
+                let count = match items.get(1) {
+                    Some(count) => match count.parse::<usize>() {
+                        Ok(count) if count > 0 && items.len() == 2 => Some(count),
+                        _ => {
+                            return Err(ConfidisError::ParseError(format!(
+                            "Invalid {} command, syntax is {} [<count>] with a count of at least 1",
+                            items[0], items[0]
+                        )))
+                        }
+                    },
+                    None => None,
+                };
+                Ok(Command {
+                    cmd: if items[0].eq_ignore_ascii_case("UNDO") {
+                        CommandType::Undo
+                    } else {
+                        CommandType::Redo
+                    },
+                    count,
+                    ..Default::default()
+                })
+            }
+            "DEBUG" | "debug" => {
+                if is(1, "CLUSTERS") {
+                    // DEBUG CLUSTERS <question>
+                    Ok(Command {
+                        cmd: CommandType::DebugClusters,
+                        question: Some(item(2)?),
+                        ..Default::default()
+                    })
+                } else {
+                    Err(ConfidisError::ParseError(format!(
+                        "Invalid DEBUG command: \"{}\"",
+                        line
+                    )))
+                }
+            }
+            "COMPARE" | "compare" => {
+                if !is(1, "SOURCES") {
+                    return Err(ConfidisError::ParseError(format!(
+                        "Invalid COMPARE command: \"{}\"",
+                        line
+                    )));
+                }
+                if items.len() < 4 {
+                    return Err(ConfidisError::ParseError(
+                        "Missing items, syntax is COMPARE SOURCES <source> <source> [<source> ...]"
+                            .into(),
+                    ));
+                }
+                // COMPARE SOURCES <source> <source> [<source> ...]
+                Ok(Command {
+                    cmd: CommandType::CompareSources,
+                    sources: Some(items[2..].to_vec()),
+                    ..Default::default()
+                })
+            }
+            "MGET" | "mget" => {
+                if items.len() < 2 {
+                    return Err(ConfidisError::ParseError(
+                        "Missing items, syntax is MGET <question> [<question> ...]".into(),
+                    ));
+                }
+                // MGET <question> [<question> ...]
+                Ok(Command {
+                    cmd: CommandType::MGet,
+                    questions: Some(items[1..].to_vec()),
+                    ..Default::default()
+                })
+            }
+            "EXPLAIN" | "explain" => {
+                // EXPLAIN <question>
+                Ok(Command {
+                    cmd: CommandType::Explain,
+                    question: Some(item(1)?),
+                    ..Default::default()
+                })
+            }
+            _ => Err(ConfidisError::ParseError(format!(
+                "Invalid command starting token: {}",
+                items[0]
+            ))),
+        }
+    }
+
+    // Parse the JSON form of a command, which needs no quoting or escaping, e.g.
+    // {"cmd": "set", "question": "q1", "answer": "a", "source": "s1"}
+    pub fn from_json(json: &str) -> Result<Command<'static>, ConfidisError> {
+        let cmd: Command<'static> = serde_json::from_str(json)
+            .map_err(|e| ConfidisError::ParseError(format!("Invalid JSON command: {}", e)))?;
+        if cmd.cmd == CommandType::Invalid {
+            return Err(ConfidisError::ParseError("Invalid command".into()));
+        }
+        cmd.validate()?;
+        Ok(cmd)
+    }
+
+    // A copy of the command that doesn't borrow from anything
+    pub fn into_owned(self) -> Command<'static> {
+        let own = |value: Option<Cow<str>>| value.map(|value| Cow::Owned(value.into_owned()));
+        let own_list = |values: Option<Vec<Cow<str>>>| {
+            values.map(|values| {
+                values
+                    .into_iter()
+                    .map(|value| Cow::Owned(value.into_owned()))
+                    .collect()
+            })
+        };
+        Command {
+            cmd: self.cmd,
+            source: own(self.source),
+            question: own(self.question),
+            answer: own(self.answer),
+            config_key: own(self.config_key),
+            config_val: own(self.config_val),
+            schema: own(self.schema),
+            question_type: own(self.question_type),
+            answer1: own(self.answer1),
+            answer2: own(self.answer2),
+            sources: own_list(self.sources),
+            questions: own_list(self.questions),
+            count: self.count,
+            as_of: self.as_of,
+            runners_up: self.runners_up,
+        }
+    }
+
+    // The value of one of the command's fields by name, e.g. "question", or a
+    // ParseError if it isn't set
+    pub fn field(&self, name: &str) -> Result<&str, ConfidisError> {
+        let value = match name {
+            "source" => &self.source,
+            "question" => &self.question,
+            "answer" => &self.answer,
+            "config_key" => &self.config_key,
+            "config_val" => &self.config_val,
+            "schema" => &self.schema,
+            "question_type" => &self.question_type,
+            "answer1" => &self.answer1,
+            "answer2" => &self.answer2,
+            _ => &None,
+        };
+        value.as_deref().ok_or_else(|| {
+            ConfidisError::ParseError(format!("{:?} command is missing \"{}\"", self.cmd, name))
+        })
+    }
+
+    // The sources of a COMPARE SOURCES command, or a ParseError if there are
+    // fewer than two
+    pub fn source_list(&self) -> Result<Vec<&str>, ConfidisError> {
+        match self.sources.as_deref() {
+            Some(sources) if sources.len() >= 2 => Ok(sources.iter().map(|s| s.as_ref()).collect()),
+            _ => Err(ConfidisError::ParseError(format!(
+                "{:?} command needs at least two \"sources\"",
+                self.cmd
+            ))),
         }
     }
+
+    // The questions of an MGET command, or a ParseError if there are none
+    pub fn question_list(&self) -> Result<Vec<&str>, ConfidisError> {
+        match self.questions.as_deref() {
+            Some(questions) if !questions.is_empty() => {
+                Ok(questions.iter().map(|q| q.as_ref()).collect())
+            }
+            _ => Err(ConfidisError::ParseError(format!(
+                "{:?} command needs at least one of \"questions\"",
+                self.cmd
+            ))),
+        }
+    }
+
+    // Check that every field the command type needs is set and that the
+    // question and source names are valid, see QuestionId and SourceId
+    pub fn validate(&self) -> Result<(), ConfidisError> {
+        for name in self.cmd.required_fields() {
+            if *name == "sources" {
+                for source in self.source_list()? {
+                    validate_command_ids(None, Some(source))?;
+                }
+            } else if *name == "questions" {
+                for question in self.question_list()? {
+                    validate_command_ids(Some(question), None)?;
+                }
+            } else {
+                self.field(name)?;
+            }
+        }
+        validate_command_ids(self.question.as_deref(), self.source.as_deref())
+    }
 }
 
+// What an answer holds. The command language and everything persisted use
+// String answers, embedders can use any hashable and comparable type with a
+// graph and equalifier for it, e.g. Graph<Vec<u8>>.
+pub trait AnswerContent: Clone + Hash + PartialEq + 'static {}
+
+impl<T: Clone + Hash + PartialEq + 'static> AnswerContent for T {}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Answer {
-    pub hash: u64,
-    pub content: String,
+pub struct Answer<A = String> {
+    // identifies equal contents, e.g. to cache distances. Not persisted, a
+    // graph hashes the answers it loads with its answer_hash, see hash.rs
+    #[serde(skip)]
+    pub hash: AnswerHash,
+    pub content: A,
     pub source: String,
 }
 
-impl Answer {
-    pub fn new(content: String, source: String) -> Self {
-        let mut hasher = DefaultHasher::new();
-        content.hash(&mut hasher);
+impl<A: AnswerContent> Answer<A> {
+    // An answer hashed with the default answer_hash
+    pub fn new(content: A, source: String) -> Self {
+        Answer::with_hasher(content, source, AnswerHasher::default())
+    }
+
+    pub fn with_hasher(content: A, source: String, hasher: AnswerHasher) -> Self {
         Answer {
-            hash: hasher.finish(),
-            content: content,
-            source: source,
+            hash: hasher.hash(&content),
+            content,
+            source,
         }
     }
 }
 
-impl fmt::Display for Answer {
+impl<A: fmt::Display> fmt::Display for Answer<A> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.content)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AnswerConfidencePair {
     pub answer: String,
     pub confidence: f64,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
-pub struct CommandResponse {
-    pub cmd: CommandType,
-    pub quality: Option<f64>,
-    pub answer: Option<String>,
-    pub confidence: Option<f64>,
-    pub distance: Option<f64>,
-    pub answers: Option<Vec<AnswerConfidencePair>>,
+impl AnswerConfidencePair {
+    // Same as CommandResponse::answer_as_f64
+    pub fn answer_as_f64(&self) -> Option<f64> {
+        self.answer.parse().ok()
+    }
+
+    // Same as CommandResponse::answer_as_vec
+    pub fn answer_as_vec(&self) -> Option<Vec<f64>> {
+        parse_numeric_vec(&self.answer)
+    }
 }
 
-impl fmt::Display for CommandResponse {
+// Approximate heap usage of a graph, see Graph::memory_stats
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemoryStats {
+    pub source_count: usize,
+    pub question_count: usize,
+    pub answer_count: usize,
+    pub distance_cache_count: usize,
+    pub source_bytes: usize,
+    pub question_bytes: usize,
+    pub answer_bytes: usize,
+    pub distance_cache_bytes: usize,
+}
+
+impl MemoryStats {
+    pub fn total_bytes(&self) -> usize {
+        self.source_bytes + self.question_bytes + self.answer_bytes + self.distance_cache_bytes
+    }
+}
+
+impl fmt::Display for MemoryStats {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.cmd {
-            CommandType::GetAnswer => write!(
+        writeln!(
+            f,
+            "sources: {} ({} bytes)",
+            self.source_count, self.source_bytes
+        )?;
+        writeln!(
+            f,
+            "questions: {} ({} bytes)",
+            self.question_count, self.question_bytes
+        )?;
+        writeln!(
+            f,
+            "answers: {} ({} bytes)",
+            self.answer_count, self.answer_bytes
+        )?;
+        writeln!(
+            f,
+            "cached distances: {} ({} bytes)",
+            self.distance_cache_count, self.distance_cache_bytes
+        )?;
+        write!(f, "total: {} bytes", self.total_bytes())
+    }
+}
+
+// Number of equal width buckets GraphStats::confidence_histogram splits 0..=1 into
+pub const CONFIDENCE_BUCKETS: usize = 10;
+
+// Aggregates over a whole graph for dashboards, see Graph::graph_stats
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GraphStats {
+    pub source_count: usize,
+    pub question_count: usize,
+    pub answer_count: usize,
+    // questions without any answers
+    pub unanswered_question_count: usize,
+    // None for a graph without sources
+    pub mean_source_quality: Option<f64>,
+    pub median_source_quality: Option<f64>,
+    // How many answered questions have a confidence in each tenth of 0..=1,
+    // from their last recomputation. A confidence of exactly 1 is in the last
+    // bucket.
+    pub confidence_histogram: Vec<usize>,
+    // answers rejected by the per-source rate limit, see rate_limit.rs
+    #[serde(default)]
+    pub rate_limited_answer_count: u64,
+}
+
+impl fmt::Display for GraphStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let quality = |quality: Option<f64>| match quality {
+            Some(quality) => format!("{:.3}", quality),
+            None => String::from("None"),
+        };
+        writeln!(
+            f,
+            "source quality: mean {}, median {}",
+            quality(self.mean_source_quality),
+            quality(self.median_source_quality)
+        )?;
+        writeln!(
+            f,
+            "unanswered questions: {}",
+            self.unanswered_question_count
+        )?;
+        writeln!(
+            f,
+            "rate limited answers: {}",
+            self.rate_limited_answer_count
+        )?;
+        write!(
+            f,
+            "question confidences by tenth: {}",
+            self.confidence_histogram
+                .iter()
+                .map(|count| count.to_string())
+                .collect::<Vec<String>>()
+                .join(" ")
+        )
+    }
+}
+
+// One answer to a question as it was weighed, see Provenance
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Evidence<A = String> {
+    pub content: A,
+    pub source: String,
+    // the source's quality and strength the answer was weighed with
+    pub source_quality: f64,
+    pub source_strength: f64,
+    // index into Provenance::cluster_confidences
+    pub cluster: usize,
+    // whether the answer is in the cluster of the chosen answer
+    pub supports: bool,
+}
+
+// Why a question has its current answer: every answer it was given, grouped
+// into clusters, and the source qualities the clusters' confidences were
+// computed from. The qualities are the ones GET ANSWER uses, so the trace
+// justifies exactly the answer GET ANSWER reports. See Graph::explain.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provenance<A = String> {
+    pub question: String,
+    // None for a question without answers
+    pub answer: Option<A>,
+    pub confidence: f64,
+    pub cluster_confidences: Vec<f64>,
+    pub evidence: Vec<Evidence<A>>,
+}
+
+impl<A: fmt::Display> fmt::Display for Provenance<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.answer {
+            Some(answer) => write!(f, "{} ({:.3}%)", answer, self.confidence * 100.)?,
+            None => write!(f, "None (0.000%)")?,
+        }
+        // supporting answers are marked with a *
+        for evidence in &self.evidence {
+            write!(
                 f,
-                "{} ({:.3}%)",
-                &self.answer.as_ref().unwrap(),
-                self.confidence.unwrap() * 100.
-            ),
-            CommandType::GetSource => write!(f, "{:.3}", self.quality.unwrap()),
-            CommandType::TestEquality => write!(f, "{:.3}", self.distance.unwrap()),
+                "\n{} {} from {} (quality {:.3}, cluster {} {:.3}%)",
+                if evidence.supports { "*" } else { " " },
+                evidence.content,
+                evidence.source,
+                evidence.source_quality,
+                evidence.cluster,
+                self.cluster_confidences[evidence.cluster] * 100.
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// An answer in a cluster, see ClusterDebug
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClusterMember<A = String> {
+    pub answer: A,
+    pub source: String,
+    // distance to the cluster's first member (its seed), 0 for the seed
+    pub distance_to_seed: f64,
+}
+
+// One cluster of a question's answers as DEBUG CLUSTERS reports it, to see
+// how the comparison method grouped the answers when tuning its parameters
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClusterDebug<A = String> {
+    pub confidence: f64,
+    // whether this is the cluster of the question's answer
+    pub correct: bool,
+    pub members: Vec<ClusterMember<A>>,
+}
+
+impl<A: fmt::Display> fmt::Display for ClusterDebug<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.3}%", self.confidence * 100.)?;
+        if self.correct {
+            write!(f, " (correct)")?;
+        }
+        for (i, member) in self.members.iter().enumerate() {
+            write!(f, "\n  {} from {}", member.answer, member.source)?;
+            if i == 0 {
+                write!(f, " (seed)")?;
+            } else {
+                write!(f, " (distance {:.3})", member.distance_to_seed)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// How often two sources agree, see Graph::compare_sources
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceAgreement {
+    pub source1: String,
+    pub source2: String,
+    // questions both sources answered
+    pub co_answered: usize,
+    // of those, the questions where their answers are in the same cluster
+    pub agreed: usize,
+    // agreed / co_answered, None if they didn't answer any question in common
+    pub agreement_rate: Option<f64>,
+}
+
+impl fmt::Display for SourceAgreement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: {} of {} agreed",
+            self.source1, self.source2, self.agreed, self.co_answered
+        )?;
+        if let Some(rate) = self.agreement_rate {
+            write!(f, " ({:.3}%)", rate * 100.)?;
+        }
+        Ok(())
+    }
+}
+
+// A question's answer as MGET reports it, "None" with a confidence of 0 for a
+// question without answers and "Unknown" for one whose confidence is below
+// min_confidence. insufficient_evidence like GET ANSWER's response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuestionAnswer {
+    pub question: String,
+    pub answer: String,
+    pub confidence: f64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub insufficient_evidence: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unknown: bool,
+}
+
+impl fmt::Display for QuestionAnswer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} ({:.3}%)",
+            self.question,
+            self.answer,
+            self.confidence * 100.
+        )?;
+        if self.insufficient_evidence {
+            write!(f, ", insufficient evidence")?;
+        }
+        Ok(())
+    }
+}
+
+// The outcome of a command, one variant per kind of result
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(into = "ResponseFields", try_from = "ResponseFields")]
+pub enum CommandResponse {
+    Set,
+    Believe,
+    Exclude,
+    Honeypot,
+    Rebuild,
+    // UNDO, the commands taken back, the most recent first
+    Undo(Vec<String>),
+    // REDO, the commands redone, the earliest first
+    Redo(Vec<String>),
+    // CONFIGURE, the setting's previous value in the form CONFIGURE takes
+    Configure {
+        previous: String,
+    },
+    // SCHEMA, the question's previous schema in the form SCHEMA takes
+    Schema {
+        previous: String,
+    },
+    // TYPE, the question's previous type in the form TYPE takes
+    Type {
+        previous: String,
+    },
+    // GET ANSWER TO, "None" with a confidence of 0 for a question without answers.
+    // sources are the sources that gave the answer (its cluster), cluster_count
+    // is the number of distinct answers to the question. runners_up are the
+    // most confident competing answers, empty unless asked for with RUNNERS UP.
+    Answer {
+        content: String,
+        confidence: f64,
+        sources: Vec<String>,
+        cluster_count: usize,
+        runners_up: Vec<AnswerConfidencePair>,
+    },
+    // GET ANSWER TO when fewer than min_sources_per_answer distinct sources
+    // answered the question, with the answer and confidence so far
+    InsufficientEvidence {
+        content: String,
+        confidence: f64,
+        source_count: usize,
+        min_sources: usize,
+    },
+    // GET ANSWER TO when the answer's confidence is below min_confidence, so
+    // that the answer can't be used by mistake
+    Unknown {
+        confidence: f64,
+        min_confidence: f64,
+    },
+    // GET ANSWERS TO, one entry per distinct answer
+    Answers(Vec<AnswerConfidencePair>),
+    // GET SOURCE
+    Source {
+        quality: f64,
+        strength: f64,
+    },
+    // TEST EQUALITY
+    Distance(f64),
+    Stats {
+        graph: GraphStats,
+        memory: MemoryStats,
+    },
+    // EXPLAIN
+    Explanation(Provenance),
+    // GET AUDIT FOR, oldest change first
+    Audit(Vec<QualityChange>),
+    // GET HISTORY OF, oldest change first
+    History(Vec<AnswerChange>),
+    // DEBUG CLUSTERS
+    Clusters(Vec<ClusterDebug>),
+    // COMPARE SOURCES, one entry per pair of sources
+    Agreement(Vec<SourceAgreement>),
+    // MGET, in the order the questions were given
+    QuestionAnswers(Vec<QuestionAnswer>),
+    // GET SHADOW
+    Shadow(ShadowStats),
+}
+
+impl CommandResponse {
+    // The type of command this is the response to
+    pub fn cmd(&self) -> CommandType {
+        match self {
+            CommandResponse::Set => CommandType::Set,
+            CommandResponse::Believe => CommandType::Believe,
+            CommandResponse::Exclude => CommandType::Exclude,
+            CommandResponse::Honeypot => CommandType::Honeypot,
+            CommandResponse::Rebuild => CommandType::Rebuild,
+            CommandResponse::Undo(_) => CommandType::Undo,
+            CommandResponse::Redo(_) => CommandType::Redo,
+            CommandResponse::Configure { .. } => CommandType::Configure,
+            CommandResponse::Schema { .. } => CommandType::Schema,
+            CommandResponse::Type { .. } => CommandType::Type,
+            CommandResponse::Answer { .. }
+            | CommandResponse::InsufficientEvidence { .. }
+            | CommandResponse::Unknown { .. } => CommandType::GetAnswer,
+            CommandResponse::Answers(_) => CommandType::GetAnswers,
+            CommandResponse::Source { .. } => CommandType::GetSource,
+            CommandResponse::Distance(_) => CommandType::TestEquality,
+            CommandResponse::Stats { .. } => CommandType::Stats,
+            CommandResponse::Explanation(_) => CommandType::Explain,
+            CommandResponse::Audit(_) => CommandType::GetAudit,
+            CommandResponse::History(_) => CommandType::GetHistory,
+            CommandResponse::Clusters(_) => CommandType::DebugClusters,
+            CommandResponse::Agreement(_) => CommandType::CompareSources,
+            CommandResponse::Shadow(_) => CommandType::GetShadow,
+            CommandResponse::QuestionAnswers(_) => CommandType::MGet,
+        }
+    }
+
+    // The answer of a GET ANSWER response as a number, None if there's no
+    // answer or it isn't one (see the numeric comparison method)
+    pub fn answer_as_f64(&self) -> Option<f64> {
+        match self {
+            CommandResponse::Answer { content, .. } => content.parse().ok(),
+            _ => None,
+        }
+    }
+
+    // The answer of a GET ANSWER response as a vector, None if there's no answer
+    // or it isn't one (see the numeric_vec comparison method)
+    pub fn answer_as_vec(&self) -> Option<Vec<f64>> {
+        match self {
+            CommandResponse::Answer { content, .. } => parse_numeric_vec(content),
+            _ => None,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+// How the REPL and the TCP server print responses, Text is the Display form
+// (e.g. "a (95.885%)") and Json the JSON form on a single line. Clients switch
+// with CONFIGURE output_format json, which changes their session rather than
+// the graph, see configure.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputFormat::Text => "text",
+            OutputFormat::Json => "json",
+        }
+    }
+
+    // Switch to the format of a CONFIGURE output_format command, returning its
+    // response, or None if cmd is any other command and should be executed
+    pub fn configure(&mut self, cmd: &Command) -> Option<Result<CommandResponse, ConfidisError>> {
+        if cmd.cmd != CommandType::Configure
+            || cmd.field("config_key").ok()?.parse::<ConfigKey>().ok()? != ConfigKey::OutputFormat
+        {
+            return None;
+        }
+        Some(cmd.field("config_val").and_then(str::parse).map(|format| {
+            CommandResponse::Configure {
+                previous: std::mem::replace(self, format).as_str().to_string(),
+            }
+        }))
+    }
+
+    // A response or error as printed in this format
+    pub fn format(&self, reply: &Result<CommandResponse, String>) -> String {
+        match (self, reply) {
+            (OutputFormat::Text, Ok(response)) => response.to_string(),
+            (OutputFormat::Text, Err(msg)) => format!("Err: {}", msg),
+            (OutputFormat::Json, Ok(response)) => response.to_json(),
+            (OutputFormat::Json, Err(msg)) => serde_json::json!({ "error": msg }).to_string(),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = ConfidisError;
+
+    fn from_str(s: &str) -> Result<OutputFormat, ConfidisError> {
+        match s.trim() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(ConfidisError::InvalidConfig {
+                key: ConfigKey::OutputFormat.to_string(),
+                reason: format!("expects {}", ConfigKey::OutputFormat.expected()),
+            }),
+        }
+    }
+}
+
+// The JSON form of a CommandResponse, the cmd and only the fields that apply
+// to it, e.g. {"cmd":"GetSource","quality":0.5,"strength":1.0}
+#[derive(Default, Serialize, Deserialize)]
+struct ResponseFields {
+    cmd: CommandType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quality: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    strength: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    answer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    confidence: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sources: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cluster_count: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    distance: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    answers: Option<Vec<AnswerConfidencePair>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    memory: Option<MemoryStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    graph: Option<GraphStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provenance: Option<Provenance>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    audit: Option<Vec<QualityChange>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    history: Option<Vec<AnswerChange>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clusters: Option<Vec<ClusterDebug>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    previous: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    agreement: Option<Vec<SourceAgreement>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    question_answers: Option<Vec<QuestionAnswer>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source_count: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_sources: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_confidence: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    commands: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shadow: Option<ShadowStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    runners_up: Option<Vec<AnswerConfidencePair>>,
+}
+
+impl From<CommandResponse> for ResponseFields {
+    fn from(response: CommandResponse) -> ResponseFields {
+        let cmd = response.cmd();
+        let fields = ResponseFields {
+            cmd,
+            ..Default::default()
+        };
+        match response {
+            CommandResponse::Set
+            | CommandResponse::Believe
+            | CommandResponse::Exclude
+            | CommandResponse::Honeypot
+            | CommandResponse::Rebuild => fields,
+            CommandResponse::Undo(commands) | CommandResponse::Redo(commands) => ResponseFields {
+                commands: Some(commands),
+                ..fields
+            },
+            CommandResponse::Configure { previous }
+            | CommandResponse::Schema { previous }
+            | CommandResponse::Type { previous } => ResponseFields {
+                previous: Some(previous),
+                ..fields
+            },
+            CommandResponse::Answer {
+                content,
+                confidence,
+                sources,
+                cluster_count,
+                runners_up,
+            } => ResponseFields {
+                answer: Some(content),
+                confidence: Some(confidence),
+                sources: Some(sources),
+                cluster_count: Some(cluster_count),
+                runners_up: (!runners_up.is_empty()).then_some(runners_up),
+                ..fields
+            },
+            CommandResponse::InsufficientEvidence {
+                content,
+                confidence,
+                source_count,
+                min_sources,
+            } => ResponseFields {
+                answer: Some(content),
+                confidence: Some(confidence),
+                source_count: Some(source_count),
+                min_sources: Some(min_sources),
+                ..fields
+            },
+            CommandResponse::Unknown {
+                confidence,
+                min_confidence,
+            } => ResponseFields {
+                confidence: Some(confidence),
+                min_confidence: Some(min_confidence),
+                ..fields
+            },
+            CommandResponse::Answers(answers) => ResponseFields {
+                answers: Some(answers),
+                ..fields
+            },
+            CommandResponse::Source { quality, strength } => ResponseFields {
+                quality: Some(quality),
+                strength: Some(strength),
+                ..fields
+            },
+            CommandResponse::Distance(distance) => ResponseFields {
+                distance: Some(distance),
+                ..fields
+            },
+            CommandResponse::Stats { graph, memory } => ResponseFields {
+                graph: Some(graph),
+                memory: Some(memory),
+                ..fields
+            },
+            CommandResponse::Explanation(provenance) => ResponseFields {
+                provenance: Some(provenance),
+                ..fields
+            },
+            CommandResponse::Audit(audit) => ResponseFields {
+                audit: Some(audit),
+                ..fields
+            },
+            CommandResponse::History(history) => ResponseFields {
+                history: Some(history),
+                ..fields
+            },
+            CommandResponse::Clusters(clusters) => ResponseFields {
+                clusters: Some(clusters),
+                ..fields
+            },
+            CommandResponse::Agreement(agreement) => ResponseFields {
+                agreement: Some(agreement),
+                ..fields
+            },
+            CommandResponse::QuestionAnswers(question_answers) => ResponseFields {
+                question_answers: Some(question_answers),
+                ..fields
+            },
+            CommandResponse::Shadow(shadow) => ResponseFields {
+                shadow: Some(shadow),
+                ..fields
+            },
+        }
+    }
+}
+
+impl TryFrom<ResponseFields> for CommandResponse {
+    type Error = String;
+
+    fn try_from(fields: ResponseFields) -> Result<CommandResponse, String> {
+        let missing = |field: &str| format!("{:?} response is missing \"{}\"", fields.cmd, field);
+        Ok(match fields.cmd {
+            CommandType::Set => CommandResponse::Set,
+            CommandType::Believe => CommandResponse::Believe,
+            CommandType::Exclude => CommandResponse::Exclude,
+            CommandType::Honeypot => CommandResponse::Honeypot,
+            CommandType::Rebuild => CommandResponse::Rebuild,
+            CommandType::Undo => CommandResponse::Undo(fields.commands.unwrap_or_default()),
+            CommandType::Redo => CommandResponse::Redo(fields.commands.unwrap_or_default()),
+            CommandType::Configure => CommandResponse::Configure {
+                previous: fields.previous.clone().unwrap_or_default(),
+            },
+            CommandType::Schema => CommandResponse::Schema {
+                previous: fields.previous.clone().unwrap_or_default(),
+            },
+            CommandType::Type => CommandResponse::Type {
+                previous: fields.previous.clone().unwrap_or_default(),
+            },
+            CommandType::GetAnswer if fields.min_confidence.is_some() => CommandResponse::Unknown {
+                confidence: fields.confidence.ok_or_else(|| missing("confidence"))?,
+                min_confidence: fields.min_confidence.unwrap_or_default(),
+            },
+            CommandType::GetAnswer if fields.min_sources.is_some() => {
+                CommandResponse::InsufficientEvidence {
+                    content: fields.answer.clone().ok_or_else(|| missing("answer"))?,
+                    confidence: fields.confidence.ok_or_else(|| missing("confidence"))?,
+                    source_count: fields.source_count.unwrap_or_default(),
+                    min_sources: fields.min_sources.unwrap_or_default(),
+                }
+            }
+            CommandType::GetAnswer => CommandResponse::Answer {
+                content: fields.answer.clone().ok_or_else(|| missing("answer"))?,
+                confidence: fields.confidence.ok_or_else(|| missing("confidence"))?,
+                sources: fields.sources.clone().unwrap_or_default(),
+                cluster_count: fields.cluster_count.unwrap_or_default(),
+                runners_up: fields.runners_up.clone().unwrap_or_default(),
+            },
             CommandType::GetAnswers => {
-                let answer_confidence_pairs = self.answers.as_ref().unwrap();
-                write!(
-                    f,
-                    "{}",
-                    answer_confidence_pairs
+                CommandResponse::Answers(fields.answers.clone().ok_or_else(|| missing("answers"))?)
+            }
+            CommandType::GetSource => CommandResponse::Source {
+                quality: fields.quality.ok_or_else(|| missing("quality"))?,
+                // responses from before strength was reported
+                strength: fields.strength.unwrap_or_default(),
+            },
+            CommandType::TestEquality => {
+                CommandResponse::Distance(fields.distance.ok_or_else(|| missing("distance"))?)
+            }
+            CommandType::Stats => CommandResponse::Stats {
+                // responses from before the aggregates were reported
+                graph: fields.graph.clone().unwrap_or_default(),
+                memory: fields.memory.clone().ok_or_else(|| missing("memory"))?,
+            },
+            CommandType::Explain => CommandResponse::Explanation(
+                fields
+                    .provenance
+                    .clone()
+                    .ok_or_else(|| missing("provenance"))?,
+            ),
+            CommandType::GetAudit => {
+                CommandResponse::Audit(fields.audit.clone().ok_or_else(|| missing("audit"))?)
+            }
+            CommandType::GetHistory => {
+                CommandResponse::History(fields.history.clone().ok_or_else(|| missing("history"))?)
+            }
+            CommandType::DebugClusters => CommandResponse::Clusters(
+                fields.clusters.clone().ok_or_else(|| missing("clusters"))?,
+            ),
+            CommandType::CompareSources => CommandResponse::Agreement(
+                fields
+                    .agreement
+                    .clone()
+                    .ok_or_else(|| missing("agreement"))?,
+            ),
+            CommandType::MGet => CommandResponse::QuestionAnswers(
+                fields
+                    .question_answers
+                    .clone()
+                    .ok_or_else(|| missing("question_answers"))?,
+            ),
+            CommandType::GetShadow => {
+                CommandResponse::Shadow(fields.shadow.clone().ok_or_else(|| missing("shadow"))?)
+            }
+            CommandType::Invalid => return Err(String::from("Invalid response")),
+        })
+    }
+}
+
+impl fmt::Display for CommandResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandResponse::Answer {
+                content,
+                confidence,
+                runners_up,
+                ..
+            } => {
+                write!(f, "{} ({:.3}%)", content, confidence * 100.)?;
+                if !runners_up.is_empty() {
+                    let runners_up: Vec<String> = runners_up
                         .iter()
                         .map(|acp| format!("{} ({:.3}%)", acp.answer, acp.confidence * 100.))
-                        .collect::<Vec<String>>()
-                        .join(", ")
-                )
-                .unwrap();
-                return Ok(());
+                        .collect();
+                    write!(f, ", runners up: {}", runners_up.join(", "))?;
+                }
+                Ok(())
+            }
+            CommandResponse::InsufficientEvidence {
+                content,
+                confidence,
+                source_count,
+                min_sources,
+            } => write!(
+                f,
+                "Insufficient evidence: {} ({:.3}%) from {} of {} sources",
+                content,
+                confidence * 100.,
+                source_count,
+                min_sources
+            ),
+            CommandResponse::Unknown {
+                confidence,
+                min_confidence,
+            } => write!(
+                f,
+                "Unknown ({:.3}%, below {:.3}%)",
+                confidence * 100.,
+                min_confidence * 100.
+            ),
+            CommandResponse::Source { quality, .. } => write!(f, "{:.3}", quality),
+            CommandResponse::Distance(distance) => write!(f, "{:.3}", distance),
+            CommandResponse::Answers(answer_confidence_pairs) => write!(
+                f,
+                "{}",
+                answer_confidence_pairs
+                    .iter()
+                    .map(|acp| format!("{} ({:.3}%)", acp.answer, acp.confidence * 100.))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            CommandResponse::Stats { graph, memory } => write!(f, "{}\n{}", graph, memory),
+            CommandResponse::Explanation(provenance) => write!(f, "{}", provenance),
+            CommandResponse::Audit(changes) => write!(
+                f,
+                "{}",
+                changes
+                    .iter()
+                    .map(|change| change.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            ),
+            CommandResponse::Clusters(clusters) => {
+                for (i, cluster) in clusters.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "cluster {}: {}", i, cluster)?;
+                }
+                Ok(())
+            }
+            CommandResponse::History(changes) => write!(
+                f,
+                "{}",
+                changes
+                    .iter()
+                    .map(|change| format!(
+                        "{} {} ({:.3}%)",
+                        change.timestamp,
+                        change.answer,
+                        change.confidence * 100.
+                    ))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            ),
+            CommandResponse::Agreement(pairs) => write!(
+                f,
+                "{}",
+                pairs
+                    .iter()
+                    .map(|pair| pair.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            ),
+            CommandResponse::QuestionAnswers(answers) => write!(
+                f,
+                "{}",
+                answers
+                    .iter()
+                    .map(|answer| answer.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            ),
+            CommandResponse::Shadow(shadow) => write!(f, "{}", shadow),
+            CommandResponse::Configure { previous }
+            | CommandResponse::Schema { previous }
+            | CommandResponse::Type { previous } => {
+                write!(f, "{}", previous)
+            }
+            CommandResponse::Set
+            | CommandResponse::Believe
+            | CommandResponse::Exclude
+            | CommandResponse::Honeypot
+            | CommandResponse::Rebuild => write!(f, ""),
+            CommandResponse::Undo(commands) if commands.is_empty() => write!(f, "Nothing to undo"),
+            CommandResponse::Redo(commands) if commands.is_empty() => write!(f, "Nothing to redo"),
+            CommandResponse::Undo(commands) | CommandResponse::Redo(commands) => {
+                write!(f, "{}", commands.join("\n"))
             }
-            _ => write!(f, ""),
         }
     }
 }
+
+#[test]
+fn test_command_from_json() {
+    let cmd = Command::from_json(
+        r#"{"cmd": "set", "question": "q1", "answer": "a \"b\"", "source": "s1"}"#,
+    )
+    .unwrap();
+    assert_eq!(cmd.cmd, CommandType::Set);
+    assert_eq!(cmd.answer.as_deref(), Some("a \"b\""));
+    assert_eq!(
+        Command::from_json(r#"{"cmd": "GetSource", "source": "s1"}"#)
+            .unwrap()
+            .cmd,
+        CommandType::GetSource
+    );
+    assert!(matches!(
+        Command::from_json(r#"{"cmd": "set", "question": "q1"}"#),
+        Err(ConfidisError::ParseError(msg)) if msg.contains("answer")
+    ));
+    assert!(Command::from_json(r#"{"cmd": "nope"}"#).is_err());
+    assert!(Command::from_json("SET q1 a FROM s1").is_err());
+}
+
+#[test]
+fn test_quoted_items() {
+    let cmd = Command::from(r#"SET q1 "New York" FROM s1"#).unwrap();
+    assert_eq!(cmd.answer.as_deref(), Some("New York"));
+    assert_eq!(cmd.to_string(), r#"SET q1 "New York" FROM s1"#);
+
+    // A newline can't start another line of a journal or script
+    let cmd = Command {
+        cmd: CommandType::Set,
+        question: Some("q1".into()),
+        answer: Some("a\n1 BELIEVE s9 \"\\ \u{7}".into()),
+        source: Some("s1".into()),
+        ..Default::default()
+    };
+    let line = cmd.to_string();
+    assert!(!line.contains('\n'));
+    assert_eq!(Command::from(&line).unwrap(), cmd);
+    assert_eq!(quote(""), "\"\"");
+    assert_eq!(quote("plain"), "plain");
+
+    assert!(Command::from(r#"SET q1 "a FROM s1"#).is_err());
+    assert!(Command::from(r#"SET q1 "a"b FROM s1"#).is_err());
+    assert!(Command::from(r#"SET q1 "\u{zz}" FROM s1"#).is_err());
+}
+
+#[test]
+fn test_command_serde_roundtrip() {
+    let cmd = Command::from("SET q1 a FROM s1").unwrap();
+    let json = serde_json::to_string(&cmd).unwrap();
+    assert_eq!(
+        json,
+        r#"{"cmd":"Set","source":"s1","question":"q1","answer":"a"}"#
+    );
+    assert_eq!(serde_json::from_str::<Command>(&json).unwrap(), cmd);
+    // Parsed text commands borrow their fields from the line
+    assert!(matches!(cmd.question, Some(Cow::Borrowed("q1"))));
+    assert!(matches!(
+        cmd.into_owned().source,
+        Some(Cow::Owned(source)) if source == "s1"
+    ));
+
+    let response = CommandResponse::Answers(vec![AnswerConfidencePair {
+        answer: String::from("a"),
+        confidence: 0.5,
+    }]);
+    let json = response.to_json();
+    assert_eq!(
+        json,
+        r#"{"cmd":"GetAnswers","answers":[{"answer":"a","confidence":0.5}]}"#
+    );
+    assert_eq!(
+        serde_json::from_str::<CommandResponse>(&json).unwrap(),
+        response
+    );
+}
+
+#[test]
+fn test_numeric_answer_accessors() {
+    let answer = |content: &str| CommandResponse::Answer {
+        content: content.to_string(),
+        confidence: 0.5,
+        sources: Vec::new(),
+        cluster_count: 1,
+        runners_up: Vec::new(),
+    };
+    assert_eq!(answer("2.5").answer_as_f64(), Some(2.5));
+    assert_eq!(answer("None").answer_as_f64(), None);
+    assert_eq!(answer("1,2.5,3").answer_as_vec(), Some(vec![1.0, 2.5, 3.0]));
+    assert_eq!(answer("1,x").answer_as_vec(), None);
+    assert_eq!(CommandResponse::Distance(0.5).answer_as_f64(), None);
+}
+
+#[test]
+fn test_output_format() {
+    let mut format = OutputFormat::default();
+    let response = CommandResponse::Source {
+        quality: 0.5,
+        strength: 1.0,
+    };
+    assert!(format
+        .configure(&Command::from("CONFIGURE maximum_strength 10").unwrap())
+        .is_none());
+    assert!(format
+        .configure(&Command::from("GET SOURCE s1").unwrap())
+        .is_none());
+    assert!(matches!(
+        format.configure(&Command::from("CONFIGURE output_format xml").unwrap()),
+        Some(Err(ConfidisError::InvalidConfig { .. }))
+    ));
+    assert_eq!(format.format(&Ok(response.clone())), response.to_string());
+
+    assert_eq!(
+        format.configure(&Command::from("CONFIGURE output_format json").unwrap()),
+        Some(Ok(CommandResponse::Configure {
+            previous: String::from("text")
+        }))
+    );
+    assert_eq!(format, OutputFormat::Json);
+    assert_eq!(
+        serde_json::from_str::<CommandResponse>(&format.format(&Ok(response.clone()))).unwrap(),
+        response
+    );
+    let error: serde_json::Value =
+        serde_json::from_str(&format.format(&Err(String::from("nope")))).unwrap();
+    assert_eq!(error["error"], "nope");
+}
@@ -0,0 +1,706 @@
+use crate::command::OutputFormat;
+#[cfg(feature = "text")]
+use crate::equalifier::Language;
+use crate::equalifier::{comparison_methods, EqualifierConfig, VecDistAlgo};
+use crate::error::ConfidisError;
+use crate::hash::AnswerHasher;
+use crate::normalize::Normalization;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+// Tunable parameters of a Graph, set with CONFIGURE <key> <value>
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GraphConfig {
+    // Default probability that a source will be correct
+    pub default_source_quality: f64,
+
+    // Starting strength of a source, if this is low (1.0) the initial quality will be changed
+    // easily by new data. If higher, it's easier to resist adversaries with the "start good, turn bad"
+    // attack
+    pub initial_source_strength: f64,
+
+    // Maximum strength of a source, impacts how effected they are by more recent
+    // correct/incorrect answers. Past it the effects a source holds are scaled
+    // down to make room for new ones, see added_effect in graph.rs.
+    pub maximum_strength: f64,
+
+    // weight_of_question = -1. * log_{log_weight_factor}(1 - confidence)
+    // 10.0 means that 90% confidence has a weight of 1. 99% confidence has a weight of 2. 99.9% has a weight of 3.
+    pub log_weight_factor: f64,
+
+    // quality of believed sources
+    pub quality_of_believed_sources: f64,
+
+    // What a source answering a question it already answered does
+    #[serde(default)]
+    pub duplicate_answers: DuplicateAnswers,
+
+    // Steps every answer goes through on SET before it's stored, see normalize.rs
+    #[serde(default)]
+    pub normalize: Normalization,
+
+    // Seconds after a question's latest answer before its reported confidence
+    // starts to decay, 0 for no decay. Signals that time-sensitive facts need
+    // to be verified again.
+    #[serde(default)]
+    pub confidence_decay_after: f64,
+
+    // Seconds in which a decaying confidence halves
+    #[serde(default = "default_confidence_half_life")]
+    pub confidence_half_life: f64,
+
+    // Answers to a question after its first late_answer_after answers count
+    // late_answer_weight times as much towards confidences, e.g. 0.5 for half
+    // a vote. Annotators who can see the earlier answers tend to follow them
+    // (herding), so their answers are less independent evidence. 0 for no
+    // discount.
+    #[serde(default)]
+    pub late_answer_after: f64,
+    #[serde(default = "default_late_answer_weight")]
+    pub late_answer_weight: f64,
+
+    // Distinct sources that must have answered a question before GET ANSWER
+    // releases its answer, rather than reporting insufficient evidence. 0 for
+    // no quorum.
+    #[serde(default)]
+    pub min_sources_per_answer: f64,
+
+    // The confidence below which GET ANSWER reports Unknown instead of the
+    // most likely answer. 0 to always report it.
+    #[serde(default)]
+    pub min_confidence: f64,
+
+    // The weight honeypot questions change their sources' qualities with,
+    // see HONEYPOT. A question answered with confidence 0.9 weighs 1 with the
+    // default log_weight_factor.
+    #[serde(default = "default_honeypot_weight")]
+    pub honeypot_weight: f64,
+
+    // Mutating commands UNDO can take back, the most recent first. 0 keeps
+    // no undo stack.
+    #[serde(default)]
+    pub undo_depth: f64,
+
+    // Seconds after which a question's effect on a source's quality and
+    // strength expires, so ancient accuracy can't shield a source that has
+    // turned bad. 0 for evidence that never expires.
+    #[serde(default)]
+    pub evidence_expires_after: f64,
+
+    // A second comparison method whose clusters and answers are computed
+    // alongside the primary one's and compared, without affecting qualities,
+    // see shadow.rs. None for no shadow.
+    #[serde(default)]
+    pub shadow_comparison_method: Option<EqualifierConfig>,
+
+    // The hash function answers are hashed with, after normalization, see
+    // hash.rs
+    #[serde(default)]
+    pub answer_hash: AnswerHasher,
+
+    // Answers a source can give in any rate_limit_window seconds, more are
+    // rejected, see rate_limit.rs. 0 for no limit.
+    #[serde(default)]
+    pub rate_limit_answers: f64,
+    #[serde(default = "default_rate_limit_window")]
+    pub rate_limit_window: f64,
+}
+
+fn default_confidence_half_life() -> f64 {
+    7. * 24. * 3600.
+}
+
+fn default_late_answer_weight() -> f64 {
+    1.0
+}
+
+fn default_honeypot_weight() -> f64 {
+    10.0
+}
+
+fn default_rate_limit_window() -> f64 {
+    60.0
+}
+
+// Whether a source's answers to the same question all count. With Allow,
+// SET q1 a FROM s1 twice counts s1 twice towards a's confidence.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateAnswers {
+    // every answer counts
+    #[default]
+    Allow,
+    // only the source's first answer counts, later ones are dropped
+    Ignore,
+    // only the source's latest answer counts, it replaces the earlier ones
+    Replace,
+}
+
+impl DuplicateAnswers {
+    // The name CONFIGURE duplicate_answers takes
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DuplicateAnswers::Allow => "allow",
+            DuplicateAnswers::Ignore => "ignore",
+            DuplicateAnswers::Replace => "replace",
+        }
+    }
+}
+
+impl Default for GraphConfig {
+    fn default() -> Self {
+        GraphConfig {
+            default_source_quality: 0.5,
+            initial_source_strength: 1.0,
+            maximum_strength: 100.0,
+            log_weight_factor: 10.0,
+            quality_of_believed_sources: 0.999,
+            duplicate_answers: DuplicateAnswers::Allow,
+            normalize: Normalization::default(),
+            confidence_decay_after: 0.0,
+            confidence_half_life: default_confidence_half_life(),
+            late_answer_after: 0.0,
+            late_answer_weight: default_late_answer_weight(),
+            min_sources_per_answer: 0.0,
+            min_confidence: 0.0,
+            honeypot_weight: default_honeypot_weight(),
+            undo_depth: 0.0,
+            evidence_expires_after: 0.0,
+            shadow_comparison_method: None,
+            answer_hash: AnswerHasher::Sip64,
+            rate_limit_answers: 0.0,
+            rate_limit_window: default_rate_limit_window(),
+        }
+    }
+}
+
+// The settings CONFIGURE can change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConfigKey {
+    DefaultSourceQuality,
+    InitialSourceStrength,
+    MaximumStrength,
+    LogWeightFactor,
+    QualityOfBelievedSources,
+    ComparisonMethod,
+    DuplicateAnswers,
+    OutputFormat,
+    Normalize,
+    ConfidenceDecayAfter,
+    ConfidenceHalfLife,
+    LateAnswerAfter,
+    LateAnswerWeight,
+    MinSourcesPerAnswer,
+    MinConfidence,
+    HoneypotWeight,
+    UndoDepth,
+    EvidenceExpiresAfter,
+    ShadowComparisonMethod,
+    AnswerHash,
+    RateLimitAnswers,
+    RateLimitWindow,
+}
+
+impl ConfigKey {
+    pub const ALL: [ConfigKey; 22] = [
+        ConfigKey::DefaultSourceQuality,
+        ConfigKey::InitialSourceStrength,
+        ConfigKey::MaximumStrength,
+        ConfigKey::LogWeightFactor,
+        ConfigKey::QualityOfBelievedSources,
+        ConfigKey::ComparisonMethod,
+        ConfigKey::DuplicateAnswers,
+        ConfigKey::OutputFormat,
+        ConfigKey::Normalize,
+        ConfigKey::ConfidenceDecayAfter,
+        ConfigKey::ConfidenceHalfLife,
+        ConfigKey::LateAnswerAfter,
+        ConfigKey::LateAnswerWeight,
+        ConfigKey::MinSourcesPerAnswer,
+        ConfigKey::MinConfidence,
+        ConfigKey::HoneypotWeight,
+        ConfigKey::UndoDepth,
+        ConfigKey::EvidenceExpiresAfter,
+        ConfigKey::ShadowComparisonMethod,
+        ConfigKey::AnswerHash,
+        ConfigKey::RateLimitAnswers,
+        ConfigKey::RateLimitWindow,
+    ];
+
+    // The name used by CONFIGURE
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConfigKey::DefaultSourceQuality => "default_source_quality",
+            ConfigKey::InitialSourceStrength => "initial_source_strength",
+            ConfigKey::MaximumStrength => "maximum_strength",
+            ConfigKey::LogWeightFactor => "log_weight_factor",
+            ConfigKey::QualityOfBelievedSources => "quality_of_believed_sources",
+            ConfigKey::ComparisonMethod => "comparison_method",
+            ConfigKey::DuplicateAnswers => "duplicate_answers",
+            ConfigKey::OutputFormat => "output_format",
+            ConfigKey::Normalize => "normalize",
+            ConfigKey::ConfidenceDecayAfter => "confidence_decay_after",
+            ConfigKey::ConfidenceHalfLife => "confidence_half_life",
+            ConfigKey::LateAnswerAfter => "late_answer_after",
+            ConfigKey::LateAnswerWeight => "late_answer_weight",
+            ConfigKey::MinSourcesPerAnswer => "min_sources_per_answer",
+            ConfigKey::MinConfidence => "min_confidence",
+            ConfigKey::HoneypotWeight => "honeypot_weight",
+            ConfigKey::UndoDepth => "undo_depth",
+            ConfigKey::EvidenceExpiresAfter => "evidence_expires_after",
+            ConfigKey::ShadowComparisonMethod => "shadow_comparison_method",
+            ConfigKey::AnswerHash => "answer_hash",
+            ConfigKey::RateLimitAnswers => "rate_limit_answers",
+            ConfigKey::RateLimitWindow => "rate_limit_window",
+        }
+    }
+
+    // Whether the setting takes a ConfigValue::Number
+    pub fn is_numeric(&self) -> bool {
+        !matches!(
+            self,
+            ConfigKey::ComparisonMethod
+                | ConfigKey::DuplicateAnswers
+                | ConfigKey::OutputFormat
+                | ConfigKey::Normalize
+                | ConfigKey::ShadowComparisonMethod
+                | ConfigKey::AnswerHash
+        )
+    }
+
+    // The kind of value the setting takes, for errors
+    pub(crate) fn expected(&self) -> &'static str {
+        match self {
+            ConfigKey::ComparisonMethod => "a comparison method",
+            ConfigKey::DuplicateAnswers => "allow, ignore or replace",
+            ConfigKey::OutputFormat => "text or json",
+            ConfigKey::Normalize => "normalization steps, e.g. trim lowercase",
+            ConfigKey::ShadowComparisonMethod => "a comparison method or none",
+            ConfigKey::AnswerHash => "sip64, xxh64 or xxh3_128",
+            _ => "a number",
+        }
+    }
+
+    // Check a value of a numeric setting. Qualities are probabilities, the other
+    // settings must be positive for qualities and weights to stay finite.
+    pub fn validate(&self, value: f64) -> Result<(), ConfidisError> {
+        let (valid, expected) = match self {
+            ConfigKey::DefaultSourceQuality
+            | ConfigKey::QualityOfBelievedSources
+            | ConfigKey::LateAnswerWeight
+            | ConfigKey::MinConfidence => ((0.0..=1.0).contains(&value), "between 0 and 1"),
+            ConfigKey::LateAnswerAfter
+            | ConfigKey::MinSourcesPerAnswer
+            | ConfigKey::UndoDepth
+            | ConfigKey::RateLimitAnswers => (
+                value >= 0.0 && value.fract() == 0.0,
+                "a whole number, at least 0",
+            ),
+            ConfigKey::InitialSourceStrength
+            | ConfigKey::ConfidenceDecayAfter
+            | ConfigKey::EvidenceExpiresAfter => (value >= 0.0, "at least 0"),
+            ConfigKey::MaximumStrength
+            | ConfigKey::ConfidenceHalfLife
+            | ConfigKey::HoneypotWeight
+            | ConfigKey::RateLimitWindow => (value > 0.0, "greater than 0"),
+            ConfigKey::LogWeightFactor => (value > 1.0, "greater than 1"),
+            ConfigKey::ComparisonMethod
+            | ConfigKey::DuplicateAnswers
+            | ConfigKey::OutputFormat
+            | ConfigKey::Normalize
+            | ConfigKey::ShadowComparisonMethod
+            | ConfigKey::AnswerHash => {
+                return Err(self.invalid(&format!("expects {}", self.expected())))
+            }
+        };
+        if valid && value.is_finite() {
+            Ok(())
+        } else {
+            Err(self.invalid(&format!("must be {}, got {}", expected, value)))
+        }
+    }
+
+    fn invalid(&self, reason: &str) -> ConfidisError {
+        ConfidisError::InvalidConfig {
+            key: self.as_str().to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ConfigKey {
+    type Err = ConfidisError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ConfigKey::ALL
+            .iter()
+            .find(|key| key.as_str() == s)
+            .copied()
+            .ok_or_else(|| ConfidisError::InvalidConfig {
+                key: s.to_string(),
+                reason: String::from("Unknown configuration key"),
+            })
+    }
+}
+
+// The value of a setting, comparison_method takes a ComparisonMethod,
+// duplicate_answers DuplicateAnswers, output_format OutputFormat, normalize a
+// Normalization, shadow_comparison_method a ShadowComparisonMethod,
+// answer_hash an AnswerHash and every other setting a Number
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigValue {
+    Number(f64),
+    ComparisonMethod(EqualifierConfig),
+    DuplicateAnswers(DuplicateAnswers),
+    OutputFormat(OutputFormat),
+    Normalize(Normalization),
+    ShadowComparisonMethod(Option<EqualifierConfig>),
+    AnswerHash(AnswerHasher),
+}
+
+impl ConfigValue {
+    // Parse the text value of CONFIGURE <key> <value>, comparison methods take
+    // their parameters as name=value pairs, e.g. "numeric max_distance=0.1"
+    pub fn parse(key: ConfigKey, value: &str) -> Result<ConfigValue, ConfidisError> {
+        if key.is_numeric() {
+            return value
+                .parse()
+                .map(ConfigValue::Number)
+                .map_err(|_| key.invalid(&format!("\"{}\" is not a number", value)));
+        }
+        if key == ConfigKey::OutputFormat {
+            return value.parse().map(ConfigValue::OutputFormat);
+        }
+        if key == ConfigKey::Normalize {
+            return value
+                .parse()
+                .map(ConfigValue::Normalize)
+                .map_err(|reason: String| key.invalid(&reason));
+        }
+        if key == ConfigKey::DuplicateAnswers {
+            return [
+                DuplicateAnswers::Allow,
+                DuplicateAnswers::Ignore,
+                DuplicateAnswers::Replace,
+            ]
+            .iter()
+            .find(|policy| policy.as_str() == value.trim())
+            .map(|&policy| ConfigValue::DuplicateAnswers(policy))
+            .ok_or_else(|| key.invalid(&format!("expects {}", key.expected())));
+        }
+        if key == ConfigKey::AnswerHash {
+            return AnswerHasher::ALL
+                .iter()
+                .find(|hasher| hasher.as_str() == value.trim())
+                .map(|&hasher| ConfigValue::AnswerHash(hasher))
+                .ok_or_else(|| key.invalid(&format!("expects {}", key.expected())));
+        }
+        if key == ConfigKey::ShadowComparisonMethod {
+            if value.trim() == "none" {
+                return Ok(ConfigValue::ShadowComparisonMethod(None));
+            }
+            return parse_comparison_method(key, value)
+                .map(|equalifier| ConfigValue::ShadowComparisonMethod(Some(equalifier)));
+        }
+        parse_comparison_method(key, value).map(ConfigValue::ComparisonMethod)
+    }
+}
+
+// Parse a comparison method with its parameters, e.g. "numeric max_distance=0.1"
+fn parse_comparison_method(key: ConfigKey, value: &str) -> Result<EqualifierConfig, ConfidisError> {
+    let params: HashMap<&str, &str> = value
+        .split_whitespace()
+        .filter_map(|s| s.split_once('='))
+        .collect();
+    let method = value.split_whitespace().next().unwrap_or_default();
+    // distances are divided by these, so they must be positive
+    let positive = |name: &str, missing: &str| -> Result<f64, ConfidisError> {
+        match params.get(name).map(|d| d.parse::<f64>()) {
+            Some(Ok(d)) if d > 0.0 && d.is_finite() => Ok(d),
+            Some(_) => Err(key.invalid(&format!("{} must be a number greater than 0", name))),
+            None => Err(key.invalid(missing)),
+        }
+    };
+    #[cfg(feature = "text")]
+    let flag = |name: &str| -> Result<bool, ConfidisError> {
+        match params.get(name) {
+            Some(flag) => flag
+                .parse()
+                .map_err(|_| key.invalid(&format!("{} must be true or false", name))),
+            None => Ok(false),
+        }
+    };
+    let equalifier = match method {
+        "exact" => EqualifierConfig::Exact,
+        "numeric" => EqualifierConfig::Numeric {
+            max_distance: positive("max_distance", "max_distance must be specified")?,
+        },
+        "numeric_vec" => EqualifierConfig::NumericVec {
+            allowed_difference: positive(
+                "allowed_difference",
+                "allowed_difference must be specified (try 1.0)",
+            )?,
+            vec_length: params
+                .get("vec_length")
+                .and_then(|s| s.parse::<usize>().ok())
+                .filter(|&length| length > 0)
+                .ok_or_else(|| {
+                    key.invalid("vec_length must be specified (vector lengths must be fixed)")
+                })?,
+            diff_fn: params
+                .get("diff_fn")
+                .and_then(|s| VecDistAlgo::from(s))
+                .ok_or_else(|| {
+                    key.invalid("diff_fn must be specified (l1, l2, percent_not_equal, iou)")
+                })?,
+        },
+        #[cfg(feature = "text")]
+        "text" => EqualifierConfig::Text {
+            max_distance: positive("max_distance", "max_distance must be specified (try 0.5)")?,
+            fold_width: flag("fold_width")?,
+            language: match params.get("language") {
+                Some(language) => language
+                    .parse()
+                    .map_err(|reason: String| key.invalid(&reason))?,
+                None => Language::default(),
+            },
+            stem: flag("stem")?,
+            stopwords: flag("stopwords")?,
+        },
+        _ => {
+            return Err(key.invalid(&format!(
+                "unknown comparison method \"{}\". Try {}",
+                method,
+                comparison_methods().join(", ")
+            )))
+        }
+    };
+    Ok(equalifier)
+}
+
+// The text form CONFIGURE takes, e.g. "0.5" or "numeric max_distance=0.1"
+impl fmt::Display for ConfigValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let method = |f: &mut fmt::Formatter, equalifier: &EqualifierConfig| match equalifier {
+            EqualifierConfig::Exact => write!(f, "exact"),
+            EqualifierConfig::Numeric { max_distance } => {
+                write!(f, "numeric max_distance={}", max_distance)
+            }
+            EqualifierConfig::NumericVec {
+                allowed_difference,
+                vec_length,
+                diff_fn,
+            } => write!(
+                f,
+                "numeric_vec allowed_difference={} vec_length={} diff_fn={}",
+                allowed_difference,
+                vec_length,
+                diff_fn.as_str()
+            ),
+            EqualifierConfig::Custom => write!(f, "custom"),
+            #[cfg(feature = "text")]
+            EqualifierConfig::Text {
+                max_distance,
+                fold_width,
+                language,
+                stem,
+                stopwords,
+            } => {
+                write!(f, "text max_distance={}", max_distance)?;
+                if *fold_width {
+                    write!(f, " fold_width=true")?;
+                }
+                if *stem || *stopwords {
+                    write!(f, " language={}", language.as_str())?;
+                }
+                if *stem {
+                    write!(f, " stem=true")?;
+                }
+                if *stopwords {
+                    write!(f, " stopwords=true")?;
+                }
+                Ok(())
+            }
+        };
+        match self {
+            ConfigValue::Number(value) => write!(f, "{}", value),
+            ConfigValue::ComparisonMethod(equalifier) => method(f, equalifier),
+            ConfigValue::ShadowComparisonMethod(Some(equalifier)) => method(f, equalifier),
+            ConfigValue::ShadowComparisonMethod(None) => write!(f, "none"),
+            ConfigValue::DuplicateAnswers(policy) => write!(f, "{}", policy.as_str()),
+            ConfigValue::AnswerHash(hasher) => write!(f, "{}", hasher.as_str()),
+            ConfigValue::OutputFormat(format) => write!(f, "{}", format.as_str()),
+            ConfigValue::Normalize(normalization) => write!(f, "{}", normalization),
+        }
+    }
+}
+
+impl GraphConfig {
+    // Set a numeric setting and return its previous value, comparison_method is
+    // part of the Graph instead
+    pub(crate) fn set(&mut self, key: ConfigKey, value: f64) -> Result<f64, ConfidisError> {
+        key.validate(value)?;
+        let field = match key {
+            ConfigKey::DefaultSourceQuality => &mut self.default_source_quality,
+            ConfigKey::InitialSourceStrength => &mut self.initial_source_strength,
+            ConfigKey::MaximumStrength => &mut self.maximum_strength,
+            ConfigKey::LogWeightFactor => &mut self.log_weight_factor,
+            ConfigKey::QualityOfBelievedSources => &mut self.quality_of_believed_sources,
+            ConfigKey::ConfidenceDecayAfter => &mut self.confidence_decay_after,
+            ConfigKey::ConfidenceHalfLife => &mut self.confidence_half_life,
+            ConfigKey::LateAnswerAfter => &mut self.late_answer_after,
+            ConfigKey::LateAnswerWeight => &mut self.late_answer_weight,
+            ConfigKey::MinSourcesPerAnswer => &mut self.min_sources_per_answer,
+            ConfigKey::MinConfidence => &mut self.min_confidence,
+            ConfigKey::HoneypotWeight => &mut self.honeypot_weight,
+            ConfigKey::UndoDepth => &mut self.undo_depth,
+            ConfigKey::EvidenceExpiresAfter => &mut self.evidence_expires_after,
+            ConfigKey::RateLimitAnswers => &mut self.rate_limit_answers,
+            ConfigKey::RateLimitWindow => &mut self.rate_limit_window,
+            ConfigKey::ComparisonMethod
+            | ConfigKey::DuplicateAnswers
+            | ConfigKey::OutputFormat
+            | ConfigKey::Normalize
+            | ConfigKey::ShadowComparisonMethod
+            | ConfigKey::AnswerHash => unreachable!(),
+        };
+        Ok(std::mem::replace(field, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{Command, CommandResponse};
+    use crate::graph::Graph;
+
+    #[test]
+    fn test_configure() {
+        for key in ConfigKey::ALL.iter() {
+            assert_eq!(key.as_str().parse::<ConfigKey>().unwrap(), *key);
+        }
+        assert!(matches!(
+            "nope".parse::<ConfigKey>(),
+            Err(ConfidisError::InvalidConfig { .. })
+        ));
+        for method in comparison_methods() {
+            let reason = match ConfigValue::parse(ConfigKey::ComparisonMethod, method) {
+                Err(ConfidisError::InvalidConfig { reason, .. }) => reason,
+                _ => String::new(),
+            };
+            assert!(!reason.starts_with("unknown comparison method"));
+        }
+        assert_eq!(
+            ConfigValue::parse(ConfigKey::ComparisonMethod, "numeric max_distance=0.5").unwrap(),
+            ConfigValue::ComparisonMethod(EqualifierConfig::Numeric { max_distance: 0.5 })
+        );
+
+        let mut g = Graph::new();
+        g.configure(ConfigKey::MaximumStrength, ConfigValue::Number(50.0))
+            .unwrap();
+        assert_eq!(g.config().maximum_strength, 50.0);
+        g.configure(
+            ConfigKey::ComparisonMethod,
+            ConfigValue::ComparisonMethod(EqualifierConfig::Numeric { max_distance: 0.5 }),
+        )
+        .unwrap();
+        assert_eq!(
+            g.equalifier_config(),
+            EqualifierConfig::Numeric { max_distance: 0.5 }
+        );
+        assert!(g
+            .configure(ConfigKey::ComparisonMethod, ConfigValue::Number(1.0))
+            .is_err());
+        assert!(g
+            .configure(
+                ConfigKey::LogWeightFactor,
+                ConfigValue::ComparisonMethod(EqualifierConfig::Exact)
+            )
+            .is_err());
+
+        assert_eq!(
+            g.execute_command(&Command::from("CONFIGURE quality_of_believed_sources 0.9").unwrap())
+                .unwrap(),
+            CommandResponse::Configure {
+                previous: String::from("0.999")
+            }
+        );
+        assert_eq!(g.config().quality_of_believed_sources, 0.9);
+        assert_eq!(
+            g.configure(
+                ConfigKey::ComparisonMethod,
+                ConfigValue::ComparisonMethod(EqualifierConfig::Exact)
+            )
+            .unwrap()
+            .to_string(),
+            "numeric max_distance=0.5"
+        );
+
+        // Values that aren't numbers or out of range are rejected
+        for line in &[
+            "CONFIGURE default_source_quality banana",
+            "CONFIGURE default_source_quality 1.5",
+            "CONFIGURE quality_of_believed_sources -0.1",
+            "CONFIGURE initial_source_strength -1",
+            "CONFIGURE maximum_strength 0",
+            "CONFIGURE log_weight_factor 1",
+            "CONFIGURE confidence_decay_after -1",
+            "CONFIGURE confidence_half_life 0",
+            "CONFIGURE late_answer_after 1.5",
+            "CONFIGURE late_answer_weight 2",
+            "CONFIGURE min_sources_per_answer -3",
+            "CONFIGURE min_confidence 1.5",
+            "CONFIGURE honeypot_weight 0",
+            "CONFIGURE undo_depth 2.5",
+            "CONFIGURE evidence_expires_after -1",
+            "CONFIGURE rate_limit_answers 0.5",
+            "CONFIGURE rate_limit_window 0",
+            "CONFIGURE log_weight_factor NaN",
+            "CONFIGURE maximum_strength inf",
+            "CONFIGURE comparison_method numeric max_distance=0",
+            "CONFIGURE comparison_method numeric_vec allowed_difference=1 vec_length=0 diff_fn=l1",
+            "CONFIGURE duplicate_answers sometimes",
+            "CONFIGURE duplicate_answers 1",
+            "CONFIGURE output_format xml",
+            "CONFIGURE normalize shout",
+            "CONFIGURE normalize regex=[",
+            "CONFIGURE shadow_comparison_method numeric",
+            "CONFIGURE answer_hash md5",
+        ] {
+            assert!(
+                matches!(
+                    g.execute_command(&Command::from(line).unwrap()),
+                    Err(ConfidisError::InvalidConfig { .. })
+                ),
+                "{}",
+                line
+            );
+        }
+        // The output format is up to whoever prints the responses
+        assert!(matches!(
+            g.execute_command(&Command::from("CONFIGURE output_format json").unwrap()),
+            Err(ConfidisError::NotImplemented(_))
+        ));
+        assert_eq!(g.config().default_source_quality, 0.5);
+        assert_eq!(g.config().maximum_strength, 50.0);
+
+        // The previous value is in the form CONFIGURE takes
+        let value = ConfigValue::ComparisonMethod(EqualifierConfig::NumericVec {
+            allowed_difference: 0.5,
+            vec_length: 3,
+            diff_fn: VecDistAlgo::IntersectionOverUnion,
+        });
+        assert_eq!(
+            ConfigValue::parse(ConfigKey::ComparisonMethod, &value.to_string()).unwrap(),
+            value
+        );
+    }
+}
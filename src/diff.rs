@@ -0,0 +1,247 @@
+// What changed between two graphs, e.g. for a "what changed since yesterday"
+// report from two snapshots:
+//
+//   let diff = diff_snapshots("snapshot-yesterday.bin", "snapshot-today.bin",
+//       DiffThresholds { confidence: 0.05, quality: 0.05 })?;
+//
+// A question is reported when its answer changed or its confidence moved by
+// more than thresholds.confidence, a source when its quality moved by more
+// than thresholds.quality. Questions and sources only in one of the graphs are
+// always reported, with None on the side they're missing from. `confidis
+// --diff <earlier> <later>` prints the same report.
+
+use crate::error::ConfidisError;
+use crate::graph::Graph;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DiffThresholds {
+    // smallest confidence change reported for an unchanged answer
+    pub confidence: f64,
+    // smallest quality change reported for a source
+    pub quality: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AnswerChange {
+    pub question: String,
+    // None if the question wasn't in the graph or had no answer
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub confidence_before: f64,
+    pub confidence_after: f64,
+}
+
+impl AnswerChange {
+    pub fn answer_changed(&self) -> bool {
+        self.before != self.after
+    }
+
+    pub fn confidence_delta(&self) -> f64 {
+        self.confidence_after - self.confidence_before
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QualityChange {
+    pub source: String,
+    // None if the source wasn't in the graph
+    pub before: Option<f64>,
+    pub after: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct GraphDiff {
+    // sorted by question
+    pub answers: Vec<AnswerChange>,
+    // sorted by source
+    pub sources: Vec<QualityChange>,
+}
+
+impl GraphDiff {
+    pub fn is_empty(&self) -> bool {
+        self.answers.is_empty() && self.sources.is_empty()
+    }
+}
+
+impl Graph {
+    // Compare this graph with a later one
+    pub fn diff(
+        &self,
+        later: &Graph,
+        thresholds: DiffThresholds,
+    ) -> Result<GraphDiff, ConfidisError> {
+        let mut diff = GraphDiff::default();
+
+        let questions: BTreeSet<&str> = self
+            .questions
+            .keys()
+            .chain(later.questions.keys())
+            .map(String::as_str)
+            .collect();
+        for question in questions {
+            let answer = |g: &Graph| -> Result<(Option<String>, f64), ConfidisError> {
+                if !g.has_question(question) {
+                    return Ok((None, 0.0));
+                }
+                let result = g.answer_to(question)?;
+                Ok((result.answer, result.confidence))
+            };
+            let (before, confidence_before) = answer(self)?;
+            let (after, confidence_after) = answer(later)?;
+            let change = AnswerChange {
+                question: question.to_string(),
+                before,
+                after,
+                confidence_before,
+                confidence_after,
+            };
+            if change.answer_changed() || change.confidence_delta().abs() > thresholds.confidence {
+                diff.answers.push(change);
+            }
+        }
+
+        let sources: BTreeSet<&str> = self
+            .sources
+            .keys()
+            .chain(later.sources.keys())
+            .map(String::as_str)
+            .collect();
+        for source in sources {
+            let before = self.source(source).map(|source| source.quality);
+            let after = later.source(source).map(|source| source.quality);
+            let moved = match (before, after) {
+                (Some(before), Some(after)) => (after - before).abs() > thresholds.quality,
+                _ => true,
+            };
+            if moved {
+                diff.sources.push(QualityChange {
+                    source: source.to_string(),
+                    before,
+                    after,
+                });
+            }
+        }
+        Ok(diff)
+    }
+}
+
+// Load two snapshot files and compare them, see Graph::diff
+pub fn diff_snapshots<P: AsRef<Path>, Q: AsRef<Path>>(
+    earlier: P,
+    later: Q,
+    thresholds: DiffThresholds,
+) -> Result<GraphDiff, ConfidisError> {
+    let load = |path: &Path| {
+        let file = File::open(path).map_err(|e| {
+            ConfidisError::Internal(format!("Couldn't open {}: {}", path.display(), e))
+        })?;
+        Graph::load_snapshot(file).map_err(ConfidisError::Internal)
+    };
+    load(earlier.as_ref())?.diff(&load(later.as_ref())?, thresholds)
+}
+
+impl fmt::Display for GraphDiff {
+    // One line per change, e.g.
+    //   q1: a (90.000%) -> b (75.000%)
+    //   q2: a (50.000%) -> a (80.000%)
+    //   s1: 0.500 -> 0.750 (+0.250)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "No changes");
+        }
+        let answer = |answer: &Option<String>, confidence: f64| match answer {
+            Some(answer) => format!("{} ({:.3}%)", answer, confidence * 100.0),
+            None => String::from("None"),
+        };
+        let quality = |quality: Option<f64>| match quality {
+            Some(quality) => format!("{:.3}", quality),
+            None => String::from("None"),
+        };
+        let mut lines = Vec::new();
+        for change in &self.answers {
+            lines.push(format!(
+                "{}: {} -> {}",
+                change.question,
+                answer(&change.before, change.confidence_before),
+                answer(&change.after, change.confidence_after)
+            ));
+        }
+        for change in &self.sources {
+            let mut line = format!(
+                "{}: {} -> {}",
+                change.source,
+                quality(change.before),
+                quality(change.after)
+            );
+            if let (Some(before), Some(after)) = (change.before, change.after) {
+                line += &format!(" ({:+.3})", after - before);
+            }
+            lines.push(line);
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+
+    #[test]
+    fn test_snapshot_diff() {
+        let mut g = Graph::new();
+        for line in &[
+            "SET q1 a FROM s1",
+            "SET q1 a FROM s2",
+            "SET q2 x FROM s1",
+            "SET q2 x FROM s2",
+            "SET q4 a FROM s1",
+        ] {
+            g.execute_command(&Command::from(line).unwrap()).unwrap();
+        }
+        let dir = std::env::temp_dir().join(format!("confidis-diff-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let earlier = dir.join("earlier.bin");
+        g.save_snapshot(File::create(&earlier).unwrap()).unwrap();
+
+        for line in &["SET q3 c FROM s1", "SET q4 b FROM s2", "SET q4 b FROM s3"] {
+            g.execute_command(&Command::from(line).unwrap()).unwrap();
+        }
+        let later = dir.join("later.bin");
+        g.save_snapshot(File::create(&later).unwrap()).unwrap();
+
+        let diff = diff_snapshots(&earlier, &later, DiffThresholds::default()).unwrap();
+        let questions: Vec<&str> = diff.answers.iter().map(|c| c.question.as_str()).collect();
+        assert_eq!(questions, vec!["q1", "q2", "q3", "q4"]);
+        // s1 lost quality on q4, which moved q1's confidence
+        assert!(!diff.answers[0].answer_changed());
+        assert!(diff.answers[0].confidence_delta() < 0.0);
+        assert_eq!(diff.answers[2].before, None);
+        assert_eq!(diff.answers[2].after.as_deref(), Some("c"));
+        assert!(diff.answers[3].answer_changed());
+        let sources: Vec<&str> = diff.sources.iter().map(|c| c.source.as_str()).collect();
+        assert_eq!(sources, vec!["s1", "s2", "s3"]);
+        assert!(diff.sources[2].before.is_none());
+        let report = format!("{}", diff);
+        assert!(report.contains("\nq4: a (") && report.contains(") -> b ("));
+
+        // Above the thresholds only changed answers and new sources are left
+        let thresholds = DiffThresholds {
+            confidence: 1.0,
+            quality: 1.0,
+        };
+        let diff = diff_snapshots(&earlier, &later, thresholds).unwrap();
+        let questions: Vec<&str> = diff.answers.iter().map(|c| c.question.as_str()).collect();
+        assert_eq!(questions, vec!["q3", "q4"]);
+        assert_eq!(diff.sources.len(), 1);
+        let diff = diff_snapshots(&earlier, &earlier, thresholds).unwrap();
+        assert!(diff.is_empty());
+        assert_eq!(format!("{}", diff), "No changes");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+// Graphviz DOT export of the source-question graph
+//
+// Sources (boxes) and questions (ellipses) are nodes and every answer is an
+// edge between its source and question, labeled with the answer and colored
+// green if it's in the question's correct cluster, red otherwise. Sources are
+// sized by quality and questions by confidence, both from the last time their
+// questions were recomputed. Render with e.g.
+//   dot -Tsvg graph.dot -o graph.svg
+//
+// Export a question's neighborhood to look into a single question: the
+// question, its sources and every other question those sources answered.
+// Nodes and edges are sorted by name so exports can be diffed.
+
+use crate::graph::Graph;
+use std::collections::BTreeSet;
+use std::io::{BufWriter, Write};
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Node size in inches for a value between 0 and 1
+fn node_size(value: f64) -> f64 {
+    0.5 + value.clamp(0.0, 1.0)
+}
+
+impl Graph {
+    // Write the whole graph as DOT, or only the neighborhood of a question
+    pub fn export_dot<W: Write>(
+        &self,
+        writer: W,
+        neighborhood: Option<&str>,
+    ) -> Result<(), String> {
+        if self.is_bulk_loading() {
+            return Err("A graph can't be exported while a bulk load is in progress".into());
+        }
+        let (question_names, source_names) = match neighborhood {
+            Some(question_name) => self.neighborhood(question_name)?,
+            None => (
+                self.questions.keys().map(String::as_str).collect(),
+                self.sources.keys().map(String::as_str).collect(),
+            ),
+        };
+        let write = |writer: &mut BufWriter<W>, line: String| {
+            writeln!(writer, "{}", line).map_err(|e| format!("Couldn't write DOT export: {}", e))
+        };
+
+        let mut writer = BufWriter::new(writer);
+        write(&mut writer, String::from("graph confidis {"))?;
+        write(&mut writer, String::from("  node [style=filled];"))?;
+        for source_name in &source_names {
+            let quality = self.sources.get(*source_name).map_or(0.0, |s| s.quality);
+            write(
+                &mut writer,
+                format!(
+                    "  \"s/{}\" [label=\"{}\\nquality {:.3}\", shape=box, fillcolor=lightblue, width={:.2}, height={:.2}];",
+                    escape(source_name),
+                    escape(source_name),
+                    quality,
+                    node_size(quality) * 1.5,
+                    node_size(quality) / 2.0
+                ),
+            )?;
+        }
+        for question_name in &question_names {
+            let question = &self.questions[*question_name];
+            let answer = question
+                .correct_answers
+                .first()
+                .map_or("None", |&i| question.answers[i].content.as_str());
+            write(
+                &mut writer,
+                format!(
+                    "  \"q/{}\" [label=\"{}\\n{} ({:.1}%)\", shape=ellipse, fillcolor=lightyellow, width={:.2}, height={:.2}];",
+                    escape(question_name),
+                    escape(question_name),
+                    escape(answer),
+                    question.confidence * 100.,
+                    node_size(question.confidence) * 1.5,
+                    node_size(question.confidence) / 2.0
+                ),
+            )?;
+        }
+        for question_name in &question_names {
+            let question = &self.questions[*question_name];
+            for (i, answer) in question.answers.iter().enumerate() {
+                if !source_names.contains(answer.source.as_str()) {
+                    continue;
+                }
+                let color = if question.correct_answers.contains(&i) {
+                    "darkgreen"
+                } else {
+                    "red"
+                };
+                write(
+                    &mut writer,
+                    format!(
+                        "  \"s/{}\" -- \"q/{}\" [label=\"{}\", color={}, fontcolor={}];",
+                        escape(&answer.source),
+                        escape(question_name),
+                        escape(&answer.content),
+                        color,
+                        color
+                    ),
+                )?;
+            }
+        }
+        write(&mut writer, String::from("}"))?;
+        writer
+            .flush()
+            .map_err(|e| format!("Couldn't write DOT export: {}", e))
+    }
+
+    // The question, the sources that answered it and the other questions those
+    // sources answered
+    fn neighborhood(
+        &self,
+        question_name: &str,
+    ) -> Result<(BTreeSet<&str>, BTreeSet<&str>), String> {
+        let question = self
+            .questions
+            .get(question_name)
+            .ok_or_else(|| format!("Unknown question: \"{}\"", question_name))?;
+        let source_names: BTreeSet<&str> = question
+            .answers
+            .iter()
+            .map(|answer| answer.source.as_str())
+            .collect();
+        let question_names = self
+            .questions
+            .values()
+            .filter(|q| {
+                q.answers
+                    .iter()
+                    .any(|answer| source_names.contains(answer.source.as_str()))
+            })
+            .map(|q| q.name.as_str())
+            .collect();
+        Ok((question_names, source_names))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_dot() {
+        let mut g = Graph::new();
+        g.set_many(&[
+            ("q1", "a", "s1"),
+            ("q1", "a", "s2"),
+            ("q1", "b \"c\"", "s3"),
+            ("q2", "d", "s2"),
+            ("q3", "e", "s4"),
+        ])
+        .unwrap();
+
+        let mut exported: Vec<u8> = Vec::new();
+        g.export_dot(&mut exported, None).unwrap();
+        let text = String::from_utf8(exported).unwrap();
+        assert!(text.starts_with("graph confidis {"));
+        assert!(text.trim_end().ends_with('}'));
+        assert_eq!(text.matches(" -- ").count(), 5);
+        assert!(text.contains("\"s/s1\" -- \"q/q1\" [label=\"a\", color=darkgreen"));
+        assert!(text.contains("\"s/s3\" -- \"q/q1\" [label=\"b \\\"c\\\"\", color=red"));
+
+        let mut exported: Vec<u8> = Vec::new();
+        g.export_dot(&mut exported, Some("q1")).unwrap();
+        let text = String::from_utf8(exported).unwrap();
+        assert!(text.contains("\"q/q2\""));
+        assert!(!text.contains("q3") && !text.contains("s4"));
+        assert!(g.export_dot(Vec::new(), Some("q9")).is_err());
+    }
+}
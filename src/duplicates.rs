@@ -0,0 +1,183 @@
+// Likely duplicate questions
+//
+// Pipelines sometimes create the same question under two ids, which then split
+// the answers that should have been one question's. Such questions were
+// answered by mostly the same sources with answers that match (a distance
+// below 1 under the comparison method). The similarity of two questions is
+// the number of sources whose answers to both match over the number of
+// sources that answered either, 1 for questions the same sources answered the
+// same way. Questions at least `threshold` similar are grouped, transitively:
+//
+//   for group in g.find_duplicate_questions(0.9, 3) {
+//       println!("{}", group);
+//   }
+//
+// A source's latest answer to a question is the one compared, and only
+// questions that share a source are compared at all.
+
+use crate::command::{Answer, AnswerContent};
+use crate::graph::Graph;
+use std::collections::HashMap;
+use std::fmt;
+
+// Questions that are likely the same question
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateQuestions {
+    // sorted by name
+    pub questions: Vec<String>,
+    // the lowest similarity of the pairs of questions that put them together
+    pub similarity: f64,
+}
+
+impl fmt::Display for DuplicateQuestions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({:.3}% similar)",
+            self.questions.join(", "),
+            self.similarity * 100.
+        )
+    }
+}
+
+fn find(parents: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[i] = root;
+    root
+}
+
+impl<A: AnswerContent> Graph<A> {
+    // Groups of questions at least threshold similar (see above), ignoring
+    // pairs that fewer than min_sources sources answered both of, so that two
+    // questions with one answer from the same source aren't duplicates
+    pub fn find_duplicate_questions(
+        &self,
+        threshold: f64,
+        min_sources: usize,
+    ) -> Vec<DuplicateQuestions> {
+        let mut names: Vec<&str> = self.questions.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        // each question's latest answer from each of its sources
+        let latest: Vec<HashMap<&str, &Answer<A>>> = names
+            .iter()
+            .map(|name| {
+                self.questions[*name]
+                    .answers
+                    .iter()
+                    .map(|answer| (answer.source.as_str(), answer))
+                    .collect()
+            })
+            .collect();
+
+        // how many sources each pair of questions shares
+        let mut questions_of: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (i, answers) in latest.iter().enumerate() {
+            for source in answers.keys() {
+                questions_of.entry(source).or_default().push(i);
+            }
+        }
+        let mut shared: HashMap<(usize, usize), usize> = HashMap::new();
+        for questions in questions_of.values() {
+            for (k, &i) in questions.iter().enumerate() {
+                for &j in &questions[k + 1..] {
+                    *shared.entry((i, j)).or_default() += 1;
+                }
+            }
+        }
+
+        let mut parents: Vec<usize> = (0..names.len()).collect();
+        let mut similarities: HashMap<usize, f64> = HashMap::new();
+        let mut pairs: Vec<((usize, usize), usize)> = shared.into_iter().collect();
+        pairs.sort_unstable();
+        for ((i, j), shared) in pairs {
+            let union = latest[i].len() + latest[j].len() - shared;
+            // every shared source matching is the most similar they can be
+            if shared < min_sources.max(1) || (shared as f64) < threshold * union as f64 {
+                continue;
+            }
+            let matching = latest[i]
+                .iter()
+                .filter(|(source, a)| {
+                    latest[j]
+                        .get(*source)
+                        .is_some_and(|b| self.answer_distance(names[i], a, b) < 1.0)
+                })
+                .count();
+            let similarity = matching as f64 / union as f64;
+            if matching < min_sources || similarity < threshold {
+                continue;
+            }
+            let (root_i, root_j) = (find(&mut parents, i), find(&mut parents, j));
+            let lowest = [
+                similarity,
+                similarities.remove(&root_i).unwrap_or(1.0),
+                similarities.remove(&root_j).unwrap_or(1.0),
+            ]
+            .iter()
+            .cloned()
+            .fold(1.0, f64::min);
+            parents[root_j] = root_i;
+            similarities.insert(root_i, lowest);
+        }
+
+        let mut groups: HashMap<usize, Vec<String>> = HashMap::new();
+        for (i, name) in names.iter().enumerate() {
+            let root = find(&mut parents, i);
+            if similarities.contains_key(&root) {
+                groups.entry(root).or_default().push(name.to_string());
+            }
+        }
+        let mut groups: Vec<DuplicateQuestions> = groups
+            .into_iter()
+            .map(|(root, questions)| DuplicateQuestions {
+                questions,
+                similarity: similarities[&root],
+            })
+            .collect();
+        groups.sort_unstable_by(|a, b| a.questions.cmp(&b.questions));
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_duplicate_questions() {
+        let mut g = Graph::new();
+        g.set_many(&[
+            ("q1", "a", "s1"),
+            ("q1", "a", "s2"),
+            ("q1", "b", "s3"),
+            ("q1-copy", "a", "s1"),
+            ("q1-copy", "a", "s2"),
+            ("q1-copy", "b", "s3"),
+            ("q1-again", "a", "s1"),
+            ("q1-again", "a", "s2"),
+            ("q1-again", "c", "s3"),
+            ("q2", "a", "s1"),
+            ("q2", "d", "s2"),
+            ("q3", "e", "s4"),
+            ("q4", "e", "s4"),
+        ])
+        .unwrap();
+
+        let groups = g.find_duplicate_questions(1.0, 2);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].questions, vec!["q1", "q1-copy"]);
+        assert_eq!(groups[0].similarity, 1.0);
+        assert_eq!(groups[0].to_string(), "q1, q1-copy (100.000% similar)");
+
+        // 2 of 3 sources agree with "q1-again", q2 and the single answer
+        // questions q3 and q4 aren't similar enough
+        let groups = g.find_duplicate_questions(0.6, 2);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].questions, vec!["q1", "q1-again", "q1-copy"]);
+        assert!((groups[0].similarity - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(g.find_duplicate_questions(0.6, 1).len(), 2);
+    }
+}
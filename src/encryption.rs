@@ -0,0 +1,164 @@
+// Encryption of journals and snapshots at rest
+//
+// Answers often hold personal data from annotation tasks. With the encryption
+// feature, journals and snapshots can be encrypted with a 256 bit key, using
+// XChaCha20-Poly1305 with a random nonce per message:
+//
+//   let key = EncryptionKey::load("confidis.key")?;    // 64 hex digits
+//   let mut g = Graph::replay_encrypted("journal.log", &key)?;
+//   g.set_journal(Journal::open_encrypted("journal.log", key.clone())?);
+//
+// An encrypted journal keeps one line per append, "<timestamp> ENCRYPTED
+// <hex>", whose message holds the appended records, see journal.rs. Each
+// message is bound to its line's position and timestamp (as associated data),
+// so records that were dropped from the middle, reordered or copied from
+// elsewhere in the journal fail to decrypt, and plain lines in an encrypted
+// journal are rejected. Records cut off the end aren't detected: the journal
+// reads as it was before they were appended, like after a crash. An encrypted
+// snapshot is b"CONFIENC" followed by the message holding the plain snapshot,
+// see snapshot.rs. SnapshotPolicy::key encrypts the snapshots a policy takes,
+// and compacting an encrypted journal encrypts its snapshot with the
+// journal's key. A journal or snapshot that was tampered with, or is read
+// with the wrong key, fails to load rather than being misread. Plain
+// snapshots still load with a key. To encrypt an existing journal, replay it,
+// attach a new encrypted journal and compact that, which writes the whole
+// graph as its encrypted snapshot. Journal records streamed to replicas,
+// StoredGraph storages and JSONL exports aren't encrypted.
+//
+// Keys can be generated with EncryptionKey::generate or e.g.
+// openssl rand -hex 32. Without the encryption feature EncryptionKey can't be
+// constructed and everything is written in plain.
+
+use std::fmt;
+#[cfg(feature = "encryption")]
+use std::path::Path;
+
+#[cfg(feature = "encryption")]
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+#[cfg(feature = "encryption")]
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+// XChaCha20 nonces are 24 bytes
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 24;
+
+#[cfg(feature = "encryption")]
+#[derive(Clone, PartialEq)]
+pub struct EncryptionKey(Key);
+
+#[cfg(not(feature = "encryption"))]
+#[derive(Clone, PartialEq)]
+pub enum EncryptionKey {}
+
+// Keys aren't printed
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EncryptionKey(..)")
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl EncryptionKey {
+    pub fn from_bytes(bytes: [u8; 32]) -> EncryptionKey {
+        EncryptionKey(bytes.into())
+    }
+
+    // A key from 64 hex digits, surrounding whitespace is ignored
+    pub fn from_hex(hex: &str) -> Result<EncryptionKey, String> {
+        let bytes = decode_hex(hex.trim())
+            .filter(|bytes| bytes.len() == 32)
+            .ok_or("An encryption key must be 64 hex digits")?;
+        let mut key = [0_u8; 32];
+        key.copy_from_slice(&bytes);
+        Ok(EncryptionKey::from_bytes(key))
+    }
+
+    // The key in a file of 64 hex digits
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<EncryptionKey, String> {
+        let path = path.as_ref();
+        let hex = std::fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+        EncryptionKey::from_hex(&hex)
+    }
+
+    // A random key
+    pub fn generate() -> EncryptionKey {
+        EncryptionKey(XChaCha20Poly1305::generate_key(&mut OsRng))
+    }
+
+    pub fn to_hex(&self) -> String {
+        encode_hex(&self.0)
+    }
+
+    // The nonce followed by the encrypted message and its tag. The message
+    // only opens with the same associated data (aad), which isn't stored.
+    pub(crate) fn seal(&self, plain: &[u8], aad: &[u8]) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = XChaCha20Poly1305::new(&self.0)
+            .encrypt(&nonce, Payload { msg: plain, aad })
+            .expect("encrypting into a Vec can't fail");
+        let mut out = nonce.to_vec();
+        out.extend(sealed);
+        out
+    }
+
+    pub(crate) fn open(&self, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+        if sealed.len() < NONCE_LEN {
+            return Err(String::from("Encrypted message is truncated"));
+        }
+        let (nonce, message) = sealed.split_at(NONCE_LEN);
+        XChaCha20Poly1305::new(&self.0)
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: message, aad })
+            .map_err(|_| String::from("Couldn't decrypt, wrong key or tampered data"))
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+impl EncryptionKey {
+    pub(crate) fn seal(&self, _plain: &[u8], _aad: &[u8]) -> Vec<u8> {
+        match *self {}
+    }
+
+    pub(crate) fn open(&self, _sealed: &[u8], _aad: &[u8]) -> Result<Vec<u8>, String> {
+        match *self {}
+    }
+}
+
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encryption_key() {
+        let key = EncryptionKey::generate();
+        assert_eq!(EncryptionKey::from_hex(&key.to_hex()), Ok(key.clone()));
+        assert!(EncryptionKey::from_hex("abc").is_err());
+        assert!(EncryptionKey::from_hex(&"zz".repeat(32)).is_err());
+        assert_eq!(format!("{:?}", key), "EncryptionKey(..)");
+
+        let sealed = key.seal(b"SET q1 a FROM s1", b"1");
+        // a fresh nonce each time
+        assert_ne!(sealed, key.seal(b"SET q1 a FROM s1", b"1"));
+        assert_eq!(key.open(&sealed, b"1").unwrap(), b"SET q1 a FROM s1");
+        assert!(key.open(&sealed, b"2").is_err());
+        assert!(EncryptionKey::generate().open(&sealed, b"1").is_err());
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(key.open(&tampered, b"1").is_err());
+        assert!(key.open(&sealed[..10], b"1").is_err());
+    }
+}
@@ -1,16 +1,120 @@
 pub use crate::command::Answer;
+use serde::{Deserialize, Serialize};
 
+mod datetime_equalifier;
+mod embedding_equalifier;
 mod exact_equalifier;
+mod geo_equalifier;
+// JS functions only exist on wasm32, see JSEqualifier
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
 mod js_equalifier;
 mod numeric_equalifier;
 mod numeric_vec_equalifier;
+#[cfg(feature = "text")]
+mod text_equalifier;
+#[cfg(feature = "text")]
+mod text_language;
+mod vec_distance;
 
+pub use self::datetime_equalifier::{parse_datetime, DatetimeEqualifier};
+pub use self::embedding_equalifier::{Embedder, EmbeddingEqualifier};
 pub use self::exact_equalifier::ExactEqualifier;
+pub use self::geo_equalifier::{parse_location, GeoEqualifier};
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
 pub use self::js_equalifier::JSEqualifier;
 pub use self::numeric_equalifier::NumericEqualifier;
-pub use self::numeric_vec_equalifier::{NumericVecEqualifier, VecDistAlgo};
+pub use self::numeric_vec_equalifier::{parse_numeric_vec, NumericVecEqualifier, VecDistAlgo};
+#[cfg(feature = "text")]
+pub use self::text_equalifier::TextEqualifier;
+#[cfg(feature = "text")]
+pub use self::text_language::Language;
 
-pub trait Equalifier {
-    fn is_valid_answer(&self, a: &Answer) -> bool;
-    fn get_distance(&self, a: &Answer, b: &Answer) -> f64;
+// The comparison methods CONFIGURE comparison_method accepts in this build.
+// Equalifiers that need extra dependencies belong behind a cargo feature, like
+// the vectorized numeric_vec loops behind "simd", and are only listed here when
+// their feature is enabled.
+pub fn comparison_methods() -> Vec<&'static str> {
+    #[allow(unused_mut)]
+    let mut methods = vec!["exact", "numeric", "numeric_vec"];
+    #[cfg(feature = "text")]
+    methods.push("text");
+    methods
+}
+
+// Compares answers with content A, 0.0 is equal and 1.0 is entirely different
+//
+// Equalifiers must be Send + Sync so a Graph can be shared between threads,
+// e.g. behind an Arc<Mutex<Graph>> in an async server.
+pub trait Equalifier<A = String>: Send + Sync {
+    fn is_valid_answer(&self, a: &Answer<A>) -> bool;
+    fn get_distance(&self, a: &Answer<A>, b: &Answer<A>) -> f64;
+
+    // Describes the equalifier so it can be persisted and rebuilt, equalifiers
+    // that can't be described (e.g. a JS function) are Custom
+    fn config(&self) -> EqualifierConfig {
+        EqualifierConfig::Custom
+    }
+}
+
+// The serializable form of the built-in equalifiers, tagged by comparison_method
+// (externally tagged so non self-describing formats like bincode work)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EqualifierConfig {
+    Exact,
+    Numeric {
+        max_distance: f64,
+    },
+    NumericVec {
+        allowed_difference: f64,
+        vec_length: usize,
+        diff_fn: VecDistAlgo,
+    },
+    Custom,
+    // after Custom, so the variants before it keep their bincode tags in
+    // builds without the "text" feature
+    #[cfg(feature = "text")]
+    Text {
+        max_distance: f64,
+        fold_width: bool,
+        language: Language,
+        stem: bool,
+        stopwords: bool,
+    },
+}
+
+impl EqualifierConfig {
+    // Construct the described equalifier, None for Custom
+    pub fn build(&self) -> Option<Box<dyn Equalifier>> {
+        match self {
+            EqualifierConfig::Exact => Some(Box::new(ExactEqualifier::new())),
+            EqualifierConfig::Numeric { max_distance } => {
+                Some(Box::new(NumericEqualifier::new(*max_distance)))
+            }
+            EqualifierConfig::NumericVec {
+                allowed_difference,
+                vec_length,
+                diff_fn,
+            } => Some(Box::new(NumericVecEqualifier::new(
+                *allowed_difference,
+                diff_fn.clone(),
+                *vec_length,
+            ))),
+            EqualifierConfig::Custom => None,
+            #[cfg(feature = "text")]
+            EqualifierConfig::Text {
+                max_distance,
+                fold_width,
+                language,
+                stem,
+                stopwords,
+            } => Some(Box::new(TextEqualifier {
+                max_distance: *max_distance,
+                fold_width: *fold_width,
+                language: *language,
+                stem: *stem,
+                stopwords: *stopwords,
+            })),
+        }
+    }
 }
@@ -0,0 +1,150 @@
+use crate::equalifier::{Answer, Equalifier};
+use num::clamp;
+
+// Compares points in time by how many seconds apart they are over
+// max_distance. Answers are RFC 3339 dates or date-times, e.g. "2024-05-01",
+// "2024-05-01T12:30:00Z" or "2024-05-01T14:30:00.5+02:00", or unix time in
+// seconds. Date-times without an offset and dates are in UTC.
+pub struct DatetimeEqualifier {
+    pub max_distance: f64,
+}
+
+impl DatetimeEqualifier {
+    pub fn new(max_distance: f64) -> Self {
+        DatetimeEqualifier { max_distance }
+    }
+}
+
+// Parse a point in time answer into unix time in seconds
+pub fn parse_datetime(content: &str) -> Option<f64> {
+    if let Ok(seconds) = content.parse::<f64>() {
+        return Some(seconds).filter(|seconds| seconds.is_finite());
+    }
+    let (date, time) = match content.find(['T', 't']) {
+        Some(i) => (&content[..i], Some(&content[i + 1..])),
+        None => (content, None),
+    };
+    let mut date = date.splitn(3, '-');
+    let year: i64 = digits(date.next()?, 4)?;
+    let month: i64 = digits(date.next()?, 2)?;
+    let day: i64 = digits(date.next()?, 2)?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let mut seconds = (days_from_civil(year, month, day) * 86400) as f64;
+    if let Some(time) = time {
+        // the offset, if any, follows the time
+        let (time, offset) = match time.find(['Z', 'z', '+', '-']) {
+            Some(i) => (&time[..i], &time[i..]),
+            None => (time, ""),
+        };
+        let mut parts = time.splitn(3, ':');
+        let hour: i64 = digits(parts.next()?, 2)?;
+        let minute: i64 = digits(parts.next()?, 2)?;
+        let second: f64 = match parts.next() {
+            // two digits, not a sign or exponent, before any fraction
+            Some(second) if second.bytes().take(2).filter(u8::is_ascii_digit).count() == 2 => {
+                second.parse().ok()?
+            }
+            Some(_) => return None,
+            None => 0.0,
+        };
+        if hour > 23 || minute > 59 || second >= 61.0 {
+            return None;
+        }
+        seconds += (hour * 3600 + minute * 60) as f64 + second;
+        seconds -= match offset {
+            "" | "Z" | "z" => 0,
+            _ => {
+                let sign = if offset.starts_with('-') { -1 } else { 1 };
+                let (hours, minutes) = offset[1..].split_once(':')?;
+                let (hours, minutes): (i64, i64) = (digits(hours, 2)?, digits(minutes, 2)?);
+                if hours > 23 || minutes > 59 {
+                    return None;
+                }
+                sign * (hours * 3600 + minutes * 60)
+            }
+        } as f64;
+    }
+    Some(seconds)
+}
+
+// A number of exactly len ASCII digits
+fn digits(s: &str, len: usize) -> Option<i64> {
+    if s.len() == len && s.bytes().all(|b| b.is_ascii_digit()) {
+        s.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 of a date in the proleptic Gregorian calendar, after
+// Howard Hinnant's days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+impl Equalifier for DatetimeEqualifier {
+    fn get_distance(&self, a: &Answer, b: &Answer) -> f64 {
+        match (parse_datetime(&a.content), parse_datetime(&b.content)) {
+            (Some(a), Some(b)) => clamp((a - b).abs() / self.max_distance, 0.0, 1.0),
+            _ => 1.0,
+        }
+    }
+    fn is_valid_answer(&self, a: &Answer) -> bool {
+        parse_datetime(&a.content).is_some()
+    }
+}
+
+#[test]
+fn datetime_distance_test() {
+    assert_eq!(parse_datetime("1970-01-01"), Some(0.0));
+    assert_eq!(parse_datetime("2024-02-29T12:00:00Z"), Some(1709208000.0));
+    assert_eq!(
+        parse_datetime("2024-02-29T14:00:00+02:00"),
+        Some(1709208000.0)
+    );
+    assert_eq!(parse_datetime("2024-02-29t12:00"), Some(1709208000.0));
+    assert_eq!(parse_datetime("1709208000.25"), Some(1709208000.25));
+    assert_eq!(parse_datetime("1969-12-31T23:59:59.5Z"), Some(-0.5));
+    for invalid in &[
+        "2023-02-29",
+        "2024-13-01",
+        "2024-1-01",
+        "2024-01-01T25:00",
+        "2024-05-01T12:30:€",
+        "2024-05-01T12:30:5€",
+        "noon",
+    ] {
+        assert_eq!(parse_datetime(invalid), None, "{}", invalid);
+    }
+
+    let answer = |content: &str| Answer::new(String::from(content), String::from("s1"));
+    let de = DatetimeEqualifier::new(60.0);
+    assert_eq!(
+        de.get_distance(
+            &answer("2024-05-01T12:00:30Z"),
+            &answer("2024-05-01T14:00:00+02:00")
+        ),
+        0.5
+    );
+    assert_eq!(
+        de.get_distance(&answer("2024-05-01"), &answer("2024-05-02")),
+        1.0
+    );
+    assert!(!de.is_valid_answer(&answer("yesterday")));
+}
@@ -0,0 +1,188 @@
+use crate::equalifier::{Answer, Equalifier};
+use crate::hash::AnswerHash;
+#[cfg(test)]
+use assert_approx_eq::assert_approx_eq;
+use log::warn;
+use num::clamp;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Once the cache holds this many embeddings it is emptied and starts refilling
+const DEFAULT_EMBEDDING_CACHE_CAPACITY: usize = 100_000;
+
+type Embedding = Arc<Vec<f32>>;
+
+// Turns an answer into a vector, e.g. by calling a sentence embedding model
+// or service. Answers with similar meaning should get vectors pointing in
+// similar directions.
+pub trait Embedder<A = String>: Send + Sync {
+    fn embed(&self, content: &A) -> Result<Vec<f32>, String>;
+}
+
+// Compares answers by the cosine distance of their embeddings, so free text
+// answers with the same meaning agree even when they share no words. The
+// distance is 1 - cosine similarity over max_distance.
+//
+// Every distinct answer is embedded once, embeddings are cached by answer hash
+// and content. An answer the embedder fails on is invalid and doesn't match,
+// failures aren't cached so it's embedded again the next time.
+//
+//   g.set_equalifier(Box::new(EmbeddingEqualifier::new(Box::new(MyModel), 0.2)));
+//
+// The embedder can't be persisted, so like a JS equalifier the comparison
+// method is custom: graphs using it can't be restored from snapshots.
+pub struct EmbeddingEqualifier<A = String> {
+    embedder: Box<dyn Embedder<A>>,
+    pub max_distance: f64,
+    capacity: usize,
+    // with the content each hash stands for, see DistanceCache. None if the
+    // embedding is empty.
+    embeddings: Mutex<HashMap<AnswerHash, (A, Option<Embedding>)>>,
+}
+
+impl<A> EmbeddingEqualifier<A> {
+    pub fn new(embedder: Box<dyn Embedder<A>>, max_distance: f64) -> Self {
+        EmbeddingEqualifier::with_capacity(embedder, max_distance, DEFAULT_EMBEDDING_CACHE_CAPACITY)
+    }
+
+    // capacity is the number of embeddings cached
+    pub fn with_capacity(
+        embedder: Box<dyn Embedder<A>>,
+        max_distance: f64,
+        capacity: usize,
+    ) -> Self {
+        EmbeddingEqualifier {
+            embedder,
+            max_distance,
+            capacity,
+            embeddings: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<A: Clone + PartialEq> EmbeddingEqualifier<A> {
+    // The embedding of an answer, None if the embedder failed on it
+    fn embedding(&self, answer: &Answer<A>) -> Option<Embedding> {
+        if let Some((content, embedding)) = self.embeddings.lock().unwrap().get(&answer.hash) {
+            if *content == answer.content {
+                return embedding.clone();
+            }
+        }
+        // embed without holding the lock, embedders can be slow
+        let embedding = match self.embedder.embed(&answer.content) {
+            Ok(embedding) if !embedding.is_empty() => Some(Arc::new(embedding)),
+            Ok(_) => None,
+            Err(e) => {
+                warn!("Couldn't embed answer: {}", e);
+                return None;
+            }
+        };
+        let mut embeddings = self.embeddings.lock().unwrap();
+        if embeddings.len() >= self.capacity {
+            embeddings.clear();
+        }
+        // an answer whose hash collides with a cached one isn't cached
+        embeddings
+            .entry(answer.hash)
+            .or_insert_with(|| (answer.content.clone(), embedding.clone()));
+        embedding
+    }
+}
+
+// 1 - cosine similarity, from 0 for the same direction to 2 for opposite ones.
+// Vectors of different lengths or without length are entirely different.
+fn cosine_distance(a: &[f32], b: &[f32]) -> f64 {
+    if a.len() != b.len() {
+        return 2.0;
+    }
+    let (mut dot, mut a_norm, mut b_norm) = (0.0_f64, 0.0_f64, 0.0_f64);
+    for (&x, &y) in a.iter().zip(b) {
+        let (x, y) = (f64::from(x), f64::from(y));
+        dot += x * y;
+        a_norm += x * x;
+        b_norm += y * y;
+    }
+    if a_norm == 0.0 || b_norm == 0.0 {
+        return 2.0;
+    }
+    1.0 - dot / (a_norm.sqrt() * b_norm.sqrt())
+}
+
+impl<A: Clone + PartialEq + Send> Equalifier<A> for EmbeddingEqualifier<A> {
+    fn is_valid_answer(&self, a: &Answer<A>) -> bool {
+        self.embedding(a).is_some()
+    }
+    fn get_distance(&self, a: &Answer<A>, b: &Answer<A>) -> f64 {
+        match (self.embedding(a), self.embedding(b)) {
+            (Some(a), Some(b)) => match cosine_distance(&a, &b) / self.max_distance {
+                distance if distance.is_nan() => 1.0,
+                distance => clamp(distance, 0.0, 1.0),
+            },
+            _ => 1.0,
+        }
+    }
+}
+
+#[test]
+fn embedding_distance_test() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // a bag of colors, counting the answers it embeds
+    struct Colors(Arc<AtomicUsize>);
+
+    impl Embedder for Colors {
+        fn embed(&self, content: &String) -> Result<Vec<f32>, String> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            if content.is_empty() {
+                return Err(String::from("nothing to embed"));
+            }
+            let count = |color: &str| content.matches(color).count() as f32;
+            Ok(vec![count("red"), count("green"), count("blue")])
+        }
+    }
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let equalifier = EmbeddingEqualifier::new(Box::new(Colors(calls.clone())), 0.5);
+    let answer = |content: &str| Answer::new(String::from(content), String::from("s1"));
+    let red = answer("red");
+    assert_eq!(equalifier.get_distance(&red, &answer("very red, red")), 0.0);
+    assert_eq!(equalifier.get_distance(&red, &answer("green")), 1.0);
+    assert_approx_eq!(
+        equalifier.get_distance(&answer("red blue"), &answer("red")),
+        (1.0 - 1.0 / 2.0_f64.sqrt()) / 0.5
+    );
+    assert!(!equalifier.is_valid_answer(&answer("")));
+    assert_eq!(equalifier.get_distance(&red, &answer("")), 1.0);
+
+    // every distinct answer is embedded once, failures are tried again
+    let embedded = calls.load(Ordering::SeqCst);
+    assert_eq!(embedded, 6);
+    assert_eq!(
+        equalifier.get_distance(&answer("red"), &answer("green")),
+        1.0
+    );
+    assert_eq!(calls.load(Ordering::SeqCst), embedded);
+    assert!(!equalifier.is_valid_answer(&answer("")));
+    assert_eq!(calls.load(Ordering::SeqCst), embedded + 1);
+
+    // answers whose hashes collide don't share an embedding
+    let mut green = answer("green");
+    green.hash = red.hash;
+    assert_eq!(equalifier.get_distance(&red, &green), 1.0);
+
+    // red and dark-red agree, green doesn't
+    let mut g = crate::graph::Graph::new();
+    g.set_equalifier(Box::new(EmbeddingEqualifier::new(
+        Box::new(Colors(calls)),
+        0.5,
+    )));
+    for line in &[
+        "SET q1 red FROM s1",
+        "SET q1 dark-red FROM s2",
+        "SET q1 green FROM s3",
+    ] {
+        g.execute_command(&crate::command::Command::from(line).unwrap())
+            .unwrap();
+    }
+    assert_eq!(g.compute_answer("q1").unwrap().0, "red");
+}
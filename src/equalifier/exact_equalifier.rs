@@ -1,6 +1,7 @@
 use crate::command::Answer;
-use crate::equalifier::Equalifier;
+use crate::equalifier::{Equalifier, EqualifierConfig};
 
+#[derive(Default)]
 pub struct ExactEqualifier {}
 
 impl ExactEqualifier {
@@ -9,11 +10,19 @@ impl ExactEqualifier {
     }
 }
 
-impl Equalifier for ExactEqualifier {
-    fn is_valid_answer(&self, _a: &Answer) -> bool {
+// Works for any answer content that can be compared
+impl<A: PartialEq> Equalifier<A> for ExactEqualifier {
+    fn is_valid_answer(&self, _a: &Answer<A>) -> bool {
         true
     }
-    fn get_distance(&self, a: &Answer, b: &Answer) -> f64 {
-        return if a.content == b.content { 0.0 } else { 1.0 };
+    fn get_distance(&self, a: &Answer<A>, b: &Answer<A>) -> f64 {
+        if a.content == b.content {
+            0.0
+        } else {
+            1.0
+        }
+    }
+    fn config(&self) -> EqualifierConfig {
+        EqualifierConfig::Exact
     }
 }
@@ -0,0 +1,74 @@
+use crate::equalifier::{Answer, Equalifier};
+#[cfg(test)]
+use assert_approx_eq::assert_approx_eq;
+use num::clamp;
+
+// The mean radius of the earth in km
+const EARTH_RADIUS: f64 = 6371.0088;
+
+// Compares locations given as "<latitude>,<longitude>" in degrees, e.g.
+// "48.8584,2.2945", by their great-circle distance in km over max_distance.
+pub struct GeoEqualifier {
+    pub max_distance: f64,
+}
+
+impl GeoEqualifier {
+    pub fn new(max_distance: f64) -> Self {
+        GeoEqualifier { max_distance }
+    }
+}
+
+// Parse a location answer, None if it isn't a latitude,longitude pair in range
+pub fn parse_location(content: &str) -> Option<(f64, f64)> {
+    let (latitude, longitude) = content.split_once(',')?;
+    let (latitude, longitude) = (
+        latitude.trim().parse::<f64>().ok()?,
+        longitude.trim().parse::<f64>().ok()?,
+    );
+    if (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude) {
+        Some((latitude, longitude))
+    } else {
+        None
+    }
+}
+
+// The haversine distance between two locations in km
+fn great_circle_distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat_a, lat_b) = (a.0.to_radians(), b.0.to_radians());
+    let (d_lat, d_lon) = ((b.0 - a.0).to_radians(), (b.1 - a.1).to_radians());
+    let h = (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * h.sqrt().min(1.0).asin()
+}
+
+impl Equalifier for GeoEqualifier {
+    fn get_distance(&self, a: &Answer, b: &Answer) -> f64 {
+        match (parse_location(&a.content), parse_location(&b.content)) {
+            (Some(a), Some(b)) => clamp(great_circle_distance(a, b) / self.max_distance, 0.0, 1.0),
+            _ => 1.0,
+        }
+    }
+    fn is_valid_answer(&self, a: &Answer) -> bool {
+        parse_location(&a.content).is_some()
+    }
+}
+
+#[test]
+fn geo_distance_test() {
+    let answer = |content: &str| Answer::new(String::from(content), String::from("s1"));
+    let ge = GeoEqualifier::new(1000.0);
+    // Paris to London is about 344 km
+    assert_approx_eq!(
+        ge.get_distance(&answer("48.8566,2.3522"), &answer("51.5074,-0.1278")),
+        0.344,
+        0.001
+    );
+    // across the antimeridian
+    assert_approx_eq!(
+        ge.get_distance(&answer("0,179.9"), &answer("0,-179.9")),
+        2.0 * EARTH_RADIUS * 0.1_f64.to_radians() / 1000.0
+    );
+    assert_eq!(ge.get_distance(&answer("10,10"), &answer("10,10")), 0.0);
+    assert_eq!(ge.get_distance(&answer("90,0"), &answer("-90,0")), 1.0);
+    assert!(!ge.is_valid_answer(&answer("91,0")));
+    assert!(!ge.is_valid_answer(&answer("Paris")));
+}
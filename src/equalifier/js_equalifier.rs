@@ -1,11 +1,5 @@
 use crate::command::Answer;
 use crate::equalifier::Equalifier;
-use futures::executor;
-use futures::future::Future;
-use js_sys::Promise;
-use wasm_bindgen_futures::spawn_local;
-use wasm_bindgen_futures::JsFuture;
-
 use wasm_bindgen::prelude::*;
 
 pub struct JSEqualifier {
@@ -20,6 +14,14 @@ impl JSEqualifier {
     }
 }
 
+// SAFETY: JS values can only be used on the thread that created them. This
+// module is only built for wasm32 without the atomics target feature, where
+// there is a single thread, so a JSEqualifier never crosses a thread boundary.
+// With atomics, wasm32 can run Rust on web workers and these impls would be
+// unsound, so there is no JSEqualifier at all.
+unsafe impl Send for JSEqualifier {}
+unsafe impl Sync for JSEqualifier {}
+
 impl Equalifier for JSEqualifier {
     fn is_valid_answer(&self, _a: &Answer) -> bool {
         true
@@ -33,6 +35,6 @@ impl Equalifier for JSEqualifier {
         );
         let js_func_ret_val = js_func_res.unwrap();
         let as_f64 = js_func_ret_val.as_f64();
-        return as_f64.unwrap();
+        as_f64.unwrap()
     }
 }
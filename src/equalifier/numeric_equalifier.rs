@@ -1,4 +1,4 @@
-use crate::equalifier::{Answer, Equalifier};
+use crate::equalifier::{Answer, Equalifier, EqualifierConfig};
 use num::clamp;
 
 pub struct NumericEqualifier {
@@ -13,13 +13,25 @@ impl NumericEqualifier {
 
 impl Equalifier for NumericEqualifier {
     fn get_distance(&self, a: &Answer, b: &Answer) -> f64 {
-        let af = a.content.parse::<f64>().unwrap();
-        let bf = b.content.parse::<f64>().unwrap();
-        clamp((af - bf).abs() / self.max_distance, 0.0, 1.0)
+        match (a.content.parse::<f64>(), b.content.parse::<f64>()) {
+            (Ok(af), Ok(bf)) if af == bf => 0.0,
+            // inf - inf and nan are no distance at all, they never match
+            (Ok(af), Ok(bf)) => match (af - bf).abs() / self.max_distance {
+                distance if distance.is_nan() => 1.0,
+                distance => clamp(distance, 0.0, 1.0),
+            },
+            // an answer that isn't a number never matches
+            _ => 1.0,
+        }
     }
     fn is_valid_answer(&self, a: &Answer) -> bool {
         a.content.parse::<f64>().is_ok()
     }
+    fn config(&self) -> EqualifierConfig {
+        EqualifierConfig::Numeric {
+            max_distance: self.max_distance,
+        }
+    }
 }
 
 #[test]
@@ -28,4 +40,10 @@ fn numeric_distance_test() {
     let a = Answer::new(String::from("2"), String::from("s1"));
     let b = Answer::new(String::from("8.56"), String::from("s2"));
     assert_eq!(nd.get_distance(&a, &b), (8.56 - 2.0) / 10.0);
+
+    let inf = Answer::new(String::from("inf"), String::from("s3"));
+    let nan = Answer::new(String::from("NaN"), String::from("s4"));
+    assert_eq!(nd.get_distance(&inf, &inf), 0.0);
+    assert_eq!(nd.get_distance(&a, &inf), 1.0);
+    assert_eq!(nd.get_distance(&nan, &nan), 1.0);
 }
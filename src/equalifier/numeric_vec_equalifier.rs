@@ -1,7 +1,12 @@
-use crate::equalifier::{Answer, Equalifier};
+use crate::equalifier::vec_distance::{l1_distance, l2_distance};
+use crate::equalifier::{Answer, Equalifier, EqualifierConfig};
+#[cfg(test)]
 use assert_approx_eq::assert_approx_eq;
 use num::clamp;
+use serde::{Deserialize, Serialize};
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum VecDistAlgo {
     L2Norm,
     L1Norm,
@@ -12,7 +17,7 @@ pub enum VecDistAlgo {
 impl VecDistAlgo {
     pub fn from(s: &str) -> Option<Self> {
         let ls = s.to_lowercase();
-        match s {
+        match ls.as_str() {
             "l1" | "l1norm" => Some(VecDistAlgo::L1Norm),
             "l2" | "l2norm" => Some(VecDistAlgo::L2Norm),
             "percent_not_equal" | "percentnotequal" => Some(VecDistAlgo::PercentNotEqual),
@@ -20,6 +25,16 @@ impl VecDistAlgo {
             _ => None,
         }
     }
+
+    // The name CONFIGURE takes for diff_fn
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VecDistAlgo::L1Norm => "l1",
+            VecDistAlgo::L2Norm => "l2",
+            VecDistAlgo::PercentNotEqual => "percent_not_equal",
+            VecDistAlgo::IntersectionOverUnion => "iou",
+        }
+    }
 }
 
 pub struct NumericVecEqualifier {
@@ -38,29 +53,31 @@ impl NumericVecEqualifier {
     }
 }
 
-fn split_to_f64_vec(a: &Answer, delimeter: &str) -> Vec<f64> {
-    a.content
-        .split(delimeter)
-        .map(|e| e.parse::<f64>().unwrap())
-        .collect()
+// Parse a numeric_vec answer, e.g. "1,2.5,3", None if any element isn't a number
+pub fn parse_numeric_vec(content: &str) -> Option<Vec<f64>> {
+    content.split(',').map(|e| e.parse::<f64>().ok()).collect()
 }
 
 impl Equalifier for NumericVecEqualifier {
     fn get_distance(&self, a: &Answer, b: &Answer) -> f64 {
-        let av: Vec<f64> = split_to_f64_vec(a, &",");
-        let bv: Vec<f64> = split_to_f64_vec(b, &",");
+        let (av, bv) = match (parse_numeric_vec(&a.content), parse_numeric_vec(&b.content)) {
+            (Some(av), Some(bv)) => (av, bv),
+            _ => return 1.0, // not numbers, maximum error
+        };
         if av.len() != bv.len() {
             return 1.0;
         }; // invalid dimensions, maximum error
-        let normalize = |x| clamp(x / self.allowed_difference, 0.0, 1.0);
+        if av == bv {
+            return 0.0;
+        }
+        // nan means inf - inf or nan elements, which never match
+        let normalize = |x: f64| match x / self.allowed_difference {
+            distance if distance.is_nan() => 1.0,
+            distance => clamp(distance, 0.0, 1.0),
+        };
         match self.diff_fn {
-            VecDistAlgo::L2Norm => normalize(
-                (0..av.len())
-                    .map(|i| (av[i] - bv[i]).powi(2))
-                    .sum::<f64>()
-                    .sqrt(),
-            ),
-            VecDistAlgo::L1Norm => normalize((0..av.len()).map(|i| (av[i] - bv[i]).abs()).sum()),
+            VecDistAlgo::L2Norm => normalize(l2_distance(&av, &bv)),
+            VecDistAlgo::L1Norm => normalize(l1_distance(&av, &bv)),
             VecDistAlgo::PercentNotEqual => normalize(
                 (0..av.len()).filter(|&i| av[i] != bv[i]).count() as f64 / (av.len() as f64),
             ),
@@ -76,8 +93,14 @@ impl Equalifier for NumericVecEqualifier {
         }
     }
     fn is_valid_answer(&self, a: &Answer) -> bool {
-        let av: Vec<f64> = split_to_f64_vec(a, &",");
-        return av.len() == self.vec_length;
+        parse_numeric_vec(&a.content).is_some_and(|av| av.len() == self.vec_length)
+    }
+    fn config(&self) -> EqualifierConfig {
+        EqualifierConfig::NumericVec {
+            allowed_difference: self.allowed_difference,
+            vec_length: self.vec_length,
+            diff_fn: self.diff_fn.clone(),
+        }
     }
 }
 
@@ -111,4 +134,7 @@ fn numeric_vector_distance_test_intersection_over_union() {
     let a = Answer::new(String::from("0,0,0,1,1,1,1,1,0,0,0"), String::from("s1"));
     let b = Answer::new(String::from("0,0,0,1,1,2,2,1,0,0,0"), String::from("s2"));
     assert_approx_eq!(nd.get_distance(&a, &b), 2.0 / 5.0);
+
+    let zeros = Answer::new(String::from("0,0,0,0,0,0,0,0,0,0,0"), String::from("s3"));
+    assert_eq!(nd.get_distance(&zeros, &zeros), 0.0);
 }
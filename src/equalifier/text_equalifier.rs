@@ -0,0 +1,206 @@
+use crate::equalifier::{Answer, Equalifier, EqualifierConfig, Language};
+#[cfg(test)]
+use assert_approx_eq::assert_approx_eq;
+use num::clamp;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Compares free text answers by the words they share. Answers are segmented
+// on unicode word boundaries (UAX #29) rather than whitespace, so answers in
+// scripts without spaces between words, e.g. Chinese or Japanese, and answers
+// mixing scripts are compared word by word too. The distance is the share of
+// distinct words the answers don't have in common (1 - Jaccard similarity)
+// over max_distance.
+//
+// With fold_width, full-width forms of ASCII characters ("ＡＢＣ１２３") are
+// compared as their ASCII counterparts and the ideographic space as a space.
+// With stopwords, words of the language without meaning of their own ("the",
+// "of") are ignored, and with stem words are compared by their stem, so "the
+// red car" and "red cars" have the same words. Both compare words lowercased.
+pub struct TextEqualifier {
+    pub max_distance: f64,
+    pub fold_width: bool,
+    pub language: Language,
+    pub stem: bool,
+    pub stopwords: bool,
+}
+
+impl TextEqualifier {
+    pub fn new(max_distance: f64, fold_width: bool) -> Self {
+        TextEqualifier {
+            max_distance,
+            fold_width,
+            language: Language::default(),
+            stem: false,
+            stopwords: false,
+        }
+    }
+
+    // The distinct words of a text
+    fn words<'a>(&self, text: &'a str) -> HashSet<Cow<'a, str>> {
+        if !self.stem && !self.stopwords {
+            return text.unicode_words().map(Cow::Borrowed).collect();
+        }
+        text.unicode_words()
+            .map(str::to_lowercase)
+            .filter(|word| !self.stopwords || !self.language.is_stopword(word))
+            .map(|word| {
+                if self.stem {
+                    Cow::Owned(self.language.stem(&word).into_owned())
+                } else {
+                    Cow::Owned(word)
+                }
+            })
+            .collect()
+    }
+
+    fn folded<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.fold_width && text.chars().any(is_full_width) {
+            Cow::Owned(text.chars().map(fold_width).collect())
+        } else {
+            Cow::Borrowed(text)
+        }
+    }
+}
+
+fn is_full_width(c: char) -> bool {
+    matches!(c, '\u{FF01}'..='\u{FF5E}' | '\u{3000}')
+}
+
+fn fold_width(c: char) -> char {
+    match c {
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        '\u{3000}' => ' ',
+        _ => c,
+    }
+}
+
+impl Equalifier for TextEqualifier {
+    fn get_distance(&self, a: &Answer, b: &Answer) -> f64 {
+        let (a, b) = (self.folded(&a.content), self.folded(&b.content));
+        if a == b {
+            return 0.0;
+        }
+        let (a_words, b_words) = (self.words(&a), self.words(&b));
+        let union = a_words.union(&b_words).count();
+        if union == 0 {
+            // neither answer has words, e.g. only punctuation or stopwords
+            return 1.0;
+        }
+        let shared = a_words.intersection(&b_words).count();
+        clamp(
+            (1.0 - shared as f64 / union as f64) / self.max_distance,
+            0.0,
+            1.0,
+        )
+    }
+    fn is_valid_answer(&self, _a: &Answer) -> bool {
+        true
+    }
+    fn config(&self) -> EqualifierConfig {
+        EqualifierConfig::Text {
+            max_distance: self.max_distance,
+            fold_width: self.fold_width,
+            language: self.language,
+            stem: self.stem,
+            stopwords: self.stopwords,
+        }
+    }
+}
+
+#[test]
+fn text_distance_test() {
+    let answer = |content: &str| Answer::new(String::from(content), String::from("s1"));
+    let te = TextEqualifier::new(1.0, false);
+    assert_eq!(te.get_distance(&answer("the cat"), &answer("the cat")), 0.0);
+    assert_eq!(
+        te.get_distance(&answer("the cat"), &answer("cat, the")),
+        0.0
+    );
+    assert_eq!(te.get_distance(&answer("the cat"), &answer("a dog")), 1.0);
+    assert_approx_eq!(
+        te.get_distance(&answer("the cat"), &answer("the dog")),
+        2.0 / 3.0
+    );
+    assert_eq!(te.get_distance(&answer("?"), &answer("!")), 1.0);
+
+    // words without spaces between them, ideographs are words of their own
+    assert_eq!(
+        te.get_distance(&answer("東京タワー"), &answer("東京 タワー")),
+        0.0
+    );
+    assert_eq!(
+        te.get_distance(&answer("東京タワー"), &answer("京都タワー")),
+        0.5
+    );
+    assert_eq!(
+        te.get_distance(&answer("Tokyo東京"), &answer("東京 Tokyo")),
+        0.0
+    );
+
+    // full-width forms only match their ASCII counterparts when folded
+    assert_eq!(
+        te.get_distance(&answer("ＡＢＣ１２３"), &answer("ABC123")),
+        1.0
+    );
+    let te = TextEqualifier::new(1.0, true);
+    assert_eq!(
+        te.get_distance(&answer("ＡＢＣ１２３"), &answer("ABC123")),
+        0.0
+    );
+    assert_eq!(
+        te.get_distance(&answer("ＡＢＣ\u{3000}x"), &answer("x ABC")),
+        0.0
+    );
+
+    // stopwords are ignored and words compared by their stem
+    let red_car = (answer("the red car"), answer("Red cars"));
+    assert_eq!(te.get_distance(&red_car.0, &red_car.1), 1.0);
+    let mut te = TextEqualifier::new(1.0, false);
+    te.stopwords = true;
+    assert_approx_eq!(te.get_distance(&red_car.0, &red_car.1), 2.0 / 3.0);
+    te.stem = true;
+    assert_eq!(te.get_distance(&red_car.0, &red_car.1), 0.0);
+    assert_eq!(te.get_distance(&answer("cities"), &answer("a city")), 0.0);
+    assert_eq!(te.get_distance(&answer("the"), &answer("a")), 1.0);
+    te.language = Language::Spanish;
+    assert_eq!(
+        te.get_distance(&answer("los coches rojos"), &answer("un coche rojo")),
+        0.0
+    );
+    assert_eq!(Language::German.stem("häuser"), "häus");
+    assert_eq!(Language::French.stem("chevaux"), "cheval");
+    assert_eq!(Language::French.stem("grandes"), "grand");
+    assert_eq!("en".parse(), Ok(Language::English));
+}
+
+#[test]
+fn text_comparison_method_test() {
+    use crate::command::{Command, CommandResponse};
+    use crate::graph::Graph;
+
+    let mut g = Graph::new();
+    let mut run = |line: &str| g.execute_command(&Command::from(line).unwrap()).unwrap();
+    run("CONFIGURE comparison_method text max_distance=0.6 fold_width=true");
+    run("SET q1 東京タワー FROM s1");
+    run("SET q1 タワー東京 FROM s2");
+    run("SET q1 京都 FROM s3");
+    assert!(matches!(
+        run("GET ANSWER TO q1"),
+        CommandResponse::Answer { content, .. } if content == "東京タワー"
+    ));
+    run("SET q2 Kyoto FROM s1");
+    run("SET q2 Ｔｏｋｙｏ FROM s2");
+    run("SET q2 Tokyo FROM s3");
+    assert!(matches!(
+        run("GET ANSWER TO q2"),
+        CommandResponse::Answer { content, .. } if content != "Kyoto"
+    ));
+    assert_eq!(
+        run("CONFIGURE comparison_method exact"),
+        CommandResponse::Configure {
+            previous: String::from("text max_distance=0.6 fold_width=true")
+        }
+    );
+}
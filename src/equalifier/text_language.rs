@@ -0,0 +1,141 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::str::FromStr;
+
+// The language of text answers, for stopword removal and stemming. The
+// stemmers are light stemmers, they mostly remove plural and gender endings
+// ("cars" -> "car", "rojas" -> "roj") rather than derivational suffixes, which
+// keeps them from merging words of different meaning.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Language {
+    #[default]
+    English,
+    French,
+    German,
+    Spanish,
+}
+
+impl Language {
+    pub const ALL: [Language; 4] = [
+        Language::English,
+        Language::French,
+        Language::German,
+        Language::Spanish,
+    ];
+
+    // The name CONFIGURE comparison_method text language=<name> takes
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Language::English => "english",
+            Language::French => "french",
+            Language::German => "german",
+            Language::Spanish => "spanish",
+        }
+    }
+
+    // ISO 639-1 code, also accepted by CONFIGURE
+    fn code(&self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::French => "fr",
+            Language::German => "de",
+            Language::Spanish => "es",
+        }
+    }
+
+    // Whether a lowercase word carries no meaning of its own
+    pub fn is_stopword(&self, word: &str) -> bool {
+        let stopwords: &[&str] = match self {
+            Language::English => &[
+                "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "in", "is", "it",
+                "of", "on", "or", "that", "the", "this", "to", "was", "were", "with",
+            ],
+            Language::French => &[
+                "au", "aux", "avec", "ce", "ces", "dans", "de", "des", "du", "en", "est", "et",
+                "la", "le", "les", "ou", "par", "pour", "sur", "un", "une",
+            ],
+            Language::German => &[
+                "am", "an", "auf", "das", "dem", "den", "der", "des", "die", "ein", "eine",
+                "einem", "einen", "einer", "eines", "im", "in", "ist", "mit", "oder", "und", "von",
+                "zu", "zum", "zur",
+            ],
+            Language::Spanish => &[
+                "al", "con", "de", "del", "el", "en", "es", "la", "las", "lo", "los", "o", "para",
+                "por", "que", "se", "un", "una", "unas", "unos", "y",
+            ],
+        };
+        stopwords.contains(&word)
+    }
+
+    // The stem of a lowercase word
+    pub fn stem<'a>(&self, word: &'a str) -> Cow<'a, str> {
+        match self {
+            Language::English => stem_english(word),
+            Language::French => stem_french(word),
+            Language::German => Cow::Borrowed(strip_first(
+                word,
+                &["ern", "em", "en", "er", "es", "e", "s"],
+                3,
+            )),
+            Language::Spanish => {
+                Cow::Borrowed(strip_first(word, &["os", "as", "es", "o", "a", "e"], 3))
+            }
+        }
+    }
+}
+
+// Harman's S stemmer, which only removes plural endings
+fn stem_english(word: &str) -> Cow<'_, str> {
+    if word.chars().count() <= 3 {
+        return Cow::Borrowed(word);
+    }
+    if let Some(stem) = word.strip_suffix("ies") {
+        if !stem.ends_with('e') && !stem.ends_with('a') {
+            return Cow::Owned(format!("{}y", stem));
+        }
+    }
+    if word.ends_with("es") && !["aes", "ees", "oes"].iter().any(|end| word.ends_with(end)) {
+        return Cow::Borrowed(&word[..word.len() - 1]);
+    }
+    if word.ends_with('s') && !word.ends_with("us") && !word.ends_with("ss") {
+        return Cow::Borrowed(&word[..word.len() - 1]);
+    }
+    Cow::Borrowed(word)
+}
+
+// Savoy's light French stemmer, which removes plural and feminine endings
+fn stem_french(word: &str) -> Cow<'_, str> {
+    if let Some(stem) = word
+        .strip_suffix("aux")
+        .filter(|stem| stem.chars().count() >= 2)
+    {
+        return Cow::Owned(format!("{}al", stem));
+    }
+    Cow::Borrowed(strip_first(strip_first(word, &["s", "x"], 3), &["e"], 3))
+}
+
+// Remove the first of suffixes word ends with, if at least min_len characters
+// remain
+fn strip_first<'a>(word: &'a str, suffixes: &[&str], min_len: usize) -> &'a str {
+    suffixes
+        .iter()
+        .filter_map(|suffix| word.strip_suffix(suffix))
+        .find(|stem| stem.chars().count() >= min_len)
+        .unwrap_or(word)
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Language::ALL
+            .iter()
+            .find(|language| language.as_str() == s || language.code() == s)
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<&str> = Language::ALL.iter().map(Language::as_str).collect();
+                format!("language must be one of {}", names.join(", "))
+            })
+    }
+}
@@ -0,0 +1,79 @@
+// Distance kernels for numeric vectors. With the "simd" feature the loops are
+// processed four lanes at a time, otherwise they are plain scalar loops.
+
+#[cfg(feature = "simd")]
+use wide::f64x4;
+
+#[cfg(feature = "simd")]
+const LANES: usize = 4;
+
+#[cfg(feature = "simd")]
+fn lanes(v: &[f64]) -> f64x4 {
+    f64x4::from([v[0], v[1], v[2], v[3]])
+}
+
+#[cfg(not(feature = "simd"))]
+pub fn l1_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| (x - y).abs()).sum()
+}
+
+#[cfg(feature = "simd")]
+pub fn l1_distance(a: &[f64], b: &[f64]) -> f64 {
+    let mut acc = f64x4::ZERO;
+    let a_chunks = a.chunks_exact(LANES);
+    let b_chunks = b.chunks_exact(LANES);
+    let tail: f64 = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .map(|(x, y)| (x - y).abs())
+        .sum();
+    for (ac, bc) in a_chunks.zip(b_chunks) {
+        acc += (lanes(ac) - lanes(bc)).abs();
+    }
+    acc.reduce_add() + tail
+}
+
+#[cfg(not(feature = "simd"))]
+pub fn l2_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+#[cfg(feature = "simd")]
+pub fn l2_distance(a: &[f64], b: &[f64]) -> f64 {
+    let mut acc = f64x4::ZERO;
+    let a_chunks = a.chunks_exact(LANES);
+    let b_chunks = b.chunks_exact(LANES);
+    let tail: f64 = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .map(|(x, y)| (x - y).powi(2))
+        .sum();
+    for (ac, bc) in a_chunks.zip(b_chunks) {
+        let diff = lanes(ac) - lanes(bc);
+        acc += diff * diff;
+    }
+    (acc.reduce_add() + tail).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_vec_distances() {
+        // 11 elements so the simd path also exercises the remainder
+        let a: Vec<f64> = (0..11).map(|i| i as f64 * 0.5).collect();
+        let b: Vec<f64> = (0..11).map(|i| (i as f64).sqrt()).collect();
+        let l1: f64 = (0..11).map(|i| (a[i] - b[i]).abs()).sum();
+        let l2: f64 = (0..11).map(|i| (a[i] - b[i]).powi(2)).sum::<f64>().sqrt();
+        assert_approx_eq!(l1_distance(&a, &b), l1);
+        assert_approx_eq!(l2_distance(&a, &b), l2);
+    }
+}
@@ -0,0 +1,109 @@
+// Errors returned by commands and the typed Graph API
+//
+// Each kind is a variant so callers can tell e.g. a bad command from a bad
+// configuration value. The Display form is the message reported to clients,
+// and errors convert into String for code that only needs that message.
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfidisError {
+    // A command that couldn't be parsed, from the text grammar or JSON
+    ParseError(String),
+    // A question, source or graph name that isn't valid, see QuestionId,
+    // SourceId and GraphManager
+    InvalidId(String),
+    // A question the operation needs doesn't exist
+    UnknownQuestion(String),
+    // A source the operation needs doesn't exist
+    UnknownSource(String),
+    // A GraphManager has no graph by that name
+    UnknownGraph(String),
+    // A CONFIGURE key that doesn't exist or a value that isn't valid for it
+    InvalidConfig {
+        key: String,
+        reason: String,
+    },
+    // An answer the equalifier can't compare
+    InvalidAnswer(String),
+    // An answer that doesn't follow its question's schema, see SCHEMA
+    SchemaViolation {
+        question: String,
+        answer: String,
+        reason: String,
+    },
+    // A source gave more answers than rate_limit_answers allows in
+    // rate_limit_window seconds, see rate_limit.rs
+    RateLimited {
+        source: String,
+        limit: usize,
+        window: f64,
+    },
+    // A command that isn't supported where it was used
+    NotImplemented(String),
+    // Answers are computed lazily while a bulk load is in progress
+    BulkLoadInProgress,
+    // finish_bulk_load without a begin_bulk_load
+    NoBulkLoad,
+    // The change was applied to the graph but writing the journal failed
+    Journal(String),
+    // Reading or writing a StoredGraph's storage failed, see storage.rs
+    Storage(String),
+    // Clustering or another internal step failed
+    Internal(String),
+}
+
+impl fmt::Display for ConfidisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfidisError::ParseError(msg) => write!(f, "{}", msg),
+            ConfidisError::InvalidId(msg) => write!(f, "Invalid id: {}", msg),
+            ConfidisError::UnknownQuestion(question) => {
+                write!(f, "Unknown question: \"{}\"", question)
+            }
+            ConfidisError::UnknownSource(source) => write!(f, "Unknown source: \"{}\"", source),
+            ConfidisError::UnknownGraph(graph) => write!(f, "Unknown graph: \"{}\"", graph),
+            ConfidisError::InvalidConfig { key, reason } => {
+                write!(f, "Invalid configuration \"{}\": {}", key, reason)
+            }
+            ConfidisError::InvalidAnswer(msg) => write!(f, "Invalid answer: {}", msg),
+            ConfidisError::SchemaViolation {
+                question,
+                answer,
+                reason,
+            } => write!(
+                f,
+                "Answer \"{}\" to \"{}\" violates its schema: {}",
+                answer, question, reason
+            ),
+            ConfidisError::RateLimited {
+                source,
+                limit,
+                window,
+            } => write!(
+                f,
+                "Source \"{}\" exceeded its rate limit of {} answers per {} seconds",
+                source, limit, window
+            ),
+            ConfidisError::NotImplemented(msg) => write!(f, "{}", msg),
+            ConfidisError::BulkLoadInProgress => {
+                write!(f, "Answers are unavailable until the bulk load is finished")
+            }
+            ConfidisError::NoBulkLoad => write!(f, "No bulk load in progress"),
+            ConfidisError::Journal(msg) => {
+                write!(f, "Change was applied but not journaled: {}", msg)
+            }
+            ConfidisError::Storage(msg) => write!(f, "Storage failed: {}", msg),
+            ConfidisError::Internal(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl Error for ConfidisError {}
+
+impl From<ConfidisError> for String {
+    fn from(e: ConfidisError) -> String {
+        e.to_string()
+    }
+}
@@ -0,0 +1,229 @@
+// Evidence expiry for source qualities
+//
+// A source's quality and strength accumulate the effect of every question it
+// answered, so a source that was accurate for years keeps a high quality long
+// after it turned bad. Each source keeps a ledger of the questions whose
+// effect it holds, since when and with which weight and correctness. With
+// evidence_expires_after set, effects older than evidence_expires_after
+// seconds are taken out of the source as if the question had never been
+// answered:
+//
+//   CONFIGURE evidence_expires_after 2592000
+//
+// Sources are checked before a SET, set_many, EXCLUDE or HONEYPOT recomputes a
+// question they answered, so a source's quality only reflects recent evidence
+// once it's used again, and GET SOURCE reports it as of its last check. A
+// question's effect expires once. Recomputing the question doesn't bring it
+// back, the source answering the question again does, as new evidence.
+//
+// The ledger is kept with the source, in snapshots and storage, and restored
+// by UNDO. REBUILD, BELIEVE and calibration start a source's ledger over, and
+// the effects it held are gone: recomputing one of those questions doesn't
+// take its effect out of the source again.
+//
+// Whether or not evidence expires, the ledger lists every effect a source
+// holds, with the source's discount when the effect was added, which removing
+// it needs once the source is stronger than maximum_strength (see added_effect
+// in graph.rs). Sources from before that, see Source::complete_ledger, start
+// a ledger once they need one.
+
+use crate::command::AnswerContent;
+use crate::graph::{effect_weights, reverted_effect, Graph};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+// The questions whose effect a source holds, by question name
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct EvidenceLedger(BTreeMap<String, Evidence>);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Evidence {
+    // unix time in ms the source's answer was first counted
+    since: u64,
+    // the question's weight and whether each of the source's answers was
+    // correct, as their effect was added
+    weight: f64,
+    correct: Vec<bool>,
+    // the effect was taken out of the source and stays out
+    expired: bool,
+    // the source's discount when the effect was added
+    #[serde(default)]
+    discount: f64,
+}
+
+impl EvidenceLedger {
+    pub(crate) fn is_expired(&self, question_name: &str) -> bool {
+        self.0
+            .get(question_name)
+            .is_some_and(|evidence| evidence.expired)
+    }
+
+    // Whether the source holds the question's effect, None if the ledger has
+    // no entry for it, see Source::complete_ledger
+    pub(crate) fn holds(&self, question_name: &str) -> Option<bool> {
+        self.0
+            .get(question_name)
+            .map(|evidence| !evidence.expired && !evidence.correct.is_empty())
+    }
+
+    // The source's discount when the question's effect was added, 0 if the
+    // ledger doesn't hold it
+    pub(crate) fn discount(&self, question_name: &str) -> f64 {
+        self.0
+            .get(question_name)
+            .map_or(0.0, |evidence| evidence.discount)
+    }
+
+    // The question's effect was added with weight and correct to a source with
+    // discount, a question first counted at now
+    pub(crate) fn added(
+        &mut self,
+        question_name: &str,
+        weight: f64,
+        correct: Vec<bool>,
+        discount: f64,
+        now: u64,
+    ) {
+        let evidence = self
+            .0
+            .entry(question_name.to_string())
+            .or_insert_with(|| Evidence {
+                since: now,
+                weight,
+                correct: Vec::new(),
+                expired: false,
+                discount,
+            });
+        evidence.weight = weight;
+        evidence.correct = correct;
+        evidence.discount = discount;
+    }
+
+    // The question's effect was removed, to be added back recomputed
+    pub(crate) fn removed(&mut self, question_name: &str) {
+        if let Some(evidence) = self.0.get_mut(question_name) {
+            evidence.correct.clear();
+        }
+    }
+
+    // The source answered the question again, which counts as new evidence
+    pub(crate) fn renew(&mut self, question_name: &str) {
+        self.0.remove(question_name);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<A: AnswerContent> Graph<A> {
+    // Expire the evidence of the sources about to be judged by the questions,
+    // those that answered them and source_names
+    pub(crate) fn expire_evidence_of<'a, Q, S>(&mut self, question_names: Q, source_names: S)
+    where
+        Q: IntoIterator<Item = &'a str>,
+        S: IntoIterator<Item = &'a str>,
+    {
+        if self.config.evidence_expires_after <= 0.0 {
+            return;
+        }
+        let mut judged: BTreeSet<String> = source_names.into_iter().map(String::from).collect();
+        for question in question_names
+            .into_iter()
+            .filter_map(|question_name| self.questions.get(question_name))
+        {
+            judged.extend(question.answers.iter().map(|answer| answer.source.clone()));
+        }
+        self.expire_evidence(judged.iter().map(String::as_str));
+    }
+
+    // Take the effects older than evidence_expires_after out of the sources
+    fn expire_evidence<'a, I>(&mut self, source_names: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let expires_after = self.config.evidence_expires_after;
+        if expires_after <= 0.0 {
+            return;
+        }
+        let now = self.now();
+        let max_age = (expires_after * 1000.) as u64;
+        for source_name in source_names {
+            let source = match self.sources.get_mut(source_name) {
+                Some(source) => source,
+                None => continue,
+            };
+            for (question_name, evidence) in source.evidence.0.iter_mut() {
+                if evidence.expired || now.saturating_sub(evidence.since) <= max_age {
+                    continue;
+                }
+                if !evidence.correct.is_empty() {
+                    let old = (source.quality, source.strength);
+                    let (quality, strength, discount) = reverted_effect(
+                        &self.config,
+                        (source.quality, source.strength, source.discount),
+                        effect_weights(evidence.weight, &evidence.correct),
+                        evidence.discount,
+                    );
+                    (source.quality, source.strength, source.discount) =
+                        (quality, strength, discount);
+                    if let Some(audit_log) = self.audit_log.as_mut() {
+                        let new = (quality, strength);
+                        audit_log.record(source_name, Some(question_name), true, old, new);
+                    }
+                }
+                evidence.correct.clear();
+                evidence.expired = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Command;
+    use crate::graph::Graph;
+
+    #[test]
+    fn test_evidence_expiry() {
+        let day = 24 * 3600 * 1000;
+        let mut g = Graph::new();
+        let run = |g: &mut Graph, time: u64, line: &str| {
+            g.set_replay_time(Some(time));
+            let response = g.execute_command(&Command::from(line).unwrap()).unwrap();
+            g.set_replay_time(None);
+            response
+        };
+        run(&mut g, 0, "CONFIGURE evidence_expires_after 864000");
+        // s1 is right on ten questions, then wrong on the eleventh
+        for i in 0..10 {
+            for source in ["s1", "s2", "s3"] {
+                run(&mut g, day, &format!("SET q{} a FROM {}", i, source));
+            }
+        }
+        let quality = |g: &Graph| g.source("s1").unwrap().quality;
+        let (trusted, strength) = (quality(&g), g.source("s1").unwrap().strength);
+
+        let mut expiring = g.fork();
+        for g in [&mut g, &mut expiring] {
+            run(g, 5 * day, "SET late a FROM s2");
+            run(g, 5 * day, "SET late a FROM s3");
+        }
+        // within 10 days nothing expires
+        run(&mut g, 5 * day, "SET late b FROM s1");
+        assert!(quality(&g) < trusted && quality(&g) > 0.5);
+
+        // after them s1's track record is gone, only the wrong answer is left
+        run(&mut expiring, 12 * day, "SET late b FROM s1");
+        assert!(quality(&expiring) < quality(&g));
+        assert!(expiring.source("s1").unwrap().strength < strength);
+
+        // expired effects stay out when their question is recomputed
+        let expired = quality(&expiring);
+        run(&mut expiring, 12 * day, "SET q0 a FROM s4");
+        assert_eq!(quality(&expiring), expired);
+        // answering again counts as new evidence
+        run(&mut expiring, 12 * day, "SET q1 a FROM s1");
+        assert!(quality(&expiring) > expired);
+    }
+}
@@ -0,0 +1,149 @@
+// C API
+//
+//   ConfidisGraph *g = confidis_graph_new();
+//   if (confidis_execute(g, "GET ANSWER TO q1") == 0)
+//       printf("%s\n", confidis_last_response(g));
+//   confidis_graph_free(g);
+//
+// confidis_execute and confidis_execute_json return 0 on success and -1 on
+// failure. Either way confidis_last_response is the JSON of the outcome, the
+// serialized CommandResponse or {"error": "..."}. The string is owned by the
+// graph and valid until the next execute or free. Panics are caught and
+// reported as errors, they never unwind into C.
+//
+// include/confidis.h is generated from this file by build.rs with the ffi
+// feature enabled.
+
+use crate::command::Command;
+use crate::error::ConfidisError;
+use crate::graph::Graph;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+pub struct ConfidisGraph {
+    graph: Graph,
+    last_response: CString,
+}
+
+impl ConfidisGraph {
+    fn execute(
+        &mut self,
+        command: *const c_char,
+        parse: fn(&str) -> Result<Command<'_>, ConfidisError>,
+    ) -> c_int {
+        let graph = &mut self.graph;
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            if command.is_null() {
+                return Err(String::from("Command is null"));
+            }
+            let text = unsafe { CStr::from_ptr(command) }
+                .to_str()
+                .map_err(|e| format!("Command isn't UTF-8: {}", e))?;
+            let cmd = parse(text)?;
+            graph.execute_command(&cmd).map_err(String::from)
+        }))
+        .unwrap_or_else(|_| Err(String::from("Command panicked")));
+        let (status, json) = match result {
+            Ok(response) => (0, response.to_json()),
+            Err(msg) => (-1, serde_json::json!({ "error": msg }).to_string()),
+        };
+        // JSON escapes control characters, so it never contains a NUL
+        self.last_response = CString::new(json).unwrap();
+        status
+    }
+}
+
+/// Create an empty graph, free it with confidis_graph_free
+#[no_mangle]
+pub extern "C" fn confidis_graph_new() -> *mut ConfidisGraph {
+    Box::into_raw(Box::new(ConfidisGraph {
+        graph: Graph::new(),
+        last_response: CString::default(),
+    }))
+}
+
+/// # Safety
+/// graph must come from confidis_graph_new and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn confidis_graph_free(graph: *mut ConfidisGraph) {
+    if !graph.is_null() {
+        drop(Box::from_raw(graph));
+    }
+}
+
+/// Execute a command in the text grammar, e.g. "SET q1 a FROM s1"
+///
+/// # Safety
+/// graph must come from confidis_graph_new, command must be a NUL terminated
+/// string
+#[no_mangle]
+pub unsafe extern "C" fn confidis_execute(
+    graph: *mut ConfidisGraph,
+    command: *const c_char,
+) -> c_int {
+    match graph.as_mut() {
+        Some(graph) => graph.execute(command, Command::from),
+        None => -1,
+    }
+}
+
+/// Execute a JSON command envelope, e.g. {"cmd": "get_answer", "question": "q1"}
+///
+/// # Safety
+/// graph must come from confidis_graph_new, json must be a NUL terminated
+/// string
+#[no_mangle]
+pub unsafe extern "C" fn confidis_execute_json(
+    graph: *mut ConfidisGraph,
+    json: *const c_char,
+) -> c_int {
+    match graph.as_mut() {
+        Some(graph) => graph.execute(json, Command::from_json),
+        None => -1,
+    }
+}
+
+/// The JSON response of the last executed command, "" before the first
+///
+/// # Safety
+/// graph must come from confidis_graph_new
+#[no_mangle]
+pub unsafe extern "C" fn confidis_last_response(graph: *const ConfidisGraph) -> *const c_char {
+    match graph.as_ref() {
+        Some(graph) => graph.last_response.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi_execute() {
+        let last = |g| {
+            unsafe { CStr::from_ptr(confidis_last_response(g)) }
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        unsafe {
+            let g = confidis_graph_new();
+            assert_eq!(last(g), "");
+            for cmd in &["SET q1 a FROM s1\0", "SET q1 a FROM s2\0"] {
+                assert_eq!(confidis_execute(g, cmd.as_ptr() as *const c_char), 0);
+            }
+            let json = "{\"cmd\": \"get_answer\", \"question\": \"q1\"}\0";
+            assert_eq!(confidis_execute_json(g, json.as_ptr() as *const c_char), 0);
+            let response: serde_json::Value = serde_json::from_str(&last(g)).unwrap();
+            assert_eq!(response["answer"], "a");
+
+            assert_eq!(confidis_execute(g, "NOPE\0".as_ptr() as *const c_char), -1);
+            assert!(last(g).contains("\"error\""));
+            assert_eq!(confidis_execute(g, ptr::null()), -1);
+            confidis_graph_free(g);
+        }
+    }
+}
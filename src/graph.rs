@@ -1,236 +1,1069 @@
-use crate::cluster::compute_clusters;
-use crate::command::{Answer, AnswerConfidencePair, Command, CommandResponse, CommandType};
-use crate::equalifier::{
-    Equalifier, ExactEqualifier, NumericEqualifier, NumericVecEqualifier, VecDistAlgo,
+use crate::audit::{AuditLog, QualityChange};
+use crate::cluster::{compute_clusters_cached, DistanceCache};
+use crate::command::{
+    Answer, AnswerConfidencePair, AnswerContent, ClusterDebug, ClusterMember, Command,
+    CommandResponse, CommandType, Evidence, GraphStats, MemoryStats, Provenance, QuestionAnswer,
+    SourceAgreement, CONFIDENCE_BUCKETS,
 };
-use log::info;
+use crate::config::{ConfigKey, ConfigValue, DuplicateAnswers, GraphConfig};
+use crate::equalifier::{Equalifier, EqualifierConfig, ExactEqualifier};
+use crate::error::ConfidisError;
+use crate::evidence::EvidenceLedger;
+use crate::hash::{AnswerHash, AnswerHasher};
+use crate::history::{AnswerChange, AnswerHistory};
+use crate::id::{validate_command_ids, QuestionId, SourceId};
+use crate::journal::{now_millis, Journal};
+use crate::question_type::{QuestionType, TypedEqualifiers};
+use crate::rate_limit::RateLimiter;
+use crate::schema::AnswerSchema;
+use crate::shadow::Shadow;
+use crate::snapshot::SnapshotSchedule;
+use crate::undo::{UndoEntry, UndoStack};
+use log::{info, warn};
+use serde::de::{self, Deserializer};
+use serde::ser::{self, Serializer};
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::mem::size_of;
 use std::result::Result;
+use std::sync::Arc;
 
-type SourceId = String;
-type QuestionId = String;
-
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Source {
-    name: SourceId,
+    pub(crate) name: String,
 
     // roughly corresponds to the probability a source will answer correctly
-    quality: f64,
+    pub(crate) quality: f64,
 
     // the amount of evidence to support the correctness of quality
-    strength: f64,
+    pub(crate) strength: f64,
+
+    // the questions whose effect the source holds, see evidence.rs
+    #[serde(default)]
+    pub(crate) evidence: EvidenceLedger,
+
+    // the log of how far every effect the source holds was scaled down to keep
+    // its strength at most maximum_strength, see added_effect
+    #[serde(default)]
+    pub(crate) discount: f64,
+
+    // whether evidence lists every question effect the source holds. Sources
+    // stored without it also hold the effects of questions their ledger has
+    // no entry for, until BELIEVE, calibration or REBUILD starts them over.
+    #[serde(default)]
+    pub(crate) complete_ledger: bool,
 }
 
-#[derive(Debug)]
-pub struct Question {
-    name: QuestionId,
-    correct_answers: Vec<Answer>,
-    weight: f64,
-    confidence: f64,
-    sources: Vec<SourceId>,
-    answers: Vec<Answer>,
+impl Source {
+    // Whether the source holds the question's effect, which BELIEVE,
+    // calibration and REBUILD take away, as does evidence expiry
+    pub(crate) fn holds_effect(&self, question_name: &str) -> bool {
+        self.evidence
+            .holds(question_name)
+            .unwrap_or(!self.complete_ledger)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Question<A = String> {
+    pub(crate) name: String,
+    // indices into answers of the members of the most confident cluster
+    pub(crate) correct_answers: Vec<usize>,
+    pub(crate) weight: f64,
+    pub(crate) confidence: f64,
+    pub(crate) answers: Vec<Answer<A>>,
+    // unix time in ms of the latest answer, 0 if unknown, see
+    // GraphConfig::confidence_decay_after
+    #[serde(default)]
+    pub(crate) answered_at: u64,
+    // sources whose answers to this question are ignored, sorted, see EXCLUDE
+    #[serde(default)]
+    pub(crate) excluded_sources: Vec<String>,
+    // the known answer of a honeypot question, see HONEYPOT
+    #[serde(default)]
+    pub(crate) honeypot: Option<A>,
+    // the schema answers must follow, see SCHEMA
+    #[serde(default)]
+    pub(crate) schema: Option<AnswerSchema>,
+    // the declared type of the answers, see TYPE
+    #[serde(default)]
+    pub(crate) question_type: Option<QuestionType>,
 }
 
-impl Default for Question {
+impl<A> Default for Question<A> {
     fn default() -> Self {
         Question {
             name: String::new(),
             correct_answers: Vec::new(),
             confidence: 0.0,
             weight: 0.0,
-            sources: Vec::new(),
             answers: Vec::new(),
+            answered_at: 0,
+            excluded_sources: Vec::new(),
+            honeypot: None,
+            schema: None,
+            question_type: None,
+        }
+    }
+}
+
+impl<A: AnswerContent> Question<A> {
+    // The indices of the answers matching the honeypot's known answer, None if
+    // the question isn't a honeypot
+    fn honeypot_matches(&self, graph: &Graph<A>) -> Option<Vec<usize>> {
+        let known = Answer::with_hasher(
+            self.honeypot.clone()?,
+            String::new(),
+            graph.config.answer_hash,
+        );
+        Some(
+            (0..self.answers.len())
+                .filter(|&i| {
+                    !self.is_excluded(&self.answers[i].source)
+                        && graph.answer_distance(&self.name, &self.answers[i], &known) < 1.0
+                })
+                .collect(),
+        )
+    }
+
+    // Hash the answers with hasher, see hash.rs
+    pub(crate) fn rehash(&mut self, hasher: AnswerHasher) {
+        for answer in &mut self.answers {
+            answer.hash = hasher.hash(&answer.content);
+        }
+    }
+}
+
+impl<A> Question<A> {
+    pub(crate) fn is_excluded(&self, source_name: &str) -> bool {
+        self.excluded_sources
+            .binary_search_by(|excluded| excluded.as_str().cmp(source_name))
+            .is_ok()
+    }
+}
+
+// The result of Graph::get_answer, answer is None for a question without answers
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnswerResult<A = String> {
+    pub answer: Option<A>,
+    pub confidence: f64,
+    // the sources in the answer's cluster, by name
+    pub sources: Vec<String>,
+    // the number of distinct answers (clusters) competing for the question
+    pub cluster_count: usize,
+    // fewer distinct sources than min_sources_per_answer answered the
+    // question, answer and confidence are what they've given so far
+    pub insufficient_evidence: bool,
+    // the confidence is below min_confidence, GET ANSWER reports Unknown
+    // rather than answer
+    pub unknown: bool,
+}
+
+// The result of Graph::get_source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceStats {
+    pub quality: f64,
+    pub strength: f64,
+}
+
+// A read-only view of a source, see Graph::sources
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceView<'a> {
+    pub name: &'a str,
+    pub quality: f64,
+    pub strength: f64,
+}
+
+// A read-only view of a question, see Graph::questions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuestionView<'a> {
+    pub name: &'a str,
+    pub confidence: f64,
+    pub weight: f64,
+    pub answer_count: usize,
+}
+
+// Whether each of the question's answers is in its correct cluster. Answers are
+// matched by position rather than by hash, so an answer whose hash collides
+// with a correct one can't be counted as correct.
+fn correct_answer_mask<A>(question: &Question<A>) -> Vec<bool> {
+    let mut correct = vec![false; question.answers.len()];
+    for &answer_index in &question.correct_answers {
+        correct[answer_index] = true;
+    }
+    correct
+}
+
+// Removing a question's effect from a source with less strength than this
+// resets the source, see remove_question_effect
+const MINIMUM_REVERT_STRENGTH: f64 = 1e-6;
+
+// The correctness of each source's answers to the question, by source, leaving
+// out excluded sources. A source's answers to a question count as one effect.
+fn answers_by_source<A>(question: &Question<A>) -> BTreeMap<&str, Vec<bool>> {
+    let mut answered: BTreeMap<&str, Vec<bool>> = BTreeMap::new();
+    for (a, correct) in question.answers.iter().zip(correct_answer_mask(question)) {
+        if !question.is_excluded(&a.source) {
+            answered.entry(&a.source).or_default().push(correct);
+        }
+    }
+    answered
+}
+
+// The (weight, correct weight) of the effect of answers with correct to a
+// question with weight
+pub(crate) fn effect_weights(weight: f64, correct: &[bool]) -> (f64, f64) {
+    let correct_count = correct.iter().filter(|&&correct| correct).count();
+    (weight * correct.len() as f64, weight * correct_count as f64)
+}
+
+// A source's (quality, strength, discount) with an effect of (weight, correct
+// weight) added. The quality is the mean of the effects the source holds
+// weighed by their strength. Past maximum_strength every effect is scaled down
+// alike rather than capping the strength, so newer effects outweigh older
+// ones, and the discount adds up the scaling so each effect can still be
+// removed exactly, in any order.
+pub(crate) fn added_effect(
+    config: &GraphConfig,
+    (quality, strength, discount): (f64, f64, f64),
+    (weight, correct): (f64, f64),
+) -> (f64, f64, f64) {
+    let total = strength + weight;
+    if weight == 0.0 || total <= 0.0 {
+        return (quality, strength, discount);
+    }
+    let quality = (quality * strength + correct) / total;
+    if total > config.maximum_strength {
+        (
+            quality,
+            config.maximum_strength,
+            discount + (total / config.maximum_strength).ln(),
+        )
+    } else {
+        (quality, total, discount)
+    }
+}
+
+// A source's (quality, strength, discount) with an effect of (weight, correct
+// weight) removed that was added when the source's discount was
+// added_discount, the inverse of added_effect. The other effects are scaled
+// back up into the room it leaves, as far as they were scaled down.
+pub(crate) fn reverted_effect(
+    config: &GraphConfig,
+    (quality, strength, discount): (f64, f64, f64),
+    (weight, correct): (f64, f64),
+    added_discount: f64,
+) -> (f64, f64, f64) {
+    if weight == 0.0 {
+        return (quality, strength, discount);
+    }
+    let scale = (added_discount - discount).exp();
+    let total = strength - weight * scale;
+    // Without strength left (e.g. this question was the only evidence of a
+    // source that started with none) the quality would be divided by ~0, so
+    // the source starts over instead
+    if total < MINIMUM_REVERT_STRENGTH {
+        return (
+            config.default_source_quality,
+            config.initial_source_strength,
+            0.0,
+        );
+    }
+    let quality = (quality * strength - correct * scale) / total;
+    let room = (config.maximum_strength / total).ln();
+    if discount <= 0.0 || room <= 0.0 {
+        (quality, total, discount)
+    } else if room >= discount {
+        (quality, total * discount.exp(), 0.0)
+    } else {
+        (quality, config.maximum_strength, discount - room)
+    }
+}
+
+// Order each cluster's members with the most common answer first (ties go to
+// the smallest answer hash) and the clusters by the hash of their first member,
+// so neither the answer a cluster reports nor the winner among clusters with
+// equal confidences depends on the order answers were submitted in
+fn order_clusters<A>(answers: &[Answer<A>], clusters: &mut [Vec<usize>]) {
+    for members in clusters.iter_mut() {
+        let mut counts: HashMap<AnswerHash, usize> = HashMap::new();
+        for &i in members.iter() {
+            *counts.entry(answers[i].hash).or_insert(0) += 1;
         }
+        members.sort_by_key(|&i| (Reverse(counts[&answers[i].hash]), answers[i].hash, i));
     }
+    clusters.sort_by_key(|members| members.first().map(|&i| answers[i].hash));
 }
 
-fn argmaxf(vec: &Vec<f64>) -> usize {
+// The index of the highest value, None if vec is empty
+fn argmaxf(vec: &[f64]) -> Option<usize> {
     let mut highest_index = 0_usize;
-    let mut highest_value = vec[0];
+    let mut highest_value = *vec.first()?;
     for (i, v) in vec.iter().enumerate() {
         if *v > highest_value {
             highest_index = i;
             highest_value = *v;
         }
     }
-    return highest_index;
+    Some(highest_index)
 }
 
-pub struct Graph {
+// A graph over answers of type A, see AnswerContent. The command interface,
+// journaling and persistence are only available for String answers. A graph is
+// Send + Sync whenever A is, so it can be shared with e.g. Arc<Mutex<Graph>>.
+pub struct Graph<A = String> {
     // All sources in system
-    sources: HashMap<String, Source>,
+    pub(crate) sources: HashMap<String, Source>,
 
-    // All questions in graph
-    questions: HashMap<String, Question>,
+    // All questions in graph. Forks share questions until one of them changes
+    // a question, see fork
+    pub(crate) questions: HashMap<String, Arc<Question<A>>>,
 
-    // Default probability that a source will be correct
-    default_source_quality: f64,
+    // Tunable parameters, see GraphConfig
+    pub(crate) config: GraphConfig,
 
-    // Starting strength of a source, if this is low (1.0) the initial quality will be changed
-    // easily by new data. If higher, it's easier to resist adversaries with the "start good, turn bad"
-    // attack
-    initial_source_strength: f64,
+    // The equality/similarity system used to compare answers
+    equalifier: Arc<dyn Equalifier<A>>,
 
-    // Maximum strength of a source, impacts how effected they are by more recent
-    // correct/incorrect answers
-    maximum_strength: f64,
+    // Memoized distances between answers under the current equalifier
+    distance_cache: DistanceCache<A>,
 
-    // weight_of_question = -1. * log_{log_weight_factor}(1 - confidence)
-    // 10.0 means that 90% confidence has a weight of 1. 99% confidence has a weight of 2. 99.9% has a weight of 3.
-    log_weight_factor: f64,
+    // The equalifiers of questions with a declared type, see question_type.rs
+    typed_equalifiers: TypedEqualifiers<A>,
 
-    // quality of believed sources
-    quality_of_believed_sources: f64,
+    // Each source's recent answers and rejected answers, see rate_limit.rs
+    rate_limiter: RateLimiter,
 
-    // The equality/similarity system used to compare answers
-    equalifier: Box<dyn Equalifier>,
+    // Questions awaiting recomputation while a bulk load is in progress
+    bulk_load: Option<BulkLoad>,
+
+    // When set, every mutation is appended to this journal
+    journal: Option<Journal>,
+
+    // When set, snapshots are written automatically, see SnapshotPolicy
+    pub(crate) snapshot_schedule: Option<SnapshotSchedule>,
+
+    // Number of times a question's answers were recomputed, see Metrics
+    recompute_count: u64,
+
+    // Callbacks run when execute_command changes answers or source qualities
+    hooks: Hooks,
+
+    // When set, source quality and strength changes are recorded here
+    pub(crate) audit_log: Option<AuditLog>,
+
+    // When set, changes of each question's answer are recorded here
+    answer_history: Option<AnswerHistory<A>>,
+
+    // Break ties and order iteration independently of submission and HashMap
+    // order, see set_deterministic
+    deterministic: bool,
+
+    // Reads of unknown sources and questions fail, see set_strict
+    strict: bool,
+
+    // The time of the journal entry being replayed, which answers get instead
+    // of the current time, see now
+    replay_time: Option<u64>,
+
+    // What UNDO and REDO can take back and redo, see GraphConfig::undo_depth
+    undo: UndoStack<A>,
+
+    // The comparison method evaluated alongside equalifier, see
+    // GraphConfig::shadow_comparison_method
+    pub(crate) shadow: Option<Shadow<A>>,
+}
+
+// Called with the question and the (answer, confidence) GET ANSWER reported
+// before and after a command
+pub type AnswerChangedHook = Box<dyn Fn(&str, &(String, f64), &(String, f64)) + Send + Sync>;
+
+// Called with the source and its quality before and after a command
+pub type SourceQualityChangedHook = Box<dyn Fn(&str, f64, f64) + Send + Sync>;
+
+#[derive(Default)]
+struct Hooks {
+    answer_changed: Vec<AnswerChangedHook>,
+    source_quality_changed: Vec<SourceQualityChangedHook>,
+}
+
+impl Hooks {
+    fn is_empty(&self) -> bool {
+        self.answer_changed.is_empty() && self.source_quality_changed.is_empty()
+    }
 }
 
-struct AnswerClustersWithConfidences {
+// What the hooks observe of the questions and sources a command affects,
+// ordered by name so hooks run in a deterministic order
+struct ObservedState {
+    answers: BTreeMap<String, (String, f64)>,
+    qualities: BTreeMap<String, f64>,
+}
+
+// Questions whose effect has been removed during a bulk load, in the order they
+// were first touched
+#[derive(Default, Clone)]
+struct BulkLoad {
+    questions: Vec<String>,
+    seen: HashSet<String>,
+}
+
+pub(crate) struct AnswerClustersWithConfidences {
     pub clusters: Vec<Vec<usize>>,
     pub cluster_confidences: Vec<f64>,
     pub correct_cluster: usize,
 }
 
+impl Default for Graph {
+    fn default() -> Self {
+        Graph::new()
+    }
+}
+
+// The persisted form of a Graph, the distance cache isn't included
+#[derive(Serialize)]
+struct GraphStateRef<'a> {
+    config: PersistedConfig<&'a GraphConfig>,
+    equalifier: EqualifierConfig,
+    sources: MapRef<'a, Source>,
+    questions: MapRef<'a, Arc<Question>>,
+}
+
+// Serializes a map in key order when sorted, so that equal graphs in
+// deterministic mode have byte for byte equal snapshots
+struct MapRef<'a, V> {
+    map: &'a HashMap<String, V>,
+    sorted: bool,
+}
+
+impl<V: Serialize> Serialize for MapRef<'_, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.sorted {
+            serializer.collect_map(self.map.iter().collect::<BTreeMap<_, _>>())
+        } else {
+            self.map.serialize(serializer)
+        }
+    }
+}
+
+// The config as persisted, by name in human readable formats like JSON and as
+// JSON text in binary ones like snapshots, whose fields are positional. Either
+// way settings added later load with their defaults from earlier states.
+pub(crate) struct PersistedConfig<T>(T);
+
+impl Serialize for PersistedConfig<&GraphConfig> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            self.0.serialize(serializer)
+        } else {
+            serde_json::to_string(self.0)
+                .map_err(ser::Error::custom)?
+                .serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PersistedConfig<GraphConfig> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            GraphConfig::deserialize(deserializer).map(PersistedConfig)
+        } else {
+            let json = String::deserialize(deserializer)?;
+            serde_json::from_str(&json)
+                .map(PersistedConfig)
+                .map_err(de::Error::custom)
+        }
+    }
+}
+
+impl From<PersistedConfig<GraphConfig>> for GraphConfig {
+    fn from(config: PersistedConfig<GraphConfig>) -> GraphConfig {
+        config.0
+    }
+}
+
+#[derive(Deserialize)]
+struct GraphState {
+    config: PersistedConfig<GraphConfig>,
+    equalifier: EqualifierConfig,
+    sources: HashMap<String, Source>,
+    questions: HashMap<String, Arc<Question>>,
+}
+
+impl Serialize for Graph {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.bulk_load.is_some() {
+            return Err(ser::Error::custom(
+                "a graph can't be serialized while a bulk load is in progress",
+            ));
+        }
+        GraphStateRef {
+            config: PersistedConfig(&self.config),
+            equalifier: self.equalifier.config(),
+            sources: MapRef {
+                map: &self.sources,
+                sorted: self.deterministic,
+            },
+            questions: MapRef {
+                map: &self.questions,
+                sorted: self.deterministic,
+            },
+        }
+        .serialize(serializer)
+    }
+}
+
+// Graphs using a custom equalifier can be serialized, but can't be deserialized
+// since the equalifier can't be reconstructed.
+impl<'de> Deserialize<'de> for Graph {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Graph::from_state(GraphState::deserialize(deserializer)?)
+    }
+}
+
 impl Graph {
-    pub fn new() -> Graph {
+    fn from_state<E: de::Error>(state: GraphState) -> Result<Graph, E> {
+        let equalifier = state.equalifier.build().ok_or_else(|| {
+            de::Error::custom("a graph with a custom equalifier can't be deserialized")
+        })?;
+        let config: GraphConfig = state.config.into();
+        let shadow = match &config.shadow_comparison_method {
+            Some(method) => Some(Shadow::new(method.build().ok_or_else(|| {
+                de::Error::custom("a graph with a custom shadow equalifier can't be deserialized")
+            })?)),
+            None => None,
+        };
+        let hasher = config.answer_hash;
+        Ok(Graph {
+            sources: state.sources,
+            // hashes aren't persisted, see hash.rs
+            questions: state
+                .questions
+                .into_iter()
+                .map(|(name, mut question)| {
+                    Arc::make_mut(&mut question).rehash(hasher);
+                    (name, question)
+                })
+                .collect(),
+            config,
+            equalifier: equalifier.into(),
+            distance_cache: DistanceCache::default(),
+            typed_equalifiers: TypedEqualifiers::default(),
+            rate_limiter: RateLimiter::default(),
+            bulk_load: None,
+            journal: None,
+            snapshot_schedule: None,
+            recompute_count: 0,
+            hooks: Hooks::default(),
+            audit_log: None,
+            answer_history: None,
+            deterministic: false,
+            strict: false,
+            replay_time: None,
+            undo: UndoStack::default(),
+            shadow,
+        })
+    }
+}
+
+impl<A: AnswerContent> Graph<A> {
+    pub fn new_with_equalifier(equalifier: Box<dyn Equalifier<A>>) -> Graph<A> {
         Graph {
             sources: HashMap::new(),
             questions: HashMap::new(),
-            default_source_quality: 0.5,
-            initial_source_strength: 1.0,
-            maximum_strength: 100.0,
-            log_weight_factor: 10.0,
-            quality_of_believed_sources: 0.999,
-            equalifier: Box::new(ExactEqualifier::new()),
+            config: GraphConfig::default(),
+            equalifier: equalifier.into(),
+            distance_cache: DistanceCache::default(),
+            typed_equalifiers: TypedEqualifiers::default(),
+            rate_limiter: RateLimiter::default(),
+            bulk_load: None,
+            journal: None,
+            snapshot_schedule: None,
+            recompute_count: 0,
+            hooks: Hooks::default(),
+            audit_log: None,
+            answer_history: None,
+            deterministic: false,
+            strict: false,
+            replay_time: None,
+            undo: UndoStack::default(),
+            shadow: None,
         }
     }
 
-    pub fn new_with_equalifier(equalifier: Box<dyn Equalifier>) -> Graph {
-        let mut g = Graph::new();
-        g.equalifier = equalifier;
-        return g;
+    pub fn config(&self) -> &GraphConfig {
+        &self.config
+    }
+
+    pub fn equalifier_config(&self) -> EqualifierConfig {
+        self.equalifier.config()
+    }
+
+    pub fn set_equalifier(&mut self, equalifier: Box<dyn Equalifier<A>>) {
+        self.equalifier = equalifier.into();
+        self.distance_cache.invalidate();
+    }
+
+    // An independent copy of the graph for trying out changes, e.g. believing a
+    // source or another comparison method, without affecting this graph.
+    //
+    // Questions and the equalifier are shared until either graph changes them,
+    // so forking only copies the sources. The fork has no journal, snapshot
+    // policy, hooks, audit log, answer history or undo stack and starts with
+    // an empty distance cache.
+    pub fn fork(&self) -> Graph<A> {
+        Graph {
+            sources: self.sources.clone(),
+            questions: self.questions.clone(),
+            config: self.config.clone(),
+            equalifier: self.equalifier.clone(),
+            distance_cache: DistanceCache::default(),
+            typed_equalifiers: TypedEqualifiers::default(),
+            rate_limiter: RateLimiter::default(),
+            bulk_load: self.bulk_load.clone(),
+            journal: None,
+            snapshot_schedule: None,
+            recompute_count: self.recompute_count,
+            hooks: Hooks::default(),
+            audit_log: None,
+            answer_history: None,
+            deterministic: self.deterministic,
+            strict: self.strict,
+            replay_time: None,
+            undo: UndoStack::default(),
+            shadow: self.shadow.clone(),
+        }
     }
 
     // Modify connected sources to indicate whether or not they're correct or incorrect
     fn add_question_effect(&mut self, question_name: &str) {
-        let question = self.questions.get_mut(question_name).unwrap();
-        let mut correct_answers: HashSet<u64> = HashSet::new();
-        for a in &question.correct_answers {
-            correct_answers.insert(a.hash);
-        }
-        for a in &question.answers {
-            let originally_correct_fac = if correct_answers.contains(&a.hash) {
-                1.
-            } else {
-                0.
+        let question = match self.questions.get(question_name) {
+            Some(question) => question,
+            None => return,
+        };
+        let now = self.now();
+        for (source_name, correct) in answers_by_source(question) {
+            let answer_source = match self.sources.get_mut(source_name) {
+                Some(source) if !source.evidence.is_expired(question_name) => source,
+                _ => continue,
             };
-            let answer_source = self.sources.get_mut(&a.source).unwrap();
-            let new_quality = (answer_source.quality * answer_source.strength
-                + question.weight * originally_correct_fac)
-                / (answer_source.strength as f64 + question.weight);
+            let discount = answer_source.discount;
+            let (quality, strength, new_discount) = added_effect(
+                &self.config,
+                (answer_source.quality, answer_source.strength, discount),
+                effect_weights(question.weight, &correct),
+            );
             info!(
                 "Adjusting {}.quality  {:.2} -> {:.2}",
-                answer_source.name, answer_source.quality, new_quality
+                answer_source.name, answer_source.quality, quality
             );
             info!(
                 "Adjusting {}.strength {:.2} -> {:.2}",
-                answer_source.name,
-                answer_source.strength,
-                answer_source.strength + question.weight
+                answer_source.name, answer_source.strength, strength
             );
-            answer_source.strength =
-                (answer_source.strength + question.weight).min(self.maximum_strength);
-            answer_source.quality = new_quality;
+            // Removing the effect needs to know the source still holds it and
+            // the discount it was added with. A source without a complete
+            // ledger only starts one once it needs it, see complete_ledger.
+            if answer_source.complete_ledger
+                || self.config.evidence_expires_after > 0.0
+                || discount > 0.0
+                || !answer_source.evidence.is_empty()
+            {
+                answer_source.evidence.added(
+                    question_name,
+                    question.weight,
+                    correct,
+                    discount,
+                    now,
+                );
+            }
+            let old = (answer_source.quality, answer_source.strength);
+            answer_source.quality = quality;
+            answer_source.strength = strength;
+            answer_source.discount = new_discount;
+            if let Some(audit_log) = self.audit_log.as_mut() {
+                audit_log.record(
+                    source_name,
+                    Some(question_name),
+                    false,
+                    old,
+                    (quality, strength),
+                );
+            }
         }
     }
 
     // Revert the effect of this question on any connected sources
     fn remove_question_effect(&mut self, question_name: &str) {
-        let question = self.questions.get_mut(question_name).unwrap();
-        let mut correct_answers: HashSet<u64> = HashSet::new();
-        for a in &question.correct_answers {
-            correct_answers.insert(a.hash);
-        }
-        for a in &question.answers {
-            let originally_correct_fac = if correct_answers.contains(&a.hash) {
-                1.
-            } else {
-                0.
+        let question = match self.questions.get(question_name) {
+            Some(question) => question,
+            None => return,
+        };
+        for (source_name, correct) in answers_by_source(question) {
+            // an effect the source no longer holds was removed already, e.g.
+            // by expire_evidence, or BELIEVE started the source over since
+            let answer_source = match self.sources.get_mut(source_name) {
+                Some(source) if source.holds_effect(question_name) => source,
+                _ => continue,
             };
-            let answer_source = self.sources.get_mut(&a.source).unwrap();
-            let new_quality = (answer_source.quality * answer_source.strength as f64
-                - question.weight * originally_correct_fac)
-                / (answer_source.strength as f64 - question.weight);
+            let added_discount = answer_source.evidence.discount(question_name);
+            answer_source.evidence.removed(question_name);
+            let (quality, strength, discount) = reverted_effect(
+                &self.config,
+                (
+                    answer_source.quality,
+                    answer_source.strength,
+                    answer_source.discount,
+                ),
+                effect_weights(question.weight, &correct),
+                added_discount,
+            );
             info!(
                 "(revert) Adjusting {}.quality  {:.2} -> {:.2}",
-                answer_source.name, answer_source.quality, new_quality
+                answer_source.name, answer_source.quality, quality
             );
             info!(
                 "(revert) Adjusting {}.strength {:.2} -> {:.2}",
-                answer_source.name,
-                answer_source.strength,
-                answer_source.strength - question.weight
+                answer_source.name, answer_source.strength, strength
             );
-            answer_source.strength -= question.weight;
-            answer_source.quality = new_quality;
+            let old = (answer_source.quality, answer_source.strength);
+            answer_source.quality = quality;
+            answer_source.strength = strength;
+            answer_source.discount = discount;
+            if let Some(audit_log) = self.audit_log.as_mut() {
+                audit_log.record(
+                    source_name,
+                    Some(question_name),
+                    true,
+                    old,
+                    (quality, strength),
+                );
+            }
+        }
+    }
+
+    // The question's clusters as a read sees them: against the source
+    // qualities without the question's own effect, like when it was last
+    // recomputed, so an answer doesn't reinforce itself. Nothing is modified.
+    pub(crate) fn compute_answer_clusters_with_confidence(
+        &self,
+        question_name: &str,
+    ) -> Result<AnswerClustersWithConfidences, ConfidisError> {
+        let qualities = self.qualities_without_effect(question_name);
+        self.with_question_equalifier(question_name, |equalifier, distance_cache| {
+            self.clusters_with_qualities(question_name, equalifier, distance_cache, &qualities)
+        })
+    }
+
+    // The qualities of the question's sources with its effect removed, like
+    // remove_question_effect would leave them. Sources it has no effect on
+    // aren't included.
+    fn qualities_without_effect(&self, question_name: &str) -> HashMap<&str, f64> {
+        let question = match self.questions.get(question_name) {
+            Some(question) => question,
+            None => return HashMap::new(),
+        };
+        // A question touched by a bulk load had its effect removed already
+        let removed = self
+            .bulk_load
+            .as_ref()
+            .is_some_and(|bulk_load| bulk_load.seen.contains(question_name));
+        if removed || question.weight == 0.0 {
+            return HashMap::new();
+        }
+        answers_by_source(question)
+            .into_iter()
+            .filter_map(|(source_name, correct)| {
+                let source = self
+                    .sources
+                    .get(source_name)
+                    .filter(|source| source.holds_effect(question_name))?;
+                let (quality, _, _) = reverted_effect(
+                    &self.config,
+                    (source.quality, source.strength, source.discount),
+                    effect_weights(question.weight, &correct),
+                    source.evidence.discount(question_name),
+                );
+                Some((source_name, quality))
+            })
+            .collect()
+    }
+
+    // Call f with the equalifier comparing the question's answers and its
+    // distance cache: its type's if it declares one, see TYPE, otherwise the
+    // graph's
+    pub(crate) fn with_question_equalifier<R>(
+        &self,
+        question_name: &str,
+        f: impl FnOnce(&dyn Equalifier<A>, &DistanceCache<A>) -> R,
+    ) -> R {
+        let typed = self
+            .questions
+            .get(question_name)
+            .and_then(|question| question.question_type.as_ref())
+            .and_then(|question_type| self.typed_equalifiers.get(question_type));
+        match typed {
+            Some(typed) => f(typed.equalifier.as_ref(), &typed.distance_cache),
+            None => f(self.equalifier.as_ref(), &self.distance_cache),
         }
     }
 
-    fn compute_answer_clusters_with_confidence(
+    // The question's clusters and their confidences under equalifier, with the
+    // current source qualities
+    pub(crate) fn clusters_with_confidence(
+        &self,
+        question_name: &str,
+        equalifier: &dyn Equalifier<A>,
+        distance_cache: &DistanceCache<A>,
+    ) -> Result<AnswerClustersWithConfidences, ConfidisError> {
+        self.clusters_with_qualities(question_name, equalifier, distance_cache, &HashMap::new())
+    }
+
+    // clusters_with_confidence with the qualities of the sources in qualities
+    // replaced
+    fn clusters_with_qualities(
         &self,
         question_name: &str,
-    ) -> Result<AnswerClustersWithConfidences, String> {
-        let question = self.questions.get(question_name).unwrap();
-        let clusters: Vec<Vec<usize>> =
-            compute_clusters(&question.answers, self.equalifier.as_ref()).unwrap();
+        equalifier: &dyn Equalifier<A>,
+        distance_cache: &DistanceCache<A>,
+        qualities: &HashMap<&str, f64>,
+    ) -> Result<AnswerClustersWithConfidences, ConfidisError> {
+        let question = self
+            .questions
+            .get(question_name)
+            .ok_or_else(|| ConfidisError::UnknownQuestion(question_name.to_string()))?;
+        let mut clusters: Vec<Vec<usize>> = if question.excluded_sources.is_empty() {
+            compute_clusters_cached(&question.answers, equalifier, distance_cache)
+                .map_err(ConfidisError::Internal)?
+        } else {
+            // Excluded answers are clustered as if they weren't there, so they
+            // can't join other answers' clusters either
+            let kept: Vec<usize> = (0..question.answers.len())
+                .filter(|&i| !question.is_excluded(&question.answers[i].source))
+                .collect();
+            let answers: Vec<Answer<A>> =
+                kept.iter().map(|&i| question.answers[i].clone()).collect();
+            compute_clusters_cached(&answers, equalifier, distance_cache)
+                .map_err(ConfidisError::Internal)?
+                .into_iter()
+                .map(|members| members.into_iter().map(|i| kept[i]).collect())
+                .collect()
+        };
+        if self.deterministic {
+            order_clusters(&question.answers, &mut clusters);
+        }
         let mut cluster_confidences: Vec<f64> = vec![0.0; clusters.len()];
 
         for (cluster_index, cluster_members) in clusters.iter().enumerate() {
             let sources = &self.sources;
             let incorrect_chance = cluster_members.iter().fold(1.0_f64, |acc, &answer_index| {
-                let answer: &Answer = &question.answers[answer_index];
-                let member_source_quality: f64 = sources[&answer.source].quality;
-                acc * (1.0 - member_source_quality)
+                let answer: &Answer<A> = &question.answers[answer_index];
+                let member_source_quality: f64 = match qualities.get(answer.source.as_str()) {
+                    Some(&quality) => quality,
+                    None => sources
+                        .get(&answer.source)
+                        .map_or(self.config.default_source_quality, |source| source.quality),
+                };
+                acc * (1.0 - member_source_quality).powf(self.answer_weight(answer_index))
             });
             cluster_confidences[cluster_index] = 1.0 - incorrect_chance;
         }
 
         info!("cluster confidences: {:?}", cluster_confidences);
 
-        let correct_cluster: usize = argmaxf(&cluster_confidences);
+        // A question without answers has no clusters
+        let correct_cluster: usize = argmaxf(&cluster_confidences).unwrap_or(0);
 
         Ok(AnswerClustersWithConfidences {
-            clusters: clusters,
-            cluster_confidences: cluster_confidences,
-            correct_cluster: correct_cluster,
+            clusters,
+            cluster_confidences,
+            correct_cluster,
         })
     }
 
-    fn compute_question_answers(&mut self, question_name: &str) -> Result<(), String> {
+    // How much the answer at index, in submission order, counts towards its
+    // cluster's confidence, see GraphConfig::late_answer_after
+    fn answer_weight(&self, answer_index: usize) -> f64 {
+        let late_answer_after = self.config.late_answer_after as usize;
+        if late_answer_after > 0 && answer_index >= late_answer_after {
+            self.config.late_answer_weight
+        } else {
+            1.0
+        }
+    }
+
+    // The most likely answer to a question, its confidence from the current
+    // source qualities and the sources that support it, None for a question
+    // without answers. Neither the question nor its sources are modified.
+    fn best_answer(&self, question_name: &str) -> Result<Option<AnswerResult<A>>, ConfidisError> {
+        let question = match self.questions.get(question_name) {
+            Some(question) if !question.answers.is_empty() => question,
+            _ => return Ok(None),
+        };
         let AnswerClustersWithConfidences {
             clusters,
             cluster_confidences,
             correct_cluster,
-        } = self
-            .compute_answer_clusters_with_confidence(question_name)
-            .unwrap();
-        let mut question = self.questions.get_mut(question_name).unwrap();
-
-        // TODO sort by best source first
-        question.correct_answers = clusters[correct_cluster]
+        } = self.compute_answer_clusters_with_confidence(question_name)?;
+        let answer_index = match clusters.get(correct_cluster).and_then(|c| c.first()) {
+            Some(&answer_index) => answer_index,
+            None => return Ok(None),
+        };
+        let mut sources: Vec<String> = clusters[correct_cluster]
             .iter()
-            .map(|answer_index| question.answers[*answer_index].clone())
+            .map(|&i| question.answers[i].source.clone())
             .collect();
+        sources.sort();
+        sources.dedup();
+        Ok(Some(AnswerResult {
+            answer: Some(question.answers[answer_index].content.clone()),
+            confidence: self.decayed_confidence(question, cluster_confidences[correct_cluster]),
+            sources,
+            cluster_count: clusters.len(),
+            insufficient_evidence: false,
+            unknown: false,
+        }))
+    }
+
+    // A question's confidence as reported, halved every confidence_half_life
+    // seconds once its latest answer is older than confidence_decay_after.
+    // Questions whose answers are of unknown age, from snapshots written before
+    // answers were timed, don't decay.
+    fn decayed_confidence(&self, question: &Question<A>, confidence: f64) -> f64 {
+        let decay_after = self.config.confidence_decay_after;
+        if decay_after <= 0.0 || question.answered_at == 0 {
+            return confidence;
+        }
+        let age = self.now().saturating_sub(question.answered_at) as f64 / 1000.;
+        if age <= decay_after {
+            return confidence;
+        }
+        confidence * 0.5_f64.powf((age - decay_after) / self.config.confidence_half_life)
+    }
+
+    // The current unix time in ms, or the time of the journal entry being
+    // replayed, so that a recovered graph's answers are as old as they were.
+    // 0 (unknown) in deterministic mode, whose output can't depend on the clock.
+    pub(crate) fn now(&self) -> u64 {
+        match self.replay_time {
+            Some(replay_time) => replay_time,
+            None if self.deterministic => 0,
+            None => now_millis(),
+        }
+    }
+
+    pub(crate) fn set_replay_time(&mut self, replay_time: Option<u64>) {
+        self.replay_time = replay_time;
+    }
+
+    // Every answer to a question with the source qualities, clusters and cluster
+    // confidences that best_answer decides from
+    fn provenance(&self, question_name: &str) -> Result<Provenance<A>, ConfidisError> {
+        let AnswerClustersWithConfidences {
+            clusters,
+            cluster_confidences,
+            correct_cluster,
+        } = self.compute_answer_clusters_with_confidence(question_name)?;
+        let question = self
+            .questions
+            .get(question_name)
+            .ok_or_else(|| ConfidisError::UnknownQuestion(question_name.to_string()))?;
+        let mut evidence = Vec::with_capacity(question.answers.len());
+        for (cluster, members) in clusters.iter().enumerate() {
+            for &answer_index in members {
+                let answer = &question.answers[answer_index];
+                let SourceStats { quality, strength } = self.source_stats(&answer.source);
+                evidence.push(Evidence {
+                    content: answer.content.clone(),
+                    source: answer.source.clone(),
+                    source_quality: quality,
+                    source_strength: strength,
+                    cluster,
+                    supports: cluster == correct_cluster,
+                });
+            }
+        }
+        let answer = clusters
+            .get(correct_cluster)
+            .and_then(|members| members.first())
+            .map(|&answer_index| question.answers[answer_index].content.clone());
+        Ok(Provenance {
+            question: question_name.to_string(),
+            confidence: answer.as_ref().map_or(0.0, |_| {
+                self.decayed_confidence(question, cluster_confidences[correct_cluster])
+            }),
+            answer,
+            cluster_confidences,
+            evidence,
+        })
+    }
+
+    fn compute_question_answers(&mut self, question_name: &str) -> Result<(), ConfidisError> {
+        self.recompute_count += 1;
+        let honeypot_matches = self
+            .questions
+            .get(question_name)
+            .and_then(|question| question.honeypot_matches(self));
+        if let Some(matches) = honeypot_matches {
+            let weight = self.config.honeypot_weight;
+            if let Some(question) = self.questions.get_mut(question_name).map(Arc::make_mut) {
+                question.correct_answers = matches;
+                question.confidence = 1.0;
+                question.weight = weight;
+            }
+            return Ok(());
+        }
+        // The question's effect was removed, the current qualities are the
+        // ones to decide from
+        let computed =
+            self.with_question_equalifier(question_name, |equalifier, distance_cache| {
+                self.clusters_with_confidence(question_name, equalifier, distance_cache)
+            })?;
+        self.compare_with_shadow(question_name, &computed)?;
+        let AnswerClustersWithConfidences {
+            clusters,
+            cluster_confidences,
+            correct_cluster,
+        } = computed;
+        let question = self
+            .questions
+            .get_mut(question_name)
+            .map(Arc::make_mut)
+            .ok_or_else(|| ConfidisError::UnknownQuestion(question_name.to_string()))?;
+        let correct_answers = match clusters.get(correct_cluster) {
+            Some(correct_answers) => correct_answers,
+            // Every answer is from an excluded source
+            None if !question.answers.is_empty() => {
+                question.correct_answers.clear();
+                question.confidence = 0.0;
+                question.weight = 0.0;
+                return Ok(());
+            }
+            None => {
+                return Err(ConfidisError::Internal(format!(
+                    "{} has no answer clusters",
+                    question_name
+                )))
+            }
+        };
+
+        // TODO sort by best source first
+        question.correct_answers = correct_answers.clone();
         info!(
             "Adjusting {}.confidence {:.2} -> {:.2}",
             question.name, question.confidence, cluster_confidences[correct_cluster]
         );
         question.confidence = cluster_confidences[correct_cluster];
         let new_weight = if question.correct_answers.len() > 1 {
-            // 1.0
-            -1.0 * (1.0 - question.confidence).log(self.log_weight_factor)
+            // A confidence of 1 (e.g. from a source with a quality of 1) would
+            // have an infinite weight that can't be removed again
+            -(1.0 - question.confidence)
+                .max(f64::EPSILON)
+                .log(self.config.log_weight_factor)
         } else {
             0.0
         };
@@ -239,333 +1072,3749 @@ impl Graph {
             question.name, question.weight, new_weight
         );
         question.weight = new_weight;
-        Ok(())
-    }
-
-    pub fn create_source_if_not_exists(&mut self, source_name: &str) -> () {
-        if !self.sources.contains_key(source_name) {
-            self.sources.insert(
-                source_name.to_string(),
-                Source {
-                    name: source_name.to_string(),
-                    quality: self.default_source_quality,
-                    strength: self.initial_source_strength,
-                },
+        if let Some(answer_history) = self.answer_history.as_mut() {
+            let answer = &question.answers[question.correct_answers[0]];
+            answer_history.record(
+                question_name,
+                answer.hash,
+                &answer.content,
+                question.confidence,
             );
         }
+        Ok(())
     }
 
-    pub fn create_question_if_not_exists(&mut self, question_name: &str) -> () {
-        if !self.questions.contains_key(question_name) {
-            self.questions.insert(
-                question_name.to_string(),
-                Question {
-                    name: question_name.to_string(),
-                    ..Default::default()
-                },
-            );
-        }
+    // DEBUG CLUSTERS <question>, every cluster of the question's answers with
+    // each member's distance to the cluster's seed. Unlike get_answer this also
+    // works during a bulk load, from the answers loaded so far.
+    pub fn debug_clusters(
+        &self,
+        question: &QuestionId,
+    ) -> Result<Vec<ClusterDebug<A>>, ConfidisError> {
+        self.cluster_debug(question)
     }
 
-    pub fn execute_command(&mut self, cmd: &Command) -> Result<CommandResponse, String> {
-        match cmd.cmd {
-            CommandType::Set => {
-                let source_name = cmd.source.as_ref().unwrap();
-                let question_name = cmd.question.as_ref().unwrap();
+    fn cluster_debug(&self, question_name: &str) -> Result<Vec<ClusterDebug<A>>, ConfidisError> {
+        let AnswerClustersWithConfidences {
+            clusters,
+            cluster_confidences,
+            correct_cluster,
+        } = self.compute_answer_clusters_with_confidence(question_name)?;
+        let question = self
+            .questions
+            .get(question_name)
+            .ok_or_else(|| ConfidisError::UnknownQuestion(question_name.to_string()))?;
+        Ok(clusters
+            .iter()
+            .enumerate()
+            .filter(|(_, members)| !members.is_empty())
+            .map(|(cluster, members)| {
+                let seed = &question.answers[members[0]];
+                ClusterDebug {
+                    confidence: cluster_confidences[cluster],
+                    correct: cluster == correct_cluster,
+                    members: members
+                        .iter()
+                        .map(|&answer_index| {
+                            let answer = &question.answers[answer_index];
+                            ClusterMember {
+                                answer: answer.content.clone(),
+                                source: answer.source.clone(),
+                                distance_to_seed: if answer_index == members[0] {
+                                    0.0
+                                } else {
+                                    self.answer_distance(question_name, seed, answer)
+                                },
+                            }
+                        })
+                        .collect(),
+                }
+            })
+            .collect())
+    }
 
-                self.create_source_if_not_exists(source_name);
-                self.create_question_if_not_exists(question_name);
+    // COMPARE SOURCES, how often each pair of the sources agree: of the
+    // questions both answered, how many of their answers are in the same
+    // cluster. Pairs are in the order the sources are given and a source's
+    // latest answer to a question is the one compared. Finds collusion or
+    // reviewers that are worth pairing.
+    pub fn compare_sources(
+        &self,
+        sources: &[SourceId],
+    ) -> Result<Vec<SourceAgreement>, ConfidisError> {
+        let names: Vec<&str> = sources.iter().map(SourceId::as_str).collect();
+        self.source_agreement(&names)
+    }
 
-                let answer = Answer::new(cmd.answer.as_ref().unwrap().clone(), source_name.clone());
+    // The distance between two answers to the question under its comparison
+    // method, below 1 for answers that match
+    pub(crate) fn answer_distance(&self, question_name: &str, a: &Answer<A>, b: &Answer<A>) -> f64 {
+        self.with_question_equalifier(question_name, |equalifier, distance_cache| {
+            distance_cache.get_distance(a, b, equalifier)
+        })
+    }
 
-                self.remove_question_effect(question_name);
-                {
-                    let question = self.questions.get_mut(question_name).unwrap();
-                    question.answers.push(answer);
+    fn source_agreement(&self, sources: &[&str]) -> Result<Vec<SourceAgreement>, ConfidisError> {
+        if self.strict {
+            if let Some(unknown) = sources.iter().find(|s| !self.sources.contains_key(**s)) {
+                return Err(ConfidisError::UnknownSource(unknown.to_string()));
+            }
+        }
+        let n = sources.len();
+        // counts of the pair (i, j) at i * n + j, for i < j
+        let mut co_answered = vec![0; n * n];
+        let mut agreed = vec![0; n * n];
+        for question in self.questions.values() {
+            let mut latest: Vec<Option<usize>> = vec![None; n];
+            for (answer_index, answer) in question.answers.iter().enumerate() {
+                if let Some(i) = sources.iter().position(|s| *s == answer.source) {
+                    latest[i] = Some(answer_index);
                 }
-                self.compute_question_answers(question_name)
-                    .expect("error computing question answer");
-                self.add_question_effect(question_name);
-
-                Ok(CommandResponse {
-                    cmd: CommandType::Set,
-                    ..Default::default()
-                })
             }
-            CommandType::GetAnswer => {
-                let question_name = cmd.question.as_ref().unwrap();
-                self.create_question_if_not_exists(question_name);
-
-                self.remove_question_effect(question_name);
-                self.compute_question_answers(question_name)
-                    .expect("error computing question answer");
-                self.add_question_effect(question_name);
-
-                let question: &Question = self.questions.get(question_name).unwrap();
-                let default_answer: Answer = Answer::new(String::from("None"), String::from(""));
-                let correct_answer = question
-                    .correct_answers
-                    .first()
-                    .or_else(|| Some(&default_answer))
-                    .unwrap();
-                Ok(CommandResponse {
-                    cmd: CommandType::GetAnswer,
-                    confidence: Some(question.confidence),
-                    answer: Some(correct_answer.content.clone()),
-                    ..Default::default()
-                })
+            if latest.iter().flatten().count() < 2 {
+                continue;
             }
-            CommandType::GetSource => {
-                let source_name = cmd.source.as_ref().unwrap();
-                self.create_source_if_not_exists(source_name);
-
-                let source: &Source = self.sources.get(source_name).unwrap();
-
-                Ok(CommandResponse {
-                    cmd: CommandType::GetSource,
-                    quality: Some(source.quality),
-                    ..Default::default()
+            let clusters = self
+                .with_question_equalifier(&question.name, |equalifier, distance_cache| {
+                    compute_clusters_cached(&question.answers, equalifier, distance_cache)
                 })
+                .map_err(ConfidisError::Internal)?;
+            let mut cluster_of = vec![0; question.answers.len()];
+            for (cluster, members) in clusters.iter().enumerate() {
+                for &answer_index in members {
+                    cluster_of[answer_index] = cluster;
+                }
             }
-            CommandType::Believe => {
-                let source_name = cmd.source.as_ref().unwrap();
-                self.create_source_if_not_exists(source_name);
+            for i in 0..n {
+                for j in i + 1..n {
+                    if let (Some(a), Some(b)) = (latest[i], latest[j]) {
+                        co_answered[i * n + j] += 1;
+                        if cluster_of[a] == cluster_of[b] {
+                            agreed[i * n + j] += 1;
+                        }
+                    }
+                }
+            }
+        }
+        let mut pairs = Vec::new();
+        for i in 0..n {
+            for j in i + 1..n {
+                let (co_answered, agreed) = (co_answered[i * n + j], agreed[i * n + j]);
+                pairs.push(SourceAgreement {
+                    source1: sources[i].to_string(),
+                    source2: sources[j].to_string(),
+                    co_answered,
+                    agreed,
+                    agreement_rate: if co_answered > 0 {
+                        Some(agreed as f64 / co_answered as f64)
+                    } else {
+                        None
+                    },
+                });
+            }
+        }
+        Ok(pairs)
+    }
 
-                let mut source = self.sources.get_mut(source_name).unwrap();
+    // Totals, source quality and question confidence aggregates, see STATS.
+    // Confidences are the ones stored when each question was last recomputed,
+    // so this doesn't cluster any answers.
+    pub fn graph_stats(&self) -> GraphStats {
+        let mut stats = GraphStats {
+            source_count: self.sources.len(),
+            question_count: self.questions.len(),
+            confidence_histogram: vec![0; CONFIDENCE_BUCKETS],
+            rate_limited_answer_count: self.rate_limiter.violations().values().sum(),
+            ..Default::default()
+        };
+        for question in self.questions.values() {
+            stats.answer_count += question.answers.len();
+            if question.answers.is_empty() {
+                stats.unanswered_question_count += 1;
+                continue;
+            }
+            let bucket = (question.confidence.clamp(0.0, 1.0) * CONFIDENCE_BUCKETS as f64) as usize;
+            stats.confidence_histogram[bucket.min(CONFIDENCE_BUCKETS - 1)] += 1;
+        }
+        let mut qualities: Vec<f64> = self.sources.values().map(|s| s.quality).collect();
+        if !qualities.is_empty() {
+            qualities.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            let mid = qualities.len() / 2;
+            stats.mean_source_quality =
+                Some(qualities.iter().sum::<f64>() / qualities.len() as f64);
+            stats.median_source_quality = Some(if qualities.len().is_multiple_of(2) {
+                (qualities[mid - 1] + qualities[mid]) / 2.0
+            } else {
+                qualities[mid]
+            });
+        }
+        stats
+    }
 
-                source.quality = self.quality_of_believed_sources;
-                source.strength = self.maximum_strength;
+    // The answers each source had rejected by the rate limit, see
+    // rate_limit.rs
+    pub fn rate_limit_violations(&self) -> &BTreeMap<String, u64> {
+        self.rate_limiter.violations()
+    }
 
-                Ok(CommandResponse {
-                    cmd: CommandType::Believe,
-                    ..Default::default()
-                })
-            }
-            CommandType::Configure => {
-                let config_key = cmd.config_key.as_ref().unwrap();
-                let config_val = cmd.config_val.as_ref().unwrap();
-                let params: HashMap<&str, &str> = config_val
-                    .split_whitespace()
-                    .filter(|&s| s.contains("="))
-                    .collect::<Vec<&str>>()
-                    .iter()
-                    .fold(HashMap::new(), |mut acc, s| {
-                        let mut components = s.split("=");
-                        acc.insert(components.next().unwrap(), components.next().unwrap());
-                        acc
-                    });
+    // Questions recomputed since this graph was created, a measure of the work
+    // done by SET, set_many and finish_bulk_load
+    pub fn recompute_count(&self) -> u64 {
+        self.recompute_count
+    }
 
-                match config_key.as_str() {
-                    "comparison_method" => match config_val.split_whitespace().next().unwrap() {
-                        "exact" => self.equalifier = Box::new(ExactEqualifier {}),
-                        "numeric" => {
-                            let max_distance = params
-                                .get("max_distance")
-                                .and_then(|d| d.parse::<f64>().ok());
+    pub fn has_question(&self, question_name: &str) -> bool {
+        self.questions.contains_key(question_name)
+    }
 
-                            if !max_distance.is_some() {
-                                return Err("max_distance must be specified".into());
-                            }
+    pub fn source(&self, source_name: &str) -> Option<&Source> {
+        self.sources.get(source_name)
+    }
 
-                            self.equalifier = Box::new(NumericEqualifier {
-                                max_distance: max_distance.unwrap(),
-                            })
-                        }
-                        "numeric_vec" => {
-                            let allowed_difference = params
-                                .get("allowed_difference")
-                                .and_then(|s| s.parse::<f64>().ok());
+    // Every source in the graph, ordered by name in deterministic mode and in no
+    // particular order otherwise
+    pub fn sources(&self) -> impl Iterator<Item = SourceView<'_>> {
+        let mut sources: Vec<&Source> = self.sources.values().collect();
+        if self.deterministic {
+            sources.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        sources.into_iter().map(|source| SourceView {
+            name: &source.name,
+            quality: source.quality,
+            strength: source.strength,
+        })
+    }
 
-                            let vec_length = params
-                                .get("vec_length")
-                                .and_then(|s| s.parse::<usize>().ok());
+    // Every question in the graph, ordered like sources. The confidence is the
+    // one computed when the question last changed, get_answer recomputes it from
+    // the current source qualities.
+    pub fn questions(&self) -> impl Iterator<Item = QuestionView<'_>> {
+        let mut questions: Vec<&Arc<Question<A>>> = self.questions.values().collect();
+        if self.deterministic {
+            questions.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        questions.into_iter().map(|question| QuestionView {
+            name: &question.name,
+            confidence: question.confidence,
+            weight: question.weight,
+            answer_count: question.answers.len(),
+        })
+    }
 
-                            let diff_fn: Option<VecDistAlgo> =
-                                params.get("diff_fn").and_then(|s| VecDistAlgo::from(s));
+    pub fn question(&self, question_name: &str) -> Option<&Question<A>> {
+        self.questions.get(question_name).map(Arc::as_ref)
+    }
 
-                            if !allowed_difference.is_some() {
-                                return Err("allowed_difference must be specified (try 1.0)".into());
-                            }
-                            if !vec_length.is_some() {
-                                return Err(
-                                    "vec_length must be specified (vector lengths must be fixed)"
-                                        .into(),
-                                );
-                            }
-                            if !diff_fn.is_some() {
-                                return Err(
-                                    "diff_fn must be specified (l1, l2, percent_not_equal, iou)"
-                                        .into(),
-                                );
-                            }
+    pub fn insert_source(&mut self, source: Source) {
+        self.sources.insert(source.name.clone(), source);
+    }
 
-                            self.equalifier = Box::new(NumericVecEqualifier {
-                                allowed_difference: allowed_difference.unwrap(),
-                                vec_length: vec_length.unwrap(),
-                                diff_fn: diff_fn.unwrap(),
-                            })
-                        }
-                        &_ => {
-                            return Err(format!("unknown comparison method \"{}\". Try exact, numeric, or numeric_vec", config_key));
-                        }
-                    },
-                    "default_source_quality" => {
-                        if let Ok(v) = (&config_val).parse() {
-                            self.default_source_quality = v;
-                        }
-                    }
-                    "log_weight_factor" => {
-                        if let Ok(v) = (&config_val).parse() {
-                            self.log_weight_factor = v;
-                        }
-                    }
-                    "initial_source_strength" => {
-                        if let Ok(v) = (&config_val).parse() {
-                            self.initial_source_strength = v;
-                        }
-                    }
-                    "maximum_strength" => {
-                        if let Ok(v) = (&config_val).parse() {
-                            self.maximum_strength = v;
+    pub fn insert_question(&mut self, question: Question<A>) {
+        self.questions
+            .insert(question.name.clone(), Arc::new(question));
+    }
+
+    // Remove a question from memory without reverting its effect on its sources
+    pub fn take_question(&mut self, question_name: &str) -> Option<Question<A>> {
+        self.questions
+            .remove(question_name)
+            .map(|question| Arc::try_unwrap(question).unwrap_or_else(|q| (*q).clone()))
+    }
+
+    pub fn create_source_if_not_exists(&mut self, source_name: &str) {
+        if !self.sources.contains_key(source_name) {
+            self.sources.insert(
+                source_name.to_string(),
+                Source {
+                    name: source_name.to_string(),
+                    quality: self.config.default_source_quality,
+                    strength: self.config.initial_source_strength,
+                    evidence: EvidenceLedger::default(),
+                    discount: 0.0,
+                    complete_ledger: true,
+                },
+            );
+        }
+    }
+
+    // Set a source's quality and strength outright, see calibrate
+    pub(crate) fn seed_source(&mut self, source_name: &str, quality: f64, strength: f64) {
+        self.undo.clear();
+        self.create_source_if_not_exists(source_name);
+        if let Some(source) = self.sources.get_mut(source_name) {
+            let old = (source.quality, source.strength);
+            source.quality = quality;
+            source.strength = strength;
+            source.evidence = EvidenceLedger::default();
+            source.discount = 0.0;
+            source.complete_ledger = true;
+            if let Some(audit_log) = self.audit_log.as_mut() {
+                audit_log.record(source_name, None, false, old, (quality, strength));
+            }
+        }
+    }
+
+    // Set a source's quality, strength and discount, keeping the evidence of
+    // its questions, see ShardedGraph::merge_sources
+    pub(crate) fn merge_source(
+        &mut self,
+        source_name: &str,
+        (quality, strength, discount): (f64, f64, f64),
+    ) {
+        self.undo.clear();
+        self.create_source_if_not_exists(source_name);
+        if let Some(source) = self.sources.get_mut(source_name) {
+            source.quality = quality;
+            source.strength = strength;
+            source.discount = discount;
+        }
+    }
+
+    pub fn create_question_if_not_exists(&mut self, question_name: &str) {
+        if !self.questions.contains_key(question_name) {
+            self.questions.insert(
+                question_name.to_string(),
+                Arc::new(Question {
+                    name: question_name.to_string(),
+                    ..Default::default()
+                }),
+            );
+        }
+    }
+
+    // Add answers with any content, each entry is (question, answer, source).
+    // Like set_many every affected question is recomputed once, but nothing is
+    // journaled, use set_many for String answers.
+    pub fn add_answers(
+        &mut self,
+        entries: Vec<(&QuestionId, A, &SourceId)>,
+    ) -> Result<(), ConfidisError> {
+        self.insert_answers(
+            entries
+                .into_iter()
+                .map(|(question, answer, source)| (question.as_str(), answer, source.as_str()))
+                .collect(),
+        )
+    }
+
+    fn insert_answers(&mut self, entries: Vec<(&str, A, &str)>) -> Result<(), ConfidisError> {
+        let entries = self.answers_to_insert(entries);
+        let replace = self.config.duplicate_answers == DuplicateAnswers::Replace;
+        let now = self.now();
+        self.expire_evidence_of(
+            entries.iter().map(|entry| entry.0),
+            entries.iter().map(|entry| entry.2),
+        );
+        let mut affected_questions: Vec<&str> = Vec::new();
+        let mut seen_questions: HashSet<&str> = HashSet::new();
+        for (question_name, _, source_name) in &entries {
+            self.create_source_if_not_exists(source_name);
+            if seen_questions.insert(question_name) {
+                self.create_question_if_not_exists(question_name);
+                match self.bulk_load.as_mut() {
+                    Some(bulk_load) => {
+                        // During a bulk load the effect is removed the first time
+                        // a question is touched and restored in finish_bulk_load
+                        if bulk_load.seen.insert(question_name.to_string()) {
+                            bulk_load.questions.push(question_name.to_string());
+                            self.remove_question_effect(question_name);
                         }
                     }
-                    &_ => {
-                        return Err(format!("Unknown configuration key: \"{}\"", config_key));
+                    None => {
+                        self.remove_question_effect(question_name);
+                        affected_questions.push(question_name);
                     }
                 }
+            }
+        }
 
-                Ok(CommandResponse {
-                    cmd: CommandType::Configure,
-                    ..Default::default()
-                })
+        for (question_name, answer_content, source_name) in entries {
+            if let Some(question) = self.questions.get_mut(question_name).map(Arc::make_mut) {
+                if replace {
+                    // The question's effect was removed above, its answers are
+                    // recomputed with the source's earlier answers gone
+                    let count = question.answers.len();
+                    question
+                        .answers
+                        .retain(|answer| answer.source != source_name);
+                    if question.answers.len() != count {
+                        question.correct_answers.clear();
+                    }
+                }
+                question.answers.push(Answer::with_hasher(
+                    answer_content,
+                    source_name.to_string(),
+                    self.config.answer_hash,
+                ));
+                question.answered_at = now;
             }
-            CommandType::TestEquality => {
-                let answer1 =
-                    Answer::new(cmd.answer1.as_ref().unwrap().into(), String::from("None"));
-                let answer2 =
-                    Answer::new(cmd.answer2.as_ref().unwrap().into(), String::from("None"));
-
-                Ok(CommandResponse {
-                    cmd: CommandType::TestEquality,
-                    distance: Some(self.equalifier.get_distance(&answer1, &answer2)),
-                    ..Default::default()
-                })
+            if let Some(source) = self.sources.get_mut(source_name) {
+                source.evidence.renew(question_name);
             }
-            CommandType::GetAnswers => {
-                let mut answers = Vec::new();
+        }
 
-                let analysis = self
-                    .compute_answer_clusters_with_confidence(cmd.question.as_ref().unwrap())
-                    .unwrap();
+        for question_name in affected_questions {
+            self.compute_question_answers(question_name)?;
+            self.add_question_effect(question_name);
+        }
 
-                let question = self.questions.get(cmd.question.as_ref().unwrap()).unwrap();
+        Ok(())
+    }
 
-                let mut answer_hashes_added = HashSet::new();
+    // Ignore the source's answers to the question, those given so far and
+    // later ones, without removing them or changing the source elsewhere
+    fn exclude_source_from_question(
+        &mut self,
+        question_name: &str,
+        source_name: &str,
+    ) -> Result<(), ConfidisError> {
+        if self
+            .questions
+            .get(question_name)
+            .is_some_and(|question| question.is_excluded(source_name))
+        {
+            return Ok(());
+        }
+        self.update_question(question_name, |question| {
+            let index = question
+                .excluded_sources
+                .binary_search_by(|excluded| excluded.as_str().cmp(source_name))
+                .unwrap_or_else(|index| index);
+            question
+                .excluded_sources
+                .insert(index, source_name.to_string());
+        })
+    }
 
-                for cluster_index in 0..analysis.clusters.len() {
-                    for answer_index in analysis.clusters[cluster_index].iter() {
-                        let answer = &question.answers[*answer_index];
-                        if answer_hashes_added.contains(&answer.hash) {
-                            continue;
-                        };
-                        answer_hashes_added.insert(answer.hash);
-                        answers.push(AnswerConfidencePair {
-                            answer: answer.content.clone(),
-                            confidence: analysis.cluster_confidences[cluster_index],
-                        });
-                    }
+    // HONEYPOT <question> <answer>, the question's answer is known: answers
+    // matching it are correct, every other answer is wrong, and the question
+    // changes its sources' qualities with honeypot_weight
+    fn mark_honeypot(&mut self, question_name: &str, answer: A) -> Result<(), ConfidisError> {
+        self.update_question(question_name, |question| question.honeypot = Some(answer))
+    }
+
+    // SCHEMA <question> <schema>, later answers to the question must follow
+    // schema, None removes it. Returns the previous schema.
+    fn replace_schema(
+        &mut self,
+        question_name: &str,
+        schema: Option<AnswerSchema>,
+    ) -> Option<AnswerSchema> {
+        self.create_question_if_not_exists(question_name);
+        let question = Arc::make_mut(self.questions.get_mut(question_name)?);
+        std::mem::replace(&mut question.schema, schema)
+    }
+
+    // TYPE <question> <type>, the question's answers are compared with the
+    // type's comparison method and later answers must be of the type, None
+    // compares them with the graph's again. The question is recomputed.
+    // Returns the previous type.
+    fn replace_question_type(
+        &mut self,
+        question_name: &str,
+        question_type: Option<QuestionType>,
+    ) -> Result<Option<QuestionType>, ConfidisError> {
+        let mut previous = None;
+        self.update_question(question_name, |question| {
+            previous = std::mem::replace(&mut question.question_type, question_type)
+        })?;
+        Ok(previous)
+    }
+
+    // Change how a question is judged, creating it if needed. Like
+    // insert_answers the question's effect is removed as it was added, before
+    // the change, and added back recomputed.
+    fn update_question<F>(&mut self, question_name: &str, update: F) -> Result<(), ConfidisError>
+    where
+        F: FnOnce(&mut Question<A>),
+    {
+        self.create_question_if_not_exists(question_name);
+        self.expire_evidence_of([question_name], []);
+        let recompute = match self.bulk_load.as_mut() {
+            Some(bulk_load) => {
+                if bulk_load.seen.insert(question_name.to_string()) {
+                    bulk_load.questions.push(question_name.to_string());
+                    self.remove_question_effect(question_name);
                 }
+                false
+            }
+            None => {
+                self.remove_question_effect(question_name);
+                true
+            }
+        };
+        if let Some(question) = self.questions.get_mut(question_name).map(Arc::make_mut) {
+            update(question);
+        }
+        if recompute && !self.questions[question_name].answers.is_empty() {
+            self.compute_question_answers(question_name)?;
+            self.add_question_effect(question_name);
+        }
+        Ok(())
+    }
 
-                Ok(CommandResponse {
-                    cmd: CommandType::GetAnswers,
-                    answers: Some(answers),
-                    ..Default::default()
-                })
+    // Re-estimate every source's quality from scratch, e.g. after the
+    // comparison method changed and past judgments no longer hold. Every
+    // source starts over at default_source_quality and initial_source_strength
+    // (believed and calibrated sources too) and the questions are recomputed
+    // in the order they were last answered, each changing its sources like a
+    // new question would. The resets aren't in the audit log. REBUILD journals
+    // this and remembers it for UNDO, ShardedGraph rebuilds every shard with it.
+    pub(crate) fn rebuild_qualities(&mut self) -> Result<(), ConfidisError> {
+        if self.is_bulk_loading() {
+            return Err(ConfidisError::BulkLoadInProgress);
+        }
+        for source in self.sources.values_mut() {
+            source.quality = self.config.default_source_quality;
+            source.strength = self.config.initial_source_strength;
+            source.evidence = EvidenceLedger::default();
+            source.discount = 0.0;
+            source.complete_ledger = true;
+        }
+        self.distance_cache.invalidate();
+        let mut order: Vec<(u64, String)> = self
+            .questions
+            .values()
+            .filter(|question| !question.answers.is_empty())
+            .map(|question| (question.answered_at, question.name.clone()))
+            .collect();
+        order.sort();
+        for (_, question_name) in order {
+            self.compute_question_answers(&question_name)?;
+            self.add_question_effect(&question_name);
+        }
+        Ok(())
+    }
+
+    fn undo_depth(&self) -> usize {
+        self.config.undo_depth as usize
+    }
+
+    // The questions and sources a mutation of question_names by source_names
+    // is about to change, including every source that answered the questions,
+    // and the configuration if it changes that
+    fn undo_entry(
+        &self,
+        description: String,
+        question_names: &[&str],
+        source_names: &[&str],
+        config: bool,
+    ) -> UndoEntry<A> {
+        let question_names: BTreeSet<&str> = question_names.iter().copied().collect();
+        let mut source_names: BTreeSet<&str> = source_names.iter().copied().collect();
+        for question in question_names
+            .iter()
+            .filter_map(|&question_name| self.questions.get(question_name))
+        {
+            source_names.extend(question.answers.iter().map(|answer| answer.source.as_str()));
+        }
+        UndoEntry {
+            description,
+            questions: question_names
+                .into_iter()
+                .map(|name| (name.to_string(), self.questions.get(name).cloned()))
+                .collect(),
+            sources: source_names
+                .into_iter()
+                .map(|name| (name.to_string(), self.sources.get(name).cloned()))
+                .collect(),
+            config: config.then(|| {
+                (
+                    self.config.clone(),
+                    self.equalifier.clone(),
+                    self.shadow.clone(),
+                )
+            }),
+        }
+    }
+
+    // Remember the state before a mutation that succeeded, for UNDO
+    fn remember_undo(&mut self, entry: UndoEntry<A>) {
+        match self.undo_depth() {
+            0 => self.undo.clear(),
+            depth => self.undo.push(entry, depth),
+        }
+    }
+
+    // Forget what UNDO and REDO could take back and redo. Replicas following
+    // the journal forget it too, so a replayed UNDO takes back the same thing.
+    pub(crate) fn clear_undo(&mut self) {
+        self.undo.clear();
+        if let Some(journal) = self.journal.as_mut() {
+            journal.undo_cleared();
+        }
+    }
+
+    // Hash answers with hasher for CONFIGURE answer_hash. Every stored answer
+    // is rehashed. Returns the previous hash function.
+    pub(crate) fn set_answer_hasher(&mut self, hasher: AnswerHasher) -> AnswerHasher {
+        let previous = std::mem::replace(&mut self.config.answer_hash, hasher);
+        if previous != hasher {
+            self.rehash_answers();
+        }
+        previous
+    }
+
+    // Hash every answer with the answer_hash, distances cached by the
+    // previous hashes are dropped
+    fn rehash_answers(&mut self) {
+        let hasher = self.config.answer_hash;
+        for question in self.questions.values_mut() {
+            Arc::make_mut(question).rehash(hasher);
+        }
+        self.distance_cache.invalidate();
+        self.typed_equalifiers.clear();
+        if let Some(shadow) = self.shadow.as_mut() {
+            shadow.invalidate_distances();
+        }
+    }
+
+    // Put back the state entry holds, returning the state it replaced
+    fn swap_undo_entry(&mut self, entry: UndoEntry<A>) -> UndoEntry<A> {
+        let mut replaced = UndoEntry {
+            description: entry.description,
+            questions: Vec::with_capacity(entry.questions.len()),
+            sources: Vec::with_capacity(entry.sources.len()),
+            config: None,
+        };
+        for (name, question) in entry.questions {
+            let current = match question {
+                Some(question) => self.questions.insert(name.clone(), question),
+                None => self.questions.remove(&name),
+            };
+            replaced.questions.push((name, current));
+        }
+        for (name, source) in entry.sources {
+            let current = match source {
+                Some(source) => self.sources.insert(name.clone(), source),
+                None => self.sources.remove(&name),
+            };
+            replaced.sources.push((name, current));
+        }
+        if let Some((config, equalifier, shadow)) = entry.config {
+            let rehash = config.answer_hash != self.config.answer_hash;
+            replaced.config = Some((
+                std::mem::replace(&mut self.config, config),
+                std::mem::replace(&mut self.equalifier, equalifier),
+                std::mem::replace(&mut self.shadow, shadow),
+            ));
+            self.distance_cache.invalidate();
+            if rehash {
+                self.rehash_answers();
             }
-            _ => Err("Not implemented or invalid command".into()),
         }
+        replaced
     }
-}
 
-#[test]
-fn test_graph_1() {
-    pretty_env_logger::init();
-    let commands: Vec<Command> = "\
-    SET q1 a FROM s1
-    SET q1 a FROM s2
-    SET q1 a FROM s3
-    SET q1 w FROM s4
+    // UNDO, take back up to count mutations, returning them most recent first
+    fn undo_mutations(&mut self, count: usize) -> Result<Vec<String>, ConfidisError> {
+        if self.is_bulk_loading() {
+            return Err(ConfidisError::BulkLoadInProgress);
+        }
+        let mut undone = Vec::new();
+        while undone.len() < count {
+            let entry = match self.undo.pop_undo() {
+                Some(entry) => entry,
+                None => break,
+            };
+            undone.push(entry.description.clone());
+            let replaced = self.swap_undo_entry(entry);
+            self.undo.push_undone(replaced);
+        }
+        Ok(undone)
+    }
 
-    SET q2 b FROM s1
-    SET q2 c FROM s2
-    SET q2 b FROM s3
-    SET q2 w FROM s4
+    // REDO, apply up to count undone mutations again, returning them in the
+    // order they're redone
+    fn redo_mutations(&mut self, count: usize) -> Result<Vec<String>, ConfidisError> {
+        if self.is_bulk_loading() {
+            return Err(ConfidisError::BulkLoadInProgress);
+        }
+        let mut redone = Vec::new();
+        while redone.len() < count {
+            let entry = match self.undo.pop_redo() {
+                Some(entry) => entry,
+                None => break,
+            };
+            redone.push(entry.description.clone());
+            let replaced = self.swap_undo_entry(entry);
+            let depth = self.undo_depth();
+            self.undo.push_redone(replaced, depth);
+        }
+        Ok(redone)
+    }
 
-    SET q3 d FROM s1
-    SET q4 e FROM s2
-    SET q5 f FROM s3
-    SET q6 w FROM s4
+    // The entries whose source hasn't answered the question yet, neither in
+    // the graph nor earlier in entries
+    // The entries insert_answers adds, those duplicate_answers doesn't ignore
+    fn answers_to_insert<'a>(
+        &self,
+        entries: Vec<(&'a str, A, &'a str)>,
+    ) -> Vec<(&'a str, A, &'a str)> {
+        match self.config.duplicate_answers {
+            DuplicateAnswers::Ignore => self.without_duplicate_answers(entries),
+            DuplicateAnswers::Allow | DuplicateAnswers::Replace => entries,
+        }
+    }
 
-    GET ANSWER TO q1
-    GET ANSWER TO q2
-    GET ANSWER TO q3
-    GET ANSWER TO q4
-    GET ANSWER TO q5
-    GET ANSWER TO q6
-    GET SOURCE s1
-    GET SOURCE s2
-    GET SOURCE s3
-    GET SOURCE s4
+    fn without_duplicate_answers<'a>(
+        &self,
+        entries: Vec<(&'a str, A, &'a str)>,
+    ) -> Vec<(&'a str, A, &'a str)> {
+        let mut answered: HashSet<(&str, &str)> = HashSet::new();
+        entries
+            .into_iter()
+            .filter(|&(question_name, _, source_name)| {
+                answered.insert((question_name, source_name))
+                    && !self.questions.get(question_name).is_some_and(|question| {
+                        question
+                            .answers
+                            .iter()
+                            .any(|answer| answer.source == source_name)
+                    })
+            })
+            .collect()
+    }
 
-    BELIEVE s4
-    GET SOURCE s4
-    GET ANSWER TO q6
+    pub fn is_bulk_loading(&self) -> bool {
+        self.bulk_load.is_some()
+    }
 
-    TEST EQUALITY a a
-    TEST EQUALITY a b
+    // GET ANSWER TO <question>
+    pub fn get_answer(&self, question: &QuestionId) -> Result<AnswerResult<A>, ConfidisError> {
+        self.answer_to(question)
+    }
 
-    GET ANSWERS TO q2
-    "
-    .lines()
-    .filter(|l| !l.trim().is_empty())
-    .map(|l| Command::from(l.trim()))
-    .filter_map(|x| x.ok())
-    .collect();
+    // The count most confident answers competing with get_answer's, with their
+    // confidences, e.g. to show a minority view next to the consensus.
+    // Honeypots have none, their answer is known.
+    pub fn runners_up(
+        &self,
+        question: &QuestionId,
+        count: usize,
+    ) -> Result<Vec<(A, f64)>, ConfidisError> {
+        self.runner_up_answers(question, count)
+    }
 
-    let mut g = Graph::new();
+    fn runner_up_answers(
+        &self,
+        question_name: &str,
+        count: usize,
+    ) -> Result<Vec<(A, f64)>, ConfidisError> {
+        let question = match self.questions.get(question_name) {
+            Some(question) if count > 0 && question.honeypot.is_none() => question,
+            _ => return Ok(Vec::new()),
+        };
+        let AnswerClustersWithConfidences {
+            clusters,
+            cluster_confidences,
+            correct_cluster,
+        } = self.compute_answer_clusters_with_confidence(question_name)?;
+        let mut competing: Vec<usize> = (0..clusters.len())
+            .filter(|&i| i != correct_cluster)
+            .collect();
+        // stable, so equal confidences keep the clusters' order
+        competing.sort_by(|&a, &b| {
+            cluster_confidences[b]
+                .partial_cmp(&cluster_confidences[a])
+                .unwrap_or(Ordering::Equal)
+        });
+        Ok(competing
+            .into_iter()
+            .take(count)
+            .map(|i| {
+                (
+                    question.answers[clusters[i][0]].content.clone(),
+                    self.decayed_confidence(question, cluster_confidences[i]),
+                )
+            })
+            .collect())
+    }
 
-    let mut outputs: Vec<String> = Vec::new();
+    // MGET <question> [<question> ...], the answers to many questions in the
+    // order given from a single read of the graph, so that a dashboard
+    // refreshing thousands of questions takes e.g. a SharedGraph's lock once.
+    // Fails like get_answer if any of the questions does.
+    pub fn get_answers_bulk(
+        &self,
+        questions: &[QuestionId],
+    ) -> Result<Vec<AnswerResult<A>>, ConfidisError> {
+        questions
+            .iter()
+            .map(|question| self.answer_to(question))
+            .collect()
+    }
 
-    for command in &commands {
-        info!("\n{}", command);
-        let output = g.execute_command(&command).unwrap();
-        if output.cmd == CommandType::GetAnswer
-            || output.cmd == CommandType::GetSource
-            || output.cmd == CommandType::TestEquality
-            || output.cmd == CommandType::GetAnswers
-        {
-            info!("> {}", output);
-            outputs.push(format!("> {}", &output));
+    pub(crate) fn answer_to(&self, question_name: &str) -> Result<AnswerResult<A>, ConfidisError> {
+        if self.bulk_load.is_some() {
+            return Err(ConfidisError::BulkLoadInProgress);
+        }
+        if self.strict && !self.questions.contains_key(question_name) {
+            return Err(ConfidisError::UnknownQuestion(question_name.to_string()));
         }
+        let mut result = self.best_answer(question_name)?.unwrap_or(AnswerResult {
+            answer: None,
+            confidence: 0.0,
+            sources: Vec::new(),
+            cluster_count: 0,
+            insufficient_evidence: false,
+            unknown: false,
+        });
+        result.insufficient_evidence =
+            self.answering_source_count(question_name) < self.min_sources_per_answer();
+        result.unknown = result.answer.is_some() && result.confidence < self.config.min_confidence;
+        Ok(result)
     }
 
-    assert_eq!(
-        outputs.join("\n"),
-        "\
-> a (95.885%)
-> b (95.607%)
-> d (86.641%)
-> e (50.379%)
-> f (86.641%)
-> w (13.359%)
-> 0.866
-> 0.504
-> 0.866
-> 0.134
-> 0.999
-> w (99.900%)
-> 0.000
-> 1.000
-> b (98.215%), c (50.379%), w (99.900%)"
-    );
+    // The number of distinct sources that answered a question
+    fn answering_source_count(&self, question_name: &str) -> usize {
+        self.questions.get(question_name).map_or(0, |question| {
+            question
+                .answers
+                .iter()
+                .map(|answer| &answer.source)
+                .collect::<HashSet<_>>()
+                .len()
+        })
+    }
+
+    fn min_sources_per_answer(&self) -> usize {
+        self.config.min_sources_per_answer as usize
+    }
+
+    // In deterministic mode the same commands always give the same output:
+    // clusters with equal confidences and the answer a cluster reports are
+    // chosen by answer (see order_clusters) rather than by which answer came
+    // first, GET ANSWERS is ordered by confidence and answer, sources,
+    // questions and snapshots are ordered by name, and answers aren't timed
+    // outside of journal replay, so confidences don't decay
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    // In strict mode GET SOURCE of a source that never answered anything fails
+    // with UnknownSource, instead of creating the source (execute_command) or
+    // reporting the quality it would start with (execute_read_command), so a
+    // typo doesn't add a source to the graph. GET ANSWER TO a question that was
+    // never asked fails with UnknownQuestion instead of reporting no answer, so
+    // callers can tell it from a question without consensus.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    // Record source quality and strength changes from now on, see AuditLog
+    pub fn set_audit_log(&mut self, audit_log: AuditLog) {
+        self.audit_log = Some(audit_log);
+    }
+
+    pub fn audit_log(&self) -> Option<&AuditLog> {
+        self.audit_log.as_ref()
+    }
+
+    pub fn take_audit_log(&mut self) -> Option<AuditLog> {
+        self.audit_log.take()
+    }
+
+    // GET AUDIT FOR <source>, the recorded changes of the source, oldest first
+    pub fn get_audit(&self, source: &SourceId) -> Result<Vec<QualityChange>, ConfidisError> {
+        self.audit_changes(source)
+    }
+
+    fn audit_changes(&self, source: &str) -> Result<Vec<QualityChange>, ConfidisError> {
+        let audit_log = self.audit_log.as_ref().ok_or_else(|| {
+            ConfidisError::NotImplemented(String::from("The audit log isn't enabled"))
+        })?;
+        Ok(audit_log.changes_for(source).cloned().collect())
+    }
+
+    // Record changes of each question's answer from now on, see AnswerHistory
+    pub fn set_answer_history(&mut self, answer_history: AnswerHistory<A>) {
+        self.answer_history = Some(answer_history);
+    }
+
+    pub fn answer_history(&self) -> Option<&AnswerHistory<A>> {
+        self.answer_history.as_ref()
+    }
+
+    pub fn take_answer_history(&mut self) -> Option<AnswerHistory<A>> {
+        self.answer_history.take()
+    }
+
+    // GET HISTORY OF <question>, the recorded answer changes of the question,
+    // oldest first
+    pub fn get_history(
+        &self,
+        question: &QuestionId,
+    ) -> Result<Vec<AnswerChange<A>>, ConfidisError> {
+        self.history_changes(question)
+    }
+
+    fn history_changes(&self, question: &str) -> Result<Vec<AnswerChange<A>>, ConfidisError> {
+        let answer_history = self.answer_history.as_ref().ok_or_else(|| {
+            ConfidisError::NotImplemented(String::from("The answer history isn't enabled"))
+        })?;
+        Ok(answer_history.changes(question))
+    }
+
+    // EXPLAIN <question>, the evidence behind the answer get_answer reports
+    pub fn explain(&self, question: &QuestionId) -> Result<Provenance<A>, ConfidisError> {
+        if self.bulk_load.is_some() {
+            return Err(ConfidisError::BulkLoadInProgress);
+        }
+        self.provenance(question)
+    }
+
+    // GET SOURCE <source>, an unknown source reports the quality and strength
+    // it would start with
+    pub fn get_source(&self, source: &SourceId) -> SourceStats {
+        self.source_stats(source)
+    }
+
+    fn source_stats(&self, source: &str) -> SourceStats {
+        match self.sources.get(source) {
+            Some(source) => SourceStats {
+                quality: source.quality,
+                strength: source.strength,
+            },
+            None => SourceStats {
+                quality: self.config.default_source_quality,
+                strength: self.config.initial_source_strength,
+            },
+        }
+    }
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph::new_with_equalifier(Box::new(ExactEqualifier::new()))
+    }
+
+    pub fn new_with_config(config: GraphConfig) -> Graph {
+        let mut g = Graph::new();
+        // a custom shadow equalifier can't be rebuilt
+        g.shadow = config
+            .shadow_comparison_method
+            .as_ref()
+            .and_then(EqualifierConfig::build)
+            .map(Shadow::new);
+        g.config = config;
+        g.config.shadow_comparison_method = g.shadow.as_ref().map(Shadow::config);
+        g
+    }
+
+    // The answer and confidence GET ANSWER reports, "None" for a question without
+    // answers. Unlike get_answer this also works during a bulk load, from the
+    // answers loaded so far.
+    pub fn compute_answer(&self, question_name: &str) -> Result<(String, f64), ConfidisError> {
+        Ok(match self.best_answer(question_name)? {
+            Some(AnswerResult {
+                answer: Some(answer),
+                confidence,
+                ..
+            }) => (answer, confidence),
+            _ => (String::from("None"), 0.0),
+        })
+    }
+
+    // A SchemaViolation if the normalized answer isn't of the question's
+    // declared type or doesn't follow its schema
+    fn check_schema(&self, question_name: &str, answer: &str) -> Result<(), ConfidisError> {
+        let question = match self.questions.get(question_name) {
+            Some(question) => question,
+            None => return Ok(()),
+        };
+        let type_check = question
+            .question_type
+            .as_ref()
+            .map(|question_type| question_type.validate(answer));
+        let schema_check = || {
+            question
+                .schema
+                .as_ref()
+                .map(|schema| schema.validate(answer))
+        };
+        match type_check.or_else(schema_check) {
+            Some(Err(reason)) => Err(ConfidisError::SchemaViolation {
+                question: question_name.to_string(),
+                answer: answer.to_string(),
+                reason,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    // A RateLimited error if one of the sources would exceed its rate limit
+    // with another answer for each time it's given, otherwise the answers
+    // count towards the sources' limits
+    fn check_rate_limit<'s>(
+        &mut self,
+        sources: impl IntoIterator<Item = &'s str>,
+    ) -> Result<(), ConfidisError> {
+        if self.config.rate_limit_answers <= 0.0 || self.replay_time.is_some() {
+            return Ok(());
+        }
+        let mut answers: BTreeMap<&str, usize> = BTreeMap::new();
+        for source in sources {
+            *answers.entry(source).or_default() += 1;
+        }
+        let limit = self.config.rate_limit_answers as usize;
+        let window = self.config.rate_limit_window;
+        self.rate_limiter
+            .admit(&answers, self.now(), limit, (window * 1000.0) as u64)
+            .map_err(|source| ConfidisError::RateLimited {
+                source,
+                limit,
+                window,
+            })
+    }
+
+    // Add many answers at once, each entry is (question, answer, source). Every
+    // affected question has its effect removed, gets all of its new answers, and
+    // is then recomputed exactly once, instead of once per answer like SET.
+    //
+    // The names are validated like QuestionId and SourceId, nothing is added if
+    // any of them is invalid.
+    pub fn set_many(&mut self, entries: &[(&str, &str, &str)]) -> Result<(), ConfidisError> {
+        for (question, _, source) in entries {
+            validate_command_ids(Some(question), Some(source))?;
+        }
+        let undo_entry = (self.undo_depth() > 0 && !self.is_bulk_loading()).then(|| {
+            let question_names: Vec<&str> = entries.iter().map(|entry| entry.0).collect();
+            let source_names: Vec<&str> = entries.iter().map(|entry| entry.2).collect();
+            self.undo_entry(
+                format!("BATCH {}", entries.len()),
+                &question_names,
+                &source_names,
+                false,
+            )
+        });
+        let normalized: Vec<(&str, String, &str)> = entries
+            .iter()
+            .map(|(question, answer, source)| {
+                (
+                    *question,
+                    self.config.normalize.apply(answer).into_owned(),
+                    *source,
+                )
+            })
+            .collect();
+        for (question, answer, _) in &normalized {
+            self.check_schema(question, answer)?;
+        }
+        // ignored duplicates don't count towards the rate limit
+        let normalized = self.answers_to_insert(normalized);
+        self.check_rate_limit(normalized.iter().map(|entry| entry.2))?;
+        self.insert_answers(normalized)?;
+        if let Some(entry) = undo_entry {
+            self.remember_undo(entry);
+        }
+        self.write_journal(|journal| journal.append_set_many(entries))?;
+        self.snapshot_if_due();
+        Ok(())
+    }
+
+    // Suspend quality/confidence updates, answers added with SET or set_many are
+    // only stored until finish_bulk_load is called
+    pub fn begin_bulk_load(&mut self) {
+        if self.bulk_load.is_none() {
+            self.bulk_load = Some(BulkLoad::default());
+            self.undo.clear();
+            if let Err(msg) = self.write_journal(|journal| journal.append_begin_bulk_load()) {
+                warn!("{}", msg);
+            }
+        }
+    }
+
+    // Recompute every question touched since begin_bulk_load in a single pass
+    pub fn finish_bulk_load(&mut self) -> Result<(), ConfidisError> {
+        let bulk_load = self.bulk_load.take().ok_or(ConfidisError::NoBulkLoad)?;
+        self.undo.clear();
+        for question_name in &bulk_load.questions {
+            self.compute_question_answers(question_name)?;
+            self.add_question_effect(question_name);
+        }
+        self.write_journal(|journal| journal.append_finish_bulk_load())?;
+        self.snapshot_if_due();
+        Ok(())
+    }
+
+    // Append every subsequent mutation to journal
+    pub fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    pub fn take_journal(&mut self) -> Option<Journal> {
+        self.journal.take()
+    }
+
+    pub(crate) fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    pub(crate) fn journal_mut(&mut self) -> Option<&mut Journal> {
+        self.journal.as_mut()
+    }
+
+    // Replace sources, questions, config and equalifier with other's, keeping
+    // this graph's journal and snapshot policy
+    pub(crate) fn replace_state(&mut self, other: Graph) {
+        self.sources = other.sources;
+        self.questions = other.questions;
+        self.config = other.config;
+        self.equalifier = other.equalifier;
+        self.distance_cache.invalidate();
+        self.typed_equalifiers = other.typed_equalifiers;
+        self.rate_limiter = other.rate_limiter;
+        self.shadow = other.shadow;
+        self.bulk_load = None;
+        self.undo.clear();
+    }
+
+    fn write_journal(
+        &mut self,
+        append: impl FnOnce(&mut Journal) -> Result<(), String>,
+    ) -> Result<(), ConfidisError> {
+        match self.journal.as_mut() {
+            Some(journal) => append(journal).map_err(ConfidisError::Journal),
+            None => Ok(()),
+        }
+    }
+
+    // Approximate bytes used by sources, questions and answers. Only owned
+    // allocations are counted, hash map overhead is not.
+    pub fn memory_stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats {
+            source_count: self.sources.len(),
+            question_count: self.questions.len(),
+            distance_cache_count: self.distance_cache.len(),
+            ..Default::default()
+        };
+        for (key, source) in &self.sources {
+            stats.source_bytes +=
+                size_of::<String>() + key.capacity() + size_of::<Source>() + source.name.capacity();
+        }
+        for (key, question) in &self.questions {
+            stats.question_bytes += size_of::<String>()
+                + key.capacity()
+                // the Arc and its two reference counts
+                + size_of::<Arc<Question>>()
+                + 2 * size_of::<usize>()
+                + size_of::<Question>()
+                + question.name.capacity()
+                + question.correct_answers.capacity() * size_of::<usize>();
+            stats.answer_count += question.answers.len();
+            stats.answer_bytes += question.answers.capacity() * size_of::<Answer>();
+            for answer in &question.answers {
+                stats.answer_bytes += answer.content.capacity() + answer.source.capacity();
+            }
+        }
+        stats.distance_cache_bytes = stats.distance_cache_count
+            * (size_of::<(AnswerHash, AnswerHash, u64)>() + size_of::<f64>());
+        stats
+    }
+
+    // Typed equivalents of the mutating commands, these are journaled like the
+    // commands they stand for
+
+    // SET <question> <answer> FROM <source>
+    pub fn set_answer(
+        &mut self,
+        question: &QuestionId,
+        answer: &str,
+        source: &SourceId,
+    ) -> Result<(), ConfidisError> {
+        self.execute_command(&Command {
+            cmd: CommandType::Set,
+            question: Some(question.as_str().into()),
+            answer: Some(answer.into()),
+            source: Some(source.as_str().into()),
+            ..Default::default()
+        })
+        .map(|_| ())
+    }
+
+    // BELIEVE <source>
+    pub fn believe(&mut self, source: &SourceId) -> Result<(), ConfidisError> {
+        self.execute_command(&Command {
+            cmd: CommandType::Believe,
+            source: Some(source.as_str().into()),
+            ..Default::default()
+        })
+        .map(|_| ())
+    }
+
+    // EXCLUDE <source> FROM <question>
+    pub fn exclude_source(
+        &mut self,
+        question: &QuestionId,
+        source: &SourceId,
+    ) -> Result<(), ConfidisError> {
+        self.execute_command(&Command {
+            cmd: CommandType::Exclude,
+            question: Some(question.as_str().into()),
+            source: Some(source.as_str().into()),
+            ..Default::default()
+        })
+        .map(|_| ())
+    }
+
+    // HONEYPOT <question> <answer>
+    pub fn set_honeypot(
+        &mut self,
+        question: &QuestionId,
+        answer: &str,
+    ) -> Result<(), ConfidisError> {
+        self.execute_command(&Command {
+            cmd: CommandType::Honeypot,
+            question: Some(question.as_str().into()),
+            answer: Some(answer.into()),
+            ..Default::default()
+        })
+        .map(|_| ())
+    }
+
+    // SCHEMA <question> <schema>, see schema.rs
+    pub fn set_schema(&mut self, question: &QuestionId, schema: &str) -> Result<(), ConfidisError> {
+        self.execute_command(&Command {
+            cmd: CommandType::Schema,
+            question: Some(question.as_str().into()),
+            schema: Some(schema.into()),
+            ..Default::default()
+        })
+        .map(|_| ())
+    }
+
+    // TYPE <question> <type>, see question_type.rs
+    pub fn set_question_type(
+        &mut self,
+        question: &QuestionId,
+        question_type: &str,
+    ) -> Result<(), ConfidisError> {
+        self.execute_command(&Command {
+            cmd: CommandType::Type,
+            question: Some(question.as_str().into()),
+            question_type: Some(question_type.into()),
+            ..Default::default()
+        })
+        .map(|_| ())
+    }
+
+    // REBUILD
+    pub fn rebuild(&mut self) -> Result<(), ConfidisError> {
+        self.execute_command(&Command {
+            cmd: CommandType::Rebuild,
+            ..Default::default()
+        })
+        .map(|_| ())
+    }
+
+    // UNDO <count>, returns the commands taken back, the most recent first
+    pub fn undo(&mut self, count: usize) -> Result<Vec<String>, ConfidisError> {
+        match self.execute_command(&Command {
+            cmd: CommandType::Undo,
+            count: Some(count),
+            ..Default::default()
+        })? {
+            CommandResponse::Undo(commands) => Ok(commands),
+            response => Err(ConfidisError::Internal(format!(
+                "Unexpected response to UNDO: {:?}",
+                response
+            ))),
+        }
+    }
+
+    // REDO <count>, returns the commands redone
+    pub fn redo(&mut self, count: usize) -> Result<Vec<String>, ConfidisError> {
+        match self.execute_command(&Command {
+            cmd: CommandType::Redo,
+            count: Some(count),
+            ..Default::default()
+        })? {
+            CommandResponse::Redo(commands) => Ok(commands),
+            response => Err(ConfidisError::Internal(format!(
+                "Unexpected response to REDO: {:?}",
+                response
+            ))),
+        }
+    }
+
+    // The state cmd is about to change, if UNDO could take it back
+    fn undo_entry_for(&self, cmd: &Command) -> Option<UndoEntry<String>> {
+        // CONFIGURE undo_depth can turn the stack on
+        if self.is_bulk_loading() || (self.undo_depth() == 0 && cmd.cmd != CommandType::Configure) {
+            return None;
+        }
+        let question_name = cmd.question.as_deref();
+        let source_name = cmd.source.as_deref();
+        match cmd.cmd {
+            CommandType::Set
+            | CommandType::Exclude
+            | CommandType::Honeypot
+            | CommandType::Schema
+            | CommandType::Type => Some(self.undo_entry(
+                cmd.to_string(),
+                question_name.as_slice(),
+                source_name.as_slice(),
+                false,
+            )),
+            CommandType::Believe => {
+                Some(self.undo_entry(cmd.to_string(), &[], source_name.as_slice(), false))
+            }
+            CommandType::Configure => Some(self.undo_entry(cmd.to_string(), &[], &[], true)),
+            CommandType::Rebuild => {
+                let question_names: Vec<&str> = self.questions.keys().map(String::as_str).collect();
+                let source_names: Vec<&str> = self.sources.keys().map(String::as_str).collect();
+                Some(self.undo_entry(cmd.to_string(), &question_names, &source_names, false))
+            }
+            _ => None,
+        }
+    }
+
+    // Run f whenever execute_command changes the answer or confidence GET ANSWER
+    // reports for a question
+    pub fn on_answer_changed<F>(&mut self, f: F)
+    where
+        F: Fn(&str, &(String, f64), &(String, f64)) + Send + Sync + 'static,
+    {
+        self.hooks.answer_changed.push(Box::new(f));
+    }
+
+    // Run f whenever execute_command changes the quality of a source
+    pub fn on_source_quality_changed<F>(&mut self, f: F)
+    where
+        F: Fn(&str, f64, f64) + Send + Sync + 'static,
+    {
+        self.hooks.source_quality_changed.push(Box::new(f));
+    }
+
+    // Sources whose quality cmd could change, cmd.source and every source that
+    // answered cmd.question
+    fn affected_sources(&self, cmd: &Command) -> HashSet<String> {
+        let mut sources: HashSet<String> = HashSet::new();
+        if cmd.cmd.is_read_only() {
+            return sources;
+        }
+        // these can change any source
+        if matches!(
+            cmd.cmd,
+            CommandType::Rebuild | CommandType::Undo | CommandType::Redo
+        ) {
+            return self.sources.keys().cloned().collect();
+        }
+        if let Some(source_name) = cmd.source.as_ref() {
+            sources.insert(source_name.to_string());
+        }
+        if let Some(question) = cmd.question.as_ref().and_then(|q| self.question(q)) {
+            for answer in &question.answers {
+                sources.insert(answer.source.clone());
+            }
+        }
+        sources
+    }
+
+    // Questions whose answer cmd could change. A command changes the quality of
+    // the affected_sources, which in turn changes the answers of every question
+    // those sources answered. CONFIGURE, REBUILD, UNDO and REDO can change every
+    // answer.
+    pub(crate) fn affected_questions(&self, cmd: &Command) -> Vec<&str> {
+        if cmd.cmd.is_read_only() {
+            return Vec::new();
+        }
+        let affected_sources = self.affected_sources(cmd);
+        let everything = matches!(
+            cmd.cmd,
+            CommandType::Configure | CommandType::Rebuild | CommandType::Undo | CommandType::Redo
+        );
+        let mut questions: Vec<&str> = self
+            .questions
+            .iter()
+            .filter(|(question_name, question)| {
+                everything
+                    || cmd.question.as_deref() == Some(question_name.as_str())
+                    || question
+                        .answers
+                        .iter()
+                        .any(|answer| affected_sources.contains(&answer.source))
+            })
+            .map(|(question_name, _)| question_name.as_str())
+            .collect();
+        if self.deterministic {
+            questions.sort_unstable();
+        }
+        questions
+    }
+
+    fn observe(&self, cmd: &Command) -> ObservedState {
+        let mut state = ObservedState {
+            answers: BTreeMap::new(),
+            qualities: BTreeMap::new(),
+        };
+        if !self.hooks.answer_changed.is_empty() {
+            for question_name in self.affected_questions(cmd) {
+                if let Ok(answer) = self.compute_answer(question_name) {
+                    state.answers.insert(question_name.to_string(), answer);
+                }
+            }
+        }
+        if !self.hooks.source_quality_changed.is_empty() {
+            for source_name in self.affected_sources(cmd) {
+                if let Some(source) = self.sources.get(&source_name) {
+                    state.qualities.insert(source_name, source.quality);
+                }
+            }
+        }
+        state
+    }
+
+    // Run the hooks for everything that differs between before and after.
+    // Questions and sources that didn't exist before start out as "None" and
+    // the default source quality.
+    fn run_hooks(&self, before: ObservedState, after: ObservedState) {
+        let no_answer = (String::from("None"), 0.0);
+        for (question_name, answer) in &after.answers {
+            let previous = before.answers.get(question_name).unwrap_or(&no_answer);
+            if previous != answer {
+                for hook in &self.hooks.answer_changed {
+                    hook(question_name, previous, answer);
+                }
+            }
+        }
+        for (source_name, &quality) in &after.qualities {
+            let previous = match before.qualities.get(source_name) {
+                Some(&previous) => previous,
+                None => self.config.default_source_quality,
+            };
+            if previous != quality {
+                for hook in &self.hooks.source_quality_changed {
+                    hook(source_name, previous, quality);
+                }
+            }
+        }
+    }
+
+    // Change a setting and return its previous value, the typed form of
+    // CONFIGURE. The value must be the kind the key takes and in range, see
+    // ConfigValue and ConfigKey::validate. Like CONFIGURE it's journaled, can be
+    // undone and runs the hooks.
+    pub fn configure(
+        &mut self,
+        key: ConfigKey,
+        value: ConfigValue,
+    ) -> Result<ConfigValue, ConfidisError> {
+        let cmd = Command {
+            cmd: CommandType::Configure,
+            config_key: Some(key.as_str().into()),
+            config_val: Some(value.to_string().into()),
+            ..Default::default()
+        };
+        self.execute_with(&cmd, |graph| graph.apply_config(key, value))
+    }
+
+    fn apply_config(
+        &mut self,
+        key: ConfigKey,
+        value: ConfigValue,
+    ) -> Result<ConfigValue, ConfidisError> {
+        match (key, value) {
+            (ConfigKey::ComparisonMethod, ConfigValue::ComparisonMethod(equalifier)) => {
+                let equalifier =
+                    equalifier
+                        .build()
+                        .ok_or_else(|| ConfidisError::InvalidConfig {
+                            key: key.to_string(),
+                            reason: String::from("a custom comparison method can't be configured"),
+                        })?;
+                let previous = self.equalifier_config();
+                self.set_equalifier(equalifier);
+                Ok(ConfigValue::ComparisonMethod(previous))
+            }
+            (
+                ConfigKey::ShadowComparisonMethod,
+                ConfigValue::ShadowComparisonMethod(equalifier),
+            ) => {
+                let equalifier = match equalifier {
+                    Some(equalifier) => {
+                        Some(equalifier.build().ok_or_else(|| ConfidisError::InvalidConfig {
+                            key: key.to_string(),
+                            reason: String::from("a custom comparison method can't be configured"),
+                        })?)
+                    }
+                    None => None,
+                };
+                let previous = self.config.shadow_comparison_method.clone();
+                self.set_shadow_equalifier(equalifier);
+                Ok(ConfigValue::ShadowComparisonMethod(previous))
+            }
+            (ConfigKey::AnswerHash, ConfigValue::AnswerHash(hasher)) => {
+                Ok(ConfigValue::AnswerHash(self.set_answer_hasher(hasher)))
+            }
+            (ConfigKey::DuplicateAnswers, ConfigValue::DuplicateAnswers(policy)) => {
+                Ok(ConfigValue::DuplicateAnswers(std::mem::replace(
+                    &mut self.config.duplicate_answers,
+                    policy,
+                )))
+            }
+            (ConfigKey::Normalize, ConfigValue::Normalize(normalization)) => Ok(
+                ConfigValue::Normalize(std::mem::replace(&mut self.config.normalize, normalization)),
+            ),
+            (ConfigKey::OutputFormat, _) => Err(ConfidisError::NotImplemented(String::from(
+                "output_format is a setting of the REPL or TCP connection printing responses, not of the graph",
+            ))),
+            (key, ConfigValue::Number(v)) if key.is_numeric() => {
+                self.config.set(key, v).map(ConfigValue::Number)
+            }
+            (key, _) => Err(ConfidisError::InvalidConfig {
+                key: key.to_string(),
+                reason: format!("expects {}", key.expected()),
+            }),
+        }
+    }
+
+    pub fn execute_command(&mut self, cmd: &Command) -> Result<CommandResponse, ConfidisError> {
+        self.execute_with(cmd, |graph| graph.apply_command(cmd))
+    }
+
+    // Run apply, which makes the changes of cmd, with everything
+    // execute_command does around them: the hooks, UNDO and the journal
+    fn execute_with<R, F>(&mut self, cmd: &Command, apply: F) -> Result<R, ConfidisError>
+    where
+        F: FnOnce(&mut Self) -> Result<R, ConfidisError>,
+    {
+        cmd.validate()?;
+        if cmd.cmd.is_read_only() || self.hooks.is_empty() {
+            return self.execute_unhooked(cmd, apply);
+        }
+        let before = self.observe(cmd);
+        let result = self.execute_unhooked(cmd, apply);
+        self.run_hooks(before, self.observe(cmd));
+        result
+    }
+
+    fn execute_unhooked<R, F>(&mut self, cmd: &Command, apply: F) -> Result<R, ConfidisError>
+    where
+        F: FnOnce(&mut Self) -> Result<R, ConfidisError>,
+    {
+        let undo_entry = self.undo_entry_for(cmd);
+        let response = apply(self)?;
+        if let Some(entry) = undo_entry {
+            self.remember_undo(entry);
+        }
+        if !cmd.cmd.is_read_only() {
+            self.write_journal(|journal| journal.append_command(cmd))?;
+            self.snapshot_if_due();
+        }
+        Ok(response)
+    }
+
+    fn apply_command(&mut self, cmd: &Command) -> Result<CommandResponse, ConfidisError> {
+        match cmd.cmd {
+            CommandType::Set => {
+                let source_name = cmd.field("source")?;
+                let question_name = cmd.field("question")?;
+                let answer_content = self.config.normalize.apply(cmd.field("answer")?);
+                self.check_schema(question_name, &answer_content)?;
+                let entries = self.answers_to_insert(vec![(
+                    question_name,
+                    answer_content.into_owned(),
+                    source_name,
+                )]);
+                self.check_rate_limit(entries.iter().map(|entry| entry.2))?;
+
+                self.insert_answers(entries)?;
+
+                Ok(CommandResponse::Set)
+            }
+            CommandType::Believe => {
+                let source_name = cmd.field("source")?;
+                self.create_source_if_not_exists(source_name);
+
+                if let Some(source) = self.sources.get_mut(source_name) {
+                    let old = (source.quality, source.strength);
+                    source.quality = self.config.quality_of_believed_sources;
+                    source.strength = self.config.maximum_strength;
+                    source.evidence = EvidenceLedger::default();
+                    source.discount = 0.0;
+                    source.complete_ledger = true;
+                    if let Some(audit_log) = self.audit_log.as_mut() {
+                        let new = (source.quality, source.strength);
+                        audit_log.record(source_name, None, false, old, new);
+                    }
+                }
+
+                Ok(CommandResponse::Believe)
+            }
+            CommandType::Exclude => {
+                self.exclude_source_from_question(cmd.field("question")?, cmd.field("source")?)?;
+                Ok(CommandResponse::Exclude)
+            }
+            CommandType::Honeypot => {
+                let answer = self.config.normalize.apply(cmd.field("answer")?);
+                self.mark_honeypot(cmd.field("question")?, answer.into_owned())?;
+                Ok(CommandResponse::Honeypot)
+            }
+            CommandType::Schema => {
+                let schema = match cmd.field("schema")?.trim() {
+                    "none" => None,
+                    schema => Some(schema.parse().map_err(|reason| {
+                        ConfidisError::ParseError(format!("Invalid schema: {}", reason))
+                    })?),
+                };
+                let previous = self.replace_schema(cmd.field("question")?, schema);
+                Ok(CommandResponse::Schema {
+                    previous: previous.map_or_else(|| String::from("none"), |s| s.to_string()),
+                })
+            }
+            CommandType::Type => {
+                let question_type = match cmd.field("question_type")?.trim() {
+                    "none" => None,
+                    question_type => Some(question_type.parse().map_err(|reason| {
+                        ConfidisError::ParseError(format!("Invalid question type: {}", reason))
+                    })?),
+                };
+                let previous = self.replace_question_type(cmd.field("question")?, question_type)?;
+                Ok(CommandResponse::Type {
+                    previous: previous.map_or_else(|| String::from("none"), |t| t.to_string()),
+                })
+            }
+            CommandType::Rebuild => {
+                self.rebuild_qualities()?;
+                Ok(CommandResponse::Rebuild)
+            }
+            CommandType::Undo => Ok(CommandResponse::Undo(
+                self.undo_mutations(cmd.count.unwrap_or(1))?,
+            )),
+            CommandType::Redo => Ok(CommandResponse::Redo(
+                self.redo_mutations(cmd.count.unwrap_or(1))?,
+            )),
+            CommandType::Configure => {
+                let key: ConfigKey = cmd.field("config_key")?.parse()?;
+                let value = ConfigValue::parse(key, cmd.field("config_val")?)?;
+                let previous = self.apply_config(key, value)?;
+                Ok(CommandResponse::Configure {
+                    previous: previous.to_string(),
+                })
+            }
+            CommandType::GetSource if !self.strict => {
+                self.create_source_if_not_exists(cmd.field("source")?);
+                self.execute_read_command(cmd)
+            }
+            _ => self.execute_read_command(cmd),
+        }
+    }
+
+    // Execute a command that doesn't modify the graph (see CommandType::is_read_only)
+    // through a shared reference. Unlike execute_command, GET SOURCE doesn't create
+    // unknown sources, it reports the quality they would start with.
+    pub fn execute_read_command(&self, cmd: &Command) -> Result<CommandResponse, ConfidisError> {
+        cmd.validate()?;
+        if self.bulk_load.is_some()
+            && matches!(
+                cmd.cmd,
+                CommandType::GetAnswer | CommandType::GetAnswers | CommandType::Explain
+            )
+        {
+            return Err(ConfidisError::BulkLoadInProgress);
+        }
+        match cmd.cmd {
+            CommandType::GetAnswer => {
+                if let Some(timestamp) = cmd.as_of {
+                    return self.as_of(timestamp)?.execute_read_command(&Command {
+                        as_of: None,
+                        ..cmd.clone()
+                    });
+                }
+                let question_name = cmd.field("question")?;
+                let result = self.answer_to(question_name)?;
+                let content = result.answer.unwrap_or_else(|| String::from("None"));
+                Ok(if result.unknown {
+                    CommandResponse::Unknown {
+                        confidence: result.confidence,
+                        min_confidence: self.config.min_confidence,
+                    }
+                } else if result.insufficient_evidence {
+                    CommandResponse::InsufficientEvidence {
+                        content,
+                        confidence: result.confidence,
+                        source_count: self.answering_source_count(question_name),
+                        min_sources: self.min_sources_per_answer(),
+                    }
+                } else {
+                    CommandResponse::Answer {
+                        content,
+                        confidence: result.confidence,
+                        sources: result.sources,
+                        cluster_count: result.cluster_count,
+                        runners_up: self
+                            .runner_up_answers(question_name, cmd.runners_up.unwrap_or(0))?
+                            .into_iter()
+                            .map(|(answer, confidence)| AnswerConfidencePair { answer, confidence })
+                            .collect(),
+                    }
+                })
+            }
+            CommandType::GetSource => {
+                let source_name = cmd.field("source")?;
+                if self.strict && !self.sources.contains_key(source_name) {
+                    return Err(ConfidisError::UnknownSource(source_name.to_string()));
+                }
+                let SourceStats { quality, strength } = self.source_stats(source_name);
+                Ok(CommandResponse::Source { quality, strength })
+            }
+            CommandType::TestEquality => {
+                let answer = |field| -> Result<Answer, ConfidisError> {
+                    Ok(Answer::with_hasher(
+                        self.config.normalize.apply(cmd.field(field)?).into_owned(),
+                        String::from("None"),
+                        self.config.answer_hash,
+                    ))
+                };
+                let (answer1, answer2) = (answer("answer1")?, answer("answer2")?);
+                for answer in &[&answer1, &answer2] {
+                    if !self.equalifier.is_valid_answer(answer) {
+                        return Err(ConfidisError::InvalidAnswer(format!(
+                            "\"{}\" can't be compared with the configured comparison method",
+                            answer.content
+                        )));
+                    }
+                }
+
+                Ok(CommandResponse::Distance(
+                    self.equalifier.get_distance(&answer1, &answer2),
+                ))
+            }
+            CommandType::GetAnswers => {
+                let mut answers = Vec::new();
+
+                let question_name = cmd.field("question")?;
+                let analysis = self.compute_answer_clusters_with_confidence(question_name)?;
+                let question = self
+                    .questions
+                    .get(question_name)
+                    .ok_or_else(|| ConfidisError::UnknownQuestion(question_name.to_string()))?;
+
+                // by content, answers whose hashes collide are still different
+                let mut answers_added = HashSet::new();
+
+                for cluster_index in 0..analysis.clusters.len() {
+                    for answer_index in analysis.clusters[cluster_index].iter() {
+                        let answer = &question.answers[*answer_index];
+                        if !answers_added.insert(&answer.content) {
+                            continue;
+                        };
+                        answers.push(AnswerConfidencePair {
+                            answer: answer.content.clone(),
+                            confidence: analysis.cluster_confidences[cluster_index],
+                        });
+                    }
+                }
+
+                if self.deterministic {
+                    answers.sort_by(|a, b| {
+                        b.confidence
+                            .partial_cmp(&a.confidence)
+                            .unwrap_or(Ordering::Equal)
+                            .then_with(|| a.answer.cmp(&b.answer))
+                    });
+                }
+                Ok(CommandResponse::Answers(answers))
+            }
+            CommandType::Stats => Ok(CommandResponse::Stats {
+                graph: self.graph_stats(),
+                memory: self.memory_stats(),
+            }),
+            CommandType::Explain => Ok(CommandResponse::Explanation(
+                self.provenance(cmd.field("question")?)?,
+            )),
+            CommandType::GetAudit => Ok(CommandResponse::Audit(
+                self.audit_changes(cmd.field("source")?)?,
+            )),
+            CommandType::DebugClusters => Ok(CommandResponse::Clusters(
+                self.cluster_debug(cmd.field("question")?)?,
+            )),
+            CommandType::GetHistory => Ok(CommandResponse::History(
+                self.history_changes(cmd.field("question")?)?,
+            )),
+            CommandType::CompareSources => Ok(CommandResponse::Agreement(
+                self.source_agreement(&cmd.source_list()?)?,
+            )),
+            CommandType::MGet => Ok(CommandResponse::QuestionAnswers(
+                cmd.question_list()?
+                    .into_iter()
+                    .map(|question| {
+                        let result = self.answer_to(question)?;
+                        let answer = match result.answer {
+                            _ if result.unknown => String::from("Unknown"),
+                            Some(answer) => answer,
+                            None => String::from("None"),
+                        };
+                        Ok(QuestionAnswer {
+                            question: question.to_string(),
+                            answer,
+                            confidence: result.confidence,
+                            insufficient_evidence: result.insufficient_evidence,
+                            unknown: result.unknown,
+                        })
+                    })
+                    .collect::<Result<_, ConfidisError>>()?,
+            )),
+            CommandType::GetShadow => Ok(CommandResponse::Shadow(self.shadow_stats()?)),
+            CommandType::Invalid => Err(ConfidisError::NotImplemented(
+                "Not implemented or invalid command".into(),
+            )),
+            _ => Err(ConfidisError::NotImplemented(format!(
+                "{:?} modifies the graph and isn't a read command",
+                cmd.cmd
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+fn question_id(name: &str) -> QuestionId {
+    QuestionId::new(name).unwrap()
+}
+
+#[cfg(test)]
+fn source_id(name: &str) -> SourceId {
+    SourceId::new(name).unwrap()
+}
+
+#[test]
+fn test_graph_1() {
+    pretty_env_logger::init();
+    let commands: Vec<Command> = "\
+    SET q1 a FROM s1
+    SET q1 a FROM s2
+    SET q1 a FROM s3
+    SET q1 w FROM s4
+
+    SET q2 b FROM s1
+    SET q2 c FROM s2
+    SET q2 b FROM s3
+    SET q2 w FROM s4
+
+    SET q3 d FROM s1
+    SET q4 e FROM s2
+    SET q5 f FROM s3
+    SET q6 w FROM s4
+
+    GET ANSWER TO q1
+    GET ANSWER TO q2
+    GET ANSWER TO q3
+    GET ANSWER TO q4
+    GET ANSWER TO q5
+    GET ANSWER TO q6
+    GET SOURCE s1
+    GET SOURCE s2
+    GET SOURCE s3
+    GET SOURCE s4
+
+    BELIEVE s4
+    GET SOURCE s4
+    GET ANSWER TO q6
+
+    TEST EQUALITY a a
+    TEST EQUALITY a b
+
+    GET ANSWERS TO q2
+    "
+    .lines()
+    .filter(|l| !l.trim().is_empty())
+    .map(|l| Command::from(l.trim()))
+    .filter_map(|x| x.ok())
+    .collect();
+
+    let mut g = Graph::new();
+
+    let mut outputs: Vec<String> = Vec::new();
+
+    for command in &commands {
+        info!("\n{}", command);
+        let output = g.execute_command(command).unwrap();
+        if matches!(
+            output,
+            CommandResponse::Answer { .. }
+                | CommandResponse::Source { .. }
+                | CommandResponse::Distance(_)
+                | CommandResponse::Answers(_)
+        ) {
+            info!("> {}", output);
+            outputs.push(format!("> {}", &output));
+        }
+    }
+
+    assert_eq!(
+        outputs.join("\n"),
+        "\
+> a (95.885%)
+> b (93.097%)
+> d (83.682%)
+> e (45.792%)
+> f (83.682%)
+> w (16.318%)
+> 0.837
+> 0.458
+> 0.837
+> 0.163
+> 0.999
+> w (99.900%)
+> 0.000
+> 1.000
+> b (93.097%), c (73.727%), w (99.900%)"
+    );
+}
+
+#[test]
+fn test_set_many() {
+    let mut g = Graph::new();
+    g.set_many(&[
+        ("q1", "a", "s1"),
+        ("q2", "b", "s1"),
+        ("q1", "a", "s2"),
+        ("q1", "c", "s3"),
+        ("q2", "b", "s2"),
+    ])
+    .unwrap();
+
+    let output = g
+        .execute_command(&Command::from("GET ANSWER TO q1").unwrap())
+        .unwrap();
+    assert!(matches!(output, CommandResponse::Answer { content, .. } if content == "a"));
+    assert_eq!(g.questions["q1"].answers.len(), 3);
+    assert_eq!(g.questions["q2"].answers.len(), 2);
+
+    // A single entry behaves exactly like SET
+    let mut g_set = Graph::new();
+    g_set
+        .execute_command(&Command::from("SET q1 a FROM s1").unwrap())
+        .unwrap();
+    let mut g_many = Graph::new();
+    g_many.set_many(&[("q1", "a", "s1")]).unwrap();
+    assert_eq!(
+        g_set.questions["q1"].confidence,
+        g_many.questions["q1"].confidence
+    );
+    assert_eq!(g_set.sources["s1"].quality, g_many.sources["s1"].quality);
+}
+
+#[test]
+fn test_bulk_load() {
+    let entries = [
+        ("q1", "a", "s1"),
+        ("q2", "b", "s1"),
+        ("q1", "a", "s2"),
+        ("q1", "c", "s3"),
+        ("q2", "b", "s2"),
+        ("q3", "d", "s3"),
+    ];
+    let mut g_many = Graph::new();
+    g_many.set_many(&entries).unwrap();
+
+    let mut g_bulk = Graph::new();
+    g_bulk.begin_bulk_load();
+    g_bulk.set_many(&entries[..3]).unwrap();
+    for (question, answer, source) in &entries[3..] {
+        let line = format!("SET {} {} FROM {}", question, answer, source);
+        let cmd = Command::from(&line).unwrap();
+        g_bulk.execute_command(&cmd).unwrap();
+    }
+    assert_eq!(
+        g_bulk
+            .execute_command(&Command::from("GET ANSWER TO q1").unwrap())
+            .err(),
+        Some(ConfidisError::BulkLoadInProgress)
+    );
+    assert_eq!(g_bulk.sources["s1"].quality, 0.5);
+    g_bulk.finish_bulk_load().unwrap();
+    assert_eq!(g_bulk.finish_bulk_load(), Err(ConfidisError::NoBulkLoad));
+
+    for q in &["q1", "q2", "q3"] {
+        assert_eq!(
+            g_many.questions[*q].confidence,
+            g_bulk.questions[*q].confidence
+        );
+    }
+    for s in &["s1", "s2", "s3"] {
+        assert_eq!(g_many.sources[*s].quality, g_bulk.sources[*s].quality);
+    }
+}
+
+#[test]
+fn test_memory_stats() {
+    let mut g = Graph::new();
+    let empty = g.memory_stats();
+    assert_eq!(empty.total_bytes(), 0);
+
+    g.set_many(&[("q1", "a", "s1"), ("q1", "b", "s2"), ("q2", "a", "s1")])
+        .unwrap();
+    let output = g.execute_command(&Command::from("STATS").unwrap()).unwrap();
+    let (graph, stats) = match output {
+        CommandResponse::Stats { graph, memory } => (graph, memory),
+        _ => panic!("STATS returned {:?}", output),
+    };
+    assert_eq!(stats.source_count, 2);
+    assert_eq!(stats.question_count, 2);
+    assert_eq!(stats.answer_count, 3);
+    assert_eq!(stats.distance_cache_count, 1);
+    assert!(stats.answer_bytes >= 3 * size_of::<Answer>());
+    assert_eq!(stats, g.memory_stats());
+
+    assert_eq!(graph.answer_count, 3);
+    assert_eq!(graph.unanswered_question_count, 0);
+    assert_eq!(graph.confidence_histogram.iter().sum::<usize>(), 2);
+    let mean = g.sources().map(|s| s.quality).sum::<f64>() / 2.0;
+    assert_eq!(graph.mean_source_quality, Some(mean));
+    assert_eq!(graph.median_source_quality, Some(mean));
+    assert_eq!(Graph::new().graph_stats().mean_source_quality, None);
+}
+
+#[test]
+fn test_get_answer_is_read_only() {
+    let mut g = Graph::new();
+    g.set_many(&[("q1", "a", "s1"), ("q1", "a", "s2"), ("q1", "b", "s3")])
+        .unwrap();
+    let qualities: Vec<f64> = ["s1", "s2", "s3"]
+        .iter()
+        .map(|s| g.sources[*s].quality)
+        .collect();
+
+    for _ in 0..3 {
+        let output = g
+            .execute_command(&Command::from("GET ANSWER TO q1").unwrap())
+            .unwrap();
+        assert!(matches!(output, CommandResponse::Answer { content, .. } if content == "a"));
+    }
+    let output = g
+        .execute_command(&Command::from("GET ANSWER TO unknown").unwrap())
+        .unwrap();
+    assert_eq!(
+        output,
+        CommandResponse::Answer {
+            content: String::from("None"),
+            confidence: 0.0,
+            sources: Vec::new(),
+            cluster_count: 0,
+            runners_up: Vec::new(),
+        }
+    );
+
+    for (s, quality) in ["s1", "s2", "s3"].iter().zip(qualities) {
+        assert_eq!(g.sources[*s].quality, quality);
+    }
+    assert!(!g.questions.contains_key("unknown"));
+
+    // A question's answer doesn't count its own effect on its sources: right
+    // after it's recomputed the read agrees with its stored confidence, and
+    // later it agrees with recomputing it
+    let q1 = question_id("q1");
+    assert_eq!(
+        g.get_answer(&q1).unwrap().confidence,
+        g.questions["q1"].confidence
+    );
+    g.set_many(&[("q2", "c", "s1"), ("q2", "c", "s3"), ("q2", "d", "s2")])
+        .unwrap();
+    let read = g.get_answer(&q1).unwrap().confidence;
+    assert_ne!(read, g.questions["q1"].confidence);
+    let mut recomputed = g.fork();
+    recomputed.remove_question_effect("q1");
+    recomputed.compute_question_answers("q1").unwrap();
+    assert!((read - recomputed.questions["q1"].confidence).abs() < 1e-12);
+}
+
+#[test]
+fn test_graph_serde_roundtrip() {
+    let mut g = Graph::new();
+    for line in &[
+        "CONFIGURE comparison_method numeric max_distance=2",
+        "CONFIGURE maximum_strength 50",
+        "SET q1 1 FROM s1",
+        "SET q1 1.5 FROM s2",
+        "SET q1 7 FROM s3",
+        "SET q2 3 FROM s3",
+    ] {
+        g.execute_command(&Command::from(line).unwrap()).unwrap();
+    }
+
+    let json = serde_json::to_string(&g).unwrap();
+    let mut restored: Graph = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.config(), g.config());
+    assert_eq!(
+        restored.equalifier_config(),
+        EqualifierConfig::Numeric { max_distance: 2.0 }
+    );
+    for s in &["s1", "s2", "s3"] {
+        assert_eq!(restored.sources[*s].quality, g.sources[*s].quality);
+        assert_eq!(restored.sources[*s].strength, g.sources[*s].strength);
+    }
+    for line in &["GET ANSWER TO q1", "GET SOURCE s3"] {
+        let cmd = Command::from(line).unwrap();
+        assert_eq!(
+            format!("{}", restored.execute_command(&cmd).unwrap()),
+            format!("{}", g.execute_command(&cmd).unwrap())
+        );
+    }
+
+    g.begin_bulk_load();
+    assert!(serde_json::to_string(&g).is_err());
+}
+
+#[test]
+fn test_typed_api() {
+    let mut g = Graph::new();
+    assert_eq!(
+        g.get_answer(&question_id("q1")).unwrap(),
+        AnswerResult {
+            answer: None,
+            confidence: 0.0,
+            sources: Vec::new(),
+            cluster_count: 0,
+            insufficient_evidence: false,
+            unknown: false,
+        }
+    );
+    g.set_answer(&question_id("q1"), "a b", &source_id("s1"))
+        .unwrap();
+    g.set_answer(&question_id("q1"), "a b", &source_id("s2"))
+        .unwrap();
+    g.believe(&source_id("s3")).unwrap();
+
+    let result = g.get_answer(&question_id("q1")).unwrap();
+    assert_eq!(result.answer.as_deref(), Some("a b"));
+    assert!((result.confidence - 0.75).abs() < 1e-4);
+    assert_eq!(result.sources, vec!["s1", "s2"]);
+    assert_eq!(result.cluster_count, 1);
+
+    let response = g
+        .execute_command(&Command::from("GET ANSWER TO q").unwrap())
+        .unwrap();
+    assert!(matches!(
+        response,
+        CommandResponse::Answer {
+            cluster_count: 0,
+            ..
+        }
+    ));
+    g.set_answer(&question_id("q"), "c", &source_id("s5"))
+        .unwrap();
+    g.set_answer(&question_id("q"), "d", &source_id("s6"))
+        .unwrap();
+    match g
+        .execute_command(&Command::from("GET ANSWER TO q").unwrap())
+        .unwrap()
+    {
+        CommandResponse::Answer {
+            content,
+            sources,
+            cluster_count,
+            ..
+        } => {
+            assert_eq!(content, "c");
+            assert_eq!(sources, vec!["s5"]);
+            assert_eq!(cluster_count, 2);
+        }
+        response => panic!("GET ANSWER returned {:?}", response),
+    }
+    let cmd = Command::from("GET ANSWER TO q RUNNERS UP 3").unwrap();
+    assert_eq!(cmd.runners_up, Some(3));
+    assert_eq!(cmd.to_string(), "GET ANSWER TO q RUNNERS UP 3");
+    assert_eq!(
+        g.execute_command(&cmd).unwrap().to_string(),
+        "c (50.000%), runners up: d (50.000%)"
+    );
+    assert_eq!(
+        g.runners_up(&question_id("q"), 1).unwrap(),
+        vec![(String::from("d"), 0.5)]
+    );
+    assert!(Command::from("GET ANSWER TO q RUNNERS UP 0").is_err());
+
+    let s1 = g.get_source(&source_id("s1"));
+    assert_eq!(s1.quality, g.sources["s1"].quality);
+    assert_eq!(s1.strength, g.sources["s1"].strength);
+    assert_eq!(
+        g.get_source(&source_id("s3")).quality,
+        g.config().quality_of_believed_sources
+    );
+    assert_eq!(
+        g.get_source(&source_id("unknown")).quality,
+        g.config().default_source_quality
+    );
+}
+
+#[test]
+fn test_structured_answers() {
+    #[derive(Debug, Clone, PartialEq, Hash)]
+    struct Location {
+        lat_e6: i64,
+        lon_e6: i64,
+    }
+
+    // Locations within 0.01 degrees of each other are the same answer
+    struct NearbyEqualifier;
+    impl Equalifier<Location> for NearbyEqualifier {
+        fn is_valid_answer(&self, _: &Answer<Location>) -> bool {
+            true
+        }
+        fn get_distance(&self, a: &Answer<Location>, b: &Answer<Location>) -> f64 {
+            let d = (a.content.lat_e6 - b.content.lat_e6)
+                .abs()
+                .max((a.content.lon_e6 - b.content.lon_e6).abs());
+            if d <= 10_000 {
+                0.0
+            } else {
+                1.0
+            }
+        }
+    }
+
+    let near = |lat_e6, lon_e6| Location { lat_e6, lon_e6 };
+    let mut g: Graph<Location> = Graph::new_with_equalifier(Box::new(NearbyEqualifier));
+    let office = question_id("office");
+    g.add_answers(vec![
+        (&office, near(52_370_000, 4_890_000), &source_id("s1")),
+        (&office, near(52_375_000, 4_892_000), &source_id("s2")),
+        (&office, near(48_850_000, 2_350_000), &source_id("s3")),
+    ])
+    .unwrap();
+
+    let result = g.get_answer(&question_id("office")).unwrap();
+    assert_eq!(result.answer, Some(near(52_370_000, 4_890_000)));
+    assert!((result.confidence - 0.75).abs() < 1e-4);
+    assert!(g.get_source(&source_id("s3")).quality < g.get_source(&source_id("s1")).quality);
+    assert_eq!(
+        g.get_answer(&question_id("elsewhere")).unwrap().answer,
+        None
+    );
+}
+
+#[test]
+fn test_error_kinds() {
+    let mut g = Graph::new();
+    let mut execute = |line: &str| g.execute_command(&Command::from(line).unwrap());
+    assert_eq!(
+        execute("CONFIGURE nope 1").err(),
+        Some(ConfidisError::InvalidConfig {
+            key: String::from("nope"),
+            reason: String::from("Unknown configuration key"),
+        })
+    );
+    assert!(matches!(
+        execute("CONFIGURE comparison_method numeric"),
+        Err(ConfidisError::InvalidConfig { key, .. }) if key == "comparison_method"
+    ));
+    assert!(matches!(
+        Command::from("FETCH q1"),
+        Err(ConfidisError::ParseError(_))
+    ));
+    assert!(matches!(
+        Command::from_json(r#"{"cmd": "set", "question": "", "answer": "a", "source": "s1"}"#),
+        Err(ConfidisError::InvalidId(_))
+    ));
+    assert!(matches!(
+        g.set_many(&[("q1", "a", "s1"), ("q2", "b", "s\n2")]),
+        Err(ConfidisError::InvalidId(_))
+    ));
+    assert!(!g.has_question("q1"));
+}
+
+#[test]
+fn test_malformed_commands_dont_panic() {
+    for line in &[
+        "GET",
+        "GET ANSWER",
+        "TEST",
+        "TEST EQUALITY a",
+        "BELIEVE",
+        "CONFIGURE",
+    ] {
+        assert!(matches!(
+            Command::from(line),
+            Err(ConfidisError::ParseError(_))
+        ));
+    }
+
+    let mut g = Graph::new();
+    for cmd_type in [
+        CommandType::Set,
+        CommandType::GetAnswer,
+        CommandType::GetAnswers,
+        CommandType::GetSource,
+        CommandType::Believe,
+        CommandType::Configure,
+        CommandType::TestEquality,
+    ] {
+        // a command built without the fields its type needs
+        let cmd = Command {
+            cmd: cmd_type,
+            ..Default::default()
+        };
+        assert!(matches!(
+            g.execute_command(&cmd),
+            Err(ConfidisError::ParseError(_))
+        ));
+    }
+    assert!(format!("{}", Command::default()).contains("INVALID"));
+
+    let mut execute = |line: &str| g.execute_command(&Command::from(line).unwrap());
+    assert_eq!(
+        execute("GET ANSWERS TO nope").err(),
+        Some(ConfidisError::UnknownQuestion(String::from("nope")))
+    );
+    assert!(matches!(
+        execute("CONFIGURE comparison_method"),
+        Err(ConfidisError::InvalidConfig { .. })
+    ));
+    execute("CONFIGURE comparison_method numeric max_distance=10").unwrap();
+    execute("SET q1 1 FROM s1").unwrap();
+    execute("SET q1 abc FROM s2").unwrap();
+    assert!(matches!(
+        execute("GET ANSWER TO q1").unwrap(),
+        CommandResponse::Answer { content, .. } if content == "1"
+    ));
+    assert!(matches!(
+        execute("TEST EQUALITY 1 abc"),
+        Err(ConfidisError::InvalidAnswer(_))
+    ));
+}
+
+#[test]
+fn test_source_and_question_views() {
+    let mut g = Graph::new();
+    g.set_many(&[("q1", "a", "s1"), ("q1", "a", "s2"), ("q2", "b", "s1")])
+        .unwrap();
+
+    let mut sources: Vec<SourceView> = g.sources().collect();
+    sources.sort_by_key(|source| source.name);
+    assert_eq!(
+        sources.iter().map(|s| s.name).collect::<Vec<_>>(),
+        vec!["s1", "s2"]
+    );
+    assert_eq!(sources[0].quality, g.get_source(&source_id("s1")).quality);
+    assert_eq!(sources[0].strength, g.get_source(&source_id("s1")).strength);
+
+    let q1 = g.questions().find(|q| q.name == "q1").unwrap();
+    assert_eq!(q1.answer_count, 2);
+    assert_eq!(q1.confidence, g.questions["q1"].confidence);
+    assert!(q1.weight > 0.0);
+    assert_eq!(g.questions().map(|q| q.answer_count).sum::<usize>(), 3);
+}
+
+#[test]
+fn test_graph_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Graph>();
+
+    let g = std::sync::Arc::new(std::sync::Mutex::new(Graph::new()));
+    let handles: Vec<_> = ["s1", "s2"]
+        .iter()
+        .map(|source| {
+            let g = g.clone();
+            std::thread::spawn(move || {
+                g.lock()
+                    .unwrap()
+                    .set_answer(&question_id("q1"), "a", &source_id(source))
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap().unwrap();
+    }
+    let result = g.lock().unwrap().get_answer(&question_id("q1")).unwrap();
+    assert_eq!(result.answer.as_deref(), Some("a"));
+}
+
+#[test]
+fn test_fork() {
+    let mut g = Graph::new();
+    g.set_many(&[("q1", "a", "s1"), ("q1", "b", "s2"), ("q2", "c", "s1")])
+        .unwrap();
+    let before = g.get_answer(&question_id("q1")).unwrap();
+    let s2_before = g.get_source(&source_id("s2"));
+
+    let mut what_if = g.fork();
+    what_if.believe(&source_id("s2")).unwrap();
+    what_if
+        .set_answer(&question_id("q3"), "d", &source_id("s2"))
+        .unwrap();
+    assert_eq!(
+        what_if
+            .get_answer(&question_id("q1"))
+            .unwrap()
+            .answer
+            .as_deref(),
+        Some("b")
+    );
+
+    // The original graph is unchanged and still shares the untouched question
+    assert_eq!(g.get_answer(&question_id("q1")).unwrap(), before);
+    assert!(!g.has_question("q3"));
+    assert_eq!(g.get_source(&source_id("s2")).quality, s2_before.quality);
+    assert!(Arc::ptr_eq(&g.questions["q2"], &what_if.questions["q2"]));
+}
+
+#[test]
+fn test_hooks() {
+    use std::sync::Mutex;
+
+    let answers = Arc::new(Mutex::new(Vec::new()));
+    let qualities = Arc::new(Mutex::new(Vec::new()));
+    let mut g = Graph::new();
+    let log = answers.clone();
+    g.on_answer_changed(move |question, before, after| {
+        log.lock()
+            .unwrap()
+            .push((question.to_string(), before.0.clone(), after.0.clone()));
+    });
+    let log = qualities.clone();
+    g.on_source_quality_changed(move |source, before, after| {
+        log.lock()
+            .unwrap()
+            .push((source.to_string(), before, after));
+    });
+
+    g.set_answer(&question_id("q1"), "a", &source_id("s1"))
+        .unwrap();
+    assert_eq!(
+        answers.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        vec![(String::from("q1"), String::from("None"), String::from("a"))]
+    );
+    qualities.lock().unwrap().clear();
+
+    // Believing s2 changes its quality and the answer to q2, which s2 answered
+    g.set_answer(&question_id("q2"), "b", &source_id("s2"))
+        .unwrap();
+    g.set_answer(&question_id("q2"), "c", &source_id("s3"))
+        .unwrap();
+    answers.lock().unwrap().clear();
+    g.believe(&source_id("s2")).unwrap();
+    let changed = qualities.lock().unwrap().pop().unwrap();
+    assert_eq!(changed.0, "s2");
+    assert_eq!(changed.2, g.config().quality_of_believed_sources);
+    assert!(answers
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|(question, _, after)| question == "q2" && after == "b"));
+
+    // Read-only commands don't run hooks
+    answers.lock().unwrap().clear();
+    g.get_answer(&question_id("q2")).unwrap();
+    g.execute_command(&Command::from("GET ANSWER TO q1").unwrap())
+        .unwrap();
+    assert!(answers.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_explain() {
+    let mut g = Graph::new();
+    g.set_many(&[("q1", "a", "s1"), ("q1", "a", "s2"), ("q1", "b", "s3")])
+        .unwrap();
+    let provenance = g.explain(&question_id("q1")).unwrap();
+    let result = g.get_answer(&question_id("q1")).unwrap();
+    assert_eq!(provenance.answer, result.answer);
+    assert_eq!(provenance.confidence, result.confidence);
+    assert_eq!(provenance.evidence.len(), 3);
+    for evidence in &provenance.evidence {
+        assert_eq!(evidence.supports, evidence.content == "a");
+        assert_eq!(
+            evidence.source_quality,
+            g.get_source(&source_id(&evidence.source)).quality
+        );
+    }
+
+    let response = g
+        .execute_read_command(&Command::from("EXPLAIN q1").unwrap())
+        .unwrap();
+    assert_eq!(response, CommandResponse::Explanation(provenance));
+    assert_eq!(
+        serde_json::from_str::<CommandResponse>(&response.to_json()).unwrap(),
+        response
+    );
+    assert!(matches!(
+        g.explain(&question_id("q2")),
+        Err(ConfidisError::UnknownQuestion(_))
+    ));
+}
+
+#[test]
+fn test_audit_log() {
+    let mut g = Graph::new();
+    let get_audit = Command::from("GET AUDIT FOR s1").unwrap();
+    assert!(matches!(
+        g.execute_read_command(&get_audit),
+        Err(ConfidisError::NotImplemented(_))
+    ));
+
+    g.set_audit_log(AuditLog::new(100));
+    g.set_many(&[("q1", "a", "s1"), ("q1", "a", "s2"), ("q1", "b", "s3")])
+        .unwrap();
+    let changes = g.get_audit(&source_id("s1")).unwrap();
+    let last = changes.last().unwrap();
+    assert_eq!(last.question.as_deref(), Some("q1"));
+    assert_eq!(last.new_quality, g.get_source(&source_id("s1")).quality);
+    assert!(last.new_quality > last.old_quality);
+
+    g.believe(&source_id("s3")).unwrap();
+    let response = g.execute_read_command(&get_audit).unwrap();
+    assert_eq!(response, CommandResponse::Audit(changes));
+    let believed = g.get_audit(&source_id("s3")).unwrap().pop().unwrap();
+    assert_eq!(believed.question, None);
+    assert_eq!(believed.new_quality, g.config().quality_of_believed_sources);
+}
+
+#[test]
+fn test_answer_history() {
+    let mut g = Graph::new();
+    let get_history = Command::from("GET HISTORY OF q1").unwrap();
+    assert!(matches!(
+        g.execute_read_command(&get_history),
+        Err(ConfidisError::NotImplemented(_))
+    ));
+
+    g.set_answer_history(AnswerHistory::new(10));
+    g.set_answer(&question_id("q1"), "a", &source_id("s1"))
+        .unwrap();
+    g.set_answer(&question_id("q1"), "a", &source_id("s2"))
+        .unwrap();
+    for source in &["s3", "s4", "s5", "s6"] {
+        g.set_answer(&question_id("q1"), "b", &source_id(source))
+            .unwrap();
+    }
+    let history = g.get_history(&question_id("q1")).unwrap();
+    let answers: Vec<&str> = history.iter().map(|c| c.answer.as_str()).collect();
+    assert_eq!(answers, vec!["a", "b"]);
+    // the confidence when the answer changed, not as more answers came in
+    assert!(
+        history.last().unwrap().confidence < g.get_answer(&question_id("q1")).unwrap().confidence
+    );
+    assert_eq!(
+        g.execute_read_command(&get_history).unwrap(),
+        CommandResponse::History(history)
+    );
+}
+
+#[test]
+fn test_debug_clusters() {
+    let mut g = Graph::new();
+    g.execute_command(
+        &Command::from("CONFIGURE comparison_method numeric max_distance=0.5").unwrap(),
+    )
+    .unwrap();
+    g.set_many(&[("q1", "1", "s1"), ("q1", "1.2", "s2"), ("q1", "4", "s3")])
+        .unwrap();
+    let clusters = g.debug_clusters(&question_id("q1")).unwrap();
+    assert_eq!(clusters.len(), 2);
+    let correct = clusters.iter().find(|c| c.correct).unwrap();
+    assert_eq!(correct.members.len(), 2);
+    assert_eq!(correct.members[0].distance_to_seed, 0.0);
+    assert!(correct.members[1].distance_to_seed > 0.0);
+    assert!(correct.members[1].distance_to_seed <= 0.5);
+    assert_eq!(
+        correct.confidence,
+        g.get_answer(&question_id("q1")).unwrap().confidence
+    );
+
+    let response = g
+        .execute_read_command(&Command::from("DEBUG CLUSTERS q1").unwrap())
+        .unwrap();
+    assert_eq!(response, CommandResponse::Clusters(clusters));
+    assert!(format!("{}", response).contains("(seed)"));
+}
+
+#[test]
+fn test_effect_with_hash_collision() {
+    let mut g = Graph::new();
+    g.create_source_if_not_exists("s1");
+    g.create_source_if_not_exists("s2");
+    // b's hash collides with the correct answer a
+    let mut b = Answer::new(String::from("b"), String::from("s2"));
+    b.hash = Answer::new(String::from("a"), String::from("s1")).hash;
+    g.insert_question(Question {
+        name: String::from("q1"),
+        answers: vec![Answer::new(String::from("a"), String::from("s1")), b],
+        correct_answers: vec![0],
+        weight: 1.0,
+        confidence: 0.9,
+        ..Default::default()
+    });
+    g.add_question_effect("q1");
+    assert!(g.source("s1").unwrap().quality > 0.5);
+    assert!(g.source("s2").unwrap().quality < 0.5);
+    g.remove_question_effect("q1");
+    assert!((g.source("s2").unwrap().quality - 0.5).abs() < 1e-9);
+
+    // GET ANSWERS lists both
+    let answers = match g
+        .execute_read_command(&Command::from("GET ANSWERS TO q1").unwrap())
+        .unwrap()
+    {
+        CommandResponse::Answers(answers) => answers,
+        response => panic!("Unexpected response {:?}", response),
+    };
+    let mut contents: Vec<&str> = answers.iter().map(|pair| pair.answer.as_str()).collect();
+    contents.sort_unstable();
+    assert_eq!(contents, ["a", "b"]);
+}
+
+#[test]
+fn test_revert_without_strength() {
+    // Sources start without strength, so q1 is all the evidence s1 and s2 have
+    let mut g = Graph::new();
+    g.config.initial_source_strength = 0.0;
+    g.set_answer(&question_id("q1"), "a", &source_id("s1"))
+        .unwrap();
+    g.set_answer(&question_id("q1"), "a", &source_id("s2"))
+        .unwrap();
+    assert_eq!(g.source("s1").unwrap().quality, 1.0);
+
+    g.remove_question_effect("q1");
+    let s1 = g.source("s1").unwrap();
+    assert_eq!((s1.quality, s1.strength), (0.5, 0.0));
+
+    g.add_question_effect("q1");
+    g.set_answer(&question_id("q1"), "b", &source_id("s3"))
+        .unwrap();
+    assert!(g.sources().all(|s| s.quality.is_finite()));
+    let (answer, confidence) = g.compute_answer("q1").unwrap();
+    assert_eq!(answer, "a");
+    assert!(confidence.is_finite());
+}
+
+#[test]
+fn test_revert_after_believe() {
+    let mut g = Graph::new();
+    g.set_many(&[
+        ("q1", "a", "s1"),
+        ("q1", "a", "s2"),
+        ("q1", "a", "s3"),
+        ("q1", "w", "s4"),
+        ("q2", "b", "s1"),
+        ("q2", "c", "s2"),
+        ("q2", "b", "s3"),
+        ("q2", "w", "s4"),
+    ])
+    .unwrap();
+    g.believe(&source_id("s4")).unwrap();
+    assert_eq!(g.source_stats("s4").quality, 0.999);
+
+    // BELIEVE took the effects out of s4, so recomputing q2 doesn't revert it
+    // in s4, only adds it back
+    g.set_answer(&question_id("q2"), "b", &source_id("s5"))
+        .unwrap();
+    let s4 = g.source("s4").unwrap();
+    assert_eq!(s4.quality, 0.999);
+    assert!(!s4.holds_effect("q1") && s4.holds_effect("q2"));
+
+    // The effect q2 adds now is taken out again exactly
+    g.remove_question_effect("q2");
+    g.add_question_effect("q2");
+    g.remove_question_effect("q2");
+    assert!((g.source("s4").unwrap().quality - 0.999).abs() < 1e-12);
+}
+
+#[test]
+fn test_deterministic() {
+    let graph = |deterministic: bool, sets: &[(&str, &str, &str)]| {
+        let mut g = Graph::new();
+        g.set_deterministic(deterministic);
+        g.execute_command(
+            &Command::from("CONFIGURE comparison_method numeric max_distance=10").unwrap(),
+        )
+        .unwrap();
+        for (question, answer, source) in sets {
+            g.set_answer(&question_id(question), answer, &source_id(source))
+                .unwrap();
+        }
+        g
+    };
+    let answers = |g: &mut Graph, question: &str| {
+        g.execute_command(&Command::from(&format!("GET ANSWERS TO {}", question)).unwrap())
+            .unwrap()
+            .to_string()
+    };
+    let sets = [
+        ("q1", "102", "s2"),
+        ("q1", "100", "s1"),
+        ("q1", "100", "s3"),
+        ("q2", "7", "s4"),
+    ];
+    let reversed: Vec<_> = sets.iter().rev().cloned().collect();
+
+    // By default the first answer of a cluster is reported
+    assert_eq!(graph(false, &sets).compute_answer("q1").unwrap().0, "102");
+    assert_eq!(
+        graph(false, &reversed).compute_answer("q1").unwrap().0,
+        "100"
+    );
+
+    // In deterministic mode the most common one is, in any order
+    let mut g = graph(true, &sets);
+    let mut g_reversed = graph(true, &reversed);
+    assert_eq!(g.compute_answer("q1").unwrap().0, "100");
+    assert_eq!(g_reversed.compute_answer("q1").unwrap().0, "100");
+    assert_eq!(answers(&mut g, "q1"), answers(&mut g_reversed, "q1"));
+    let names: Vec<&str> = g.sources().map(|s| s.name).collect();
+    assert_eq!(names, vec!["s1", "s2", "s3", "s4"]);
+    let names: Vec<&str> = g.questions().map(|q| q.name).collect();
+    assert_eq!(names, vec!["q1", "q2"]);
+
+    // Tied clusters are broken the same way in any order
+    let tied = [("q3", "1", "s1"), ("q3", "50", "s2")];
+    let tied_reversed = [("q3", "50", "s2"), ("q3", "1", "s1")];
+    assert_eq!(
+        graph(true, &tied).compute_answer("q3").unwrap(),
+        graph(true, &tied_reversed).compute_answer("q3").unwrap()
+    );
+    assert_eq!(
+        answers(&mut graph(true, &tied), "q3"),
+        answers(&mut graph(true, &tied_reversed), "q3")
+    );
+
+    // Equal graphs have equal snapshots
+    assert_eq!(
+        bincode::serialize(&g).unwrap(),
+        bincode::serialize(&graph(true, &sets)).unwrap()
+    );
+}
+
+#[test]
+fn test_duplicate_answers() {
+    let graph = |policy: &str, sets: &[(&str, &str, &str)]| {
+        let mut g = Graph::new();
+        g.execute_command(
+            &Command::from(&format!("CONFIGURE duplicate_answers {}", policy)).unwrap(),
+        )
+        .unwrap();
+        for (question, answer, source) in sets {
+            g.set_answer(&question_id(question), answer, &source_id(source))
+                .unwrap();
+        }
+        g
+    };
+    let sets = [("q1", "a", "s1"), ("q1", "a", "s1"), ("q1", "b", "s2")];
+
+    // By default s1 counts twice, like two sources agreeing
+    let g = graph("allow", &sets);
+    assert_eq!(g.questions["q1"].answers.len(), 3);
+    let (answer, confidence) = g.compute_answer("q1").unwrap();
+    assert_eq!(answer, "a");
+    assert_eq!(confidence, 0.75);
+
+    let g = graph("ignore", &sets);
+    assert_eq!(g.questions["q1"].answers.len(), 2);
+    assert_eq!(g.compute_answer("q1").unwrap().1, 0.5);
+    let mut ignored = graph("ignore", &[("q1", "a", "s1")]);
+    ignored
+        .set_many(&[("q1", "b", "s1"), ("q2", "c", "s1"), ("q2", "d", "s1")])
+        .unwrap();
+    assert_eq!(ignored.compute_answer("q1").unwrap().0, "a");
+    assert_eq!(ignored.compute_answer("q2").unwrap().0, "c");
+
+    // The source's latest answer replaces the earlier one, and the effect the
+    // earlier one had on the sources is reverted
+    let g = graph(
+        "replace",
+        &[("q1", "a", "s1"), ("q1", "a", "s2"), ("q1", "b", "s1")],
+    );
+    assert_eq!(g.questions["q1"].answers.len(), 2);
+    assert_eq!(g.compute_answer("q1").unwrap().1, 0.5);
+    for source in g.sources() {
+        assert!((source.quality - 0.5).abs() < 1e-9);
+        assert!((source.strength - 1.0).abs() < 1e-9);
+    }
+    let mut replaced = graph("replace", &[]);
+    replaced
+        .set_many(&[("q1", "a", "s1"), ("q1", "b", "s1")])
+        .unwrap();
+    assert_eq!(
+        replaced.compute_answer("q1").unwrap(),
+        (String::from("b"), 0.5)
+    );
+
+    assert_eq!(
+        replaced
+            .execute_command(&Command::from("CONFIGURE duplicate_answers allow").unwrap())
+            .unwrap(),
+        CommandResponse::Configure {
+            previous: String::from("replace")
+        }
+    );
+}
+
+#[test]
+fn test_normalize() {
+    let mut g = Graph::new();
+    g.execute_command(
+        &Command::from("CONFIGURE normalize trim lowercase strip_punctuation").unwrap(),
+    )
+    .unwrap();
+    g.set_answer(&question_id("q1"), " Paris.", &source_id("s1"))
+        .unwrap();
+    g.set_answer(&question_id("q1"), "PARIS", &source_id("s2"))
+        .unwrap();
+    g.set_many(&[("q1", "paris!", "s3"), ("q1", "Lyon", "s4")])
+        .unwrap();
+
+    // The answers are stored normalized, with equal hashes
+    let answers = &g.questions["q1"].answers;
+    assert!(answers[..3].iter().all(|answer| answer.content == "paris"));
+    assert!(answers[..3]
+        .iter()
+        .all(|answer| answer.hash == answers[0].hash));
+    assert_eq!(answers[3].content, "lyon");
+    assert_eq!(g.compute_answer("q1").unwrap().0, "paris");
+
+    // Answers stored before are kept as they were
+    assert_eq!(
+        g.execute_command(&Command::from("CONFIGURE normalize none").unwrap())
+            .unwrap(),
+        CommandResponse::Configure {
+            previous: String::from("trim lowercase strip_punctuation")
+        }
+    );
+    g.set_answer(&question_id("q1"), "Paris", &source_id("s5"))
+        .unwrap();
+    assert_eq!(g.questions["q1"].answers[4].content, "Paris");
+    assert_eq!(g.questions["q1"].answers[0].content, "paris");
+}
+
+#[test]
+fn test_strict() {
+    let mut g = Graph::new();
+    g.set_strict(true);
+    g.set_answer(&question_id("q1"), "a", &source_id("s1"))
+        .unwrap();
+    let cmd = Command::from("GET SOURCE s2").unwrap();
+    assert_eq!(
+        g.execute_command(&cmd),
+        Err(ConfidisError::UnknownSource(String::from("s2")))
+    );
+    assert_eq!(
+        g.execute_read_command(&cmd),
+        Err(ConfidisError::UnknownSource(String::from("s2")))
+    );
+    assert!(g.source("s2").is_none());
+    assert!(g
+        .execute_command(&Command::from("GET SOURCE s1").unwrap())
+        .is_ok());
+
+    let cmd = Command::from("GET ANSWER TO q2").unwrap();
+    assert_eq!(
+        g.execute_command(&cmd),
+        Err(ConfidisError::UnknownQuestion(String::from("q2")))
+    );
+    assert_eq!(
+        g.get_answer(&question_id("q2")).err(),
+        Some(ConfidisError::UnknownQuestion(String::from("q2")))
+    );
+    assert_eq!(
+        g.get_answer(&question_id("q1")).unwrap().answer.unwrap(),
+        "a"
+    );
+
+    g.set_strict(false);
+    assert_eq!(g.get_answer(&question_id("q2")).unwrap().answer, None);
+    assert!(g
+        .execute_command(&Command::from("GET SOURCE s2").unwrap())
+        .is_ok());
+    assert!(g.source("s2").is_some());
+}
+
+#[test]
+fn test_compare_sources() {
+    let mut g = Graph::new();
+    g.set_many(&[
+        ("q1", "a", "s1"),
+        ("q1", "a", "s2"),
+        ("q1", "b", "s3"),
+        ("q2", "c", "s1"),
+        ("q2", "d", "s2"),
+        ("q2", "d", "s3"),
+        ("q3", "e", "s1"),
+    ])
+    .unwrap();
+    let pairs = g
+        .compare_sources(&[source_id("s1"), source_id("s2"), source_id("s4")])
+        .unwrap();
+    assert_eq!(pairs.len(), 3);
+    assert_eq!(
+        (pairs[0].source1.as_str(), pairs[0].source2.as_str()),
+        ("s1", "s2")
+    );
+    assert_eq!((pairs[0].co_answered, pairs[0].agreed), (2, 1));
+    assert_eq!(pairs[0].agreement_rate, Some(0.5));
+    assert_eq!((pairs[1].co_answered, pairs[1].agreement_rate), (0, None));
+
+    let response = g
+        .execute_read_command(&Command::from("COMPARE SOURCES s2 s3").unwrap())
+        .unwrap();
+    assert_eq!(response.to_string(), "s2 s3: 1 of 2 agreed (50.000%)");
+    assert_eq!(
+        serde_json::from_str::<CommandResponse>(&response.to_json()).unwrap(),
+        response
+    );
+    let cmd = Command::from_json(r#"{"cmd": "compare_sources", "sources": ["s1", "s3"]}"#).unwrap();
+    assert_eq!(cmd.to_string(), "COMPARE SOURCES s1 s3");
+    assert!(g.execute_command(&cmd).is_ok());
+    assert!(Command::from("COMPARE SOURCES s1").is_err());
+    assert!(Command::from_json(r#"{"cmd": "compare_sources", "sources": ["s1"]}"#).is_err());
+
+    g.set_strict(true);
+    assert_eq!(
+        g.compare_sources(&[source_id("s1"), source_id("s4")]).err(),
+        Some(ConfidisError::UnknownSource(String::from("s4")))
+    );
+}
+
+#[test]
+fn test_confidence_decay() {
+    let mut g = Graph::new();
+    g.set_many(&[("q1", "a", "s1"), ("q1", "a", "s2"), ("q2", "b", "s1")])
+        .unwrap();
+    let fresh = g.get_answer(&question_id("q1")).unwrap().confidence;
+    let fresh_q2 = g.get_answer(&question_id("q2")).unwrap().confidence;
+    assert!(g.questions["q1"].answered_at > 0);
+
+    // q1 was last answered 3 days ago, 2 days after its confidence started to
+    // decay with a half life of a day
+    let day = 24 * 3600 * 1000;
+    Arc::make_mut(g.questions.get_mut("q1").unwrap()).answered_at -= 3 * day;
+    assert_eq!(g.get_answer(&question_id("q1")).unwrap().confidence, fresh);
+    for line in &[
+        "CONFIGURE confidence_decay_after 86400",
+        "CONFIGURE confidence_half_life 86400",
+    ] {
+        g.execute_command(&Command::from(line).unwrap()).unwrap();
+    }
+    // the clock moves on between reads, so they agree to within a few ms
+    let decayed = g.get_answer(&question_id("q1")).unwrap().confidence;
+    assert!((decayed - fresh / 4.).abs() < 1e-6);
+    assert!((g.compute_answer("q1").unwrap().1 - decayed).abs() < 1e-6);
+    assert!((g.explain(&question_id("q1")).unwrap().confidence - decayed).abs() < 1e-6);
+    assert_eq!(
+        g.get_answer(&question_id("q2")).unwrap().confidence,
+        fresh_q2
+    );
+
+    // Answering again makes the question fresh
+    g.set_answer(&question_id("q1"), "a", &source_id("s3"))
+        .unwrap();
+    assert!(g.get_answer(&question_id("q1")).unwrap().confidence > fresh);
+
+    // Replayed answers are as old as their journal entries
+    g.set_replay_time(Some(day));
+    g.set_answer(&question_id("q3"), "c", &source_id("s1"))
+        .unwrap();
+    g.set_replay_time(None);
+    assert_eq!(g.questions["q3"].answered_at, day);
+    assert_eq!(g.get_answer(&question_id("q3")).unwrap().confidence, 0.0);
+}
+
+#[test]
+fn test_late_answer_weight() {
+    let mut g = Graph::new();
+    g.set_many(&[("q1", "a", "s1"), ("q1", "b", "s2")]).unwrap();
+    // s3 and s4 follow s2
+    g.set_many(&[("q1", "b", "s3"), ("q1", "b", "s4")]).unwrap();
+    let (answer, confidence) = g.compute_answer("q1").unwrap();
+    assert_eq!(answer, "b");
+
+    // Half a vote each
+    let mut discounted = g.fork();
+    for line in &[
+        "CONFIGURE late_answer_after 2",
+        "CONFIGURE late_answer_weight 0.5",
+    ] {
+        discounted
+            .execute_command(&Command::from(line).unwrap())
+            .unwrap();
+    }
+    let (answer, discounted_confidence) = discounted.compute_answer("q1").unwrap();
+    assert_eq!(answer, "b");
+    assert!(discounted_confidence < confidence);
+    assert_eq!(
+        discounted.explain(&question_id("q1")).unwrap().confidence,
+        discounted_confidence
+    );
+
+    // Only the first answer counts
+    discounted.config.late_answer_after = 1.0;
+    discounted.config.late_answer_weight = 0.0;
+    assert_eq!(discounted.compute_answer("q1").unwrap().0, "a");
+}
+
+#[test]
+fn test_min_sources_per_answer() {
+    let mut g = Graph::new();
+    g.execute_command(&Command::from("CONFIGURE min_sources_per_answer 3").unwrap())
+        .unwrap();
+    // The same source answering twice is still one source
+    g.set_many(&[("q1", "a", "s1"), ("q1", "a", "s1"), ("q1", "a", "s2")])
+        .unwrap();
+    let (_, confidence) = g.compute_answer("q1").unwrap();
+    let get = |g: &mut Graph| {
+        g.execute_command(&Command::from("GET ANSWER TO q1").unwrap())
+            .unwrap()
+    };
+    let response = get(&mut g);
+    assert_eq!(
+        response,
+        CommandResponse::InsufficientEvidence {
+            content: String::from("a"),
+            confidence,
+            source_count: 2,
+            min_sources: 3,
+        }
+    );
+    assert_eq!(
+        response.to_string(),
+        format!(
+            "Insufficient evidence: a ({:.3}%) from 2 of 3 sources",
+            confidence * 100.
+        )
+    );
+    let json = serde_json::to_string(&response).unwrap();
+    assert_eq!(
+        serde_json::from_str::<CommandResponse>(&json).unwrap(),
+        response
+    );
+    assert!(
+        g.get_answer(&question_id("q1"))
+            .unwrap()
+            .insufficient_evidence
+    );
+    assert!(
+        g.get_answer(&question_id("q2"))
+            .unwrap()
+            .insufficient_evidence
+    );
+
+    g.set_answer(&question_id("q1"), "b", &source_id("s3"))
+        .unwrap();
+    assert!(matches!(
+        get(&mut g),
+        CommandResponse::Answer { content, .. } if content == "a"
+    ));
+    let response = g
+        .execute_command(&Command::from("MGET q1 q2").unwrap())
+        .unwrap();
+    let lines: Vec<String> = response.to_string().lines().map(String::from).collect();
+    assert!(lines[0].starts_with("q1: a (") && lines[0].ends_with("%)"));
+    assert_eq!(lines[1], "q2: None (0.000%), insufficient evidence");
+}
+
+#[test]
+fn test_exclude() {
+    let mut g = Graph::new();
+    g.set_many(&[
+        ("q1", "a", "s1"),
+        ("q1", "a", "s2"),
+        ("q1", "b", "s3"),
+        ("q1", "b", "s4"),
+        ("q1", "b", "s5"),
+        ("q2", "c", "s4"),
+        ("q3", "d", "s1"),
+        ("q3", "d", "s6"),
+    ])
+    .unwrap();
+    assert_eq!(g.compute_answer("q1").unwrap().0, "b");
+    let s4_before = g.source_stats("s4");
+
+    let cmd = Command::from("EXCLUDE s4 FROM q1").unwrap();
+    assert_eq!(cmd.to_string(), "EXCLUDE s4 FROM q1");
+    assert_eq!(g.execute_command(&cmd).unwrap(), CommandResponse::Exclude);
+    g.exclude_source(&question_id("q1"), &source_id("s5"))
+        .unwrap();
+    // The answers are kept, but a wins without s4's and s5's
+    assert_eq!(g.questions["q1"].answers.len(), 5);
+    assert_eq!(g.questions["q1"].excluded_sources, vec!["s4", "s5"]);
+    let result = g.get_answer(&question_id("q1")).unwrap();
+    assert_eq!(result.answer.as_deref(), Some("a"));
+    assert_eq!(result.cluster_count, 2);
+    // s4 is only judged by q2 now, where its answer still counts
+    assert_ne!(g.source_stats("s4"), s4_before);
+    assert_eq!(
+        g.get_answer(&question_id("q2")).unwrap().sources,
+        vec!["s4"]
+    );
+
+    // Later answers of excluded sources are ignored too
+    g.set_many(&[("q1", "b", "s4"), ("q1", "b", "s4")]).unwrap();
+    assert_eq!(g.compute_answer("q1").unwrap().0, "a");
+
+    // Without answers left the question has none, and s6 is as if it hadn't
+    // answered it
+    for source in &["s1", "s6"] {
+        g.exclude_source(&question_id("q3"), &source_id(source))
+            .unwrap();
+    }
+    assert_eq!(g.compute_answer("q3").unwrap(), (String::from("None"), 0.0));
+    assert_eq!(
+        g.source_stats("s6"),
+        SourceStats {
+            quality: g.config().default_source_quality,
+            strength: g.config().initial_source_strength,
+        }
+    );
+
+    let mut bytes = Vec::new();
+    g.save_snapshot(&mut bytes).unwrap();
+    let restored = Graph::load_snapshot(&bytes[..]).unwrap();
+    assert_eq!(restored.questions["q1"].excluded_sources, vec!["s4", "s5"]);
+    assert_eq!(restored.compute_answer("q1").unwrap().0, "a");
+}
+
+#[test]
+fn test_honeypot() {
+    let mut g = Graph::new();
+    let cmd = Command::from("HONEYPOT q1 a").unwrap();
+    assert_eq!(cmd.to_string(), "HONEYPOT q1 a");
+    assert_eq!(g.execute_command(&cmd).unwrap(), CommandResponse::Honeypot);
+    // Served like any question, so the wrong majority wins. GET ANSWER reports
+    // the confidence computed from the sources' qualities, like GET ANSWERS,
+    // rather than the known answer, which would give the honeypot away.
+    g.set_many(&[("q1", "a", "s1"), ("q1", "b", "s2"), ("q1", "b", "s3")])
+        .unwrap();
+    let result = g.get_answer(&question_id("q1")).unwrap();
+    assert_eq!(result.answer.as_deref(), Some("b"));
+    assert_eq!(result.sources, vec!["s2", "s3"]);
+    let answer = g
+        .execute_command(&Command::from("GET ANSWER TO q1").unwrap())
+        .unwrap();
+    let answers = g
+        .execute_command(&Command::from("GET ANSWERS TO q1").unwrap())
+        .unwrap();
+    match (answer, answers) {
+        (
+            CommandResponse::Answer {
+                content,
+                confidence,
+                ..
+            },
+            CommandResponse::Answers(answers),
+        ) => {
+            let best = answers
+                .iter()
+                .max_by(|a, b| a.confidence.partial_cmp(&b.confidence).unwrap())
+                .unwrap();
+            assert_eq!(
+                (content.as_str(), confidence),
+                (best.answer.as_str(), best.confidence)
+            );
+            assert_eq!(confidence, result.confidence);
+        }
+        responses => panic!("unexpected responses {:?}", responses),
+    }
+
+    // With honeypot_weight, the default 10, rather than the weight of a
+    // question answered like this
+    let mut regular = Graph::new();
+    regular
+        .set_many(&[("q1", "a", "s1"), ("q1", "a", "s4"), ("q1", "b", "s2")])
+        .unwrap();
+    let honeypot_quality = g.source_stats("s1").quality;
+    assert!(honeypot_quality > regular.source_stats("s1").quality);
+    assert!((honeypot_quality - 10.5 / 11.0).abs() < 1e-9);
+    assert!((g.source_stats("s2").quality - 0.5 / 11.0).abs() < 1e-9);
+
+    // Marking an answered question recomputes it, the effect it had is undone,
+    // but what GET ANSWER reports stays the same
+    let mut marked = regular.fork();
+    marked.set_honeypot(&question_id("q1"), "b").unwrap();
+    assert!((marked.source_stats("s1").quality - 0.5 / 11.0).abs() < 1e-9);
+    assert_eq!(
+        marked.compute_answer("q1").unwrap(),
+        regular.compute_answer("q1").unwrap()
+    );
+
+    let mut bytes = Vec::new();
+    g.save_snapshot(&mut bytes).unwrap();
+    let restored = Graph::load_snapshot(&bytes[..]).unwrap();
+    assert_eq!(restored.questions["q1"].honeypot.as_deref(), Some("a"));
+}
+
+#[test]
+fn test_schema() {
+    let mut g = Graph::new();
+    g.execute_command(&Command::from("CONFIGURE normalize trim").unwrap())
+        .unwrap();
+    // The schema is the rest of the line as written
+    let cmd = Command::from("SCHEMA q1 int   min=0 max=120").unwrap();
+    assert_eq!(cmd.schema.as_deref(), Some("int   min=0 max=120"));
+    assert_eq!(Command::from(&cmd.to_string()).unwrap(), cmd);
+    assert_eq!(
+        g.execute_command(&cmd).unwrap(),
+        CommandResponse::Schema {
+            previous: String::from("none")
+        }
+    );
+    // Answers are checked normalized
+    g.set_answer(&question_id("q1"), " 42 ", &source_id("s1"))
+        .unwrap();
+    // Violating answers are rejected, nothing is stored
+    assert_eq!(
+        g.set_answer(&question_id("q1"), "forty-two", &source_id("s2")),
+        Err(ConfidisError::SchemaViolation {
+            question: String::from("q1"),
+            answer: String::from("forty-two"),
+            reason: String::from("must be an integer"),
+        })
+    );
+    assert!(matches!(
+        g.set_many(&[("q2", "a", "s1"), ("q1", "121", "s3")]),
+        Err(ConfidisError::SchemaViolation { .. })
+    ));
+    assert_eq!(g.questions["q1"].answers.len(), 1);
+    assert!(!g.questions.contains_key("q2"));
+    assert!(!g.sources.contains_key("s2"));
+    assert!(Command::from("SCHEMA q1").is_err());
+    assert!(matches!(
+        g.execute_command(&Command::from("SCHEMA q1 date").unwrap()),
+        Err(ConfidisError::ParseError(_))
+    ));
+
+    let mut bytes = Vec::new();
+    g.save_snapshot(&mut bytes).unwrap();
+    let mut restored = Graph::load_snapshot(&bytes[..]).unwrap();
+    assert!(restored.set_many(&[("q1", "-1", "s2")]).is_err());
+    restored.set_schema(&question_id("q1"), "none").unwrap();
+    restored.set_many(&[("q1", "-1", "s2")]).unwrap();
+    assert_eq!(g.questions["q1"].schema, "int min=0 max=120".parse().ok());
+
+    // Quoted enum values and JSON schemas with spaces
+    for line in &[
+        r#"SCHEMA city enum "New York" Boston"#,
+        r#"SCHEMA name json {"type": "string", "maxLength": 8}"#,
+    ] {
+        let cmd = Command::from(line).unwrap();
+        assert_eq!(cmd.to_string(), *line);
+        assert_eq!(Command::from(&cmd.to_string()).unwrap(), cmd);
+        g.execute_command(&cmd).unwrap();
+    }
+    for line in &[r#"SET city "New York" FROM s1"#, "SET name Ada FROM s1"] {
+        g.execute_command(&Command::from(line).unwrap()).unwrap();
+    }
+    for line in &["SET city New FROM s2", r#"SET name "Ada Lovelace" FROM s2"#] {
+        assert!(matches!(
+            g.execute_command(&Command::from(line).unwrap()),
+            Err(ConfidisError::SchemaViolation { .. })
+        ));
+    }
+    let mut bytes = Vec::new();
+    g.save_snapshot(&mut bytes).unwrap();
+    let restored = Graph::load_snapshot(&bytes[..]).unwrap();
+    assert_eq!(
+        restored.questions["city"].schema,
+        r#"enum "New York" Boston"#.parse().ok()
+    );
+}
+
+#[test]
+fn test_rate_limit() {
+    let mut g = Graph::new();
+    g.execute_command(&Command::from("CONFIGURE rate_limit_answers 2").unwrap())
+        .unwrap();
+    g.set_many(&[("q1", "a", "s1"), ("q2", "a", "s1"), ("q1", "a", "s2")])
+        .unwrap();
+    assert_eq!(
+        g.set_answer(&question_id("q3"), "a", &source_id("s1")),
+        Err(ConfidisError::RateLimited {
+            source: String::from("s1"),
+            limit: 2,
+            window: 60.0,
+        })
+    );
+    // A batch with a source over its limit adds nothing
+    assert!(g
+        .set_many(&[("q3", "a", "s2"), ("q3", "b", "s1"), ("q4", "b", "s1")])
+        .is_err());
+    assert!(!g.questions.contains_key("q3"));
+    g.set_answer(&question_id("q3"), "a", &source_id("s2"))
+        .unwrap();
+    assert_eq!(
+        g.rate_limit_violations(),
+        &BTreeMap::from([(String::from("s1"), 3)])
+    );
+    assert_eq!(g.graph_stats().rate_limited_answer_count, 3);
+
+    // Journal replay isn't limited
+    g.set_replay_time(Some(1));
+    g.set_answer(&question_id("q3"), "a", &source_id("s1"))
+        .unwrap();
+    g.set_replay_time(None);
+
+    // Deterministic mode is, on its clock that doesn't move
+    let mut g = Graph::new();
+    g.set_deterministic(true);
+    g.execute_command(&Command::from("CONFIGURE rate_limit_answers 2").unwrap())
+        .unwrap();
+    g.set_many(&[("q1", "a", "s1"), ("q2", "a", "s1")]).unwrap();
+    assert!(g
+        .set_answer(&question_id("q3"), "a", &source_id("s1"))
+        .is_err());
+
+    // Duplicates duplicate_answers ignores aren't counted
+    let mut g = Graph::new();
+    for line in &[
+        "CONFIGURE rate_limit_answers 2",
+        "CONFIGURE duplicate_answers ignore",
+    ] {
+        g.execute_command(&Command::from(line).unwrap()).unwrap();
+    }
+    g.set_answer(&question_id("q1"), "a", &source_id("s1"))
+        .unwrap();
+    g.set_many(&[("q1", "b", "s1"), ("q1", "c", "s1")]).unwrap();
+    g.set_answer(&question_id("q1"), "d", &source_id("s1"))
+        .unwrap();
+    g.set_answer(&question_id("q2"), "a", &source_id("s1"))
+        .unwrap();
+    assert!(g.rate_limit_violations().is_empty());
+}
+
+#[test]
+fn test_question_type() {
+    let mut g = Graph::new();
+    g.set_many(&[
+        ("q1", "48.8584,2.2945", "s1"),
+        ("q1", "48.8583,2.2944", "s2"),
+        ("q1", "51.5007,-0.1246", "s3"),
+        ("q2", "red", "s1"),
+        ("q2", "red", "s2"),
+    ])
+    .unwrap();
+    // Under the exact comparison method every location is its own answer
+    assert_eq!(g.get_answer(&question_id("q1")).unwrap().cluster_count, 3);
+    let cmd = Command::from("TYPE q1 geo  max_distance=0.5").unwrap();
+    assert_eq!(cmd.question_type.as_deref(), Some("geo  max_distance=0.5"));
+    assert_eq!(Command::from(&cmd.to_string()).unwrap(), cmd);
+    assert_eq!(
+        g.execute_command(&cmd).unwrap(),
+        CommandResponse::Type {
+            previous: String::from("none")
+        }
+    );
+    // The question is recomputed with the type's comparison method, the
+    // other questions keep the graph's
+    let answer = g.get_answer(&question_id("q1")).unwrap();
+    assert_eq!(answer.cluster_count, 2);
+    assert_eq!(answer.sources, vec!["s1", "s2"]);
+    assert_eq!(g.get_answer(&question_id("q2")).unwrap().cluster_count, 1);
+    assert_eq!(
+        g.set_answer(&question_id("q1"), "Paris", &source_id("s4")),
+        Err(ConfidisError::SchemaViolation {
+            question: String::from("q1"),
+            answer: String::from("Paris"),
+            reason: String::from("must be a location as <latitude>,<longitude>"),
+        })
+    );
+    assert!(matches!(
+        g.execute_command(&Command::from("TYPE q2 boolean").unwrap()),
+        Err(ConfidisError::ParseError(_))
+    ));
+
+    let mut bytes = Vec::new();
+    g.save_snapshot(&mut bytes).unwrap();
+    let mut restored = Graph::load_snapshot(&bytes[..]).unwrap();
+    assert_eq!(
+        restored.get_answer(&question_id("q1")),
+        g.get_answer(&question_id("q1"))
+    );
+    restored
+        .set_question_type(&question_id("q1"), "none")
+        .unwrap();
+    assert_eq!(
+        restored
+            .get_answer(&question_id("q1"))
+            .unwrap()
+            .cluster_count,
+        3
+    );
+    assert_eq!(
+        g.questions["q1"].question_type,
+        "geo max_distance=0.5".parse().ok()
+    );
+}
+
+#[test]
+fn test_rebuild() {
+    let answers = [
+        [("q1", "1", "s1"), ("q1", "1.1", "s2"), ("q1", "9", "s3")],
+        [("q2", "5", "s1"), ("q2", "5.2", "s2"), ("q2", "5", "s3")],
+    ];
+    let mut g = Graph::new();
+    for entries in &answers {
+        g.set_many(entries).unwrap();
+    }
+    g.believe(&source_id("s3")).unwrap();
+    g.execute_command(
+        &Command::from("CONFIGURE comparison_method numeric max_distance=1").unwrap(),
+    )
+    .unwrap();
+    let cmd = Command::from("REBUILD").unwrap();
+    assert_eq!(cmd.to_string(), "REBUILD");
+    assert_eq!(g.execute_command(&cmd).unwrap(), CommandResponse::Rebuild);
+
+    // The same as answering with the comparison method from the start, without
+    // the BELIEVE
+    let mut fresh = Graph::new();
+    fresh
+        .execute_command(
+            &Command::from("CONFIGURE comparison_method numeric max_distance=1").unwrap(),
+        )
+        .unwrap();
+    fresh.begin_bulk_load();
+    for entries in &answers {
+        fresh.set_many(entries).unwrap();
+    }
+    fresh.finish_bulk_load().unwrap();
+    for source_name in ["s1", "s2", "s3"] {
+        let (rebuilt, expected) = (g.source_stats(source_name), fresh.source_stats(source_name));
+        assert!((rebuilt.quality - expected.quality).abs() < 1e-9);
+        assert!((rebuilt.strength - expected.strength).abs() < 1e-9);
+    }
+    assert!(g.source_stats("s2").quality > g.source_stats("s3").quality);
+    assert_eq!(g.compute_answer("q1"), fresh.compute_answer("q1"));
+
+    g.begin_bulk_load();
+    assert_eq!(g.rebuild(), Err(ConfidisError::BulkLoadInProgress));
+}
+
+#[test]
+fn test_configure_journaled() {
+    let path =
+        std::env::temp_dir().join(format!("confidis-configure-{}.journal", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut g = Graph::new();
+    g.set_journal(Journal::open(&path).unwrap());
+    g.configure(ConfigKey::UndoDepth, ConfigValue::Number(4.0))
+        .unwrap();
+    g.configure(
+        ConfigKey::AnswerHash,
+        ConfigValue::AnswerHash(AnswerHasher::Xxh64),
+    )
+    .unwrap();
+    g.configure(
+        ConfigKey::ComparisonMethod,
+        ConfigValue::ComparisonMethod(EqualifierConfig::Numeric { max_distance: 0.5 }),
+    )
+    .unwrap();
+    g.sync_journal().unwrap();
+
+    // The typed settings survive replay like CONFIGURE does
+    let replayed = Graph::replay(&path).unwrap();
+    assert_eq!(replayed.config().undo_depth, 4.0);
+    assert_eq!(replayed.config().answer_hash, AnswerHasher::Xxh64);
+    assert_eq!(
+        replayed.equalifier_config(),
+        EqualifierConfig::Numeric { max_distance: 0.5 }
+    );
+
+    // and can be undone
+    g.undo(1).unwrap();
+    assert_eq!(g.equalifier_config(), EqualifierConfig::Exact);
+    assert_eq!(g.config().answer_hash, AnswerHasher::Xxh64);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_undo() {
+    let path = std::env::temp_dir().join(format!("confidis-undo-{}.journal", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut g = Graph::new();
+    g.set_journal(Journal::open(&path).unwrap());
+    let run = |g: &mut Graph, line: &str| {
+        g.execute_command(&Command::from(line).unwrap())
+            .unwrap()
+            .to_string()
+    };
+    // Nothing is remembered without an undo_depth
+    run(&mut g, "SET q1 a FROM s1");
+    assert_eq!(run(&mut g, "UNDO"), "Nothing to undo");
+    run(&mut g, "CONFIGURE undo_depth 10");
+    run(&mut g, "SET q1 a FROM s2");
+    let before = (
+        run(&mut g, "GET ANSWERS TO q1"),
+        run(&mut g, "GET SOURCE s1"),
+    );
+
+    // A wrong batch and BELIEVE are taken back exactly
+    g.set_many(&[("q1", "b", "s3"), ("q1", "b", "s4"), ("q2", "c", "s3")])
+        .unwrap();
+    run(&mut g, "BELIEVE s3");
+    assert_eq!(run(&mut g, "UNDO 2"), "BELIEVE s3\nBATCH 3");
+    assert_eq!(
+        (
+            run(&mut g, "GET ANSWERS TO q1"),
+            run(&mut g, "GET SOURCE s1"),
+        ),
+        before
+    );
+    assert!(!g.has_question("q2"));
+    assert!(g.source("s3").is_none());
+
+    assert_eq!(g.redo(1).unwrap(), vec!["BATCH 3"]);
+    assert!(g.has_question("q2"));
+    assert_eq!(g.redo(5).unwrap(), vec!["BELIEVE s3"]);
+    assert_eq!(
+        g.source("s3").unwrap().quality,
+        g.config().quality_of_believed_sources
+    );
+    assert_eq!(run(&mut g, "REDO"), "Nothing to redo");
+
+    // CONFIGURE is undone too, and a new mutation forgets what could be redone
+    run(&mut g, "CONFIGURE min_confidence 0.5");
+    assert_eq!(g.undo(1).unwrap(), vec!["CONFIGURE min_confidence 0.5"]);
+    assert_eq!(g.config().min_confidence, 0.0);
+    run(&mut g, "SET q3 d FROM s1");
+    assert!(g.redo(1).unwrap().is_empty());
+
+    // Replaying the journal takes back the same commands
+    g.sync_journal().unwrap();
+    let mut replayed = Graph::replay(&path).unwrap();
+    for line in ["GET ANSWERS TO q1", "GET SOURCE s3", "GET SOURCE s4"] {
+        assert_eq!(run(&mut replayed, line), run(&mut g, line));
+    }
+    assert!(replayed.has_question("q2"));
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(Command::from("UNDO 3").unwrap().to_string(), "UNDO 3");
+    assert!(Command::from("UNDO 0").is_err());
+    assert!(Command::from("REDO one").is_err());
+}
+
+#[test]
+fn test_min_confidence() {
+    let mut g = Graph::new();
+    g.set_many(&[("q1", "a", "s1"), ("q1", "b", "s2"), ("q2", "c", "s1")])
+        .unwrap();
+    g.set_many(&[("q2", "c", "s2"), ("q2", "c", "s3")]).unwrap();
+    let (_, confidence) = g.compute_answer("q1").unwrap();
+    let (_, confident) = g.compute_answer("q2").unwrap();
+    let min_confidence = (confidence + confident) / 2.;
+    g.execute_command(
+        &Command::from(&format!("CONFIGURE min_confidence {}", min_confidence)).unwrap(),
+    )
+    .unwrap();
+
+    let response = g
+        .execute_command(&Command::from("GET ANSWER TO q1").unwrap())
+        .unwrap();
+    assert_eq!(
+        response,
+        CommandResponse::Unknown {
+            confidence,
+            min_confidence,
+        }
+    );
+    let json = serde_json::to_string(&response).unwrap();
+    assert!(!json.contains("\"answer\""));
+    assert_eq!(
+        serde_json::from_str::<CommandResponse>(&json).unwrap(),
+        response
+    );
+    assert!(g.get_answer(&question_id("q1")).unwrap().unknown);
+    assert!(matches!(
+        g.execute_command(&Command::from("GET ANSWER TO q2").unwrap()).unwrap(),
+        CommandResponse::Answer { content, .. } if content == "c"
+    ));
+    // A question without answers is still None
+    assert!(matches!(
+        g.execute_command(&Command::from("GET ANSWER TO q3").unwrap()).unwrap(),
+        CommandResponse::Answer { content, .. } if content == "None"
+    ));
+
+    let answers = match g
+        .execute_command(&Command::from("MGET q1 q2").unwrap())
+        .unwrap()
+    {
+        CommandResponse::QuestionAnswers(answers) => answers,
+        response => panic!("unexpected response {:?}", response),
+    };
+    assert_eq!(
+        (answers[0].answer.as_str(), answers[0].unknown),
+        ("Unknown", true)
+    );
+    assert_eq!(
+        (answers[1].answer.as_str(), answers[1].unknown),
+        ("c", false)
+    );
+}
+
+#[test]
+fn test_mget() {
+    let mut g = Graph::new();
+    g.set_many(&[("q1", "a", "s1"), ("q1", "a", "s2"), ("q2", "b", "s1")])
+        .unwrap();
+    let results = g
+        .get_answers_bulk(&[question_id("q2"), question_id("q3"), question_id("q1")])
+        .unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].answer.as_deref(), Some("b"));
+    assert_eq!(
+        (results[1].answer.as_deref(), results[1].confidence),
+        (None, 0.0)
+    );
+    assert_eq!(
+        results[2].confidence,
+        g.get_answer(&question_id("q1")).unwrap().confidence
+    );
+
+    let response = g
+        .execute_read_command(&Command::from("MGET q1 q3").unwrap())
+        .unwrap();
+    assert_eq!(response.to_string(), "q1: a (75.000%)\nq3: None (0.000%)");
+    assert_eq!(
+        serde_json::from_str::<CommandResponse>(&response.to_json()).unwrap(),
+        response
+    );
+    let cmd = Command::from_json(r#"{"cmd": "mget", "questions": ["q1", "q2"]}"#).unwrap();
+    assert_eq!(cmd.to_string(), "MGET q1 q2");
+    assert!(Command::from("MGET").is_err());
+    assert!(Command::from_json(r#"{"cmd": "mget", "questions": []}"#).is_err());
+
+    g.set_strict(true);
+    assert_eq!(
+        g.execute_read_command(&Command::from("MGET q1 q3").unwrap())
+            .err(),
+        Some(ConfidisError::UnknownQuestion(String::from("q3")))
+    );
+    g.begin_bulk_load();
+    assert_eq!(
+        g.get_answers_bulk(&[question_id("q1")]).err(),
+        Some(ConfidisError::BulkLoadInProgress)
+    );
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #![proptest_config(proptest::prelude::ProptestConfig::with_cases(64))]
+
+    // Adding a question's effect and removing it again leaves every source as
+    // it was, also once sources are stronger than maximum_strength
+    #[test]
+    fn test_effect_reversal(
+        maximum_strength in 1.0..5.0_f64,
+        sets in proptest::collection::vec((0..4_usize, 0..3_usize, 0..5_usize), 1..60),
+    ) {
+        let mut g = Graph::new();
+        g.config.maximum_strength = maximum_strength;
+        // Removing an answer scales rounding errors by up to (strength + weight)
+        // / strength, so sources answer a question at most twice to keep them
+        // far below the tolerance
+        let mut answered: HashMap<(usize, usize), usize> = HashMap::new();
+        for (question, answer, source) in sets {
+            let count = answered.entry((question, source)).or_insert(0);
+            *count += 1;
+            if *count > 2 {
+                continue;
+            }
+            g.set_answer(
+                &question_id(&format!("q{}", question)),
+                &format!("a{}", answer),
+                &source_id(&format!("s{}", source)),
+            )
+            .unwrap();
+        }
+        let snapshot = |g: &Graph| -> Vec<(String, f64, f64)> {
+            let mut sources: Vec<_> = g
+                .sources()
+                .map(|s| (s.name.to_string(), s.quality, s.strength))
+                .collect();
+            sources.sort_by(|a, b| a.0.cmp(&b.0));
+            sources
+        };
+        let assert_restored = |before: &[(String, f64, f64)], after: &[(String, f64, f64)]| {
+            for (b, a) in before.iter().zip(after) {
+                assert!((b.1 - a.1).abs() < 1e-9, "{} quality {} -> {}", b.0, b.1, a.1);
+                assert!((b.2 - a.2).abs() < 1e-9, "{} strength {} -> {}", b.0, b.2, a.2);
+            }
+        };
+        let before = snapshot(&g);
+        for (name, quality, _) in &before {
+            assert!((0.0..=1.0).contains(quality), "{} quality {}", name, quality);
+        }
+        let questions: Vec<String> = g.questions.keys().cloned().collect();
+        for question in &questions {
+            g.add_question_effect(question);
+            g.remove_question_effect(question);
+            assert_restored(&before, &snapshot(&g));
+        }
+    }
+
+    // Question effects added and removed in any interleaved order leave every
+    // source where it started, with its strength at most maximum_strength on
+    // the way. Had a removal taken a quality out of 0..1, the clamp would have
+    // lost what it takes to get back. Scaling effects back up scales up their
+    // rounding errors too, so how close is close enough depends on how far a
+    // source's effects were scaled down.
+    #[test]
+    fn test_effect_reversal_in_any_order(
+        maximum_strength in 1.0..5.0_f64,
+        sets in proptest::collection::vec((0..6_usize, 0..3_usize, 0..5_usize), 1..60),
+        steps in proptest::collection::vec((proptest::bool::ANY, 0..6_usize), 0..80),
+    ) {
+        // answered without scaling, so without their effects the sources are
+        // back at the defaults
+        let mut g = Graph::new();
+        g.config.maximum_strength = f64::MAX;
+        for (question, answer, source) in sets {
+            g.set_answer(
+                &question_id(&format!("q{}", question)),
+                &format!("a{}", answer),
+                &source_id(&format!("s{}", source)),
+            )
+            .unwrap();
+        }
+        let questions: Vec<String> = g.questions.keys().cloned().collect();
+        for question in &questions {
+            g.remove_question_effect(question);
+        }
+        g.config.maximum_strength = maximum_strength;
+
+        // the most each source's effects were scaled down
+        let mut discounts: HashMap<String, f64> = HashMap::new();
+        let mut held = vec![false; questions.len()];
+        let removals = (0..questions.len()).map(|i| (false, i));
+        for (add, i) in steps.into_iter().chain(removals) {
+            let i = i % questions.len();
+            if add == held[i] {
+                continue;
+            }
+            if add {
+                g.add_question_effect(&questions[i]);
+            } else {
+                g.remove_question_effect(&questions[i]);
+            }
+            held[i] = add;
+            for source in g.sources.values() {
+                assert!(source.strength <= maximum_strength + 1e-9);
+                let discount = discounts.entry(source.name.clone()).or_default();
+                *discount = discount.max(source.discount);
+            }
+        }
+        for source in g.sources() {
+            let epsilon = 1e-12 * discounts.get(source.name).map_or(1.0, |d| d.exp());
+            let quality = g.config.default_source_quality;
+            let strength = g.config.initial_source_strength;
+            assert!(
+                (source.quality - quality).abs() <= epsilon,
+                "{} quality {}",
+                source.name,
+                source.quality
+            );
+            assert!(
+                (source.strength - strength).abs() <= epsilon,
+                "{} strength {}",
+                source.name,
+                source.strength
+            );
+        }
+    }
 }
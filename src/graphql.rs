@@ -0,0 +1,418 @@
+// GraphQL API
+//
+//   query {
+//     question(name: "q1") { answer confidence answers { content source { name quality } } }
+//     sources(first: 10) { name quality answers { question content } }
+//   }
+//   mutation { set(question: "q1", answer: "a", source: "s1") { answer confidence } }
+//
+// Mutations are executed as commands, so they're journaled like any other.
+// execute_graphql takes a standard GraphQL request body, {"query": "...",
+// "variables": {...}, "operationName": "..."}, and returns the response body.
+// With the http feature the HTTP server serves it at POST /graphql.
+//
+// execute_graphql_with_auth checks the session's role like the TCP server,
+// see auth.rs: queries need the read role and each mutation the role of its
+// command, e.g. believe needs admin.
+
+use crate::auth::AuthSession;
+use crate::command::{Command, CommandType};
+use crate::graph::Graph;
+use juniper::http::GraphQLRequest;
+use juniper::{graphql_object, EmptySubscription, FieldError, FieldResult, RootNode};
+use std::marker::PhantomData;
+use std::sync::{Mutex, MutexGuard};
+
+// juniper requires a Sync context, so the graph is behind a Mutex, borrowed
+// for the duration of a request. The objects below carry the lifetime of the
+// borrow so juniper can tell which Context they resolve with.
+pub struct Context<'a> {
+    graph: Mutex<&'a mut Graph>,
+    auth: AuthSession,
+}
+
+impl juniper::Context for Context<'_> {}
+
+impl<'a> Context<'a> {
+    fn graph(&self) -> MutexGuard<'_, &'a mut Graph> {
+        self.graph.lock().unwrap()
+    }
+
+    // Ok if the session may read the graph
+    fn read(&self) -> FieldResult<()> {
+        Ok(self.auth.authorize(CommandType::GetAnswer)?)
+    }
+
+    fn execute(&self, cmd: Command) -> FieldResult<()> {
+        self.auth.authorize(cmd.cmd)?;
+        self.graph()
+            .execute_command(&cmd)
+            .map(|_| ())
+            .map_err(FieldError::from)
+    }
+}
+
+pub struct QuestionObject<'a> {
+    name: String,
+    context: PhantomData<&'a ()>,
+}
+
+impl QuestionObject<'_> {
+    fn new(name: String) -> Self {
+        QuestionObject {
+            name,
+            context: PhantomData,
+        }
+    }
+}
+
+#[graphql_object(name = "Question", context = Context<'a>)]
+impl<'a> QuestionObject<'a> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    // "None" if the question has no answers
+    fn answer(&self, context: &Context<'a>) -> FieldResult<String> {
+        Ok(context.graph().compute_answer(&self.name)?.0)
+    }
+
+    fn confidence(&self, context: &Context<'a>) -> FieldResult<f64> {
+        Ok(context.graph().compute_answer(&self.name)?.1)
+    }
+
+    fn answers(&self, context: &Context<'a>) -> Vec<AnswerObject<'a>> {
+        let g = context.graph();
+        g.question(&self.name)
+            .map(|question| {
+                question
+                    .answers
+                    .iter()
+                    .map(|answer| AnswerObject {
+                        question: self.name.clone(),
+                        content: answer.content.clone(),
+                        source: answer.source.clone(),
+                        context: PhantomData,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+pub struct SourceObject<'a> {
+    name: String,
+    context: PhantomData<&'a ()>,
+}
+
+impl SourceObject<'_> {
+    fn new(name: String) -> Self {
+        SourceObject {
+            name,
+            context: PhantomData,
+        }
+    }
+}
+
+#[graphql_object(name = "Source", context = Context<'a>)]
+impl<'a> SourceObject<'a> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn quality(&self, context: &Context<'a>) -> f64 {
+        let g = context.graph();
+        g.source(&self.name)
+            .map(|source| source.quality)
+            .unwrap_or(g.config().default_source_quality)
+    }
+
+    fn strength(&self, context: &Context<'a>) -> f64 {
+        let g = context.graph();
+        g.source(&self.name)
+            .map(|source| source.strength)
+            .unwrap_or(g.config().initial_source_strength)
+    }
+
+    // Every answer this source gave, ordered by question name
+    fn answers(&self, context: &Context<'a>) -> Vec<AnswerObject<'a>> {
+        let g = context.graph();
+        let mut answers: Vec<AnswerObject<'a>> = g
+            .questions
+            .values()
+            .flat_map(|question| {
+                question
+                    .answers
+                    .iter()
+                    .filter(|answer| answer.source == self.name)
+                    .map(move |answer| AnswerObject {
+                        question: question.name.clone(),
+                        content: answer.content.clone(),
+                        source: answer.source.clone(),
+                        context: PhantomData,
+                    })
+            })
+            .collect();
+        answers.sort_by(|a, b| a.question.cmp(&b.question));
+        answers
+    }
+}
+
+pub struct AnswerObject<'a> {
+    question: String,
+    content: String,
+    source: String,
+    context: PhantomData<&'a ()>,
+}
+
+#[graphql_object(name = "Answer", context = Context<'a>)]
+impl<'a> AnswerObject<'a> {
+    fn question(&self) -> QuestionObject<'a> {
+        QuestionObject::new(self.question.clone())
+    }
+
+    fn content(&self) -> &str {
+        &self.content
+    }
+
+    fn source(&self) -> SourceObject<'a> {
+        SourceObject::new(self.source.clone())
+    }
+}
+
+// Names in order, from the one after `after`, at most `first` of them
+fn page(mut names: Vec<String>, after: Option<String>, first: Option<i32>) -> Vec<String> {
+    names.sort();
+    let start = match after {
+        Some(after) => names.partition_point(|name| *name <= after),
+        None => 0,
+    };
+    let end = match first {
+        Some(first) => (start + first.max(0) as usize).min(names.len()),
+        None => names.len(),
+    };
+    names[start..end].to_vec()
+}
+
+pub struct Query<'a>(PhantomData<&'a ()>);
+
+#[graphql_object(context = Context<'a>)]
+impl<'a> Query<'a> {
+    fn question(context: &Context<'a>, name: String) -> FieldResult<Option<QuestionObject<'a>>> {
+        context.read()?;
+        if context.graph().has_question(&name) {
+            Ok(Some(QuestionObject::new(name)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Questions ordered by name, optionally only those starting with prefix
+    fn questions(
+        context: &Context<'a>,
+        prefix: Option<String>,
+        after: Option<String>,
+        first: Option<i32>,
+    ) -> FieldResult<Vec<QuestionObject<'a>>> {
+        context.read()?;
+        let prefix = prefix.unwrap_or_default();
+        let names: Vec<String> = context
+            .graph()
+            .questions
+            .keys()
+            .filter(|name| name.starts_with(&prefix))
+            .cloned()
+            .collect();
+        Ok(page(names, after, first)
+            .into_iter()
+            .map(QuestionObject::new)
+            .collect())
+    }
+
+    fn source(context: &Context<'a>, name: String) -> FieldResult<Option<SourceObject<'a>>> {
+        context.read()?;
+        if context.graph().source(&name).is_some() {
+            Ok(Some(SourceObject::new(name)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Sources ordered by name
+    fn sources(
+        context: &Context<'a>,
+        after: Option<String>,
+        first: Option<i32>,
+    ) -> FieldResult<Vec<SourceObject<'a>>> {
+        context.read()?;
+        let names: Vec<String> = context.graph().sources.keys().cloned().collect();
+        Ok(page(names, after, first)
+            .into_iter()
+            .map(SourceObject::new)
+            .collect())
+    }
+}
+
+pub struct Mutation<'a>(PhantomData<&'a ()>);
+
+#[graphql_object(context = Context<'a>)]
+impl<'a> Mutation<'a> {
+    fn set(
+        context: &Context<'a>,
+        question: String,
+        answer: String,
+        source: String,
+    ) -> FieldResult<QuestionObject<'a>> {
+        context.execute(Command {
+            cmd: CommandType::Set,
+            question: Some(question.clone().into()),
+            answer: Some(answer.into()),
+            source: Some(source.into()),
+            ..Default::default()
+        })?;
+        Ok(QuestionObject::new(question))
+    }
+
+    fn believe(context: &Context<'a>, source: String) -> FieldResult<SourceObject<'a>> {
+        context.execute(Command {
+            cmd: CommandType::Believe,
+            source: Some(source.clone().into()),
+            ..Default::default()
+        })?;
+        Ok(SourceObject::new(source))
+    }
+
+    fn configure(context: &Context<'a>, key: String, value: String) -> FieldResult<bool> {
+        context.execute(Command {
+            cmd: CommandType::Configure,
+            config_key: Some(key.into()),
+            config_val: Some(value.into()),
+            ..Default::default()
+        })?;
+        Ok(true)
+    }
+}
+
+pub type Schema<'a> = RootNode<'static, Query<'a>, Mutation<'a>, EmptySubscription<Context<'a>>>;
+
+pub fn schema<'a>() -> Schema<'a> {
+    Schema::new(
+        Query(PhantomData),
+        Mutation(PhantomData),
+        EmptySubscription::new(),
+    )
+}
+
+// Execute a GraphQL request body against g, returning the response body
+pub fn execute_graphql(g: &mut Graph, request: &str) -> Result<String, String> {
+    execute_graphql_with_auth(g, request, AuthSession::default())
+}
+
+// execute_graphql with the fields and mutations auth may run, others fail
+// with an error in the response
+pub fn execute_graphql_with_auth(
+    g: &mut Graph,
+    request: &str,
+    auth: AuthSession,
+) -> Result<String, String> {
+    let request: GraphQLRequest =
+        serde_json::from_str(request).map_err(|e| format!("Invalid GraphQL request: {}", e))?;
+    let context = Context {
+        graph: Mutex::new(g),
+        auth,
+    };
+    let response = request.execute_sync(&schema(), &context);
+    serde_json::to_string(&response).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Tokens;
+    use std::sync::Arc;
+
+    fn run(g: &mut Graph, query: &str) -> serde_json::Value {
+        let request = serde_json::json!({ "query": query }).to_string();
+        serde_json::from_str(&execute_graphql(g, &request).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_graphql() {
+        let mut g = Graph::new();
+        for source in &["s1", "s2"] {
+            let mutation = format!(
+                "mutation {{ set(question: \"q1\", answer: \"a\", source: \"{}\") {{ name }} }}",
+                source
+            );
+            assert_eq!(run(&mut g, &mutation)["data"]["set"]["name"], "q1");
+        }
+        run(&mut g, "mutation { believe(source: \"s3\") { name } }");
+
+        let response = run(
+            &mut g,
+            "{
+                question(name: \"q1\") { answer confidence answers { source { name } } }
+                sources(first: 2) { name quality answers { question { name } content } }
+                missing: question(name: \"nope\") { name }
+            }",
+        );
+        let data = &response["data"];
+        assert_eq!(data["question"]["answer"], "a");
+        assert!((data["question"]["confidence"].as_f64().unwrap() - 0.75).abs() < 1e-4);
+        assert_eq!(data["question"]["answers"][1]["source"]["name"], "s2");
+        assert_eq!(data["sources"].as_array().unwrap().len(), 2);
+        assert_eq!(data["sources"][0]["answers"][0]["question"]["name"], "q1");
+        assert!(data["missing"].is_null());
+
+        let response = run(
+            &mut g,
+            "mutation { configure(key: \"nope\", value: \"1\") }",
+        );
+        assert!(response["errors"][0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("Unknown configuration key"));
+    }
+
+    #[test]
+    fn test_graphql_auth() {
+        let tokens = Arc::new(Tokens::parse("r1 read\nw1 write\n").unwrap());
+        let run_as = |g: &mut Graph, token: Option<&str>, query: &str| -> serde_json::Value {
+            let mut auth = AuthSession::new(Some(tokens.clone()));
+            if let Some(token) = token {
+                auth.authenticate(token).unwrap();
+            }
+            let request = serde_json::json!({ "query": query }).to_string();
+            serde_json::from_str(&execute_graphql_with_auth(g, &request, auth).unwrap()).unwrap()
+        };
+        let error = |response: serde_json::Value| {
+            response["errors"][0]["message"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        };
+        let mut g = Graph::new();
+        let set = "mutation { set(question: \"q1\", answer: \"a\", source: \"s1\") { name } }";
+        let query = "{ question(name: \"q1\") { answer } }";
+
+        assert!(error(run_as(&mut g, None, query)).starts_with("Authentication required"));
+        assert!(error(run_as(&mut g, None, set)).starts_with("Authentication required"));
+        assert_eq!(
+            error(run_as(&mut g, Some("r1"), set)),
+            "Permission denied, Set needs the write role"
+        );
+        assert_eq!(
+            error(run_as(
+                &mut g,
+                Some("w1"),
+                "mutation { believe(source: \"s1\") { name } }"
+            )),
+            "Permission denied, Believe needs the admin role"
+        );
+        assert_eq!(run_as(&mut g, Some("w1"), set)["data"]["set"]["name"], "q1");
+        assert_eq!(
+            run_as(&mut g, Some("r1"), query)["data"]["question"]["answer"],
+            "a"
+        );
+    }
+}
@@ -0,0 +1,142 @@
+// Answer hashing
+//
+// Every stored answer is hashed, after normalization, so equal answers can be
+// told apart from different ones cheaply: distances are cached by the hashes
+// of the two answers, GET ANSWERS lists each distinct answer once and equally
+// sized clusters are ordered by hash. Two different answers with the same hash
+// share cached distances, so a graph with billions of distinct answers should
+// use a 128 bit hash:
+//
+//   CONFIGURE answer_hash xxh3_128
+//
+// The hash functions:
+//
+//   sip64     SipHash 1-3 of the standard library, 64 bit, the default
+//   xxh64     XXH64, 64 bit, faster on long answers
+//   xxh3_128  XXH3, 128 bit
+//
+// Hashes aren't persisted, they're computed when a graph is loaded, so
+// changing the hash function, or a Rust version changing SipHash, never leaves
+// stale hashes behind. Changing it rehashes every answer and empties the
+// distance cache.
+
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use twox_hash::{XxHash3_128, XxHash64};
+
+// The hash of an answer, 64 bit hashes take the low half
+pub type AnswerHash = u128;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnswerHasher {
+    #[default]
+    Sip64,
+    Xxh64,
+    Xxh3_128,
+}
+
+impl AnswerHasher {
+    pub const ALL: [AnswerHasher; 3] = [
+        AnswerHasher::Sip64,
+        AnswerHasher::Xxh64,
+        AnswerHasher::Xxh3_128,
+    ];
+
+    // The name CONFIGURE answer_hash takes
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnswerHasher::Sip64 => "sip64",
+            AnswerHasher::Xxh64 => "xxh64",
+            AnswerHasher::Xxh3_128 => "xxh3_128",
+        }
+    }
+
+    pub fn hash<A: Hash + ?Sized>(&self, content: &A) -> AnswerHash {
+        match self {
+            AnswerHasher::Sip64 => {
+                let mut hasher = DefaultHasher::new();
+                content.hash(&mut hasher);
+                hasher.finish().into()
+            }
+            AnswerHasher::Xxh64 => {
+                let mut hasher = XxHash64::with_seed(0);
+                content.hash(&mut hasher);
+                hasher.finish().into()
+            }
+            AnswerHasher::Xxh3_128 => {
+                let mut hasher = Xxh3Hasher(XxHash3_128::new());
+                content.hash(&mut hasher);
+                hasher.0.finish_128()
+            }
+        }
+    }
+}
+
+// Feeds Hash implementations to XXH3, which only finishes to 128 bits itself
+struct Xxh3Hasher(XxHash3_128);
+
+impl Hasher for Xxh3Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.write(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0.finish_128() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{Command, CommandResponse};
+    use crate::graph::Graph;
+
+    #[test]
+    fn test_answer_hash() {
+        for hasher in AnswerHasher::ALL.iter() {
+            assert_eq!(hasher.hash("Paris"), hasher.hash(&String::from("Paris")));
+            assert_ne!(hasher.hash("Paris"), hasher.hash("paris"));
+        }
+        assert!(AnswerHasher::Sip64.hash("Paris") <= u64::MAX.into());
+        assert!(AnswerHasher::Xxh3_128.hash("Paris") > u64::MAX.into());
+
+        let mut g = Graph::new();
+        let mut run = |line: &str| g.execute_command(&Command::from(line).unwrap()).unwrap();
+        run("CONFIGURE normalize trim lowercase");
+        run("SET q1 Paris FROM s1");
+        run("SET q1 paris FROM s2");
+        run("SET q1 Lyon FROM s3");
+        assert_eq!(
+            run("CONFIGURE answer_hash xxh3_128"),
+            CommandResponse::Configure {
+                previous: String::from("sip64")
+            }
+        );
+        // answers are rehashed, normalized
+        let answers = &g.questions["q1"].answers;
+        assert_eq!(answers[0].hash, AnswerHasher::Xxh3_128.hash("paris"));
+        assert_eq!(answers[0].hash, answers[1].hash);
+        assert_ne!(answers[0].hash, answers[2].hash);
+
+        let mut run = |line: &str| g.execute_command(&Command::from(line).unwrap()).unwrap();
+        run("SET q1 PARIS FROM s4");
+        assert_eq!(
+            g.questions["q1"].answers[3].hash,
+            AnswerHasher::Xxh3_128.hash("paris")
+        );
+        let answer = g.compute_answer("q1").unwrap();
+
+        // hashes are computed again when a snapshot is loaded
+        let mut snapshot = Vec::new();
+        g.save_snapshot(&mut snapshot).unwrap();
+        let loaded = Graph::load_snapshot(&snapshot[..]).unwrap();
+        assert_eq!(loaded.config().answer_hash, AnswerHasher::Xxh3_128);
+        assert_eq!(
+            loaded.questions["q1"].answers[3].hash,
+            g.questions["q1"].answers[3].hash
+        );
+        assert_eq!(loaded.compute_answer("q1").unwrap(), answer);
+    }
+}
@@ -0,0 +1,99 @@
+// Bounded history of each question's answer
+//
+// A graph with an answer history records the answer and confidence of a
+// question whenever its recomputed answer differs from the last one recorded,
+// keeping the most recent `capacity` changes per question. A question that
+// keeps alternating between answers (flapping) shows up as a long history.
+//
+//   g.set_answer_history(AnswerHistory::new(100));
+//   g.execute_command(&Command::from("GET HISTORY OF q1")?)?;
+//
+// Like the audit log, timestamps come from the system clock, so the history
+// isn't available on wasm32-unknown-unknown.
+
+use crate::hash::AnswerHash;
+use crate::journal::now_millis;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnswerChange<A = String> {
+    // unix timestamp in ms
+    pub timestamp: u64,
+    pub answer: A,
+    pub confidence: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnswerHistory<A = String> {
+    capacity: usize,
+    // The changes of each question with the hash of their answer, to tell
+    // whether a recomputed answer is a change
+    questions: HashMap<String, VecDeque<(AnswerHash, AnswerChange<A>)>>,
+}
+
+impl<A: Clone> AnswerHistory<A> {
+    // capacity is the number of changes kept per question
+    pub fn new(capacity: usize) -> AnswerHistory<A> {
+        AnswerHistory {
+            capacity,
+            questions: HashMap::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Record the recomputed answer of a question if it isn't the last one
+    // recorded, dropping the question's oldest change when full
+    pub(crate) fn record(&mut self, question: &str, hash: AnswerHash, answer: &A, confidence: f64) {
+        if self.capacity == 0 {
+            return;
+        }
+        let changes = self.questions.entry(question.to_string()).or_default();
+        if changes.back().map(|(last, _)| *last) == Some(hash) {
+            return;
+        }
+        if changes.len() == self.capacity {
+            changes.pop_front();
+        }
+        changes.push_back((
+            hash,
+            AnswerChange {
+                timestamp: now_millis(),
+                answer: answer.clone(),
+                confidence,
+            },
+        ));
+    }
+
+    // The answer changes of a question, oldest first
+    pub fn changes(&self, question: &str) -> Vec<AnswerChange<A>> {
+        self.questions
+            .get(question)
+            .map(|changes| changes.iter().map(|(_, change)| change.clone()).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answer_history() {
+        let mut history = AnswerHistory::new(2);
+        history.record("q1", 1, &String::from("a"), 0.5);
+        history.record("q1", 1, &String::from("a"), 0.75);
+        history.record("q1", 2, &String::from("b"), 0.6);
+        history.record("q1", 1, &String::from("a"), 0.8);
+        let answers: Vec<String> = history
+            .changes("q1")
+            .into_iter()
+            .map(|change| change.answer)
+            .collect();
+        assert_eq!(answers, vec!["b", "a"]);
+        assert!(history.changes("q2").is_empty());
+    }
+}
@@ -0,0 +1,416 @@
+// HTTP REST API
+//
+//   POST /questions/{q}/answers  {"answer": "...", "source": "..."}  SET
+//   GET  /questions/{q}/answer                                      GET ANSWER
+//   GET  /sources/{s}                                               GET SOURCE
+//   POST /commands               raw command text, e.g. "BELIEVE s1", or a
+//                                JSON command envelope, e.g.
+//                                {"cmd": "believe", "source": "s1"}
+//   GET  /metrics                Prometheus metrics, see Metrics
+//   POST /graphql                GraphQL, with the graphql feature
+//
+// Successful responses are the JSON serialized CommandResponse. Failures are
+// {"error": "..."} with a 400 (bad command or body), 401 (no or unknown
+// token), 403 (the token's role doesn't allow the command), 404 (unknown
+// route) or 413 (body longer than MAX_BODY_LEN bytes) status. Like the TCP server, the graph is owned by a GraphWorker.
+//
+// With tokens set (see set_tokens) every request needs an
+// "Authorization: Bearer <token>" header whose role allows its command, like
+// AUTH on the TCP server, see auth.rs. /metrics needs the read role.
+
+use crate::auth::{AuthSession, Tokens};
+use crate::command::{Command, CommandType};
+use crate::graph::Graph;
+use crate::worker::{GraphWorker, Reply};
+use log::warn;
+use serde::Deserialize;
+use std::io::Read;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Method, Request, Response};
+
+// The longest request body the server reads, so a client can't run it out of
+// memory with an endless body
+const MAX_BODY_LEN: usize = 16 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct AnswerBody {
+    answer: String,
+    source: String,
+}
+
+pub struct HttpServer {
+    server: tiny_http::Server,
+    worker: GraphWorker,
+    tokens: Option<Arc<Tokens>>,
+}
+
+impl HttpServer {
+    pub fn bind<A, F>(addr: A, make_graph: F) -> Result<HttpServer, String>
+    where
+        A: ToSocketAddrs,
+        F: FnOnce() -> Graph + Send + 'static,
+    {
+        let server = tiny_http::Server::http(addr).map_err(|e| format!("Couldn't bind: {}", e))?;
+        Ok(HttpServer {
+            server,
+            worker: GraphWorker::spawn(make_graph),
+            tokens: None,
+        })
+    }
+
+    // Require requests to carry one of tokens, see auth.rs
+    pub fn set_tokens(&mut self, tokens: Tokens) {
+        self.tokens = Some(Arc::new(tokens));
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.server
+            .server_addr()
+            .to_ip()
+            .ok_or_else(|| String::from("Not listening on an IP address"))
+    }
+
+    // The worker executing this server's commands, e.g. to share the graph
+    // with a WebSocketServer
+    pub fn worker(&self) -> GraphWorker {
+        self.worker.clone()
+    }
+
+    // Handle requests until the server fails
+    pub fn run(self) -> Result<(), String> {
+        for request in self.server.incoming_requests() {
+            let worker = self.worker.clone();
+            let auth = AuthSession::new(self.tokens.clone());
+            thread::spawn(move || handle_request(request, &worker, auth));
+        }
+        Ok(())
+    }
+}
+
+fn json_response(status: u16, body: String) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+fn error_body(msg: &str) -> String {
+    serde_json::json!({ "error": msg }).to_string()
+}
+
+// Decode %XX escapes in a path segment
+fn percent_decode(segment: &str) -> Result<String, String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = segment
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| format!("Invalid escape in path segment {}", segment))?;
+            decoded.push(hex);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|e| e.to_string())
+}
+
+// The command for a request, or the status and message to fail with
+fn route(method: &Method, url: &str, body: &str) -> Result<Command<'static>, (u16, String)> {
+    let path = url.split('?').next().unwrap_or("");
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let decode = |segment: &str| percent_decode(segment).map_err(|msg| (400, msg));
+    match (method, segments.as_slice()) {
+        (Method::Post, ["questions", question, "answers"]) => {
+            let body: AnswerBody =
+                serde_json::from_str(body).map_err(|e| (400, format!("Invalid body: {}", e)))?;
+            Ok(Command {
+                cmd: CommandType::Set,
+                question: Some(decode(question)?.into()),
+                answer: Some(body.answer.into()),
+                source: Some(body.source.into()),
+                ..Default::default()
+            })
+        }
+        (Method::Get, ["questions", question, "answer"]) => Ok(Command {
+            cmd: CommandType::GetAnswer,
+            question: Some(decode(question)?.into()),
+            ..Default::default()
+        }),
+        (Method::Get, ["sources", source]) => Ok(Command {
+            cmd: CommandType::GetSource,
+            source: Some(decode(source)?.into()),
+            ..Default::default()
+        }),
+        (Method::Post, ["commands"]) if body.trim_start().starts_with('{') => {
+            Command::from_json(body).map_err(|e| (400, e.to_string()))
+        }
+        (Method::Post, ["commands"]) => Command::from(body.trim())
+            .map(Command::into_owned)
+            .map_err(|msg| (400, format!("Invalid command: {}", msg))),
+        _ => Err((404, format!("No route for {} {}", method, path))),
+    }
+}
+
+// The request's body, or the status and message to fail with
+fn read_body(request: &mut Request) -> Result<String, (u16, String)> {
+    let too_long = || (413, format!("Body longer than {} bytes", MAX_BODY_LEN));
+    if request.body_length().is_some_and(|len| len > MAX_BODY_LEN) {
+        return Err(too_long());
+    }
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_LEN as u64 + 1)
+        .read_to_string(&mut body)
+        .map_err(|e| (400, format!("Couldn't read body: {}", e)))?;
+    if body.len() > MAX_BODY_LEN {
+        return Err(too_long());
+    }
+    Ok(body)
+}
+
+// Authenticate auth with the request's bearer token, if the server has tokens
+fn authenticate(request: &Request, auth: &mut AuthSession) -> Result<(), (u16, String)> {
+    if !auth.has_tokens() {
+        return Ok(());
+    }
+    let token = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .and_then(|header| header.value.as_str().strip_prefix("Bearer "));
+    match token {
+        Some(token) => auth.authenticate(token.trim()).map(|_| ()),
+        None => Err(String::from(
+            "Authentication required, send an Authorization: Bearer <token> header",
+        )),
+    }
+    .map_err(|msg| (401, msg))
+}
+
+// Ok if auth may run commands of type cmd, otherwise the status to fail with
+fn authorize(auth: &AuthSession, cmd: CommandType) -> Result<(), (u16, String)> {
+    auth.authorize(cmd).map_err(|msg| match auth.role() {
+        Some(_) => (403, msg),
+        None => (401, msg),
+    })
+}
+
+fn handle_request(mut request: Request, worker: &GraphWorker, mut auth: AuthSession) {
+    if let Err((status, msg)) = authenticate(&request, &mut auth) {
+        if let Err(e) = request.respond(json_response(status, error_body(&msg))) {
+            warn!("Couldn't send HTTP response: {}", e);
+        }
+        return;
+    }
+    if request.method() == &Method::Get && request.url() == "/metrics" {
+        let response = match authorize(&auth, CommandType::Stats).map(|_| worker.metrics_text()) {
+            Err((status, msg)) => json_response(status, error_body(&msg)),
+            Ok(Ok(text)) => Response::from_string(text).with_header(
+                Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap(),
+            ),
+            Ok(Err(msg)) => json_response(500, error_body(&msg)),
+        };
+        if let Err(e) = request.respond(response) {
+            warn!("Couldn't send HTTP response: {}", e);
+        }
+        return;
+    }
+    #[cfg(feature = "graphql")]
+    if request.method() == &Method::Post && request.url() == "/graphql" {
+        let response = match read_body(&mut request) {
+            Err((status, msg)) => json_response(status, error_body(&msg)),
+            Ok(body) => match worker
+                .with_graph(move |g| crate::graphql::execute_graphql_with_auth(g, &body, auth))
+            {
+                Ok(Ok(response)) => json_response(200, response),
+                Ok(Err(msg)) => json_response(400, error_body(&msg)),
+                Err(msg) => json_response(500, error_body(&msg)),
+            },
+        };
+        if let Err(e) = request.respond(response) {
+            warn!("Couldn't send HTTP response: {}", e);
+        }
+        return;
+    }
+    let response = match read_body(&mut request) {
+        Err((status, msg)) => json_response(status, error_body(&msg)),
+        Ok(body) => match route(request.method(), request.url(), &body)
+            .and_then(|cmd| authorize(&auth, cmd.cmd).map(|_| cmd))
+        {
+            Err((status, msg)) => json_response(status, error_body(&msg)),
+            Ok(cmd) => {
+                let reply: Reply = worker.execute(cmd);
+                match reply {
+                    Ok(response) => json_response(200, serde_json::to_string(&response).unwrap()),
+                    Err(msg) => json_response(400, error_body(&msg)),
+                }
+            }
+        },
+    };
+    if let Err(e) = request.respond(response) {
+        warn!("Couldn't send HTTP response: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+
+    fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+        request_as(addr, None, method, path, body)
+    }
+
+    fn request_as(
+        addr: SocketAddr,
+        token: Option<&str>,
+        method: &str,
+        path: &str,
+        body: &str,
+    ) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let auth = token.map_or(String::new(), |token| {
+            format!("Authorization: Bearer {}\r\n", token)
+        });
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}Content-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            auth,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line).unwrap();
+        let status: u16 = status_line.split(' ').nth(1).unwrap().parse().unwrap();
+        let mut response = String::new();
+        reader.read_to_string(&mut response).unwrap();
+        let body = response.split("\r\n\r\n").nth(1).unwrap().to_string();
+        (status, body)
+    }
+
+    #[test]
+    fn test_http_api() {
+        let server = HttpServer::bind("127.0.0.1:0", Graph::new).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        for source in &["s1", "s2"] {
+            let body = format!("{{\"answer\": \"a b\", \"source\": \"{}\"}}", source);
+            let (status, _) = request(addr, "POST", "/questions/q%2D1/answers", &body);
+            assert_eq!(status, 200);
+        }
+
+        let (status, body) = request(addr, "GET", "/questions/q%2D1/answer", "");
+        assert_eq!(status, 200);
+        let response: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(response["answer"], "a b");
+
+        let (status, _) = request(addr, "POST", "/commands", "BELIEVE s3");
+        assert_eq!(status, 200);
+        let (status, body) = request(addr, "GET", "/sources/s3", "");
+        assert_eq!(status, 200);
+        assert!(body.contains("0.999"));
+
+        let body = r#"{"cmd": "test_equality", "answer1": "a", "answer2": "a"}"#;
+        let (status, body) = request(addr, "POST", "/commands", body);
+        assert_eq!(status, 200);
+        assert!(body.contains("\"distance\":0.0"));
+
+        assert_eq!(request(addr, "POST", "/commands", "NOPE").0, 400);
+        assert_eq!(
+            request(addr, "POST", "/commands", r#"{"cmd": "nope"}"#).0,
+            400
+        );
+        assert_eq!(request(addr, "POST", "/questions/q/answers", "{}").0, 400);
+        assert_eq!(request(addr, "GET", "/nothing", "").0, 404);
+
+        // bodies longer than MAX_BODY_LEN aren't read
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "POST /commands HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_LEN + 1
+        )
+        .unwrap();
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line).unwrap();
+        assert!(status_line.contains(" 413 "));
+
+        let (status, body) = request(addr, "GET", "/metrics", "");
+        assert_eq!(status, 200);
+        assert!(body.contains("confidis_commands_total{cmd=\"Set\",result=\"ok\"} 2"));
+        assert!(body.contains("confidis_questions 1"));
+
+        #[cfg(feature = "graphql")]
+        {
+            let query = r#"{"query": "{ question(name: \"q-1\") { answer } }"}"#;
+            let (status, body) = request(addr, "POST", "/graphql", query);
+            assert_eq!(status, 200);
+            assert_eq!(body, r#"{"data":{"question":{"answer":"a b"}}}"#);
+        }
+    }
+
+    #[test]
+    fn test_http_auth() {
+        let mut server = HttpServer::bind("127.0.0.1:0", Graph::new).unwrap();
+        server.set_tokens(Tokens::parse("r1 read\nw1 write\n").unwrap());
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let set = r#"{"answer": "a", "source": "s1"}"#;
+        assert_eq!(request(addr, "POST", "/questions/q1/answers", set).0, 401);
+        assert_eq!(
+            request(addr, "POST", "/commands", "SET q1 a FROM s1").0,
+            401
+        );
+        assert_eq!(request(addr, "GET", "/metrics", "").0, 401);
+        let (status, body) = request_as(addr, Some("x"), "GET", "/questions/q1/answer", "");
+        assert_eq!(status, 401);
+        assert!(body.contains("Invalid token"));
+
+        let (status, body) = request_as(addr, Some("r1"), "POST", "/commands", "SET q1 a FROM s1");
+        assert_eq!(status, 403);
+        assert!(body.contains("Permission denied, Set needs the write role"));
+        assert_eq!(
+            request_as(addr, Some("r1"), "POST", "/questions/q1/answers", set).0,
+            403
+        );
+        assert_eq!(
+            request_as(addr, Some("w1"), "POST", "/commands", "BELIEVE s1").0,
+            403
+        );
+
+        assert_eq!(
+            request_as(addr, Some("w1"), "POST", "/commands", "SET q1 a FROM s1").0,
+            200
+        );
+        let (status, body) = request_as(addr, Some("r1"), "GET", "/questions/q1/answer", "");
+        assert_eq!(status, 200);
+        assert!(body.contains("\"answer\":\"a\""));
+        assert_eq!(request_as(addr, Some("r1"), "GET", "/metrics", "").0, 200);
+
+        #[cfg(feature = "graphql")]
+        {
+            let mutation = r#"{"query": "mutation { set(question: \"q1\", answer: \"b\", source: \"s2\") { name } }"}"#;
+            let (status, body) = request_as(addr, Some("r1"), "POST", "/graphql", mutation);
+            assert_eq!(status, 200);
+            assert!(body.contains("Permission denied, Set needs the write role"));
+            let (_, body) = request(addr, "POST", "/graphql", mutation);
+            assert!(body.contains("Authentication required"));
+            let (_, body) = request_as(addr, Some("w1"), "POST", "/graphql", mutation);
+            assert_eq!(body, r#"{"data":{"set":{"name":"q1"}}}"#);
+        }
+    }
+}
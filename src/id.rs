@@ -0,0 +1,135 @@
+// Validated names of questions and sources
+//
+// The typed Graph API takes a QuestionId or SourceId instead of a &str, so a
+// question can't be passed where a source is expected. Names can contain any
+// character except control characters and whitespace (which would break line
+// based protocols and files, and the text grammar's items) and are limited to
+// MAX_ID_LENGTH bytes.
+
+use crate::error::ConfidisError;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+pub const MAX_ID_LENGTH: usize = 1024;
+
+pub(crate) fn validate(kind: &str, name: &str) -> Result<(), ConfidisError> {
+    let reason = if name.is_empty() {
+        "is empty"
+    } else if name.len() > MAX_ID_LENGTH {
+        "is too long"
+    } else if name.chars().any(char::is_control) {
+        "contains a control character"
+    } else if name.chars().any(char::is_whitespace) {
+        "contains whitespace"
+    } else {
+        return Ok(());
+    };
+    Err(ConfidisError::InvalidId(format!(
+        "{} name \"{}\" {}",
+        kind,
+        name.escape_debug(),
+        reason
+    )))
+}
+
+macro_rules! define_id {
+    ($name:ident, $kind:expr) => {
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+        #[serde(try_from = "String", into = "String")]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn new<S: Into<String>>(name: S) -> Result<Self, ConfidisError> {
+                let name = name.into();
+                validate($kind, &name)?;
+                Ok($name(name))
+            }
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ConfidisError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                $name::new(s)
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = ConfidisError;
+
+            fn try_from(s: String) -> Result<Self, Self::Error> {
+                $name::new(s)
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(id: $name) -> String {
+                id.0
+            }
+        }
+    };
+}
+
+define_id!(QuestionId, "question");
+define_id!(SourceId, "source");
+
+// Check the question and source names a command refers to
+pub(crate) fn validate_command_ids(
+    question: Option<&str>,
+    source: Option<&str>,
+) -> Result<(), ConfidisError> {
+    if let Some(question) = question {
+        validate("question", question)?;
+    }
+    if let Some(source) = source {
+        validate("source", source)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids() {
+        assert_eq!(QuestionId::new("q/1").unwrap().as_str(), "q/1");
+        assert!(matches!(
+            QuestionId::new("q 1"),
+            Err(ConfidisError::InvalidId(msg)) if msg.contains("whitespace")
+        ));
+        assert!(SourceId::new("s\u{a0}1").is_err());
+        assert!(QuestionId::new("").is_err());
+        assert!(SourceId::new("a\nb").is_err());
+        assert!(SourceId::new("s".repeat(MAX_ID_LENGTH + 1)).is_err());
+        assert!(matches!(
+            "".parse::<SourceId>(),
+            Err(ConfidisError::InvalidId(_))
+        ));
+
+        let id: QuestionId = serde_json::from_str("\"q1\"").unwrap();
+        assert_eq!(serde_json::to_string(&id).unwrap(), "\"q1\"");
+        assert!(serde_json::from_str::<QuestionId>("\"\"").is_err());
+    }
+}
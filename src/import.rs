@@ -0,0 +1,786 @@
+// Bulk import of answers from CSV and Label Studio exports
+//
+// Each row is one answer: a question, the answer given and the source that
+// gave it. CsvMapping says which columns hold which field, by header name or by
+// position. Rows are ingested in file order through the bulk load path, so
+// every question is only recomputed once.
+//
+// Two columns are optional. A timestamp column (unix time in ms) ingests the
+// rows in time order instead, rows with equal timestamps in file order, so
+// which answer came first, e.g. for late_answer_after or duplicate_answers
+// replace, follows when they were given rather than how the file is sorted.
+// The answers themselves are still timed when they're imported, as the journal
+// is in time order. A weight column skips the rows weighted 0 and imports those
+// weighted 1; answers can't be weighted individually, so other weights are
+// rejected rather than ignored.
+//
+// A Label Studio JSON export (Export > JSON) is a list of tasks, each with the
+// annotations annotators made. Each task becomes a question named by its id,
+// each annotator a source named by their email (or user id) and each
+// annotation an answer. When the export holds results of more than one
+// control, e.g. a choice and a text area, each control is its own question,
+// "<task id>/<control name>". Results are flattened into answers by type:
+//
+//   choices, labels, *labels   the chosen labels, sorted, joined by ","
+//   taxonomy                   each path joined by "/", sorted, joined by ","
+//   textarea                   the texts joined by " "
+//   rating, number, datetime   the value, e.g. "4" for the numeric method
+//   rectangle                  "x,y,width,height" for the numeric_vec method
+//   ellipse                    "x,y,radiusX,radiusY"
+//   keypoint                   "x,y"
+//   polygon                    "x1,y1,x2,y2,..."
+//   pairwise                   "left" or "right"
+//   anything else              the value as JSON
+//
+// An annotation with several results of one control, e.g. a box per object,
+// gives one answer of their flattened values, sorted and joined by ";".
+// Cancelled annotations and relations are skipped. Predictions are imported
+// as sources named "model:<model_version>" if LabelStudioMapping::predictions
+// is set.
+//
+// Answers may hold any text, e.g. multi-word labels, as the command grammar
+// and the journal quote them. Question and source names are validated like
+// QuestionId and SourceId, so a task id, control or annotator with whitespace
+// rejects the whole file.
+
+use crate::graph::Graph;
+use crate::id::validate;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::io::Read;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CsvColumn {
+    Index(usize),
+    Name(String),
+}
+
+impl From<usize> for CsvColumn {
+    fn from(index: usize) -> Self {
+        CsvColumn::Index(index)
+    }
+}
+
+impl From<&str> for CsvColumn {
+    fn from(name: &str) -> Self {
+        CsvColumn::Name(name.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CsvMapping {
+    pub question: CsvColumn,
+    pub answer: CsvColumn,
+    pub source: CsvColumn,
+
+    // Ingest rows in the order of this column's unix times in ms
+    pub timestamp: Option<CsvColumn>,
+    // Skip rows whose weight in this column is 0, see the top
+    pub weight: Option<CsvColumn>,
+
+    // Whether the first row holds column names, required for CsvColumn::Name
+    pub has_headers: bool,
+
+    pub delimiter: u8,
+}
+
+impl Default for CsvMapping {
+    fn default() -> Self {
+        CsvMapping {
+            question: "question".into(),
+            answer: "answer".into(),
+            source: "source".into(),
+            timestamp: None,
+            weight: None,
+            has_headers: true,
+            delimiter: b',',
+        }
+    }
+}
+
+fn resolve_column(
+    column: &CsvColumn,
+    headers: Option<&csv::StringRecord>,
+) -> Result<usize, String> {
+    match column {
+        CsvColumn::Index(index) => Ok(*index),
+        CsvColumn::Name(name) => headers
+            .ok_or_else(|| {
+                format!(
+                    "Column \"{}\" given by name but the CSV has no headers",
+                    name
+                )
+            })?
+            .iter()
+            .position(|header| header.trim() == name)
+            .ok_or_else(|| format!("CSV has no column \"{}\"", name)),
+    }
+}
+
+fn field(row: &csv::StringRecord, index: usize, line: u64) -> Result<&str, String> {
+    row.get(index)
+        .ok_or_else(|| format!("Row on line {} has no column {}", line, index))
+}
+
+struct CsvRow {
+    question: String,
+    answer: String,
+    source: String,
+    // 0 without a timestamp column
+    timestamp: u64,
+}
+
+impl Graph {
+    // Returns the number of answers ingested. The whole file is validated before
+    // anything is added, so a bad row leaves the graph untouched.
+    pub fn import_csv<R: Read>(
+        &mut self,
+        reader: R,
+        mapping: &CsvMapping,
+    ) -> Result<usize, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(mapping.has_headers)
+            .delimiter(mapping.delimiter)
+            .from_reader(reader);
+        let headers = if mapping.has_headers {
+            Some(
+                reader
+                    .headers()
+                    .map_err(|e| format!("Couldn't read CSV headers: {}", e))?
+                    .clone(),
+            )
+        } else {
+            None
+        };
+        let question_col = resolve_column(&mapping.question, headers.as_ref())?;
+        let answer_col = resolve_column(&mapping.answer, headers.as_ref())?;
+        let source_col = resolve_column(&mapping.source, headers.as_ref())?;
+        let optional_column = |column: &Option<CsvColumn>| {
+            column
+                .as_ref()
+                .map(|column| resolve_column(column, headers.as_ref()))
+                .transpose()
+        };
+        let timestamp_col = optional_column(&mapping.timestamp)?;
+        let weight_col = optional_column(&mapping.weight)?;
+
+        let mut rows: Vec<CsvRow> = Vec::new();
+        for row in reader.records() {
+            let row = row.map_err(|e| format!("Couldn't read CSV: {}", e))?;
+            let line = row.position().map_or(0, |p| p.line());
+            if let Some(weight_col) = weight_col {
+                match field(&row, weight_col, line)?.trim().parse::<f64>() {
+                    Ok(0.0) => continue,
+                    Ok(1.0) => {}
+                    _ => {
+                        return Err(format!(
+                            "Row on line {} has an invalid weight, expected 0 (skip) or 1",
+                            line
+                        ))
+                    }
+                }
+            }
+            let timestamp = match timestamp_col {
+                Some(timestamp_col) => field(&row, timestamp_col, line)?
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|_| {
+                        format!(
+                            "Row on line {} has an invalid timestamp, expected unix time in ms",
+                            line
+                        )
+                    })?,
+                None => 0,
+            };
+            let question = field(&row, question_col, line)?;
+            let source = field(&row, source_col, line)?;
+            if question.is_empty() || source.is_empty() {
+                return Err(format!(
+                    "Row on line {} is missing a question or source",
+                    line
+                ));
+            }
+            // Answers are quoted when journaled, names can't be
+            validate("question", question)
+                .and_then(|_| validate("source", source))
+                .map_err(|e| format!("Row on line {}: {}", line, e))?;
+            rows.push(CsvRow {
+                question: question.to_string(),
+                answer: field(&row, answer_col, line)?.to_string(),
+                source: source.to_string(),
+                timestamp,
+            });
+        }
+        // A stable sort, so rows given at the same time stay in file order
+        rows.sort_by_key(|row| row.timestamp);
+
+        let entries: Vec<(&str, &str, &str)> = rows
+            .iter()
+            .map(|row| {
+                (
+                    row.question.as_str(),
+                    row.answer.as_str(),
+                    row.source.as_str(),
+                )
+            })
+            .collect();
+        // Join a bulk load the caller already started instead of finishing it
+        let own_bulk_load = !self.is_bulk_loading();
+        if own_bulk_load {
+            self.begin_bulk_load();
+        }
+        self.set_many(&entries)?;
+        if own_bulk_load {
+            self.finish_bulk_load()?;
+        }
+        Ok(entries.len())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LabelStudioMapping {
+    // Only import results of these controls (their from_name), every
+    // control's when empty
+    pub controls: Vec<String>,
+
+    // Import each task's predictions too
+    pub predictions: bool,
+}
+
+#[derive(Deserialize)]
+struct LabelStudioTask {
+    id: Option<Value>,
+    #[serde(default)]
+    annotations: Vec<LabelStudioAnnotation>,
+    #[serde(default)]
+    predictions: Vec<LabelStudioAnnotation>,
+}
+
+// Annotations and predictions share a layout
+#[derive(Deserialize)]
+struct LabelStudioAnnotation {
+    #[serde(default)]
+    completed_by: Value,
+    #[serde(default)]
+    model_version: Value,
+    #[serde(default)]
+    was_cancelled: bool,
+    #[serde(default)]
+    result: Vec<LabelStudioResult>,
+}
+
+#[derive(Deserialize)]
+struct LabelStudioResult {
+    from_name: Option<String>,
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    value: Value,
+}
+
+// A number or string as text, None for anything else
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+// The scalars in a value, a lone scalar counting as a list of one
+fn scalars(value: &Value) -> Vec<String> {
+    match value {
+        Value::Array(values) => values.iter().filter_map(scalar).collect(),
+        value => scalar(value).into_iter().collect(),
+    }
+}
+
+fn sorted_list(mut items: Vec<String>) -> String {
+    items.sort();
+    items.join(",")
+}
+
+// The numbers of the value's fields, in order, None if one is missing
+fn geometry(value: &Value, fields: &[&str]) -> Option<String> {
+    let numbers: Option<Vec<String>> = fields
+        .iter()
+        .map(|field| value.get(field).filter(|v| v.is_number()).and_then(scalar))
+        .collect();
+    Some(numbers?.join(","))
+}
+
+// A result's value as an answer, see the table at the top
+fn flatten_result(result: &LabelStudioResult) -> String {
+    let value = &result.value;
+    let flattened = match result.kind.as_str() {
+        "textarea" => value.get("text").map(|text| {
+            scalars(text)
+                .iter()
+                .flat_map(|text| text.split_whitespace())
+                .collect::<Vec<&str>>()
+                .join(" ")
+        }),
+        "taxonomy" => value
+            .get("taxonomy")
+            .and_then(Value::as_array)
+            .map(|paths| sorted_list(paths.iter().map(|path| scalars(path).join("/")).collect())),
+        "rectangle" => geometry(value, &["x", "y", "width", "height"]),
+        "ellipse" => geometry(value, &["x", "y", "radiusX", "radiusY"]),
+        "keypoint" => geometry(value, &["x", "y"]),
+        "polygon" => value.get("points").and_then(Value::as_array).map(|points| {
+            points
+                .iter()
+                .flat_map(scalars)
+                .collect::<Vec<String>>()
+                .join(",")
+        }),
+        "pairwise" => value.get("selected").and_then(scalar),
+        // choices, labels, rectanglelabels, rating, number, datetime, ... keep
+        // their payload in the field named after their type
+        kind => value.get(kind).map(|payload| sorted_list(scalars(payload))),
+    };
+    flattened.unwrap_or_else(|| value.to_string())
+}
+
+// Who made an annotation or prediction, None if the export doesn't say
+fn annotation_source(annotation: &LabelStudioAnnotation, prediction: bool) -> Option<String> {
+    if prediction {
+        let version = scalar(&annotation.model_version).unwrap_or_default();
+        return Some(format!("model:{}", version));
+    }
+    match &annotation.completed_by {
+        Value::Object(user) => user
+            .get("email")
+            .and_then(scalar)
+            .filter(|email| !email.is_empty())
+            .or_else(|| user.get("id").and_then(scalar)),
+        user => scalar(user),
+    }
+}
+
+impl Graph {
+    // Returns the number of answers ingested. Like import_csv, the whole export
+    // is validated before anything is added.
+    pub fn import_label_studio<R: Read>(
+        &mut self,
+        reader: R,
+        mapping: &LabelStudioMapping,
+    ) -> Result<usize, String> {
+        let tasks: Vec<LabelStudioTask> = serde_json::from_reader(reader)
+            .map_err(|e| format!("Couldn't read Label Studio export: {}", e))?;
+
+        // (task id, control, source, answer) in export order
+        let mut answers: Vec<(String, String, String, String)> = Vec::new();
+        for (index, task) in tasks.iter().enumerate() {
+            let task_id = task
+                .id
+                .as_ref()
+                .and_then(scalar)
+                .ok_or_else(|| format!("Task {} has no id", index))?;
+            let predictions = if mapping.predictions {
+                &task.predictions[..]
+            } else {
+                &[]
+            };
+            let annotations = task
+                .annotations
+                .iter()
+                .map(|annotation| (annotation, false))
+                .chain(predictions.iter().map(|prediction| (prediction, true)));
+            for (annotation, prediction) in annotations {
+                if annotation.was_cancelled {
+                    continue;
+                }
+                let source = annotation_source(annotation, prediction)
+                    .ok_or_else(|| format!("An annotation of task {} has no annotator", task_id))?;
+                // Each control's flattened results, controls in order of
+                // their first result
+                let mut controls: Vec<(&str, Vec<String>)> = Vec::new();
+                for result in &annotation.result {
+                    let control = match &result.from_name {
+                        Some(control) if result.kind != "relation" => control.as_str(),
+                        _ => continue,
+                    };
+                    if !mapping.controls.is_empty()
+                        && !mapping.controls.iter().any(|c| c == control)
+                    {
+                        continue;
+                    }
+                    let flattened = flatten_result(result);
+                    match controls.iter_mut().find(|(name, _)| *name == control) {
+                        Some((_, values)) => values.push(flattened),
+                        None => controls.push((control, vec![flattened])),
+                    }
+                }
+                for (control, mut values) in controls {
+                    values.sort();
+                    let answer = values.join(";");
+                    if answer.is_empty() {
+                        continue;
+                    }
+                    answers.push((task_id.clone(), control.to_string(), source.clone(), answer));
+                }
+            }
+        }
+
+        let control_count = answers
+            .iter()
+            .map(|(_, control, _, _)| control)
+            .collect::<BTreeSet<_>>()
+            .len();
+        let questions: Vec<String> = answers
+            .iter()
+            .map(|(task_id, control, _, _)| match control_count {
+                1 => task_id.clone(),
+                _ => format!("{}/{}", task_id, control),
+            })
+            .collect();
+        let entries: Vec<(&str, &str, &str)> = answers
+            .iter()
+            .zip(&questions)
+            .map(|((_, _, source, answer), question)| {
+                (question.as_str(), answer.as_str(), source.as_str())
+            })
+            .collect();
+        for (question, _, source) in &entries {
+            validate("question", question)
+                .and_then(|_| validate("source", source))
+                .map_err(|e| format!("Invalid Label Studio export: {}", e))?;
+        }
+        let own_bulk_load = !self.is_bulk_loading();
+        if own_bulk_load {
+            self.begin_bulk_load();
+        }
+        self.set_many(&entries)?;
+        if own_bulk_load {
+            self.finish_bulk_load()?;
+        }
+        Ok(entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+    use crate::journal::Journal;
+
+    fn run(g: &mut Graph, line: &str) -> String {
+        format!(
+            "{}",
+            g.execute_command(&Command::from(line).unwrap()).unwrap()
+        )
+    }
+
+    #[test]
+    fn test_import_csv() {
+        let csv = "source,question,answer\ns1,q1,a\ns2,q1,a\ns3,q1,b\ns1,q2,\"x, y\"\n";
+        let mut g = Graph::new();
+        assert_eq!(
+            g.import_csv(csv.as_bytes(), &CsvMapping::default())
+                .unwrap(),
+            4
+        );
+        assert!(!g.is_bulk_loading());
+
+        let mut expected = Graph::new();
+        for line in &["SET q1 a FROM s1", "SET q1 a FROM s2", "SET q1 b FROM s3"] {
+            run(&mut expected, line);
+        }
+        assert_eq!(
+            run(&mut g, "GET ANSWER TO q1"),
+            run(&mut expected, "GET ANSWER TO q1")
+        );
+        assert_eq!(g.question("q2").unwrap().answers[0].content, "x, y");
+    }
+
+    #[test]
+    fn test_import_csv_replays() {
+        let path = std::env::temp_dir().join(format!("confidis-import-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let csv = "source,question,answer\n\
+                   s1,q1,\"New York\"\n\
+                   s2,q1,\"New York\n1 BELIEVE s9\"\n\
+                   s3,q2,\"say \"\"hi\"\" \\ bye\"\n";
+        let mut g = Graph::new();
+        g.set_journal(Journal::open(&path).unwrap());
+        assert_eq!(
+            g.import_csv(csv.as_bytes(), &CsvMapping::default())
+                .unwrap(),
+            3
+        );
+
+        let restored = Graph::replay(&path).unwrap();
+        for question in &["q1", "q2"] {
+            let contents = |g: &Graph| -> Vec<String> {
+                g.question(question)
+                    .unwrap()
+                    .answers
+                    .iter()
+                    .map(|a| a.content.to_string())
+                    .collect()
+            };
+            assert_eq!(contents(&restored), contents(&g));
+        }
+        assert_eq!(
+            restored.question("q1").unwrap().answers[1].content,
+            "New York\n1 BELIEVE s9"
+        );
+        assert_eq!(
+            restored.question("q2").unwrap().answers[0].content,
+            "say \"hi\" \\ bye"
+        );
+        assert!(restored.source("s9").is_none());
+        std::fs::remove_file(&path).unwrap();
+
+        // names can't hold whitespace, the whole file is rejected
+        let bad = "source,question,answer\ns1,q1,a\ns2,q 1,a\n";
+        let mut g = Graph::new();
+        assert!(g
+            .import_csv(bad.as_bytes(), &CsvMapping::default())
+            .err()
+            .unwrap()
+            .contains("line 3"));
+        assert!(g.question("q1").is_none());
+    }
+
+    #[test]
+    fn test_import_csv_mapping() {
+        let csv = "1;q1;a;s1\n2;q1;a;s2\n3;q1;b;s3\n";
+        let mapping = CsvMapping {
+            question: 1.into(),
+            answer: 2.into(),
+            source: 3.into(),
+            timestamp: None,
+            weight: None,
+            has_headers: false,
+            delimiter: b';',
+        };
+        let mut g = Graph::new();
+        assert_eq!(g.import_csv(csv.as_bytes(), &mapping).unwrap(), 3);
+        let sources: Vec<&str> = g
+            .question("q1")
+            .unwrap()
+            .answers
+            .iter()
+            .map(|a| a.source.as_str())
+            .collect();
+        assert_eq!(sources, vec!["s1", "s2", "s3"]);
+
+        let bad = "1;q1;a;s1\n2;q 1;a;s2\n";
+        assert!(Graph::new()
+            .import_csv(bad.as_bytes(), &mapping)
+            .err()
+            .unwrap()
+            .contains("line 2"));
+        assert!(Graph::new()
+            .import_csv("a,b,c\n".as_bytes(), &CsvMapping::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_import_csv_timestamp_and_weight() {
+        let csv = "question,answer,source,time,weight\n\
+                   q1,b,s1,2000,1\n\
+                   q2,a,s2,3000,1\n\
+                   q1,a,s1,1000,1\n\
+                   q2,a,s1,1000,1\n\
+                   q2,c,s3,500,0\n";
+        let mapping = CsvMapping {
+            timestamp: Some("time".into()),
+            weight: Some("weight".into()),
+            ..CsvMapping::default()
+        };
+        let mut g = Graph::new();
+        run(&mut g, "CONFIGURE duplicate_answers replace");
+        assert_eq!(g.import_csv(csv.as_bytes(), &mapping).unwrap(), 4);
+        let answers = |g: &Graph, question: &str| -> Vec<(String, String)> {
+            g.question(question)
+                .unwrap()
+                .answers
+                .iter()
+                .map(|a| (a.source.to_string(), a.content.to_string()))
+                .collect()
+        };
+        // s1's later answer replaces its earlier one, though it comes first
+        assert_eq!(
+            answers(&g, "q1"),
+            vec![(String::from("s1"), String::from("b"))]
+        );
+        // rows are ingested in time order, the row weighted 0 is skipped
+        assert_eq!(
+            answers(&g, "q2"),
+            vec![
+                (String::from("s1"), String::from("a")),
+                (String::from("s2"), String::from("a")),
+            ]
+        );
+        assert!(g.source("s3").is_none());
+
+        for bad in &[
+            "question,answer,source,time,weight\nq1,a,s1,1000,0.5\n",
+            "question,answer,source,time,weight\nq1,a,s1,yesterday,1\n",
+            "question,answer,source,time,weight\nq1,a,s1,,1\n",
+        ] {
+            let mut g = Graph::new();
+            assert!(g
+                .import_csv(bad.as_bytes(), &mapping)
+                .err()
+                .unwrap()
+                .contains("line 2"));
+            assert!(g.question("q1").is_none());
+        }
+        let mapping = CsvMapping {
+            timestamp: Some("when".into()),
+            ..CsvMapping::default()
+        };
+        assert!(Graph::new().import_csv(csv.as_bytes(), &mapping).is_err());
+    }
+
+    #[test]
+    fn test_import_label_studio_replays() {
+        let path =
+            std::env::temp_dir().join(format!("confidis-import-ls-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let export = r#"[
+            {"id": 1, "annotations": [
+                {"completed_by": 7, "result": [
+                    {"from_name": "summary", "type": "textarea",
+                     "value": {"text": ["Two people", "walking\nFROM s9"]}},
+                    {"from_name": "mood", "type": "choices",
+                     "value": {"choices": ["Very \"happy\"", "Calm"]}}]},
+                {"completed_by": 8, "result": [
+                    {"from_name": "summary", "type": "textarea",
+                     "value": {"text": "Two  people walking FROM s9"}}]}]}
+        ]"#;
+        let mut g = Graph::new();
+        g.set_journal(Journal::open(&path).unwrap());
+        assert_eq!(
+            g.import_label_studio(export.as_bytes(), &LabelStudioMapping::default())
+                .unwrap(),
+            3
+        );
+
+        let restored = Graph::replay(&path).unwrap();
+        let answers = |g: &Graph, question: &str| -> Vec<(String, String)> {
+            g.question(question)
+                .unwrap()
+                .answers
+                .iter()
+                .map(|a| (a.source.to_string(), a.content.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            answers(&restored, "1/summary"),
+            vec![
+                (
+                    String::from("7"),
+                    String::from("Two people walking FROM s9")
+                ),
+                (
+                    String::from("8"),
+                    String::from("Two people walking FROM s9")
+                ),
+            ]
+        );
+        assert_eq!(
+            answers(&restored, "1/mood"),
+            vec![(String::from("7"), String::from("Calm,Very \"happy\""))]
+        );
+        assert_eq!(answers(&restored, "1/summary"), answers(&g, "1/summary"));
+        assert!(restored.source("s9").is_none());
+        std::fs::remove_file(&path).unwrap();
+
+        let bad = r#"[{"id": "task 1", "annotations": [{"completed_by": 7, "result": [
+            {"from_name": "mood", "type": "choices", "value": {"choices": ["Calm"]}}]}]}]"#;
+        let mut g = Graph::new();
+        assert!(g
+            .import_label_studio(bad.as_bytes(), &LabelStudioMapping::default())
+            .err()
+            .unwrap()
+            .contains("whitespace"));
+        assert!(g.question("task 1").is_none());
+    }
+
+    #[test]
+    fn test_import_label_studio() {
+        let export = r#"[
+            {"id": 1, "data": {"text": "great"}, "annotations": [
+                {"completed_by": {"id": 7, "email": "ann@example.com"}, "result": [
+                    {"from_name": "sentiment", "to_name": "text", "type": "choices",
+                     "value": {"choices": ["Positive"]}}]},
+                {"completed_by": 8, "result": [
+                    {"from_name": "sentiment", "to_name": "text", "type": "choices",
+                     "value": {"choices": ["Positive"]}},
+                    {"from_name": "box", "to_name": "image", "type": "rectangle",
+                     "value": {"x": 10, "y": 20.5, "width": 30, "height": 40, "rotation": 0}}]},
+                {"completed_by": 9, "was_cancelled": true, "result": [
+                    {"from_name": "sentiment", "to_name": "text", "type": "choices",
+                     "value": {"choices": ["Negative"]}}]}],
+             "predictions": [
+                {"model_version": "v2", "result": [
+                    {"from_name": "sentiment", "to_name": "text", "type": "choices",
+                     "value": {"choices": ["Negative"]}}]}]},
+            {"id": 2, "annotations": [
+                {"completed_by": 8, "result": [
+                    {"from_name": "sentiment", "to_name": "text", "type": "labels",
+                     "value": {"start": 0, "end": 4, "labels": ["Person", "Org"]}},
+                    {"from_name": "sentiment", "to_name": "text", "type": "labels",
+                     "value": {"start": 9, "end": 12, "labels": ["City"]}},
+                    {"type": "relation", "from_id": "a", "to_id": "b"}]}]}
+        ]"#;
+        let mut g = Graph::new();
+        assert_eq!(
+            g.import_label_studio(export.as_bytes(), &LabelStudioMapping::default())
+                .unwrap(),
+            4
+        );
+        let answers = |g: &Graph, question: &str| -> Vec<(String, String)> {
+            g.question(question)
+                .unwrap()
+                .answers
+                .iter()
+                .map(|a| (a.source.to_string(), a.content.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            answers(&g, "1/sentiment"),
+            vec![
+                (String::from("ann@example.com"), String::from("Positive")),
+                (String::from("8"), String::from("Positive")),
+            ]
+        );
+        assert_eq!(
+            answers(&g, "1/box"),
+            vec![(String::from("8"), String::from("10,20.5,30,40"))]
+        );
+        assert_eq!(
+            answers(&g, "2/sentiment"),
+            vec![(String::from("8"), String::from("City;Org,Person"))]
+        );
+
+        // one control names questions by task, predictions are a source each
+        let mapping = LabelStudioMapping {
+            controls: vec![String::from("sentiment")],
+            predictions: true,
+        };
+        let mut g = Graph::new();
+        assert_eq!(
+            g.import_label_studio(export.as_bytes(), &mapping).unwrap(),
+            4
+        );
+        assert_eq!(
+            run(&mut g, "GET ANSWER TO 1").split(' ').next(),
+            Some("Positive")
+        );
+        assert!(answers(&g, "1").contains(&(String::from("model:v2"), String::from("Negative"))));
+
+        assert!(Graph::new()
+            .import_label_studio(r#"[{"annotations": []}]"#.as_bytes(), &mapping)
+            .is_err());
+        assert!(Graph::new()
+            .import_label_studio("{".as_bytes(), &mapping)
+            .is_err());
+    }
+}
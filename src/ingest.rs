@@ -0,0 +1,188 @@
+// Ingestion of answers from a message stream
+//
+// Every message is a JSON object {"question": "...", "answer": "...",
+// "source": "..."}. IngestRunner polls a MessageStream for a batch, applies it
+// with set_many so each affected question is recomputed once per batch, syncs
+// the graph's journal and only then commits the batch. A crash before the
+// commit makes the stream deliver the batch again, so no acknowledged answer
+// can be lost. Messages that aren't valid JSON answers are skipped.
+
+#[cfg(feature = "kafka")]
+pub mod kafka;
+
+use crate::graph::Graph;
+use futures::executor::block_on;
+use futures::{FutureExt, Stream, StreamExt};
+use log::warn;
+use serde::Deserialize;
+
+// A source of message payloads, e.g. a Kafka topic
+pub trait MessageStream {
+    // The next batch of payloads, empty if none arrived in time
+    fn poll(&mut self) -> Result<Vec<Vec<u8>>, String>;
+
+    // Acknowledge every payload returned by poll so far
+    fn commit(&mut self) -> Result<(), String>;
+
+    // Whether poll will never return anything again
+    fn is_finished(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AnswerMessage {
+    question: String,
+    answer: String,
+    source: String,
+}
+
+// A MessageStream over any futures Stream of payloads. Nothing can be
+// acknowledged, commit does nothing.
+pub struct StreamSource<S> {
+    stream: S,
+    batch_size: usize,
+    finished: bool,
+}
+
+impl<S: Stream<Item = Vec<u8>> + Unpin> StreamSource<S> {
+    pub fn new(stream: S, batch_size: usize) -> StreamSource<S> {
+        StreamSource {
+            stream,
+            batch_size: batch_size.max(1),
+            finished: false,
+        }
+    }
+}
+
+impl<S: Stream<Item = Vec<u8>> + Unpin> MessageStream for StreamSource<S> {
+    // Waits for the first payload, then takes whatever else is ready
+    fn poll(&mut self) -> Result<Vec<Vec<u8>>, String> {
+        let mut batch = Vec::new();
+        match block_on(self.stream.next()) {
+            Some(payload) => batch.push(payload),
+            None => self.finished = true,
+        }
+        while !self.finished && batch.len() < self.batch_size {
+            match self.stream.next().now_or_never() {
+                Some(Some(payload)) => batch.push(payload),
+                Some(None) => self.finished = true,
+                None => break,
+            }
+        }
+        Ok(batch)
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct IngestStats {
+    pub batches: usize,
+    pub applied: usize,
+    pub invalid: usize,
+}
+
+pub struct IngestRunner<M> {
+    stream: M,
+    pub stats: IngestStats,
+}
+
+impl<M: MessageStream> IngestRunner<M> {
+    pub fn new(stream: M) -> IngestRunner<M> {
+        IngestRunner {
+            stream,
+            stats: IngestStats::default(),
+        }
+    }
+
+    pub fn into_stream(self) -> M {
+        self.stream
+    }
+
+    // Apply one batch, returning the number of answers applied. Nothing is
+    // committed if applying or journaling fails.
+    pub fn run_once(&mut self, g: &mut Graph) -> Result<usize, String> {
+        let payloads = self.stream.poll()?;
+        if payloads.is_empty() {
+            return Ok(0);
+        }
+        let mut messages: Vec<AnswerMessage> = Vec::with_capacity(payloads.len());
+        for payload in &payloads {
+            match serde_json::from_slice(payload) {
+                Ok(message) => messages.push(message),
+                Err(e) => {
+                    warn!("Skipping invalid answer message: {}", e);
+                    self.stats.invalid += 1;
+                }
+            }
+        }
+        let entries: Vec<(&str, &str, &str)> = messages
+            .iter()
+            .map(|m| (m.question.as_str(), m.answer.as_str(), m.source.as_str()))
+            .collect();
+        g.set_many(&entries)?;
+        g.sync_journal()?;
+        self.stream.commit()?;
+        self.stats.batches += 1;
+        self.stats.applied += entries.len();
+        Ok(entries.len())
+    }
+
+    // Apply batches until the stream finishes or an error occurs
+    pub fn run(&mut self, g: &mut Graph) -> Result<IngestStats, String> {
+        while !self.stream.is_finished() {
+            self.run_once(g)?;
+        }
+        Ok(self.stats.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::Journal;
+
+    #[test]
+    fn test_ingest_stream() {
+        let path =
+            std::env::temp_dir().join(format!("confidis-ingest-{}.journal", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut g = Graph::new();
+        g.set_journal(Journal::open(&path).unwrap());
+
+        let payloads: Vec<Vec<u8>> = vec![
+            r#"{"question": "q1", "answer": "a", "source": "s1"}"#,
+            r#"{"question": "q1", "answer": "a", "source": "s2"}"#,
+            "not json",
+            r#"{"question": "q2", "answer": "b", "source": "s1"}"#,
+        ]
+        .into_iter()
+        .map(|payload| payload.as_bytes().to_vec())
+        .collect();
+        let mut runner = IngestRunner::new(StreamSource::new(futures::stream::iter(payloads), 2));
+        let stats = runner.run(&mut g).unwrap();
+        assert_eq!(
+            stats,
+            IngestStats {
+                batches: 2,
+                applied: 3,
+                invalid: 1,
+            }
+        );
+
+        let replayed = Graph::replay(&path).unwrap();
+        assert_eq!(
+            replayed.compute_answer("q1").unwrap(),
+            g.compute_answer("q1").unwrap()
+        );
+        assert_eq!(replayed.compute_answer("q2").unwrap().0, "b");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,51 @@
+// Kafka as a MessageStream
+//
+// Offsets are stored in Kafka under the consumer group. Offsets of polled
+// messages are only committed by commit, after IngestRunner has journaled
+// them, so a restarted consumer resumes with the first unjournaled message.
+
+use super::MessageStream;
+use ::kafka::consumer::{Consumer, FetchOffset, GroupOffsetStorage};
+
+fn kafka_err(e: ::kafka::Error) -> String {
+    format!("Kafka error: {}", e)
+}
+
+pub struct KafkaSource {
+    consumer: Consumer,
+}
+
+impl KafkaSource {
+    // Consume topic as group, starting at the earliest message the first time
+    // the group is used
+    pub fn connect(hosts: Vec<String>, topic: &str, group: &str) -> Result<KafkaSource, String> {
+        let consumer = Consumer::from_hosts(hosts)
+            .with_topic(topic.to_string())
+            .with_group(group.to_string())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .with_offset_storage(Some(GroupOffsetStorage::Kafka))
+            .create()
+            .map_err(kafka_err)?;
+        Ok(KafkaSource { consumer })
+    }
+}
+
+impl MessageStream for KafkaSource {
+    fn poll(&mut self) -> Result<Vec<Vec<u8>>, String> {
+        let mut batch = Vec::new();
+        for message_set in self.consumer.poll().map_err(kafka_err)?.iter() {
+            for message in message_set.messages() {
+                batch.push(message.value.to_vec());
+            }
+            // Only marks the messages consumed, commit makes it permanent
+            self.consumer
+                .consume_messageset(message_set)
+                .map_err(kafka_err)?;
+        }
+        Ok(batch)
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        self.consumer.commit_consumed().map_err(kafka_err)
+    }
+}
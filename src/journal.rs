@@ -0,0 +1,1168 @@
+// Append-only journal of the mutations applied to a Graph
+//
+// Every line is "<unix timestamp in ms> <record>" where the record is one of
+//   a mutating command in the text grammar, e.g. SET q1 a FROM s1, with
+//   values that contain whitespace quoted, e.g. SET q1 "New York" FROM s1
+//   BATCH <n>, followed by n SET lines added together with Graph::set_many
+//   BEGIN BULK LOAD / FINISH BULK LOAD
+//   SEED SOURCE <source> <quality> <strength>, sets a source's quality and
+//   strength, written by Graph::calibrate
+//   SNAPSHOT <file>, replaces the graph with a snapshot stored next to the
+//   journal, written by compaction
+//   ENCRYPTED <hex>, the records of one append encrypted with the journal's
+//   key and bound to the line's position and timestamp, see encryption.rs.
+//   An encrypted journal holds nothing else.
+//
+// Graph::replay re-applies the records in order to reconstruct the graph. A
+// trailing record that was only partially written (e.g. the process crashed
+// mid-append) is ignored, and cut off when the journal is opened for appending
+// so the next record starts on a line of its own.
+//
+// Compaction bounds the size of a journal: the records are folded into a
+// snapshot and the journal is rewritten as a single SNAPSHOT record followed
+// by the tail that couldn't be folded in (an unfinished bulk load).
+//
+// Followers (see follow) receive every record as it's appended, which is how a
+// primary streams its journal to replicas, see replication.rs.
+
+use crate::calibration::SourceCalibration;
+use crate::command::{quote, split_items, Command, CommandType};
+use crate::encryption::{decode_hex, encode_hex, EncryptionKey};
+use crate::error::ConfidisError;
+use crate::graph::Graph;
+use log::warn;
+use std::borrow::Cow;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The current unix time in ms. SystemTime::now panics on
+// wasm32-unknown-unknown, so GraphJS asks JavaScript's Date instead.
+#[cfg(target_arch = "wasm32")]
+pub fn now_millis() -> u64 {
+    js_sys::Date::now() as u64
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// Records parsed for a replay borrow from the journal's lines, see read
+#[derive(Debug)]
+pub enum JournalRecord<'a> {
+    Command(Box<Command<'a>>),
+    SetMany(Vec<(Cow<'a, str>, Cow<'a, str>, Cow<'a, str>)>),
+    BeginBulkLoad,
+    FinishBulkLoad,
+    SeedSource {
+        source: Cow<'a, str>,
+        quality: f64,
+        strength: f64,
+    },
+    // File name of a snapshot, relative to the journal's directory
+    Snapshot(String),
+}
+
+#[derive(Debug)]
+pub struct JournalEntry<'a> {
+    pub timestamp: u64,
+    pub record: JournalRecord<'a>,
+}
+
+impl JournalEntry<'_> {
+    pub fn into_owned(self) -> JournalEntry<'static> {
+        let own = |s: Cow<str>| Cow::Owned(s.into_owned());
+        let record = match self.record {
+            JournalRecord::Command(cmd) => JournalRecord::Command(Box::new(cmd.into_owned())),
+            JournalRecord::SetMany(batch) => JournalRecord::SetMany(
+                batch
+                    .into_iter()
+                    .map(|(q, a, s)| (own(q), own(a), own(s)))
+                    .collect(),
+            ),
+            JournalRecord::BeginBulkLoad => JournalRecord::BeginBulkLoad,
+            JournalRecord::FinishBulkLoad => JournalRecord::FinishBulkLoad,
+            JournalRecord::SeedSource {
+                source,
+                quality,
+                strength,
+            } => JournalRecord::SeedSource {
+                source: own(source),
+                quality,
+                strength,
+            },
+            JournalRecord::Snapshot(file) => JournalRecord::Snapshot(file),
+        };
+        JournalEntry {
+            timestamp: self.timestamp,
+            record,
+        }
+    }
+}
+
+pub struct Journal {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    // Receive the lines of each record appended, see follow
+    followers: Vec<Sender<String>>,
+    // Records are encrypted with this key, see encryption.rs
+    key: Option<EncryptionKey>,
+    // The position of the next line in the file, which encrypted records are
+    // bound to. Only counted when there's a key.
+    next_line: usize,
+}
+
+impl Journal {
+    // Open a journal for appending, creating the file if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Journal, String> {
+        Journal::open_with_key(path, None)
+    }
+
+    // Open a journal for appending records encrypted with key. Fails if the
+    // journal already holds plain records.
+    #[cfg(feature = "encryption")]
+    pub fn open_encrypted<P: AsRef<Path>>(path: P, key: EncryptionKey) -> Result<Journal, String> {
+        Journal::open_with_key(path, Some(key))
+    }
+
+    fn open_with_key<P: AsRef<Path>>(
+        path: P,
+        key: Option<EncryptionKey>,
+    ) -> Result<Journal, String> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Couldn't open journal {}: {}", path.display(), e))?;
+        truncate_torn_line(&mut file)
+            .map_err(|e| format!("Couldn't repair journal {}: {}", path.display(), e))?;
+        let next_line = match key {
+            Some(_) => count_encrypted_lines(&path)?,
+            None => 0,
+        };
+        Ok(Journal {
+            path,
+            writer: BufWriter::new(file),
+            followers: Vec::new(),
+            key,
+            next_line,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn key(&self) -> Option<&EncryptionKey> {
+        self.key.as_ref()
+    }
+
+    // Receives the lines of every record appended from now on, until the
+    // receiver is dropped
+    pub fn follow(&mut self) -> Receiver<String> {
+        let (lines, receiver) = channel();
+        self.followers.push(lines);
+        receiver
+    }
+
+    pub fn append_command(&mut self, cmd: &Command) -> Result<(), String> {
+        let line = format!("{} {}\n", now_millis(), cmd);
+        self.write(&line)
+    }
+
+    pub fn append_set_many(&mut self, entries: &[(&str, &str, &str)]) -> Result<(), String> {
+        self.write(&batch_lines(now_millis(), entries))
+    }
+
+    pub fn append_begin_bulk_load(&mut self) -> Result<(), String> {
+        self.write(&format!("{} BEGIN BULK LOAD\n", now_millis()))
+    }
+
+    pub fn append_finish_bulk_load(&mut self) -> Result<(), String> {
+        self.write(&format!("{} FINISH BULK LOAD\n", now_millis()))
+    }
+
+    pub fn append_seed_sources(
+        &mut self,
+        calibrations: &[SourceCalibration],
+    ) -> Result<(), String> {
+        let timestamp = now_millis();
+        let lines: String = calibrations
+            .iter()
+            .map(|c| seed_source_line(timestamp, &c.source, c.quality, c.strength))
+            .collect();
+        self.write(&lines)
+    }
+
+    // Tell followers UNDO can't take back the records before this point, see
+    // Graph::clear_undo. Nothing is written to the file.
+    pub(crate) fn undo_cleared(&mut self) {
+        let line = format!("{} CLEAR UNDO\n", now_millis());
+        self.followers
+            .retain(|follower| follower.send(line.clone()).is_ok());
+    }
+
+    // Flush and fsync the journal so every appended record survives a crash
+    pub fn sync(&mut self) -> Result<(), String> {
+        self.writer
+            .flush()
+            .and_then(|_| self.writer.get_ref().sync_data())
+            .map_err(|e| format!("Couldn't sync journal: {}", e))
+    }
+
+    // Discard every record, used once they are all covered by a snapshot
+    pub fn truncate(&mut self) -> Result<(), String> {
+        self.writer
+            .flush()
+            .and_then(|_| self.writer.get_ref().set_len(0))
+            .and_then(|_| self.writer.get_ref().sync_all())
+            .map_err(|e| format!("Couldn't truncate journal: {}", e))?;
+        self.next_line = 0;
+        Ok(())
+    }
+
+    fn write(&mut self, lines: &str) -> Result<(), String> {
+        let written = match self.key.as_ref() {
+            Some(key) => Cow::Owned(encrypted_line(now_millis(), self.next_line, key, lines)),
+            None => Cow::Borrowed(lines),
+        };
+        self.writer
+            .write_all(written.as_bytes())
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| format!("Couldn't write to journal: {}", e))?;
+        if self.key.is_some() {
+            self.next_line += 1;
+        }
+        self.followers
+            .retain(|follower| follower.send(lines.to_string()).is_ok());
+        Ok(())
+    }
+
+    // Every complete record of the journal at path
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<JournalEntry<'static>>, String> {
+        Journal::read_with_key(path.as_ref(), None)
+    }
+
+    // Every complete record of a journal encrypted with key
+    #[cfg(feature = "encryption")]
+    pub fn read_encrypted<P: AsRef<Path>>(
+        path: P,
+        key: &EncryptionKey,
+    ) -> Result<Vec<JournalEntry<'static>>, String> {
+        Journal::read_with_key(path.as_ref(), Some(key))
+    }
+
+    fn read_with_key(
+        path: &Path,
+        key: Option<&EncryptionKey>,
+    ) -> Result<Vec<JournalEntry<'static>>, String> {
+        let lines = read_lines(path, key)?;
+        Ok(parse_entries(&lines)?
+            .into_iter()
+            .map(JournalEntry::into_owned)
+            .collect())
+    }
+
+    // Fold a journal into a snapshot, keeping an unfinished bulk load as the
+    // tail. The journal must describe the graph from empty, and must not be open
+    // for appending elsewhere. Returns the number of records folded in.
+    pub fn compact<P: AsRef<Path>>(path: P) -> Result<usize, String> {
+        let path = path.as_ref();
+        let entries = Journal::read(path)?;
+        let mut tail_start = entries.len();
+        for (i, entry) in entries.iter().enumerate() {
+            match entry.record {
+                JournalRecord::BeginBulkLoad if tail_start == entries.len() => tail_start = i,
+                JournalRecord::FinishBulkLoad => tail_start = entries.len(),
+                _ => {}
+            }
+        }
+        let mut g = Graph::new();
+        g.apply_journal_entries(&entries[..tail_start], base_dir(path), None)?;
+        rewrite_compacted(path, &g, &entries, tail_start, None)?;
+        Ok(tail_start)
+    }
+}
+
+fn base_dir(path: &Path) -> &Path {
+    path.parent().unwrap_or_else(|| Path::new(""))
+}
+
+fn batch_lines<Q: AsRef<str>, A: AsRef<str>, S: AsRef<str>>(
+    timestamp: u64,
+    entries: &[(Q, A, S)],
+) -> String {
+    let mut lines = format!("{} BATCH {}\n", timestamp, entries.len());
+    for (question, answer, source) in entries {
+        lines.push_str(&format!(
+            "{} SET {} {} FROM {}\n",
+            timestamp,
+            quote(question.as_ref()),
+            quote(answer.as_ref()),
+            quote(source.as_ref())
+        ));
+    }
+    lines
+}
+
+// The lines of an append as one ENCRYPTED record, to be written as line
+// number position of the journal
+fn encrypted_line(timestamp: u64, position: usize, key: &EncryptionKey, lines: &str) -> String {
+    let aad = record_aad(position, &timestamp.to_string());
+    format!(
+        "{} ENCRYPTED {}\n",
+        timestamp,
+        encode_hex(&key.seal(lines.as_bytes(), &aad))
+    )
+}
+
+// What an encrypted record is bound to, so it only decrypts on its own line.
+// A journal cut short still decrypts, every line left is where it was.
+fn record_aad(position: usize, timestamp: &str) -> Vec<u8> {
+    format!("{} {}", position, timestamp).into_bytes()
+}
+
+// Cut a line that was only partially written off the end of a journal, so
+// appending doesn't glue the next record onto it
+fn truncate_torn_line(file: &mut File) -> std::io::Result<()> {
+    let len = file.metadata()?.len();
+    let mut end = len;
+    let mut buffer = [0; 4096];
+    while end > 0 {
+        let start = end.saturating_sub(buffer.len() as u64);
+        let chunk = &mut buffer[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        if let Some(i) = chunk.iter().rposition(|&byte| byte == b'\n') {
+            end = start + i as u64 + 1;
+            break;
+        }
+        end = start;
+    }
+    if end < len {
+        warn!("Removing partially written journal line");
+        file.set_len(end)?;
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+// The number of lines of an encrypted journal, failing if one of them isn't
+// encrypted. A torn last line must have been cut off, see truncate_torn_line.
+fn count_encrypted_lines(path: &Path) -> Result<usize, String> {
+    let file =
+        File::open(path).map_err(|e| format!("Couldn't open journal {}: {}", path.display(), e))?;
+    let mut count = 0;
+    for line in BufReader::new(file).split(b'\n') {
+        let line = line.map_err(|e| format!("Couldn't read journal: {}", e))?;
+        if !line
+            .splitn(2, |&byte| byte == b' ')
+            .nth(1)
+            .is_some_and(|record| record.starts_with(b"ENCRYPTED "))
+        {
+            return Err(format!(
+                "Journal {} holds plain records, it can't be appended to encrypted",
+                path.display()
+            ));
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+// f64's Display is the shortest text that parses back to the same value
+fn seed_source_line(timestamp: u64, source: &str, quality: f64, strength: f64) -> String {
+    format!(
+        "{} SEED SOURCE {} {} {}\n",
+        timestamp,
+        quote(source),
+        quality,
+        strength
+    )
+}
+
+fn entry_lines(entry: &JournalEntry) -> String {
+    match &entry.record {
+        JournalRecord::Command(cmd) => format!("{} {}\n", entry.timestamp, cmd),
+        JournalRecord::SetMany(batch) => batch_lines(entry.timestamp, batch),
+        JournalRecord::BeginBulkLoad => format!("{} BEGIN BULK LOAD\n", entry.timestamp),
+        JournalRecord::FinishBulkLoad => format!("{} FINISH BULK LOAD\n", entry.timestamp),
+        JournalRecord::SeedSource {
+            source,
+            quality,
+            strength,
+        } => seed_source_line(entry.timestamp, source, *quality, *strength),
+        JournalRecord::Snapshot(file) => format!("{} SNAPSHOT {}\n", entry.timestamp, file),
+    }
+}
+
+// Write g as a snapshot next to the journal at path and atomically replace the
+// journal with a SNAPSHOT record followed by entries[tail_start..], both
+// encrypted with key if given. Snapshots referenced by the replaced records are
+// deleted afterwards.
+fn rewrite_compacted(
+    path: &Path,
+    g: &Graph,
+    entries: &[JournalEntry],
+    tail_start: usize,
+    key: Option<&EncryptionKey>,
+) -> Result<(), String> {
+    let dir = base_dir(path);
+    let journal_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("Invalid journal path {}", path.display()))?;
+    let timestamp = now_millis();
+    let mut snapshot_name = format!("{}.{:020}.snapshot", journal_name, timestamp);
+    let mut suffix = 1;
+    while dir.join(&snapshot_name).exists() {
+        snapshot_name = format!("{}.{:020}-{}.snapshot", journal_name, timestamp, suffix);
+        suffix += 1;
+    }
+
+    let write_err = |e: std::io::Error| format!("Couldn't write compacted journal: {}", e);
+    let snapshot_path = dir.join(&snapshot_name);
+    let snapshot_tmp = snapshot_path.with_extension("tmp");
+    let file = File::create(&snapshot_tmp).map_err(write_err)?;
+    g.save_snapshot_with_key(&file, key)?;
+    file.sync_all()
+        .and_then(|_| fs::rename(&snapshot_tmp, &snapshot_path))
+        .map_err(write_err)?;
+
+    let mut lines = entry_lines(&JournalEntry {
+        timestamp,
+        record: JournalRecord::Snapshot(snapshot_name.clone()),
+    });
+    for entry in &entries[tail_start..] {
+        lines.push_str(&entry_lines(entry));
+    }
+    if let Some(key) = key {
+        lines = encrypted_line(timestamp, 0, key, &lines);
+    }
+    let journal_tmp = dir.join(format!("{}.compact.tmp", journal_name));
+    let mut file = File::create(&journal_tmp).map_err(write_err)?;
+    file.write_all(lines.as_bytes())
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::rename(&journal_tmp, path))
+        .map_err(write_err)?;
+
+    for entry in &entries[..tail_start] {
+        if let JournalRecord::Snapshot(old) = &entry.record {
+            if *old != snapshot_name {
+                if let Err(e) = fs::remove_file(dir.join(old)) {
+                    warn!("Couldn't remove old journal snapshot {}: {}", old, e);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// The complete lines of the journal at path, without their line endings, with
+// ENCRYPTED records decrypted with key
+fn read_lines(path: &Path, key: Option<&EncryptionKey>) -> Result<Vec<String>, String> {
+    let file =
+        File::open(path).map_err(|e| format!("Couldn't open journal {}: {}", path.display(), e))?;
+    let mut lines: Vec<String> = Vec::new();
+    let mut reader = BufReader::new(file);
+    let mut position = 0;
+    loop {
+        let mut line = String::new();
+        let read = reader
+            .read_line(&mut line)
+            .map_err(|e| format!("Couldn't read journal: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        if !line.ends_with('\n') {
+            warn!("Ignoring partially written journal line: {}", line);
+            break;
+        }
+        line.truncate(line.trim_end().len());
+        let encrypted = line.split_once(' ').and_then(|(timestamp, record)| {
+            record
+                .strip_prefix("ENCRYPTED ")
+                .map(|hex| (timestamp, hex))
+        });
+        match (encrypted, key) {
+            (None, None) => lines.push(line),
+            (None, Some(_)) => {
+                return Err(format!(
+                    "Journal {} is encrypted but line {} is plain",
+                    path.display(),
+                    position + 1
+                ))
+            }
+            (Some(_), None) => {
+                return Err(format!(
+                    "Journal {} is encrypted, read it with its key",
+                    path.display()
+                ))
+            }
+            (Some((timestamp, hex)), Some(key)) => {
+                let sealed = decode_hex(hex).ok_or_else(|| {
+                    format!(
+                        "Invalid journal line {}: bad encrypted record",
+                        position + 1
+                    )
+                })?;
+                let plain = key
+                    .open(&sealed, &record_aad(position, timestamp))
+                    .and_then(|plain| String::from_utf8(plain).map_err(|e| e.to_string()))
+                    .map_err(|e| format!("Couldn't read encrypted journal record: {}", e))?;
+                lines.extend(plain.lines().map(String::from));
+            }
+        }
+        position += 1;
+    }
+    Ok(lines)
+}
+
+// Parse journal lines into records that borrow from them
+fn parse_entries(lines: &[String]) -> Result<Vec<JournalEntry<'_>>, String> {
+    let mut entries = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let (timestamp, record) = split_line(&lines[i], i)?;
+        i += 1;
+        let record = if record == "BEGIN BULK LOAD" {
+            JournalRecord::BeginBulkLoad
+        } else if record == "FINISH BULK LOAD" {
+            JournalRecord::FinishBulkLoad
+        } else if let Some(seed) = record.strip_prefix("SEED SOURCE ") {
+            let invalid = || format!("Invalid journal line {}: {}", i, record);
+            let items = split_items(seed).map_err(|_| invalid())?;
+            match &items[..] {
+                [source, quality, strength] => JournalRecord::SeedSource {
+                    source: source.clone(),
+                    quality: quality.parse().map_err(|_| invalid())?,
+                    strength: strength.parse().map_err(|_| invalid())?,
+                },
+                _ => return Err(invalid()),
+            }
+        } else if let Some(file) = record.strip_prefix("SNAPSHOT ") {
+            JournalRecord::Snapshot(file.to_string())
+        } else if let Some(count) = record.strip_prefix("BATCH ") {
+            let count: usize = count
+                .parse()
+                .map_err(|_| format!("Invalid journal line {}: {}", i, record))?;
+            if i + count > lines.len() {
+                warn!("Ignoring partially written journal batch");
+                break;
+            }
+            let mut batch = Vec::with_capacity(count);
+            for (line_index, line) in lines.iter().enumerate().skip(i).take(count) {
+                let (_, set_line) = split_line(line, line_index)?;
+                let cmd = Command::from(set_line)?;
+                if cmd.cmd != CommandType::Set {
+                    return Err(format!(
+                        "Invalid journal line {}: expected SET in batch",
+                        line_index + 1
+                    ));
+                }
+                batch.push((
+                    cmd.question.unwrap(),
+                    cmd.answer.unwrap(),
+                    cmd.source.unwrap(),
+                ));
+            }
+            i += count;
+            JournalRecord::SetMany(batch)
+        } else {
+            JournalRecord::Command(Box::new(Command::from(record)?))
+        };
+        entries.push(JournalEntry { timestamp, record });
+    }
+    Ok(entries)
+}
+
+fn split_line(line: &str, line_index: usize) -> Result<(u64, &str), String> {
+    let mut parts = line.splitn(2, ' ');
+    let timestamp = parts.next().and_then(|t| t.parse::<u64>().ok());
+    match (timestamp, parts.next()) {
+        (Some(timestamp), Some(record)) => Ok((timestamp, record)),
+        _ => Err(format!("Invalid journal line {}: {}", line_index + 1, line)),
+    }
+}
+
+impl Graph {
+    // Reconstruct a graph from a journal
+    pub fn replay<P: AsRef<Path>>(path: P) -> Result<Graph, String> {
+        let mut g = Graph::new();
+        g.apply_journal(path)?;
+        Ok(g)
+    }
+
+    // Reconstruct a graph from a journal encrypted with key
+    #[cfg(feature = "encryption")]
+    pub fn replay_encrypted<P: AsRef<Path>>(path: P, key: &EncryptionKey) -> Result<Graph, String> {
+        let mut g = Graph::new();
+        g.apply_journal_with_key(path.as_ref(), Some(key))?;
+        Ok(g)
+    }
+
+    // Apply every record of a journal to this graph, returning how many were
+    // applied. The records are not written to this graph's own journal.
+    pub fn apply_journal<P: AsRef<Path>>(&mut self, path: P) -> Result<usize, String> {
+        self.apply_journal_with_key(path.as_ref(), None)
+    }
+
+    pub(crate) fn apply_journal_with_key(
+        &mut self,
+        path: &Path,
+        key: Option<&EncryptionKey>,
+    ) -> Result<usize, String> {
+        let lines = read_lines(path, key)?;
+        let entries = parse_entries(&lines)?;
+        // Snapshots are suspended too, one taken mid replay would truncate the
+        // journal and cause the replayed records to be applied twice on recovery
+        let journal = self.take_journal();
+        let snapshot_schedule = self.snapshot_schedule.take();
+        let result = self.apply_journal_entries(&entries, base_dir(path), key);
+        if let Some(journal) = journal {
+            self.set_journal(journal);
+        }
+        self.snapshot_schedule = snapshot_schedule;
+        result.map(|_| entries.len())
+    }
+
+    // The graph as it was at timestamp (unix time in ms), for GET ANSWER TO ...
+    // AS OF. It's rebuilt from the latest snapshot the snapshot policy kept
+    // from at or before timestamp, if any, and the journal records up to
+    // timestamp. The journal only holds the records since the latest snapshot,
+    // so between two older snapshots the graph is the one of the earlier
+    // snapshot, and before the oldest snapshot (or a compacted journal's
+    // snapshot) history isn't available.
+    pub fn as_of(&self, timestamp: u64) -> Result<Graph, ConfidisError> {
+        let journal = self
+            .journal()
+            .ok_or_else(|| ConfidisError::NotImplemented(String::from("AS OF needs a journal")))?;
+        let (path, key) = (journal.path().to_path_buf(), journal.key());
+        let snapshots = self.snapshot_history().map_err(ConfidisError::Internal)?;
+        let lines = read_lines(&path, key).map_err(ConfidisError::Internal)?;
+        let entries = parse_entries(&lines).map_err(ConfidisError::Internal)?;
+
+        let available_from = match (snapshots.first(), entries.first()) {
+            (Some(&(taken_at, _)), _) => taken_at,
+            (
+                None,
+                Some(JournalEntry {
+                    timestamp: compacted_at,
+                    record: JournalRecord::Snapshot(_),
+                }),
+            ) => *compacted_at,
+            (None, _) => 0,
+        };
+        if timestamp < available_from {
+            return Err(ConfidisError::NotImplemented(format!(
+                "History before {} isn't available",
+                available_from
+            )));
+        }
+
+        let mut g = match snapshots
+            .iter()
+            .rev()
+            .find(|&&(taken_at, _)| taken_at <= timestamp)
+        {
+            Some((_, snapshot)) => {
+                let file = File::open(snapshot).map_err(|e| {
+                    ConfidisError::Internal(format!("Couldn't open {}: {}", snapshot.display(), e))
+                })?;
+                Graph::load_snapshot_with_key(file, self.snapshot_key())
+                    .map_err(ConfidisError::Internal)?
+            }
+            None => Graph::new(),
+        };
+        let until = entries
+            .iter()
+            .take_while(|entry| entry.timestamp <= timestamp)
+            .count();
+        g.apply_journal_entries(&entries[..until], base_dir(&path), key)
+            .map_err(ConfidisError::Internal)?;
+        g.set_replay_time(Some(timestamp));
+        Ok(g)
+    }
+
+    // Make every mutation so far durable, does nothing without a journal
+    pub fn sync_journal(&mut self) -> Result<(), String> {
+        match self.journal_mut() {
+            Some(journal) => journal.sync(),
+            None => Ok(()),
+        }
+    }
+
+    // Replace the attached journal with a snapshot of this graph, bounding its
+    // size. The journal stays attached and later mutations are appended after
+    // the SNAPSHOT record. Returns the number of records folded in.
+    pub fn compact_journal(&mut self) -> Result<usize, String> {
+        if self.is_bulk_loading() {
+            return Err("The journal can't be compacted while a bulk load is in progress".into());
+        }
+        let mut journal = self
+            .take_journal()
+            .ok_or_else(|| String::from("No journal attached"))?;
+        let path = journal.path().to_path_buf();
+        let key = journal.key.clone();
+        let result = journal.sync().and_then(|_| {
+            let entries = Journal::read_with_key(&path, key.as_ref())?;
+            rewrite_compacted(&path, self, &entries, entries.len(), key.as_ref())?;
+            Ok(entries.len())
+        });
+        // The old handle points at the replaced file, its followers move to
+        // the new one
+        let followers = std::mem::take(&mut journal.followers);
+        drop(journal);
+        let reopened = Journal::open_with_key(&path, key).map(|mut journal| {
+            journal.followers = followers;
+            self.set_journal(journal);
+        });
+        // Replays start from the compacted snapshot, which can't be undone
+        self.clear_undo();
+        reopened?;
+        result
+    }
+
+    // Apply the complete records in lines, e.g. received from a follow
+    // receiver. Returns how many were applied.
+    pub fn apply_journal_lines(&mut self, lines: &[String]) -> Result<usize, String> {
+        let entries = parse_entries(lines)?;
+        self.apply_journal_entries(&entries, Path::new(""), None)?;
+        Ok(entries.len())
+    }
+
+    // Snapshot records are read with key
+    fn apply_journal_entries(
+        &mut self,
+        entries: &[JournalEntry],
+        base_dir: &Path,
+        key: Option<&EncryptionKey>,
+    ) -> Result<(), String> {
+        for entry in entries {
+            self.set_replay_time(Some(entry.timestamp));
+            let result = self.apply_journal_entry(entry, base_dir, key);
+            self.set_replay_time(None);
+            result?;
+        }
+        Ok(())
+    }
+
+    fn apply_journal_entry(
+        &mut self,
+        entry: &JournalEntry,
+        base_dir: &Path,
+        key: Option<&EncryptionKey>,
+    ) -> Result<(), String> {
+        match &entry.record {
+            JournalRecord::Command(cmd) => match self.execute_command(cmd) {
+                // Journals from before CONFIGURE values were validated can
+                // hold values that were ignored or are now out of range
+                Err(e @ ConfidisError::InvalidConfig { .. })
+                    if cmd.cmd == CommandType::Configure =>
+                {
+                    warn!("Skipping journaled \"{}\": {}", cmd, e);
+                }
+                result => {
+                    result?;
+                }
+            },
+            JournalRecord::SetMany(batch) => {
+                let batch: Vec<(&str, &str, &str)> = batch
+                    .iter()
+                    .map(|(q, a, s)| (q.as_ref(), a.as_ref(), s.as_ref()))
+                    .collect();
+                self.set_many(&batch)?;
+            }
+            JournalRecord::BeginBulkLoad => self.begin_bulk_load(),
+            JournalRecord::FinishBulkLoad => self.finish_bulk_load()?,
+            JournalRecord::SeedSource {
+                source,
+                quality,
+                strength,
+            } => self.seed_source(source, *quality, *strength),
+            JournalRecord::Snapshot(file) => {
+                let path = base_dir.join(file);
+                let file = File::open(&path).map_err(|e| {
+                    format!("Couldn't open journal snapshot {}: {}", path.display(), e)
+                })?;
+                self.replace_state(Graph::load_snapshot_with_key(file, key)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::CommandResponse;
+    use crate::id::{QuestionId, SourceId};
+    use std::fs;
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "confidis-journal-{}-{}.log",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_journal_replay() {
+        let path = journal_path("replay");
+        let mut g = Graph::new();
+        g.set_journal(Journal::open(&path).unwrap());
+        for line in &[
+            "CONFIGURE comparison_method numeric max_distance=2",
+            "SET q1 1 FROM s1",
+            "GET ANSWER TO q1",
+            "BELIEVE s2",
+        ] {
+            g.execute_command(&Command::from(line).unwrap()).unwrap();
+        }
+        g.calibrate(&[("g1", "1")], &[("g1", "1", "s1"), ("g1", "5", "s4")])
+            .unwrap();
+        g.set_many(&[("q1", "1.5", "s2"), ("q2", "4", "s3")])
+            .unwrap();
+        g.begin_bulk_load();
+        g.set_many(&[("q2", "9", "s1")]).unwrap();
+        g.execute_command(&Command::from("SET q3 1 FROM s2").unwrap())
+            .unwrap();
+        g.finish_bulk_load().unwrap();
+
+        let entries = Journal::read(&path).unwrap();
+        // the GET isn't journaled, calibrate seeds one source per record
+        assert_eq!(entries.len(), 10);
+
+        let mut restored = Graph::replay(&path).unwrap();
+        for line in &[
+            "GET ANSWER TO q1",
+            "GET ANSWER TO q2",
+            "GET SOURCE s1",
+            "GET SOURCE s4",
+        ] {
+            let cmd = Command::from(line).unwrap();
+            assert_eq!(
+                format!("{}", restored.execute_command(&cmd).unwrap()),
+                format!("{}", g.execute_command(&cmd).unwrap())
+            );
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn assert_same_answers(a: &mut Graph, b: &mut Graph) {
+        for line in &["GET ANSWER TO q1", "GET ANSWER TO q2", "GET SOURCE s1"] {
+            let cmd = Command::from(line).unwrap();
+            assert_eq!(
+                format!("{}", a.execute_command(&cmd).unwrap()),
+                format!("{}", b.execute_command(&cmd).unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn test_journal_compaction() {
+        let path = journal_path("compact");
+        let mut g = Graph::new();
+        g.set_journal(Journal::open(&path).unwrap());
+        for line in &["SET q1 a FROM s1", "SET q1 a FROM s2", "SET q2 b FROM s1"] {
+            g.execute_command(&Command::from(line).unwrap()).unwrap();
+        }
+        assert_eq!(g.compact_journal().unwrap(), 3);
+        assert_eq!(Journal::read(&path).unwrap().len(), 1);
+        g.execute_command(&Command::from("SET q2 c FROM s3").unwrap())
+            .unwrap();
+        assert_same_answers(&mut Graph::replay(&path).unwrap(), &mut g);
+
+        // compacting again replaces the first snapshot
+        assert_eq!(g.compact_journal().unwrap(), 2);
+        let entries = Journal::read(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_same_answers(&mut Graph::replay(&path).unwrap(), &mut g);
+
+        let snapshot = match &entries[0].record {
+            JournalRecord::Snapshot(file) => path.parent().unwrap().join(file),
+            _ => panic!("expected a snapshot record"),
+        };
+        drop(g);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&snapshot).unwrap();
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_journal() {
+        let path = journal_path("encrypted");
+        let key = EncryptionKey::generate();
+        let mut g = Graph::new();
+        g.set_journal(Journal::open_encrypted(&path, key.clone()).unwrap());
+        g.execute_command(&Command::from("SET q1 secret FROM s1").unwrap())
+            .unwrap();
+        g.set_many(&[("q1", "secret", "s2"), ("q2", "b", "s1")])
+            .unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("secret") && !contents.contains("s1"));
+        assert_eq!(Journal::read_encrypted(&path, &key).unwrap().len(), 2);
+        assert_same_answers(&mut Graph::replay_encrypted(&path, &key).unwrap(), &mut g);
+        assert!(Graph::replay(&path).is_err());
+        assert!(Graph::replay_encrypted(&path, &EncryptionKey::generate()).is_err());
+
+        // compaction encrypts the snapshot and the records after it
+        g.compact_journal().unwrap();
+        g.execute_command(&Command::from("SET q2 c FROM s3").unwrap())
+            .unwrap();
+        let entries = Journal::read_encrypted(&path, &key).unwrap();
+        let snapshot = match &entries[0].record {
+            JournalRecord::Snapshot(file) => path.parent().unwrap().join(file),
+            _ => panic!("expected a snapshot record"),
+        };
+        let bytes = fs::read(&snapshot).unwrap();
+        assert_eq!(&bytes[..8], crate::snapshot::ENCRYPTED_SNAPSHOT_MAGIC);
+        assert!(Graph::load_snapshot(&bytes[..]).is_err());
+        assert_same_answers(&mut Graph::replay_encrypted(&path, &key).unwrap(), &mut g);
+        drop(g);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&snapshot).unwrap();
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_journal_rejects_tampering() {
+        let path = journal_path("tampering");
+        let key = EncryptionKey::generate();
+        let mut g = Graph::new();
+        g.set_journal(Journal::open_encrypted(&path, key.clone()).unwrap());
+        for line in &["SET q1 a FROM s1", "SET q1 a FROM s2", "SET q1 b FROM s3"] {
+            g.execute_command(&Command::from(line).unwrap()).unwrap();
+        }
+        drop(g);
+        let lines: Vec<String> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert_eq!(Journal::read_encrypted(&path, &key).unwrap().len(), 3);
+
+        let tampered = [
+            // a record dropped
+            [&lines[0], &lines[2]].map(String::as_str).concat(),
+            // two records swapped
+            [&lines[1], &lines[0], &lines[2]]
+                .map(String::as_str)
+                .concat(),
+            // a record replayed
+            [&lines[0], &lines[1], &lines[2], &lines[1]]
+                .map(String::as_str)
+                .concat(),
+            // a plain record injected
+            [&lines[0], &lines[1], &lines[2]]
+                .map(String::as_str)
+                .concat()
+                + "1 SET q1 b FROM s9\n",
+        ];
+        for contents in &tampered {
+            fs::write(&path, contents).unwrap();
+            assert!(Graph::replay_encrypted(&path, &key).is_err());
+        }
+
+        // records cut off the end go unnoticed, the journal replays the graph
+        // from before they were appended
+        fs::write(&path, [&lines[0], &lines[1]].map(String::as_str).concat()).unwrap();
+        let g = Graph::replay_encrypted(&path, &key).unwrap();
+        assert_eq!(g.compute_answer("q1").unwrap().0, "a");
+        assert!(g.source("s3").is_none());
+
+        // a plain journal is only encrypted by compacting a new journal
+        fs::write(&path, "1 SET q1 a FROM s1\n").unwrap();
+        assert!(Graph::replay_encrypted(&path, &key).is_err());
+        assert!(Journal::open_encrypted(&path, key.clone()).is_err());
+        let mut g = Graph::replay(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        g.set_journal(Journal::open_encrypted(&path, key.clone()).unwrap());
+        g.compact_journal().unwrap();
+        g.execute_command(&Command::from("SET q1 a FROM s2").unwrap())
+            .unwrap();
+        assert_same_answers(&mut Graph::replay_encrypted(&path, &key).unwrap(), &mut g);
+        let entries = Journal::read_encrypted(&path, &key).unwrap();
+        drop(g);
+        fs::remove_file(&path).unwrap();
+        for entry in entries {
+            if let JournalRecord::Snapshot(file) = entry.record {
+                fs::remove_file(path.parent().unwrap().join(file)).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_journal_compaction_keeps_unfinished_bulk_load() {
+        let path = journal_path("compact-tail");
+        let mut g = Graph::new();
+        g.set_journal(Journal::open(&path).unwrap());
+        g.set_many(&[("q1", "a", "s1"), ("q1", "a", "s2")]).unwrap();
+        g.begin_bulk_load();
+        g.set_many(&[("q2", "b", "s1")]).unwrap();
+        drop(g.take_journal());
+
+        assert_eq!(Journal::compact(&path).unwrap(), 1);
+        let entries = Journal::read(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(matches!(entries[1].record, JournalRecord::BeginBulkLoad));
+
+        let mut restored = Graph::replay(&path).unwrap();
+        assert!(restored.is_bulk_loading());
+        restored.finish_bulk_load().unwrap();
+        g.finish_bulk_load().unwrap();
+        assert_same_answers(&mut restored, &mut g);
+
+        if let JournalRecord::Snapshot(file) = &entries[0].record {
+            fs::remove_file(path.parent().unwrap().join(file)).unwrap();
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_answer_as_of() {
+        let path = journal_path("as-of");
+        fs::write(
+            &path,
+            "1000 SET q1 a FROM s1\n2000 SET q1 b FROM s2\n2000 SET q1 b FROM s3\n3000 SET q1 a FROM s4\n",
+        )
+        .unwrap();
+        let mut g = Graph::replay(&path).unwrap();
+        g.set_journal(Journal::open(&path).unwrap());
+        let answer = |g: &Graph, line: &str| {
+            g.execute_read_command(&Command::from(line).unwrap())
+                .map(|response| match response {
+                    CommandResponse::Answer { content, .. } => content,
+                    response => panic!("unexpected {:?}", response),
+                })
+                .map_err(|e| e.to_string())
+        };
+        let answer_as_of =
+            |g: &Graph, timestamp: u64| answer(g, &format!("GET ANSWER TO q1 AS OF {}", timestamp));
+        // q1 wasn't asked yet
+        assert_eq!(answer_as_of(&g, 500), Ok(String::from("None")));
+        assert_eq!(answer_as_of(&g, 1500), Ok(String::from("a")));
+        assert_eq!(answer_as_of(&g, 2500), Ok(String::from("b")));
+        assert_eq!(answer_as_of(&g, 3000), Ok(String::from("a")));
+        assert_eq!(answer_as_of(&g, 2999), answer_as_of(&g, 2000));
+        // s4 answering a again, the graph itself is unchanged
+        assert_eq!(answer(&g, "GET ANSWER TO q1"), Ok(String::from("a")));
+
+        g.compact_journal().unwrap();
+        let compacted_at = Journal::read(&path).unwrap()[0].timestamp;
+        assert!(answer_as_of(&g, 2500)
+            .unwrap_err()
+            .contains("isn't available"));
+        assert_eq!(answer_as_of(&g, compacted_at), Ok(String::from("a")));
+
+        let entries = Journal::read(&path).unwrap();
+        drop(g);
+        if let JournalRecord::Snapshot(file) = &entries[0].record {
+            fs::remove_file(path.parent().unwrap().join(file)).unwrap();
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_journal_quotes_values() {
+        let path = journal_path("quotes");
+        let mut g = Graph::new();
+        g.set_journal(Journal::open(&path).unwrap());
+        let answer = "New York\n1 BELIEVE s9";
+        g.set_answer(
+            &QuestionId::new("q1").unwrap(),
+            answer,
+            &SourceId::new("s1").unwrap(),
+        )
+        .unwrap();
+        g.set_many(&[("q1", answer, "s2"), ("q2", "\"quoted\" \\", "s1")])
+            .unwrap();
+        g.calibrate(&[("g1", "a b")], &[("g1", "a b", "s\"3")])
+            .unwrap();
+
+        let restored = Graph::replay(&path).unwrap();
+        assert_eq!(restored.questions["q1"].answers.len(), 2);
+        assert_eq!(restored.questions["q1"].answers[0].content, answer);
+        assert_eq!(restored.questions["q2"].answers[0].content, "\"quoted\" \\");
+        assert!(restored.source("s9").is_none());
+        assert!(restored.source("s\"3").is_some());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_journal_ignores_torn_write() {
+        let path = journal_path("torn");
+        fs::write(&path, "1 SET q1 a FROM s1\n2 BATCH 2\n2 SET q1 a FROM s2\n").unwrap();
+        assert_eq!(Journal::read(&path).unwrap().len(), 1);
+
+        fs::write(&path, "1 SET q1 a FROM s1\n2 SET q1 a FR").unwrap();
+        assert_eq!(Journal::read(&path).unwrap().len(), 1);
+
+        fs::write(&path, "SET q1 a FROM s1\n").unwrap();
+        assert!(Journal::read(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_journal_appends_after_torn_write() {
+        let path = journal_path("torn-append");
+        fs::write(&path, "1 SET q1 a FROM s1\n2 SET q1 b FROM s2 extra").unwrap();
+        let mut journal = Journal::open(&path).unwrap();
+        journal
+            .append_command(&Command::from("SET q2 c FROM s3").unwrap())
+            .unwrap();
+        drop(journal);
+        assert_eq!(Journal::read(&path).unwrap().len(), 2);
+        let g = Graph::replay(&path).unwrap();
+        assert_eq!(g.compute_answer("q1").unwrap().0, "a");
+        assert_eq!(g.compute_answer("q2").unwrap().0, "c");
+
+        // an encrypted record goes on the line its position is bound to
+        #[cfg(feature = "encryption")]
+        {
+            let key = EncryptionKey::generate();
+            fs::remove_file(&path).unwrap();
+            let mut journal = Journal::open_encrypted(&path, key.clone()).unwrap();
+            journal
+                .append_command(&Command::from("SET q1 a FROM s1").unwrap())
+                .unwrap();
+            drop(journal);
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(b"2 ENCRYPTED 00").unwrap();
+            let mut journal = Journal::open_encrypted(&path, key.clone()).unwrap();
+            journal
+                .append_command(&Command::from("SET q2 c FROM s3").unwrap())
+                .unwrap();
+            drop(journal);
+            assert_eq!(Journal::read_encrypted(&path, &key).unwrap().len(), 2);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_journal_replay_skips_invalid_configure() {
+        // Values CONFIGURE used to ignore don't stop a replay
+        let path = journal_path("configure");
+        fs::write(
+            &path,
+            "1 CONFIGURE default_source_quality banana\n2 CONFIGURE maximum_strength -1\n3 SET q1 a FROM s1\n",
+        )
+        .unwrap();
+        let g = Graph::replay(&path).unwrap();
+        assert_eq!(g.config(), &crate::config::GraphConfig::default());
+        assert!(g.has_question("q1"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,267 @@
+// JSON Lines export/import of a Graph
+//
+// One JSON object per line, distinguished by "type":
+//   {"type":"config","config":{...},"equalifier":{...}}
+//   {"type":"source","name":"s1","quality":0.5,"strength":1.0}
+//   {"type":"question","name":"q1","correct_answers":[0],"weight":0.3,"confidence":0.5,"answered_at":1700000000000}
+//   {"type":"question",...,"excluded_sources":["s3"]} with sources excluded
+//   {"type":"question",...,"honeypot":"a"} for a honeypot, see HONEYPOT
+//   {"type":"question",...,"schema":"int min=0"} with a schema, see SCHEMA
+//   {"type":"question",...,"question_type":"geo max_distance=0.1"} with a
+//   declared type, see TYPE
+//   {"type":"answer","question":"q1","content":"a","source":"s1"}
+//
+// The config comes first, then every source, then each question followed by
+// its answers in the order they were given. Sources and questions are sorted by
+// name so two exports of the same state are identical and can be diffed.
+
+use crate::command::Answer;
+use crate::config::GraphConfig;
+use crate::equalifier::EqualifierConfig;
+use crate::graph::{Graph, Question, Source};
+use crate::question_type::QuestionType;
+use crate::schema::AnswerSchema;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::Arc;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record {
+    Config {
+        config: GraphConfig,
+        equalifier: EqualifierConfig,
+    },
+    Source(Source),
+    Question {
+        name: String,
+        correct_answers: Vec<usize>,
+        weight: f64,
+        confidence: f64,
+        // exports from before answers were timed don't have it
+        #[serde(default)]
+        answered_at: u64,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        excluded_sources: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        honeypot: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        schema: Option<AnswerSchema>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        question_type: Option<QuestionType>,
+    },
+    Answer {
+        question: String,
+        content: String,
+        source: String,
+    },
+}
+
+fn write_record<W: Write>(writer: &mut W, record: &Record) -> Result<(), String> {
+    serde_json::to_writer(&mut *writer, record)
+        .map_err(|e| e.to_string())
+        .and_then(|_| writer.write_all(b"\n").map_err(|e| e.to_string()))
+        .map_err(|e| format!("Couldn't write JSONL export: {}", e))
+}
+
+impl Graph {
+    pub fn export_jsonl<W: Write>(&self, writer: W) -> Result<(), String> {
+        if self.is_bulk_loading() {
+            return Err("A graph can't be exported while a bulk load is in progress".into());
+        }
+        let mut writer = BufWriter::new(writer);
+        write_record(
+            &mut writer,
+            &Record::Config {
+                config: self.config().clone(),
+                equalifier: self.equalifier_config(),
+            },
+        )?;
+
+        let mut source_names: Vec<&String> = self.sources.keys().collect();
+        source_names.sort();
+        for source_name in source_names {
+            write_record(
+                &mut writer,
+                &Record::Source(self.sources[source_name].clone()),
+            )?;
+        }
+
+        let mut question_names: Vec<&String> = self.questions.keys().collect();
+        question_names.sort();
+        for question_name in question_names {
+            let question = &self.questions[question_name];
+            write_record(
+                &mut writer,
+                &Record::Question {
+                    name: question.name.clone(),
+                    correct_answers: question.correct_answers.clone(),
+                    weight: question.weight,
+                    confidence: question.confidence,
+                    answered_at: question.answered_at,
+                    excluded_sources: question.excluded_sources.clone(),
+                    honeypot: question.honeypot.clone(),
+                    schema: question.schema.clone(),
+                    question_type: question.question_type.clone(),
+                },
+            )?;
+            for answer in &question.answers {
+                write_record(
+                    &mut writer,
+                    &Record::Answer {
+                        question: question.name.clone(),
+                        content: answer.content.clone(),
+                        source: answer.source.clone(),
+                    },
+                )?;
+            }
+        }
+        writer
+            .flush()
+            .map_err(|e| format!("Couldn't write JSONL export: {}", e))
+    }
+
+    pub fn import_jsonl<R: Read>(reader: R) -> Result<Graph, String> {
+        let mut graph: Option<Graph> = None;
+        for (i, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line.map_err(|e| format!("Couldn't read JSONL import: {}", e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: Record = serde_json::from_str(&line)
+                .map_err(|e| format!("Invalid JSONL record on line {}: {}", i + 1, e))?;
+
+            if let Record::Config { config, equalifier } = record {
+                if graph.is_some() {
+                    return Err(format!("Duplicate config record on line {}", i + 1));
+                }
+                let mut g = Graph::new_with_config(config);
+                g.set_equalifier(equalifier.build().ok_or_else(|| {
+                    "A graph with a custom equalifier can't be imported".to_string()
+                })?);
+                graph = Some(g);
+                continue;
+            }
+            let g = graph
+                .as_mut()
+                .ok_or_else(|| format!("Expected a config record before line {}", i + 1))?;
+            match record {
+                Record::Config { .. } => unreachable!(),
+                Record::Source(source) => g.insert_source(source),
+                Record::Question {
+                    name,
+                    correct_answers,
+                    weight,
+                    confidence,
+                    answered_at,
+                    excluded_sources,
+                    honeypot,
+                    schema,
+                    question_type,
+                } => g.insert_question(Question {
+                    name,
+                    correct_answers,
+                    weight,
+                    confidence,
+                    answers: Vec::new(),
+                    answered_at,
+                    excluded_sources,
+                    honeypot,
+                    schema,
+                    question_type,
+                }),
+                Record::Answer {
+                    question,
+                    content,
+                    source,
+                } => match g.questions.get_mut(&question).map(Arc::make_mut) {
+                    Some(q) => {
+                        q.answers
+                            .push(Answer::with_hasher(content, source, g.config.answer_hash))
+                    }
+                    None => {
+                        return Err(format!(
+                            "Answer on line {} references unknown question {}",
+                            i + 1,
+                            question
+                        ))
+                    }
+                },
+            }
+        }
+
+        let graph = graph.ok_or_else(|| "JSONL import has no config record".to_string())?;
+        for question in graph.questions.values() {
+            if question
+                .correct_answers
+                .iter()
+                .any(|&i| i >= question.answers.len())
+            {
+                return Err(format!(
+                    "Question {} has correct answers that don't exist",
+                    question.name
+                ));
+            }
+        }
+        Ok(graph)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+
+    #[test]
+    fn test_jsonl_roundtrip() {
+        let mut g = Graph::new();
+        for line in &[
+            "CONFIGURE comparison_method numeric max_distance=0.5",
+            "SET q1 1 FROM s1",
+            "SET q1 1.2 FROM s2",
+            "SET q1 4 FROM s3",
+            "SET q2 \"7\" FROM s3",
+            "BELIEVE s2",
+        ] {
+            g.execute_command(&Command::from(line).unwrap()).unwrap();
+        }
+
+        let mut exported: Vec<u8> = Vec::new();
+        g.export_jsonl(&mut exported).unwrap();
+        let text = String::from_utf8(exported.clone()).unwrap();
+        assert_eq!(text.lines().count(), 1 + 3 + 2 + 4);
+        assert!(text.starts_with("{\"type\":\"config\""));
+
+        let mut restored = Graph::import_jsonl(&exported[..]).unwrap();
+        let mut reexported: Vec<u8> = Vec::new();
+        restored.export_jsonl(&mut reexported).unwrap();
+        assert_eq!(exported, reexported);
+
+        for line in &["GET ANSWER TO q1", "GET ANSWERS TO q2", "GET SOURCE s3"] {
+            let cmd = Command::from(line).unwrap();
+            assert_eq!(
+                format!("{}", restored.execute_command(&cmd).unwrap()),
+                format!("{}", g.execute_command(&cmd).unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn test_jsonl_import_errors() {
+        let config = "{\"type\":\"config\",\"config\":{\"default_source_quality\":0.5,\"initial_source_strength\":1.0,\"maximum_strength\":100.0,\"log_weight_factor\":10.0,\"quality_of_believed_sources\":0.999},\"equalifier\":\"exact\"}";
+        assert!(Graph::import_jsonl(config.as_bytes()).is_ok());
+        assert!(Graph::import_jsonl("".as_bytes())
+            .err()
+            .unwrap()
+            .contains("no config"));
+
+        let orphan = format!(
+            "{}\n{{\"type\":\"answer\",\"question\":\"q\",\"content\":\"a\",\"source\":\"s\"}}",
+            config
+        );
+        assert!(Graph::import_jsonl(orphan.as_bytes())
+            .err()
+            .unwrap()
+            .contains("line 2"));
+    }
+}
@@ -1,19 +1,88 @@
 extern crate console_error_panic_hook;
 extern crate wasm_bindgen;
 
+#[cfg(feature = "arrow")]
+pub mod arrow_export;
+#[cfg(feature = "async")]
+pub mod async_graph;
+pub mod audit;
+#[cfg(any(
+    feature = "server",
+    feature = "http",
+    feature = "websocket",
+    feature = "graphql"
+))]
+pub mod auth;
+pub mod calibration;
 pub mod cluster;
 pub mod command;
+pub mod config;
+pub mod diff;
+pub mod dot;
+pub mod duplicates;
+pub mod encryption;
 pub mod equalifier;
+pub mod error;
+pub mod evidence;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod graph;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+pub mod hash;
+pub mod history;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod id;
+pub mod import;
+#[cfg(feature = "ingest")]
+pub mod ingest;
+pub mod journal;
+pub mod jsonl;
+pub mod manager;
+pub mod metrics;
+#[cfg(feature = "node")]
+pub mod node;
+pub mod normalize;
+pub mod question_type;
+pub mod rate_limit;
+pub mod reliability;
+#[cfg(feature = "server")]
+pub mod replication;
+#[cfg(feature = "server")]
+pub mod resp;
+pub mod schema;
+pub mod script;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod shadow;
+pub mod sharded_graph;
+pub mod shared_graph;
+pub mod simulate;
+pub mod snapshot;
+pub mod storage;
+pub mod strategy;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+pub mod undo;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+#[cfg(any(
+    feature = "server",
+    feature = "http",
+    feature = "websocket",
+    feature = "node"
+))]
+pub mod worker;
 
-use command::Command;
+use command::{Command, CommandResponse, CommandType};
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
 use equalifier::JSEqualifier;
 use graph::Graph;
-use std::panic;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
-struct GraphJS {
+pub struct GraphJS {
     g: Box<Graph>,
 }
 
@@ -23,6 +92,7 @@ pub fn setup_js_panic() {
 
 #[wasm_bindgen]
 impl GraphJS {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         setup_js_panic();
         GraphJS {
@@ -30,6 +100,7 @@ impl GraphJS {
         }
     }
 
+    #[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
     pub fn new_with_equalifier(js_func: &js_sys::Function) -> Self {
         setup_js_panic();
         let equalifier = Box::new(JSEqualifier::new(js_func));
@@ -39,18 +110,86 @@ impl GraphJS {
     }
 
     pub fn execute_command(&mut self, cmd_string: &str) -> Result<JsValue, JsValue> {
-        let cmd = Command::from(cmd_string);
-        if cmd.is_err() {
-            return Err(JsValue::from_str(&cmd.err().unwrap()));
-        }
-        let res = self.g.execute_command(&cmd.unwrap());
-        if res.is_err() {
-            let err_val = res.err().unwrap();
-            return Err(JsValue::from_str(&err_val));
-        }
-        match JsValue::from_serde(&res.unwrap()) {
-            Ok(v) => Ok(v),
-            Err(v) => Err(JsValue::from_str("Error parsing command response")),
+        let cmd = Command::from(cmd_string).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        to_js(&self.execute(cmd)?)
+    }
+
+    // Same as execute_command for a JSON command envelope, e.g.
+    // {"cmd": "set", "question": "q1", "answer": "a", "source": "s1"}
+    pub fn execute_json_command(&mut self, json: &str) -> Result<JsValue, JsValue> {
+        let cmd = Command::from_json(json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        to_js(&self.execute(cmd)?)
+    }
+
+    // The methods below skip the text grammar, so names and answers can
+    // contain spaces and quotes
+
+    pub fn set(&mut self, question: &str, answer: &str, source: &str) -> Result<(), JsValue> {
+        self.execute(Command {
+            cmd: CommandType::Set,
+            question: Some(question.into()),
+            answer: Some(answer.into()),
+            source: Some(source.into()),
+            ..Default::default()
+        })
+        .map(|_| ())
+    }
+
+    // { cmd: "GetAnswer", answer: "a", confidence: 0.9, ... }
+    pub fn get_answer(&mut self, question: &str) -> Result<JsValue, JsValue> {
+        to_js(&self.execute(Command {
+            cmd: CommandType::GetAnswer,
+            question: Some(question.into()),
+            ..Default::default()
+        })?)
+    }
+
+    pub fn get_source_quality(&mut self, source: &str) -> Result<f64, JsValue> {
+        let response = self.execute(Command {
+            cmd: CommandType::GetSource,
+            source: Some(source.into()),
+            ..Default::default()
+        })?;
+        match response {
+            CommandResponse::Source { quality, .. } => Ok(quality),
+            _ => Err(JsValue::from_str("Unexpected response to GET SOURCE")),
         }
     }
+
+    pub fn believe(&mut self, source: &str) -> Result<(), JsValue> {
+        self.execute(Command {
+            cmd: CommandType::Believe,
+            source: Some(source.into()),
+            ..Default::default()
+        })
+        .map(|_| ())
+    }
+
+    // The graph as JSONL, e.g. to sync locally collected answers to a server
+    pub fn export_jsonl(&self) -> Result<String, JsValue> {
+        let mut out: Vec<u8> = Vec::new();
+        self.g
+            .export_jsonl(&mut out)
+            .map_err(|msg| JsValue::from_str(&msg))?;
+        String::from_utf8(out).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    pub fn import_jsonl(data: &str) -> Result<GraphJS, JsValue> {
+        setup_js_panic();
+        let g = Graph::import_jsonl(data.as_bytes()).map_err(|msg| JsValue::from_str(&msg))?;
+        Ok(GraphJS { g: Box::new(g) })
+    }
+}
+
+impl GraphJS {
+    fn execute(&mut self, cmd: Command) -> Result<CommandResponse, JsValue> {
+        self.g
+            .execute_command(&cmd)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+}
+
+fn to_js(response: &CommandResponse) -> Result<JsValue, JsValue> {
+    #[allow(deprecated)]
+    JsValue::from_serde(response).map_err(|_| JsValue::from_str("Error parsing command response"))
 }
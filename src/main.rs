@@ -1,10 +1,12 @@
-mod cluster;
-mod command;
-mod equalifier;
-mod graph;
-
 // use std::io;
-use command::Command;
+use confidis::audit::AuditLog;
+use confidis::command::{Command, OutputFormat};
+use confidis::diff::{diff_snapshots, DiffThresholds};
+use confidis::graph;
+use confidis::history::AnswerHistory;
+use confidis::journal::Journal;
+use confidis::snapshot::SnapshotPolicy;
+use confidis::strategy::{compare_strategies, Strategy};
 use std::fs;
 use std::io::{stdin, stdout, BufRead, Write};
 use structopt::StructOpt;
@@ -14,26 +16,149 @@ struct Cli {
     // filepath to execute commands from
     #[structopt(parse(from_os_str))]
     filepath: Option<std::path::PathBuf>,
+
+    // journal to restore the graph from and append mutating commands to
+    #[structopt(long, parse(from_os_str))]
+    journal: Option<std::path::PathBuf>,
+
+    // directory to periodically write snapshots to and restore the graph from
+    #[structopt(long, parse(from_os_str))]
+    snapshot_dir: Option<std::path::PathBuf>,
+
+    // number of mutating commands between snapshots
+    #[structopt(long, default_value = "1000")]
+    snapshot_every: u64,
+
+    // number of recent source quality changes to keep for GET AUDIT FOR
+    #[structopt(long)]
+    audit_capacity: Option<usize>,
+
+    // number of recent answer changes to keep per question for GET HISTORY OF
+    #[structopt(long)]
+    history_capacity: Option<usize>,
+
+    // break ties by answer and order output by name, see Graph::set_deterministic
+    #[structopt(long)]
+    deterministic: bool,
+
+    // fail GET SOURCE and GET ANSWER TO of unknown sources and questions, see
+    // Graph::set_strict
+    #[structopt(long)]
+    strict: bool,
+
+    // print responses as text or json, see CONFIGURE output_format
+    #[structopt(long, default_value = "text")]
+    output_format: OutputFormat,
+
+    // check the "> expected" lines in the file against the command outputs
+    #[structopt(long)]
+    check: bool,
+
+    // print what changed between two snapshots, earlier first, see diff_snapshots
+    #[structopt(long, number_of_values = 2, parse(from_os_str))]
+    diff: Vec<std::path::PathBuf>,
+
+    // smallest confidence change --diff reports for an unchanged answer
+    #[structopt(long, default_value = "0")]
+    confidence_threshold: f64,
+
+    // smallest source quality change --diff reports
+    #[structopt(long, default_value = "0")]
+    quality_threshold: f64,
+
+    // run the commands in the file under two aggregation strategies and
+    // compare their answers, see compare_strategies
+    #[structopt(long, number_of_values = 2)]
+    compare_strategies: Vec<Strategy>,
 }
 
 fn main() {
     let args = Cli::from_args();
+
+    if let [earlier, later] = &args.diff[..] {
+        let thresholds = DiffThresholds {
+            confidence: args.confidence_threshold,
+            quality: args.quality_threshold,
+        };
+        let diff = diff_snapshots(earlier, later, thresholds).expect("Couldn't diff snapshots");
+        match args.output_format {
+            OutputFormat::Text => println!("{}", diff),
+            OutputFormat::Json => println!("{}", serde_json::to_string(&diff).unwrap()),
+        }
+        return;
+    }
+    if let [a, b] = &args.compare_strategies[..] {
+        let filepath = args
+            .filepath
+            .as_ref()
+            .expect("--compare-strategies needs a file of commands");
+        let contents = fs::read_to_string(filepath).expect("Couldn't read file");
+        let comparison = compare_strategies(&contents, a, b).unwrap_or_else(|e| panic!("{}", e));
+        match args.output_format {
+            OutputFormat::Text => println!("{}", comparison),
+            OutputFormat::Json => println!("{}", serde_json::to_string(&comparison).unwrap()),
+        }
+        return;
+    }
     let mut g = graph::Graph::new();
 
-    if args.filepath.is_some() {
-        let contents = fs::read_to_string(args.filepath.unwrap()).expect("Couldn't read file");
+    if let Some(snapshot_dir) = &args.snapshot_dir {
+        g = graph::Graph::recover(snapshot_dir, args.journal.as_ref())
+            .expect("Couldn't recover graph");
+    } else if let Some(journal_path) = &args.journal {
+        if journal_path.exists() {
+            g = graph::Graph::replay(journal_path).expect("Couldn't replay journal");
+        }
+    }
+    if let Some(journal_path) = &args.journal {
+        g.set_journal(Journal::open(journal_path).expect("Couldn't open journal"));
+    }
+    if let Some(snapshot_dir) = &args.snapshot_dir {
+        g.set_snapshot_policy(SnapshotPolicy {
+            every_mutations: Some(args.snapshot_every),
+            ..SnapshotPolicy::new(snapshot_dir)
+        })
+        .expect("Couldn't set up snapshots");
+    }
+    if let Some(capacity) = args.audit_capacity {
+        g.set_audit_log(AuditLog::new(capacity));
+    }
+    if let Some(capacity) = args.history_capacity {
+        g.set_answer_history(AnswerHistory::new(capacity));
+    }
+    g.set_deterministic(args.deterministic);
+    g.set_strict(args.strict);
+    let mut output_format = args.output_format;
+
+    if let Some(filepath) = args.filepath {
+        let contents = fs::read_to_string(filepath).expect("Couldn't read file");
+
+        if args.check {
+            let report = g
+                .run_script(&contents)
+                .unwrap_or_else(|msg| panic!("{}", msg));
+            println!("{}", report);
+            if !report.passed() {
+                std::process::exit(1);
+            }
+            return;
+        }
 
         let lines = contents.lines().filter(|line| !line.is_empty());
 
         let commands: Vec<Command> = lines
-            .map(|line| Command::from(line).expect(&format!("Invalid line: \"{}\"", line)))
+            .map(|line| {
+                Command::from(line).unwrap_or_else(|_| panic!("Invalid line: \"{}\"", line))
+            })
             .collect();
 
-        for ref command in &commands {
-            let output = g
-                .execute_command(command)
-                .expect("Couldn't execute command");
-            println!("{}", output);
+        for command in &commands {
+            let output = match output_format.configure(command) {
+                Some(output) => output,
+                None => g.execute_command(command),
+            }
+            .expect("Couldn't execute command");
+            println!("{}", output_format.format(&Ok(output)));
         }
         return;
     }
@@ -41,24 +166,27 @@ fn main() {
     // REPL
 
     print!("> ");
-    stdout().flush();
-    for ref line in stdin().lock().lines().filter_map(|x| x.ok()) {
-        if line.len() > 0 {
+    stdout().flush().unwrap();
+    for ref line in stdin().lock().lines().map_while(Result::ok) {
+        if !line.is_empty() {
             match Command::from(line) {
-                Ok(cmd) => match g.execute_command(&cmd) {
-                    Ok(result) => {
-                        println!("{}", result);
-                    }
-                    Err(msg) => {
-                        println!("Err: {}", msg);
-                    }
-                },
-                Err(msg) => {
+                Ok(cmd) => {
+                    let result = match output_format.configure(&cmd) {
+                        Some(result) => result,
+                        None => g.execute_command(&cmd),
+                    };
+                    println!("{}", output_format.format(&result.map_err(String::from)));
+                }
+                Err(msg) if output_format == OutputFormat::Text => {
                     println!("Invalid Command: \"{}\"\nErr: {}", line, msg);
                 }
+                Err(msg) => {
+                    let result = Err(format!("Invalid command: {}", msg));
+                    println!("{}", output_format.format(&result));
+                }
             }
         }
         print!("> ");
-        stdout().flush();
+        stdout().flush().unwrap();
     }
 }
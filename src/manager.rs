@@ -0,0 +1,168 @@
+// Many independent graphs in one process
+//
+// A GraphManager owns Graphs by name, e.g. one per tenant or task type. Each
+// graph has its own sources, questions, configuration and comparison method,
+// and nothing is shared between them: a SET in one graph never changes a
+// source quality in another, even for a source of the same name.
+//
+// Commands in the text grammar are routed by the name of their graph in front
+// of them:
+//
+//   manager.create("tenant1")?;
+//   manager.execute_line("tenant1 SET q1 a FROM s1")?;
+//   manager.execute_line("tenant1 GET ANSWER TO q1")?;
+//
+// Graph names follow the question and source name rules (see id.rs) and can't
+// contain whitespace, since that ends the prefix.
+
+use crate::command::{Command, CommandResponse};
+use crate::error::ConfidisError;
+use crate::graph::Graph;
+use crate::id::validate;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct GraphManager {
+    graphs: HashMap<String, Graph>,
+}
+
+fn validate_graph_name(name: &str) -> Result<(), ConfidisError> {
+    validate("graph", name)?;
+    if name.chars().any(char::is_whitespace) {
+        return Err(ConfidisError::InvalidId(format!(
+            "graph name \"{}\" contains whitespace",
+            name
+        )));
+    }
+    Ok(())
+}
+
+impl GraphManager {
+    pub fn new() -> GraphManager {
+        GraphManager::default()
+    }
+
+    // Add a new empty graph
+    pub fn create(&mut self, name: &str) -> Result<&mut Graph, ConfidisError> {
+        self.insert(name, Graph::new())
+    }
+
+    // Add a graph that was set up elsewhere, e.g. restored from a snapshot or
+    // with its own equalifier
+    pub fn insert(&mut self, name: &str, graph: Graph) -> Result<&mut Graph, ConfidisError> {
+        validate_graph_name(name)?;
+        if self.graphs.contains_key(name) {
+            return Err(ConfidisError::InvalidId(format!(
+                "graph name \"{}\" is already in use",
+                name
+            )));
+        }
+        Ok(self.graphs.entry(name.to_string()).or_insert(graph))
+    }
+
+    // Drop a graph, returning it
+    pub fn remove(&mut self, name: &str) -> Result<Graph, ConfidisError> {
+        self.graphs
+            .remove(name)
+            .ok_or_else(|| ConfidisError::UnknownGraph(name.to_string()))
+    }
+
+    // The names of the graphs, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.graphs.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn len(&self) -> usize {
+        self.graphs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.graphs.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Graph> {
+        self.graphs.get(name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Graph> {
+        self.graphs.get_mut(name)
+    }
+
+    // Execute a command on the named graph
+    pub fn execute(&mut self, name: &str, cmd: &Command) -> Result<CommandResponse, ConfidisError> {
+        self.graphs
+            .get_mut(name)
+            .ok_or_else(|| ConfidisError::UnknownGraph(name.to_string()))?
+            .execute_command(cmd)
+    }
+
+    // Execute "<graph> <command>", e.g. "tenant1 GET ANSWER TO q1"
+    pub fn execute_line(&mut self, line: &str) -> Result<CommandResponse, ConfidisError> {
+        let line = line.trim_start();
+        let (name, command) = line.split_at(line.find(char::is_whitespace).unwrap_or(line.len()));
+        if name.is_empty() {
+            return Err(ConfidisError::ParseError(String::from(
+                "Command is missing the graph name in front of it",
+            )));
+        }
+        self.execute(name, &Command::from(command)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graph_manager() {
+        let mut manager = GraphManager::new();
+        manager.create("b").unwrap();
+        manager.create("a").unwrap();
+        assert_eq!(manager.names(), vec!["a", "b"]);
+        assert!(matches!(
+            manager.create("a"),
+            Err(ConfidisError::InvalidId(_))
+        ));
+        assert!(manager.create("c d").is_err());
+        assert!(manager.create("").is_err());
+
+        // The same source answers differently in each graph
+        for line in &[
+            "a SET q1 x FROM s1",
+            "a SET q1 x FROM s2",
+            "b SET q1 y FROM s1",
+            "b SET q1 z FROM s2",
+            "b CONFIGURE maximum_strength 10",
+        ] {
+            manager.execute_line(line).unwrap();
+        }
+        assert_eq!(
+            manager
+                .execute_line("a GET ANSWER TO q1")
+                .unwrap()
+                .to_string(),
+            "x (75.000%)"
+        );
+        assert_eq!(manager.get("a").unwrap().config().maximum_strength, 100.0);
+        assert_ne!(
+            manager.get("a").unwrap().source("s1").unwrap().quality,
+            manager.get("b").unwrap().source("s1").unwrap().quality
+        );
+
+        assert_eq!(
+            manager.execute_line("c GET ANSWER TO q1").err(),
+            Some(ConfidisError::UnknownGraph(String::from("c")))
+        );
+        assert!(matches!(
+            manager.execute_line("a NOPE"),
+            Err(ConfidisError::ParseError(_))
+        ));
+        assert!(manager.execute_line("  ").is_err());
+
+        manager.remove("a").unwrap();
+        assert_eq!(manager.names(), vec!["b"]);
+        assert!(manager.remove("a").is_err());
+    }
+}
@@ -0,0 +1,194 @@
+// Prometheus metrics
+//
+// Metrics counts executed commands by type and outcome, keeps a histogram of
+// command latency, and reports the size of a graph and how many question
+// recomputations it has done. render() produces the Prometheus text format:
+//
+//   confidis_commands_total{cmd="Set",result="ok"} 2
+//   confidis_command_duration_seconds_bucket{le="0.001"} 2
+//   confidis_questions 1
+//   confidis_rate_limited_answers_total{source="s1"} 3
+//
+// Metrics can be shared between threads. The graph gauges are only updated by
+// observe_graph, e.g. right before rendering.
+
+use crate::command::CommandType;
+use crate::graph::Graph;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Upper bounds in seconds of the latency histogram buckets
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.1, 1.0,
+];
+
+#[derive(Default)]
+struct CommandCounts {
+    ok: u64,
+    err: u64,
+}
+
+#[derive(Default)]
+struct MetricsState {
+    // keyed by the CommandType name, e.g. "GetAnswer"
+    commands: BTreeMap<String, CommandCounts>,
+    // non-cumulative count per bucket, the last one is +Inf
+    latency_buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    latency_sum: f64,
+    latency_count: u64,
+    questions: usize,
+    sources: usize,
+    answers: usize,
+    recompute_iterations: u64,
+    // answers rejected by the rate limit, by source
+    rate_limited: BTreeMap<String, u64>,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    state: Mutex<MetricsState>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    pub fn record_command(&self, cmd: &CommandType, latency: Duration, ok: bool) {
+        let mut state = self.state.lock().unwrap();
+        let counts = state.commands.entry(format!("{:?}", cmd)).or_default();
+        if ok {
+            counts.ok += 1;
+        } else {
+            counts.err += 1;
+        }
+        let seconds = latency.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        state.latency_buckets[bucket] += 1;
+        state.latency_sum += seconds;
+        state.latency_count += 1;
+    }
+
+    pub fn observe_graph(&self, g: &Graph) {
+        let stats = g.memory_stats();
+        let mut state = self.state.lock().unwrap();
+        state.questions = stats.question_count;
+        state.sources = stats.source_count;
+        state.answers = stats.answer_count;
+        state.recompute_iterations = g.recompute_count();
+        state.rate_limited = g.rate_limit_violations().clone();
+    }
+
+    pub fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut out = String::new();
+        // Writing to a String can't fail
+        let _ = write_metrics(&mut out, &state);
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) -> std::fmt::Result {
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} {}", name, kind)
+}
+
+fn write_metrics(out: &mut String, state: &MetricsState) -> std::fmt::Result {
+    header(
+        out,
+        "confidis_commands_total",
+        "counter",
+        "Commands executed",
+    )?;
+    for (cmd, counts) in &state.commands {
+        for (result, count) in &[("ok", counts.ok), ("error", counts.err)] {
+            writeln!(
+                out,
+                "confidis_commands_total{{cmd=\"{}\",result=\"{}\"}} {}",
+                cmd, result, count
+            )?;
+        }
+    }
+
+    let name = "confidis_command_duration_seconds";
+    header(out, name, "histogram", "Command execution latency")?;
+    let mut cumulative = 0;
+    for (bound, count) in LATENCY_BUCKETS.iter().zip(&state.latency_buckets) {
+        cumulative += count;
+        writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative)?;
+    }
+    writeln!(
+        out,
+        "{}_bucket{{le=\"+Inf\"}} {}",
+        name, state.latency_count
+    )?;
+    writeln!(out, "{}_sum {}", name, state.latency_sum)?;
+    writeln!(out, "{}_count {}", name, state.latency_count)?;
+
+    for (name, help, value) in &[
+        (
+            "confidis_questions",
+            "Questions in the graph",
+            state.questions,
+        ),
+        ("confidis_sources", "Sources in the graph", state.sources),
+        ("confidis_answers", "Answers in the graph", state.answers),
+    ] {
+        header(out, name, "gauge", help)?;
+        writeln!(out, "{} {}", name, value)?;
+    }
+
+    let name = "confidis_recompute_iterations_total";
+    header(out, name, "counter", "Question answer recomputations")?;
+    writeln!(out, "{} {}", name, state.recompute_iterations)?;
+
+    let name = "confidis_rate_limited_answers_total";
+    header(out, name, "counter", "Answers rejected by the rate limit")?;
+    for (source, count) in &state.rate_limited {
+        // label values escape backslashes and quotes
+        let source = source.replace('\\', "\\\\").replace('"', "\\\"");
+        writeln!(out, "{}{{source=\"{}\"}} {}", name, source, count)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+
+    #[test]
+    fn test_metrics_render() {
+        let metrics = Metrics::new();
+        let mut g = Graph::new();
+        for line in &["SET q1 a FROM s1", "SET q1 a FROM s2", "GET ANSWER TO q1"] {
+            let cmd = Command::from(line).unwrap();
+            let ok = g.execute_command(&cmd).is_ok();
+            metrics.record_command(&cmd.cmd, Duration::from_micros(200), ok);
+        }
+        metrics.record_command(&CommandType::Configure, Duration::from_secs(2), false);
+        metrics.observe_graph(&g);
+
+        let text = metrics.render();
+        for line in &[
+            "confidis_commands_total{cmd=\"Set\",result=\"ok\"} 2",
+            "confidis_commands_total{cmd=\"Configure\",result=\"error\"} 1",
+            "confidis_command_duration_seconds_bucket{le=\"0.0001\"} 0",
+            "confidis_command_duration_seconds_bucket{le=\"0.00025\"} 3",
+            "confidis_command_duration_seconds_bucket{le=\"1\"} 3",
+            "confidis_command_duration_seconds_bucket{le=\"+Inf\"} 4",
+            "confidis_command_duration_seconds_count 4",
+            "confidis_questions 1",
+            "confidis_sources 2",
+            "confidis_answers 2",
+            "confidis_recompute_iterations_total 2",
+        ] {
+            assert!(text.lines().any(|l| l == *line), "missing {}", line);
+        }
+    }
+}
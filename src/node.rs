@@ -0,0 +1,106 @@
+// Node.js bindings
+//
+//   const { Graph } = require("./confidis.node")
+//   const g = new Graph()
+//   await g.set("q1", "a", "s1")
+//   await g.getAnswer("q1") // { cmd: "GetAnswer", answer: "a", confidence: 0.5, ... }
+//
+// Every method returns a promise. Commands run on a GraphWorker, so the graph
+// never blocks the event loop and commands from concurrent promises are
+// executed one at a time, in the order they were issued.
+
+use crate::command::{Command, CommandResponse, CommandType};
+use crate::graph::Graph;
+use crate::worker::GraphWorker;
+use napi::bindgen_prelude::AsyncTask;
+use napi::{Env, Error, JsUnknown, Result, Task};
+use napi_derive::napi;
+
+pub struct ExecuteTask {
+    worker: GraphWorker,
+    // Parsing happens on the calling thread, a parse error rejects the promise
+    cmd: std::result::Result<Command<'static>, String>,
+}
+
+impl Task for ExecuteTask {
+    type Output = CommandResponse;
+    type JsValue = JsUnknown;
+
+    fn compute(&mut self) -> Result<CommandResponse> {
+        let cmd = std::mem::replace(&mut self.cmd, Err(String::from("Already executed")))
+            .map_err(Error::from_reason)?;
+        self.worker.execute(cmd).map_err(Error::from_reason)
+    }
+
+    fn resolve(&mut self, env: Env, response: CommandResponse) -> Result<JsUnknown> {
+        env.to_js_value(&response)
+    }
+}
+
+#[napi(js_name = "Graph")]
+pub struct NodeGraph {
+    worker: GraphWorker,
+}
+
+#[napi]
+impl NodeGraph {
+    #[napi(constructor)]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        NodeGraph {
+            worker: GraphWorker::spawn(Graph::new),
+        }
+    }
+
+    fn task(&self, cmd: std::result::Result<Command<'static>, String>) -> AsyncTask<ExecuteTask> {
+        AsyncTask::new(ExecuteTask {
+            worker: self.worker.clone(),
+            cmd,
+        })
+    }
+
+    // A command in the text grammar, e.g. "SET q1 a FROM s1"
+    #[napi]
+    pub fn execute_command(&self, command: String) -> AsyncTask<ExecuteTask> {
+        self.task(
+            Command::from(&command)
+                .map(Command::into_owned)
+                .map_err(String::from),
+        )
+    }
+
+    // A JSON command envelope, e.g. '{"cmd": "believe", "source": "s1"}'
+    #[napi]
+    pub fn execute_json_command(&self, json: String) -> AsyncTask<ExecuteTask> {
+        self.task(Command::from_json(&json).map_err(String::from))
+    }
+
+    #[napi]
+    pub fn set(&self, question: String, answer: String, source: String) -> AsyncTask<ExecuteTask> {
+        self.task(Ok(Command {
+            cmd: CommandType::Set,
+            question: Some(question.into()),
+            answer: Some(answer.into()),
+            source: Some(source.into()),
+            ..Default::default()
+        }))
+    }
+
+    #[napi]
+    pub fn get_answer(&self, question: String) -> AsyncTask<ExecuteTask> {
+        self.task(Ok(Command {
+            cmd: CommandType::GetAnswer,
+            question: Some(question.into()),
+            ..Default::default()
+        }))
+    }
+
+    #[napi]
+    pub fn get_source(&self, source: String) -> AsyncTask<ExecuteTask> {
+        self.task(Ok(Command {
+            cmd: CommandType::GetSource,
+            source: Some(source.into()),
+            ..Default::default()
+        }))
+    }
+}
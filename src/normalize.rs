@@ -0,0 +1,230 @@
+// Answer normalization
+//
+// Sources often give the same answer in different forms, " Paris", "paris" or
+// "Paris.", which exact comparison counts as different answers. A
+// Normalization is a pipeline of steps every answer goes through on SET,
+// before it's hashed and stored, so the exact match fast path sees the
+// normalized content too:
+//
+//   CONFIGURE normalize trim lowercase strip_punctuation
+//
+// The steps, applied in the order given:
+//
+//   trim               remove leading and trailing whitespace
+//   lowercase          lowercase every letter
+//   nfc, nfkc          unicode normalization form C or KC
+//   strip_punctuation  remove unicode punctuation
+//   regex=<p>          remove every match of the regex <p>
+//   regex=<p>=><r>     replace every match of <p> with <r>, which can refer
+//                      to groups of <p> like $1
+//
+// CONFIGURE splits its value on whitespace, so patterns match whitespace with
+// \s. "none" removes every step. Answers stored before the steps were changed
+// are kept as they were.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Debug, Clone)]
+pub enum NormalizeStep {
+    Trim,
+    Lowercase,
+    Nfc,
+    Nfkc,
+    StripPunctuation,
+    Regex { pattern: Regex, replacement: String },
+}
+
+impl PartialEq for NormalizeStep {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                NormalizeStep::Regex {
+                    pattern,
+                    replacement,
+                },
+                NormalizeStep::Regex {
+                    pattern: other_pattern,
+                    replacement: other_replacement,
+                },
+            ) => pattern.as_str() == other_pattern.as_str() && replacement == other_replacement,
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+fn punctuation() -> &'static Regex {
+    static PUNCTUATION: OnceLock<Regex> = OnceLock::new();
+    PUNCTUATION.get_or_init(|| Regex::new(r"\p{P}+").unwrap())
+}
+
+impl NormalizeStep {
+    pub fn apply(&self, answer: &str) -> String {
+        match self {
+            NormalizeStep::Trim => answer.trim().to_string(),
+            NormalizeStep::Lowercase => answer.to_lowercase(),
+            NormalizeStep::Nfc => answer.nfc().collect(),
+            NormalizeStep::Nfkc => answer.nfkc().collect(),
+            NormalizeStep::StripPunctuation => punctuation().replace_all(answer, "").into_owned(),
+            NormalizeStep::Regex {
+                pattern,
+                replacement,
+            } => pattern
+                .replace_all(answer, replacement.as_str())
+                .into_owned(),
+        }
+    }
+}
+
+impl fmt::Display for NormalizeStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NormalizeStep::Trim => write!(f, "trim"),
+            NormalizeStep::Lowercase => write!(f, "lowercase"),
+            NormalizeStep::Nfc => write!(f, "nfc"),
+            NormalizeStep::Nfkc => write!(f, "nfkc"),
+            NormalizeStep::StripPunctuation => write!(f, "strip_punctuation"),
+            NormalizeStep::Regex {
+                pattern,
+                replacement,
+            } if replacement.is_empty() => write!(f, "regex={}", pattern),
+            NormalizeStep::Regex {
+                pattern,
+                replacement,
+            } => write!(f, "regex={}=>{}", pattern, replacement),
+        }
+    }
+}
+
+impl FromStr for NormalizeStep {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "trim" => NormalizeStep::Trim,
+            "lowercase" => NormalizeStep::Lowercase,
+            "nfc" => NormalizeStep::Nfc,
+            "nfkc" => NormalizeStep::Nfkc,
+            "strip_punctuation" => NormalizeStep::StripPunctuation,
+            _ => {
+                let regex = s.strip_prefix("regex=").ok_or_else(|| {
+                    format!(
+                        "unknown step \"{}\". Try trim, lowercase, nfc, nfkc, strip_punctuation or regex=<pattern>[=><replacement>]",
+                        s
+                    )
+                })?;
+                let (pattern, replacement) = regex.split_once("=>").unwrap_or((regex, ""));
+                NormalizeStep::Regex {
+                    pattern: Regex::new(pattern)
+                        .map_err(|e| format!("invalid regex \"{}\": {}", pattern, e))?,
+                    replacement: replacement.to_string(),
+                }
+            }
+        })
+    }
+}
+
+// The steps answers go through on SET, none by default. Serialized in the text
+// form CONFIGURE takes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Normalization(pub Vec<NormalizeStep>);
+
+impl Normalization {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn apply<'a>(&self, answer: &'a str) -> Cow<'a, str> {
+        let mut answer = Cow::Borrowed(answer);
+        for step in &self.0 {
+            answer = Cow::Owned(step.apply(&answer));
+        }
+        answer
+    }
+}
+
+impl fmt::Display for Normalization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "none");
+        }
+        let steps: Vec<String> = self.0.iter().map(NormalizeStep::to_string).collect();
+        write!(f, "{}", steps.join(" "))
+    }
+}
+
+impl FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == "none" {
+            return Ok(Normalization::default());
+        }
+        s.split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Normalization)
+    }
+}
+
+impl From<Normalization> for String {
+    fn from(normalization: Normalization) -> String {
+        normalization.to_string()
+    }
+}
+
+impl TryFrom<String> for Normalization {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalization() {
+        let normalization: Normalization = "trim lowercase strip_punctuation".parse().unwrap();
+        assert_eq!(normalization.apply("  Paris. "), "paris");
+        assert_eq!(normalization.apply("¿Qué?"), "qué");
+        assert!(matches!(
+            Normalization::default().apply("Paris"),
+            Cow::Borrowed("Paris")
+        ));
+
+        // "ﬁ" is a ligature of "fi" in KC, "é" composed and decomposed are one
+        // NFC form
+        let normalization: Normalization = "nfkc".parse().unwrap();
+        assert_eq!(normalization.apply("ﬁne"), "fine");
+        let normalization: Normalization = "nfc".parse().unwrap();
+        assert_eq!(normalization.apply("e\u{301}"), "\u{e9}");
+
+        let normalization: Normalization = r"regex=\s+=>_ regex=^the_".parse().unwrap();
+        assert_eq!(normalization.apply("the  big apple"), "big_apple");
+        let normalization: Normalization = r"regex=(\d+)\s*km=>${1}000m".parse().unwrap();
+        assert_eq!(normalization.apply("5 km"), "5000m");
+
+        // The text form parses back to the same steps
+        for text in &["none", "trim nfkc", r"regex=\s+=>_ regex=x"] {
+            let normalization: Normalization = text.parse().unwrap();
+            assert_eq!(normalization.to_string(), *text);
+            let json = serde_json::to_string(&normalization).unwrap();
+            assert_eq!(
+                serde_json::from_str::<Normalization>(&json).unwrap(),
+                normalization
+            );
+        }
+        assert!("trim uppercase".parse::<Normalization>().is_err());
+        assert!("regex=(".parse::<Normalization>().is_err());
+    }
+}
@@ -0,0 +1,326 @@
+// Declared question types
+//
+// A graph compares answers with its comparison_method, which suits a graph
+// whose questions all take the same kind of answer. With a mix, e.g. a
+// labeling project asking for a category, a count and a location per item, a
+// question can declare its type instead:
+//
+//   TYPE q1 numeric max_distance=0.5
+//
+// The question's answers are then compared with the type's comparison method
+// rather than comparison_method, and a SET whose answer isn't of the type is
+// rejected like an answer violating the question's schema. The types:
+//
+//   categorical                  labels, compared exactly
+//   numeric [max_distance=<x>]   numbers, equal values by default
+//   numeric_vec vec_length=<n> [allowed_difference=<x>] [diff_fn=<f>]
+//                                n comma separated numbers, allowed_difference
+//                                1 and diff_fn l2 by default
+//   text [max_distance=<x>] ...  free text, with the parameters of the text
+//                                comparison method, max_distance 0.5 by default
+//   geo [max_distance=<km>]      "<latitude>,<longitude>" in degrees, within
+//                                0.1 km by default
+//   datetime [max_distance=<s>]  RFC 3339 dates and date-times or unix time in
+//                                seconds, within 60 s by default
+//
+// "none" removes the type. Types only apply to graphs of String answers,
+// graphs of other answers compare every answer with their equalifier.
+
+use crate::cluster::DistanceCache;
+use crate::config::{ConfigKey, ConfigValue};
+use crate::equalifier::{
+    comparison_methods, parse_datetime, parse_location, parse_numeric_vec, DatetimeEqualifier,
+    Equalifier, EqualifierConfig, GeoEqualifier,
+};
+use crate::error::ConfidisError;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+// numeric max_distance by default, only numbers that are equal match
+const NUMERIC_MAX_DISTANCE: f64 = 1e-9;
+const TEXT_MAX_DISTANCE: f64 = 0.5;
+const GEO_MAX_DISTANCE: f64 = 0.1;
+const DATETIME_MAX_DISTANCE: f64 = 60.0;
+
+// The question types TYPE accepts in this build
+pub fn question_types() -> Vec<&'static str> {
+    let mut types = vec!["categorical", "numeric", "numeric_vec"];
+    if comparison_methods().contains(&"text") {
+        types.push("text");
+    }
+    types.extend(["geo", "datetime"]);
+    types
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Comparison {
+    // one of the comparison methods, exact for categorical
+    Method(EqualifierConfig),
+    Geo { max_distance: f64 },
+    Datetime { max_distance: f64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct QuestionType {
+    comparison: Comparison,
+    // the text form, which also identifies the type's equalifier
+    text: String,
+}
+
+impl PartialEq for QuestionType {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl QuestionType {
+    // The type's name, e.g. "numeric"
+    pub fn name(&self) -> &str {
+        self.text.split(' ').next().unwrap_or_default()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    // Ok if the answer is of the type, otherwise why it isn't
+    pub fn validate(&self, answer: &str) -> Result<(), String> {
+        let valid = match &self.comparison {
+            Comparison::Method(EqualifierConfig::Numeric { .. }) => {
+                answer.parse::<f64>().is_ok_and(f64::is_finite)
+            }
+            Comparison::Method(EqualifierConfig::NumericVec { vec_length, .. }) => {
+                parse_numeric_vec(answer).is_some_and(|numbers| {
+                    numbers.len() == *vec_length && numbers.iter().all(|n| n.is_finite())
+                })
+            }
+            Comparison::Method(_) => true,
+            Comparison::Geo { .. } => parse_location(answer).is_some(),
+            Comparison::Datetime { .. } => parse_datetime(answer).is_some(),
+        };
+        if valid {
+            return Ok(());
+        }
+        Err(match &self.comparison {
+            Comparison::Method(EqualifierConfig::NumericVec { vec_length, .. }) => {
+                format!("must be {} comma separated numbers", vec_length)
+            }
+            Comparison::Geo { .. } => String::from("must be a location as <latitude>,<longitude>"),
+            Comparison::Datetime { .. } => {
+                String::from("must be an RFC 3339 date or date-time, or unix time in seconds")
+            }
+            _ => String::from("must be a number"),
+        })
+    }
+
+    // The equalifier comparing answers of the type
+    pub fn equalifier(&self) -> Box<dyn Equalifier> {
+        match &self.comparison {
+            Comparison::Method(method) => method
+                .build()
+                .expect("question types only use built-in comparison methods"),
+            Comparison::Geo { max_distance } => Box::new(GeoEqualifier::new(*max_distance)),
+            Comparison::Datetime { max_distance } => {
+                Box::new(DatetimeEqualifier::new(*max_distance))
+            }
+        }
+    }
+}
+
+impl fmt::Display for QuestionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl FromStr for QuestionType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, params) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+        let name = question_types()
+            .into_iter()
+            .find(|&type_name| type_name == name)
+            .ok_or_else(|| {
+                format!(
+                    "unknown question type \"{}\". Try {}",
+                    name,
+                    question_types().join(", ")
+                )
+            })?;
+        // Defaults come first, so parameters given override them
+        let method = |defaults: &str| -> Result<Comparison, String> {
+            let method = if name == "categorical" { "exact" } else { name };
+            match ConfigValue::parse(
+                ConfigKey::ComparisonMethod,
+                &format!("{} {} {}", method, defaults, params),
+            ) {
+                Ok(ConfigValue::ComparisonMethod(method)) => Ok(Comparison::Method(method)),
+                Err(ConfidisError::InvalidConfig { reason, .. }) => Err(reason),
+                result => Err(format!("unexpected comparison method {:?}", result)),
+            }
+        };
+        let max_distance = |default: f64| -> Result<f64, String> {
+            let mut max_distance = default;
+            for param in params.split_whitespace() {
+                max_distance = match param.split_once('=') {
+                    Some(("max_distance", value)) => value
+                        .parse()
+                        .ok()
+                        .filter(|d: &f64| *d > 0.0 && d.is_finite())
+                        .ok_or("max_distance must be a number greater than 0")?,
+                    _ => return Err(format!("unknown parameter \"{}\"", param)),
+                };
+            }
+            Ok(max_distance)
+        };
+        let comparison = match name {
+            "categorical" => method("")?,
+            "numeric" => method(&format!("max_distance={}", NUMERIC_MAX_DISTANCE))?,
+            "numeric_vec" => method("allowed_difference=1 diff_fn=l2")?,
+            "text" => method(&format!("max_distance={}", TEXT_MAX_DISTANCE))?,
+            "geo" => Comparison::Geo {
+                max_distance: max_distance(GEO_MAX_DISTANCE)?,
+            },
+            _ => Comparison::Datetime {
+                max_distance: max_distance(DATETIME_MAX_DISTANCE)?,
+            },
+        };
+        let text = match &comparison {
+            Comparison::Method(EqualifierConfig::Exact) => String::from("categorical"),
+            Comparison::Method(method) => ConfigValue::ComparisonMethod(method.clone()).to_string(),
+            Comparison::Geo { max_distance } | Comparison::Datetime { max_distance } => {
+                format!("{} max_distance={}", name, max_distance)
+            }
+        };
+        Ok(QuestionType { comparison, text })
+    }
+}
+
+impl From<QuestionType> for String {
+    fn from(question_type: QuestionType) -> String {
+        question_type.text
+    }
+}
+
+impl TryFrom<String> for QuestionType {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+// The equalifier of a question type with the distances it computed
+pub(crate) struct TypedEqualifier<A> {
+    pub(crate) equalifier: Box<dyn Equalifier<A>>,
+    pub(crate) distance_cache: DistanceCache<A>,
+}
+
+// The equalifiers of the question types in a graph, each built the first time
+// a question of the type is compared
+pub(crate) struct TypedEqualifiers<A> {
+    built: Mutex<HashMap<String, Arc<TypedEqualifier<A>>>>,
+}
+
+impl<A> Default for TypedEqualifiers<A> {
+    fn default() -> Self {
+        TypedEqualifiers {
+            built: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<A: 'static> TypedEqualifiers<A> {
+    // The type's equalifier, None in graphs whose answers aren't Strings
+    pub(crate) fn get(&self, question_type: &QuestionType) -> Option<Arc<TypedEqualifier<A>>> {
+        let mut built = self.built.lock().unwrap();
+        if let Some(typed) = built.get(question_type.as_str()) {
+            return Some(typed.clone());
+        }
+        let equalifier: Box<dyn Any> = Box::new(question_type.equalifier());
+        let typed = Arc::new(TypedEqualifier {
+            equalifier: *equalifier.downcast::<Box<dyn Equalifier<A>>>().ok()?,
+            distance_cache: DistanceCache::default(),
+        });
+        built.insert(question_type.as_str().to_string(), typed.clone());
+        Some(typed)
+    }
+
+    // Drop the equalifiers with their cached distances, e.g. once answers
+    // are rehashed
+    pub(crate) fn clear(&mut self) {
+        self.built.get_mut().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_question_type() {
+        let parse = |text: &str| text.parse::<QuestionType>();
+        assert_eq!(parse("categorical").unwrap().to_string(), "categorical");
+        assert_eq!(
+            parse("numeric max_distance=0.5").unwrap().to_string(),
+            "numeric max_distance=0.5"
+        );
+        assert_eq!(
+            parse("numeric_vec vec_length=2").unwrap().to_string(),
+            "numeric_vec allowed_difference=1 vec_length=2 diff_fn=l2"
+        );
+        assert_eq!(parse("geo").unwrap().to_string(), "geo max_distance=0.1");
+        assert_eq!(
+            parse("datetime max_distance=3600").unwrap().to_string(),
+            "datetime max_distance=3600"
+        );
+        for invalid in &[
+            "boolean",
+            "numeric max_distance=0",
+            "numeric_vec",
+            "geo radius=1",
+        ] {
+            assert!(parse(invalid).is_err(), "{}", invalid);
+        }
+
+        let numeric = parse("numeric").unwrap();
+        assert_eq!(numeric.validate("4.5"), Ok(()));
+        assert_eq!(numeric.validate("NaN"), Err("must be a number".into()));
+        let vector = parse("numeric_vec vec_length=2").unwrap();
+        assert_eq!(vector.validate("1,2"), Ok(()));
+        assert!(vector.validate("1,2,3").is_err());
+        assert!(parse("geo").unwrap().validate("48.85,2.35").is_ok());
+        assert!(parse("geo").unwrap().validate("Paris").is_err());
+        assert!(parse("datetime").unwrap().validate("2024-05-01").is_ok());
+        assert!(parse("categorical").unwrap().validate("anything").is_ok());
+
+        // equal numbers match by default, unlike exact comparison
+        let typed = TypedEqualifiers::<String>::default();
+        let equalifier = typed.get(&numeric).unwrap();
+        let answer = |content: &str| crate::command::Answer::new(content.into(), "s1".into());
+        assert_eq!(
+            equalifier
+                .equalifier
+                .get_distance(&answer("1"), &answer("1.0")),
+            0.0
+        );
+        assert_eq!(
+            equalifier
+                .equalifier
+                .get_distance(&answer("1"), &answer("1.1")),
+            1.0
+        );
+        // graphs of other answers have no typed equalifiers
+        assert!(TypedEqualifiers::<Vec<u8>>::default()
+            .get(&numeric)
+            .is_none());
+    }
+}
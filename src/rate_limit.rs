@@ -0,0 +1,98 @@
+// Per-source rate limiting of answers
+//
+// A single compromised source can flood a graph with answers, drowning out the
+// honest sources of every question it answers. With rate_limit_answers set, a
+// source can give at most that many answers in any rate_limit_window seconds:
+//
+//   CONFIGURE rate_limit_answers 100
+//   CONFIGURE rate_limit_window 60
+//
+// A SET, or a set_many batch, that would take a source over its limit is
+// rejected with a RateLimited error and adds nothing. The rejected answers are
+// counted per source, see Graph::rate_limit_violations, and in STATS.
+//
+// Limits apply to commands as they're executed. Journal replay isn't limited,
+// its answers were admitted when they were given. Graphs in deterministic mode
+// don't read the clock, every answer is given at time 0 (see Graph::now), so
+// their windows never move on and the limit caps a source's answers in all.
+// The windows and counts aren't persisted, a restored graph starts with empty
+// ones.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RateLimiter {
+    // unix time in ms of each source's answers in the current window, oldest
+    // first
+    recent: HashMap<String, VecDeque<u64>>,
+    // answers rejected per source
+    violations: BTreeMap<String, u64>,
+}
+
+impl RateLimiter {
+    // Admit the answers, the number given by each source, at now if no source
+    // exceeds limit answers within window_ms. Otherwise nothing is admitted,
+    // the rejected answers are counted and the first source over its limit is
+    // returned.
+    pub(crate) fn admit(
+        &mut self,
+        answers: &BTreeMap<&str, usize>,
+        now: u64,
+        limit: usize,
+        window_ms: u64,
+    ) -> Result<(), String> {
+        let mut exceeded = None;
+        for (&source, &count) in answers {
+            let times = self.recent.entry(source.to_string()).or_default();
+            while times.front().is_some_and(|&time| time + window_ms <= now) {
+                times.pop_front();
+            }
+            if times.len() + count > limit {
+                *self.violations.entry(source.to_string()).or_default() += count as u64;
+                exceeded = exceeded.or(Some(source));
+            }
+        }
+        if let Some(source) = exceeded {
+            return Err(source.to_string());
+        }
+        for (&source, &count) in answers {
+            self.recent
+                .get_mut(source)
+                .expect("every source's window was pruned above")
+                .extend(std::iter::repeat_n(now, count));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn violations(&self) -> &BTreeMap<String, u64> {
+        &self.violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::default();
+        let batch = |answers: &[(&'static str, usize)]| -> BTreeMap<&str, usize> {
+            answers.iter().copied().collect()
+        };
+        assert!(limiter.admit(&batch(&[("s1", 2)]), 0, 3, 1000).is_ok());
+        assert!(limiter.admit(&batch(&[("s1", 1)]), 500, 3, 1000).is_ok());
+        // a batch with any source over its limit admits nobody
+        assert_eq!(
+            limiter.admit(&batch(&[("s1", 1), ("s2", 1)]), 999, 3, 1000),
+            Err(String::from("s1"))
+        );
+        assert!(limiter.admit(&batch(&[("s2", 3)]), 999, 3, 1000).is_ok());
+        // the first two answers left the window
+        assert!(limiter.admit(&batch(&[("s1", 2)]), 1000, 3, 1000).is_ok());
+        assert!(limiter.admit(&batch(&[("s1", 1)]), 1000, 3, 1000).is_err());
+        assert_eq!(
+            limiter.violations(),
+            &BTreeMap::from([(String::from("s1"), 2)])
+        );
+    }
+}
@@ -0,0 +1,263 @@
+// Per-source reliability reports, e.g. for vendor QA reviews
+//
+// One row per source with its quality and strength, how many answers it gave,
+// how often its latest answer to a question agreed with the consensus (was in
+// the question's correct cluster) and how its quality moved recently:
+//
+//   g.export_reliability_report(file, ReportFormat::Csv, Some('/'))?;
+//
+// Questions don't carry tags, so a question's tag is the part of its name
+// before tag_separator ("geo/q1" is tagged "geo"), and agreement is also broken
+// down by tag. Questions without the separator, or all of them without a
+// separator, are only counted in the overall agreement.
+//
+// Only questions with a weight count towards agreement: a question answered by
+// a single source agrees with itself. Excluded answers don't count either. The
+// trend is the quality change over the source's last RECENT_CHANGES changes in
+// the audit log, None without an audit log or changes.
+
+use crate::command::AnswerContent;
+use crate::error::ConfidisError;
+use crate::graph::Graph;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+
+const RECENT_CHANGES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    // one row per source, with an agreement column per tag
+    Csv,
+    // an array of SourceReliability objects
+    Json,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Agreement {
+    // questions whose consensus the source's latest answer was compared with
+    pub questions: usize,
+    // of those, how many it agreed with
+    pub agreed: usize,
+}
+
+impl Agreement {
+    // The share of questions the source agreed on, None without any
+    pub fn rate(&self) -> Option<f64> {
+        match self.questions {
+            0 => None,
+            questions => Some(self.agreed as f64 / questions as f64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceReliability {
+    pub source: String,
+    pub quality: f64,
+    pub strength: f64,
+    // every answer the source gave, including replaced ones
+    pub answers: usize,
+    pub agreement: Agreement,
+    pub trend: Option<f64>,
+    pub agreement_by_tag: BTreeMap<String, Agreement>,
+}
+
+impl<A: AnswerContent> Graph<A> {
+    // A report row for every source, sorted by name
+    pub fn reliability_report(
+        &self,
+        tag_separator: Option<char>,
+    ) -> Result<Vec<SourceReliability>, ConfidisError> {
+        if self.is_bulk_loading() {
+            return Err(ConfidisError::BulkLoadInProgress);
+        }
+        let mut rows: BTreeMap<&str, SourceReliability> = self
+            .sources
+            .values()
+            .map(|source| {
+                let row = SourceReliability {
+                    source: source.name.clone(),
+                    quality: source.quality,
+                    strength: source.strength,
+                    answers: 0,
+                    agreement: Agreement::default(),
+                    trend: self.quality_trend(&source.name),
+                    agreement_by_tag: BTreeMap::new(),
+                };
+                (source.name.as_str(), row)
+            })
+            .collect();
+
+        for question in self.questions.values() {
+            let tag = tag_separator
+                .and_then(|separator| question.name.split_once(separator))
+                .map(|(tag, _)| tag);
+            // each source's latest answer
+            let mut latest: HashMap<&str, usize> = HashMap::new();
+            for (i, answer) in question.answers.iter().enumerate() {
+                if let Some(row) = rows.get_mut(answer.source.as_str()) {
+                    row.answers += 1;
+                }
+                latest.insert(answer.source.as_str(), i);
+            }
+            if question.weight <= 0.0 {
+                continue;
+            }
+            for (source, i) in latest {
+                let row = match rows.get_mut(source) {
+                    Some(row) if !question.is_excluded(source) => row,
+                    _ => continue,
+                };
+                let agreed = question.correct_answers.contains(&i) as usize;
+                row.agreement.questions += 1;
+                row.agreement.agreed += agreed;
+                if let Some(tag) = tag {
+                    let by_tag = row.agreement_by_tag.entry(tag.to_string()).or_default();
+                    by_tag.questions += 1;
+                    by_tag.agreed += agreed;
+                }
+            }
+        }
+        Ok(rows.into_values().collect())
+    }
+
+    // Write reliability_report to writer as CSV or JSON
+    pub fn export_reliability_report<W: Write>(
+        &self,
+        writer: W,
+        format: ReportFormat,
+        tag_separator: Option<char>,
+    ) -> Result<(), ConfidisError> {
+        let rows = self.reliability_report(tag_separator)?;
+        let export_err = |e: String| {
+            ConfidisError::Internal(format!("Couldn't write reliability report: {}", e))
+        };
+        match format {
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(writer, &rows).map_err(|e| export_err(e.to_string()))
+            }
+            ReportFormat::Csv => write_csv(writer, &rows).map_err(|e| export_err(e.to_string())),
+        }
+    }
+
+    fn quality_trend(&self, source_name: &str) -> Option<f64> {
+        let changes: Vec<_> = self.audit_log()?.changes_for(source_name).collect();
+        let recent = &changes[changes.len().saturating_sub(RECENT_CHANGES)..];
+        Some(recent.last()?.new_quality - recent.first()?.old_quality)
+    }
+}
+
+fn write_csv<W: Write>(writer: W, rows: &[SourceReliability]) -> Result<(), csv::Error> {
+    let mut tags: Vec<&String> = rows
+        .iter()
+        .flat_map(|row| row.agreement_by_tag.keys())
+        .collect();
+    tags.sort();
+    tags.dedup();
+
+    let optional = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+    let mut csv = csv::Writer::from_writer(writer);
+    let mut header: Vec<String> = [
+        "source",
+        "quality",
+        "strength",
+        "answers",
+        "questions",
+        "agreed",
+        "agreement",
+        "trend",
+    ]
+    .iter()
+    .map(|column| column.to_string())
+    .collect();
+    header.extend(tags.iter().map(|tag| format!("agreement:{}", tag)));
+    csv.write_record(&header)?;
+    for row in rows {
+        let mut record = vec![
+            row.source.clone(),
+            row.quality.to_string(),
+            row.strength.to_string(),
+            row.answers.to_string(),
+            row.agreement.questions.to_string(),
+            row.agreement.agreed.to_string(),
+            optional(row.agreement.rate()),
+            optional(row.trend),
+        ];
+        record.extend(tags.iter().map(|&tag| {
+            optional(
+                row.agreement_by_tag
+                    .get(tag)
+                    .and_then(|agreement| agreement.rate()),
+            )
+        }));
+        csv.write_record(&record)?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditLog;
+    use crate::command::Command;
+
+    #[test]
+    fn test_reliability_report() {
+        let mut g = Graph::new();
+        g.set_audit_log(AuditLog::new(100));
+        for line in &[
+            "SET geo/q1 paris FROM s1",
+            "SET geo/q1 paris FROM s2",
+            "SET geo/q1 rome FROM s3",
+            "SET math/q2 4 FROM s1",
+            "SET math/q2 5 FROM s2",
+            "SET math/q2 4 FROM s3",
+            "SET math/q2 4 FROM s4",
+            "SET q3 x FROM s1",
+        ] {
+            g.execute_command(&Command::from(line).unwrap()).unwrap();
+        }
+
+        let report = g.reliability_report(Some('/')).unwrap();
+        let names: Vec<&str> = report.iter().map(|row| row.source.as_str()).collect();
+        assert_eq!(names, vec!["s1", "s2", "s3", "s4"]);
+        let s1 = &report[0];
+        assert_eq!(s1.answers, 3);
+        // q3 has a single source, so no weight
+        assert_eq!(
+            s1.agreement,
+            Agreement {
+                questions: 2,
+                agreed: 2
+            }
+        );
+        assert!(s1.trend.unwrap() > 0.0);
+        let s2 = &report[1];
+        assert_eq!(s2.agreement.rate(), Some(0.5));
+        assert_eq!(s2.agreement_by_tag["geo"].rate(), Some(1.0));
+        assert_eq!(s2.agreement_by_tag["math"].rate(), Some(0.0));
+        assert!(!report[3].agreement_by_tag.contains_key("geo"));
+
+        let mut csv: Vec<u8> = Vec::new();
+        g.export_reliability_report(&mut csv, ReportFormat::Csv, Some('/'))
+            .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[0],
+            "source,quality,strength,answers,questions,agreed,agreement,trend,agreement:geo,agreement:math"
+        );
+        assert!(lines[4].starts_with("s4,") && lines[4].ends_with(",,1"));
+
+        let mut json: Vec<u8> = Vec::new();
+        g.export_reliability_report(&mut json, ReportFormat::Json, None)
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 4);
+        assert_eq!(json[1]["agreement"]["agreed"], 1);
+        assert!(json[1]["agreement_by_tag"].as_object().unwrap().is_empty());
+    }
+}
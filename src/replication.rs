@@ -0,0 +1,579 @@
+// Primary/replica replication of a graph's journal
+//
+// A primary serves its journal with a ReplicationServer, which every replica
+// connects to. The primary sends a snapshot of its graph, then every journal
+// record as it's appended:
+//   SNAPSHOT <n>                      followed by n bytes, see save_snapshot
+//   <timestamp> <record>              a journal record, see journal.rs
+//   <timestamp> HEARTBEAT             sent when no record was appended for a
+//                                     heartbeat interval
+//   <timestamp> CLEAR UNDO            sent when the primary forgets its UNDO
+//                                     history, e.g. on compaction
+// or "-<error>" if it can't, e.g. when its graph has no journal. Records are
+// applied with the primary's timestamps, so a replica's graph matches the
+// primary's. UNDO and REDO are journaled as commands, so the primary forgets
+// its UNDO history when it snapshots for a new replica, and every replica
+// forgets its own whenever the primary does, keeping their histories equal.
+//
+// A ReplicationServer with tokens (see set_tokens) only streams to replicas
+// that first send "AUTH <token>" with a token of the admin role, since they
+// receive everything, see Replica::set_token and auth.rs. A replica that
+// sends nothing within AUTH_TIMEOUT is told authentication is required.
+//
+// A Replica applies the stream to the graph of its GraphWorker and reconnects
+// with a fresh snapshot when the connection fails. A server serving the
+// replica (see Server::set_replica) rejects mutating commands and only serves
+// reads while the replica heard from its primary within max_staleness, so a
+// read never sees a graph older than that. Commands the replica applies aren't
+// reported to the worker's subscribers.
+
+use crate::auth::{AuthSession, Role, Tokens};
+use crate::command::CommandType;
+use crate::graph::Graph;
+use crate::journal::now_millis;
+use crate::server::{read_line_limited, MAX_LINE_LEN};
+use crate::worker::GraphWorker;
+use log::{info, warn};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Streams the journal of a worker's graph to replicas
+pub struct ReplicationServer {
+    listener: TcpListener,
+    worker: GraphWorker,
+    heartbeat: Duration,
+    tokens: Option<Arc<Tokens>>,
+}
+
+impl ReplicationServer {
+    // The worker's graph needs a journal, see Graph::set_journal
+    pub fn bind<A: ToSocketAddrs>(addr: A, worker: GraphWorker) -> Result<Self, String> {
+        let listener = TcpListener::bind(addr).map_err(|e| format!("Couldn't bind: {}", e))?;
+        Ok(ReplicationServer {
+            listener,
+            worker,
+            heartbeat: DEFAULT_HEARTBEAT,
+            tokens: None,
+        })
+    }
+
+    // Require replicas to authenticate with an admin token, see auth.rs
+    pub fn set_tokens(&mut self, tokens: Tokens) {
+        self.tokens = Some(Arc::new(tokens));
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.listener.local_addr().map_err(|e| e.to_string())
+    }
+
+    // How long the journal can be idle before replicas get a heartbeat, which
+    // has to be well below their max staleness. 1 second by default.
+    pub fn set_heartbeat(&mut self, heartbeat: Duration) {
+        self.heartbeat = heartbeat;
+    }
+
+    // Accept replicas until the listener fails
+    pub fn run(self) -> Result<(), String> {
+        for stream in self.listener.incoming() {
+            let stream = stream.map_err(|e| format!("Couldn't accept replica: {}", e))?;
+            let worker = self.worker.clone();
+            let heartbeat = self.heartbeat;
+            let auth = AuthSession::new(self.tokens.clone());
+            thread::spawn(move || {
+                let peer = stream.peer_addr().ok();
+                info!("Replica {:?} connected", peer);
+                if let Err(e) = stream_journal(stream, &worker, heartbeat, auth) {
+                    warn!("Replica {:?} disconnected: {}", peer, e);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+// A snapshot of g and a receiver of every record appended after it
+fn follow_graph(g: &mut Graph) -> Result<(Vec<u8>, Receiver<String>), String> {
+    let mut snapshot = Vec::new();
+    g.save_snapshot(&mut snapshot)?;
+    // The replica starts from the snapshot, which can't be undone
+    g.clear_undo();
+    let journal = g
+        .journal_mut()
+        .ok_or("Replication needs a journal on the primary")?;
+    Ok((snapshot, journal.follow()))
+}
+
+// Ok if the replica sent AUTH with an admin token, or the server has no tokens
+fn authenticate_replica(stream: &TcpStream, mut auth: AuthSession) -> Result<(), String> {
+    if !auth.has_tokens() {
+        return Ok(());
+    }
+    let mut line = String::new();
+    stream
+        .set_read_timeout(Some(AUTH_TIMEOUT))
+        .and_then(|_| read_line_limited(&mut BufReader::new(stream), &mut line, MAX_LINE_LEN))
+        .and_then(|_| stream.set_read_timeout(None))
+        .or_else(|e| match e.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => Ok(()),
+            _ => Err(format!("Couldn't read from replica: {}", e)),
+        })?;
+    let token = line
+        .trim_end()
+        .strip_prefix("AUTH ")
+        .ok_or("Authentication required, send AUTH <token> first")?;
+    match auth.authenticate(token)? {
+        Role::Admin => Ok(()),
+        role => Err(format!(
+            "Permission denied, replication needs the admin role, not {}",
+            role
+        )),
+    }
+}
+
+fn stream_journal(
+    stream: TcpStream,
+    worker: &GraphWorker,
+    heartbeat: Duration,
+    auth: AuthSession,
+) -> Result<(), String> {
+    let write_err = |e: std::io::Error| format!("Couldn't write to replica: {}", e);
+    let authenticated = authenticate_replica(&stream, auth);
+    let mut writer = BufWriter::new(stream);
+    let (snapshot, records) = match authenticated
+        .and_then(|_| worker.with_graph(follow_graph))
+        .and_then(|sync| sync)
+    {
+        Ok(sync) => sync,
+        Err(msg) => {
+            writeln!(writer, "-{}", msg)
+                .and_then(|_| writer.flush())
+                .map_err(write_err)?;
+            return Err(msg);
+        }
+    };
+    writeln!(writer, "SNAPSHOT {}", snapshot.len())
+        .and_then(|_| writer.write_all(&snapshot))
+        .and_then(|_| writer.flush())
+        .map_err(write_err)?;
+    loop {
+        match records.recv_timeout(heartbeat) {
+            Ok(lines) => writer.write_all(lines.as_bytes()),
+            Err(RecvTimeoutError::Timeout) => writeln!(writer, "{} HEARTBEAT", now_millis()),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(String::from("The primary's journal was detached"))
+            }
+        }
+        .and_then(|_| writer.flush())
+        .map_err(write_err)?;
+    }
+}
+
+// Whether a replica can serve a command, shared between the Replica and the
+// connections of its server
+#[derive(Debug)]
+pub struct ReplicaStatus {
+    primary: String,
+    max_staleness: Duration,
+    // unix time in ms the primary was last heard from, 0 before the first sync
+    last_contact: AtomicU64,
+}
+
+impl ReplicaStatus {
+    pub fn new(primary: &str, max_staleness: Duration) -> ReplicaStatus {
+        ReplicaStatus {
+            primary: primary.to_string(),
+            max_staleness,
+            last_contact: AtomicU64::new(0),
+        }
+    }
+
+    // How long ago the primary was last heard from, None before the first sync
+    pub fn staleness(&self) -> Option<Duration> {
+        match self.last_contact.load(Ordering::Relaxed) {
+            0 => None,
+            last_contact => Some(Duration::from_millis(
+                now_millis().saturating_sub(last_contact),
+            )),
+        }
+    }
+
+    // Ok if the replica can serve commands of type cmd
+    pub fn check(&self, cmd: CommandType) -> Result<(), String> {
+        if !cmd.is_read_only() {
+            return Err(format!(
+                "Read-only replica, send {:?} to the primary {}",
+                cmd, self.primary
+            ));
+        }
+        match self.staleness() {
+            None => Err(format!(
+                "Replica hasn't synced with its primary {} yet",
+                self.primary
+            )),
+            Some(staleness) if staleness > self.max_staleness => Err(format!(
+                "Replica last heard from its primary {} ms ago, more than its max staleness of {} ms",
+                staleness.as_millis(),
+                self.max_staleness.as_millis()
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn touch(&self) {
+        self.last_contact.store(now_millis(), Ordering::Relaxed);
+    }
+}
+
+// Keeps the graph of a worker in sync with a primary
+pub struct Replica {
+    worker: GraphWorker,
+    status: Arc<ReplicaStatus>,
+    token: Option<String>,
+}
+
+impl Replica {
+    // A replica of the ReplicationServer at primary, whose reads may be up to
+    // max_staleness old
+    pub fn new(primary: &str, worker: GraphWorker, max_staleness: Duration) -> Replica {
+        Replica {
+            worker,
+            status: Arc::new(ReplicaStatus::new(primary, max_staleness)),
+            token: None,
+        }
+    }
+
+    // Authenticate with token when connecting, for a primary with tokens
+    pub fn set_token(&mut self, token: &str) {
+        self.token = Some(token.to_string());
+    }
+
+    pub fn status(&self) -> Arc<ReplicaStatus> {
+        self.status.clone()
+    }
+
+    // Replace the graph with the primary's and apply its records until the
+    // connection fails
+    pub fn sync(&self) -> Result<(), String> {
+        let read_err = |e: std::io::Error| format!("Couldn't read from primary: {}", e);
+        let mut stream = TcpStream::connect(&self.status.primary)
+            .map_err(|e| format!("Couldn't connect to {}: {}", self.status.primary, e))?;
+        if let Some(token) = &self.token {
+            writeln!(stream, "AUTH {}", token)
+                .map_err(|e| format!("Couldn't write to primary: {}", e))?;
+        }
+        let mut reader = BufReader::new(stream);
+
+        let header = read_line(&mut reader)?;
+        let len: usize = match header.strip_prefix("SNAPSHOT ") {
+            Some(len) => len
+                .parse()
+                .map_err(|_| format!("Invalid snapshot header {}", header))?,
+            None => return Err(header.strip_prefix('-').unwrap_or(&header).to_string()),
+        };
+        let mut snapshot = vec![0; len];
+        reader.read_exact(&mut snapshot).map_err(read_err)?;
+        let primary = Graph::load_snapshot(&snapshot[..])?;
+        self.worker.with_graph(|g| g.replace_state(primary))?;
+        self.status.touch();
+        info!("Synced with primary {}", self.status.primary);
+
+        loop {
+            let line = read_line(&mut reader)?;
+            let record = line.split_once(' ').map_or("", |(_, record)| record);
+            if record == "HEARTBEAT" {
+                self.status.touch();
+                continue;
+            }
+            if record == "CLEAR UNDO" {
+                self.worker.with_graph(|g| g.clear_undo())?;
+                self.status.touch();
+                continue;
+            }
+            let mut lines = vec![line.clone()];
+            if let Some(count) = record.strip_prefix("BATCH ") {
+                let count: usize = count
+                    .parse()
+                    .map_err(|_| format!("Invalid journal record {}", line))?;
+                for _ in 0..count {
+                    lines.push(read_line(&mut reader)?);
+                }
+            }
+            self.worker
+                .with_graph(move |g| g.apply_journal_lines(&lines))??;
+            self.status.touch();
+        }
+    }
+
+    // Sync with the primary, reconnecting whenever the connection fails
+    pub fn run(self) {
+        loop {
+            if let Err(msg) = self.sync() {
+                warn!("Replication from {} failed: {}", self.status.primary, msg);
+            }
+            thread::sleep(RECONNECT_DELAY);
+        }
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => Err(String::from("The primary closed the connection")),
+        Ok(_) if !line.ends_with('\n') => Err(String::from("The primary closed the connection")),
+        Ok(_) => {
+            line.truncate(line.trim_end().len());
+            Ok(line)
+        }
+        Err(e) => Err(format!("Couldn't read from primary: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{Command, CommandResponse};
+    use crate::journal::Journal;
+    use std::fs;
+    use std::time::Instant;
+
+    #[test]
+    fn test_replication() {
+        let path =
+            std::env::temp_dir().join(format!("confidis-replication-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let journal_path = path.clone();
+        let primary = GraphWorker::spawn(move || {
+            let mut g = Graph::new();
+            g.set_journal(Journal::open(journal_path).unwrap());
+            g
+        });
+        let run = |worker: &GraphWorker, line: &str| {
+            worker.execute(Command::from(line).unwrap().into_owned())
+        };
+        run(&primary, "SET q1 a FROM s1").unwrap();
+        run(&primary, "SET q1 a FROM s2").unwrap();
+
+        let mut server = ReplicationServer::bind("127.0.0.1:0", primary.clone()).unwrap();
+        server.set_heartbeat(Duration::from_millis(20));
+        let addr = server.local_addr().unwrap().to_string();
+        thread::spawn(move || server.run());
+
+        let replica_worker = GraphWorker::spawn(Graph::new);
+        let replica = Replica::new(&addr, replica_worker.clone(), Duration::from_secs(5));
+        let status = replica.status();
+        assert_eq!(
+            status.check(CommandType::GetAnswer),
+            Err(format!(
+                "Replica hasn't synced with its primary {} yet",
+                addr
+            ))
+        );
+        thread::spawn(move || replica.run());
+
+        // mutations after the snapshot are streamed, batches included
+        run(&primary, "SET q2 b FROM s1").unwrap();
+        primary
+            .with_graph(|g| g.set_many(&[("q2", "b", "s3"), ("q3", "c", "s2")]))
+            .unwrap()
+            .unwrap();
+        primary
+            .with_graph(|g| g.compact_journal())
+            .unwrap()
+            .unwrap();
+        run(&primary, "BELIEVE s3").unwrap();
+
+        let answers = |worker: &GraphWorker| -> Vec<String> {
+            [
+                "GET ANSWER TO q1",
+                "GET ANSWER TO q2",
+                "GET ANSWER TO q3",
+                "GET SOURCE s3",
+            ]
+            .iter()
+            .map(|line| format!("{}", run(worker, line).unwrap_or(CommandResponse::Set)))
+            .collect()
+        };
+        let expected = answers(&primary);
+        let start = Instant::now();
+        while answers(&replica_worker) != expected {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "replica didn't catch up"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // heartbeats keep an idle replica within its max staleness
+        let last_contact = status.last_contact.load(Ordering::Relaxed);
+        while status.last_contact.load(Ordering::Relaxed) == last_contact {
+            assert!(start.elapsed() < Duration::from_secs(10), "no heartbeat");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(status.check(CommandType::GetAnswer), Ok(()));
+        assert_eq!(
+            status.check(CommandType::Set),
+            Err(format!(
+                "Read-only replica, send Set to the primary {}",
+                addr
+            ))
+        );
+
+        let stale = ReplicaStatus::new("primary:7371", Duration::from_millis(500));
+        stale
+            .last_contact
+            .store(now_millis() - 2000, Ordering::Relaxed);
+        assert!(stale
+            .check(CommandType::GetAnswer)
+            .unwrap_err()
+            .starts_with("Replica last heard from its primary"));
+
+        drop(primary);
+        for entry in Journal::read(&path).unwrap() {
+            if let crate::journal::JournalRecord::Snapshot(file) = entry.record {
+                let _ = fs::remove_file(path.parent().unwrap().join(file));
+            }
+        }
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_replication_undo() {
+        let path = std::env::temp_dir().join(format!(
+            "confidis-replication-undo-{}.log",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let journal_path = path.clone();
+        let primary = GraphWorker::spawn(move || {
+            let mut g = Graph::new();
+            g.set_journal(Journal::open(journal_path).unwrap());
+            g
+        });
+        let run = |worker: &GraphWorker, line: &str| {
+            worker
+                .execute(Command::from(line).unwrap().into_owned())
+                .map(|response| format!("{}", response))
+        };
+        for line in &[
+            "CONFIGURE undo_depth 10",
+            "SET q1 a FROM s1",
+            "SET q1 b FROM s2",
+        ] {
+            run(&primary, line).unwrap();
+        }
+
+        let server = ReplicationServer::bind("127.0.0.1:0", primary.clone()).unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        thread::spawn(move || server.run());
+        let replica_worker = GraphWorker::spawn(Graph::new);
+        let replica = Replica::new(&addr, replica_worker.clone(), Duration::from_secs(5));
+        thread::spawn(move || replica.run());
+
+        let answers = |worker: &GraphWorker| -> Vec<String> {
+            [
+                "GET ANSWERS TO q1",
+                "GET ANSWERS TO q2",
+                "GET ANSWERS TO q3",
+            ]
+            .iter()
+            .map(|line| run(worker, line).unwrap_or_else(|e| e.to_string()))
+            .collect()
+        };
+        let start = Instant::now();
+        let wait_for_replica = || {
+            while answers(&replica_worker) != answers(&primary) {
+                assert!(
+                    start.elapsed() < Duration::from_secs(10),
+                    "replica diverged, {:?} != {:?}",
+                    answers(&replica_worker),
+                    answers(&primary)
+                );
+                thread::sleep(Duration::from_millis(10));
+            }
+        };
+        wait_for_replica();
+
+        // the snapshot the replica started from can't be undone on either
+        run(&primary, "UNDO 1").unwrap();
+        run(&primary, "SET q2 c FROM s1").unwrap();
+        wait_for_replica();
+        assert!(answers(&primary)[0].contains('b'));
+
+        // nor can what compaction covers
+        run(&primary, "SET q2 c FROM s2").unwrap();
+        primary
+            .with_graph(|g| g.compact_journal())
+            .unwrap()
+            .unwrap();
+        run(&primary, "SET q3 d FROM s1").unwrap();
+        run(&primary, "UNDO 3").unwrap();
+        run(&primary, "SET q3 e FROM s2").unwrap();
+        wait_for_replica();
+        assert!(answers(&primary)[1].contains('c'));
+
+        drop(primary);
+        for entry in Journal::read(&path).unwrap() {
+            if let crate::journal::JournalRecord::Snapshot(file) = entry.record {
+                let _ = fs::remove_file(path.parent().unwrap().join(file));
+            }
+        }
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_replication_auth() {
+        let path = std::env::temp_dir().join(format!(
+            "confidis-replication-auth-{}.log",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let journal_path = path.clone();
+        let primary = GraphWorker::spawn(move || {
+            let mut g = Graph::new();
+            g.set_journal(Journal::open(journal_path).unwrap());
+            g
+        });
+        let mut server = ReplicationServer::bind("127.0.0.1:0", primary.clone()).unwrap();
+        server.set_tokens(Tokens::parse("w1 write\na1 admin\n").unwrap());
+        let addr = server.local_addr().unwrap().to_string();
+        thread::spawn(move || server.run());
+
+        let mut replica = Replica::new(
+            &addr,
+            GraphWorker::spawn(Graph::new),
+            Duration::from_secs(5),
+        );
+        replica.set_token("x");
+        assert_eq!(replica.sync(), Err(String::from("Invalid token")));
+        replica.set_token("w1");
+        assert_eq!(
+            replica.sync(),
+            Err(String::from(
+                "Permission denied, replication needs the admin role, not write"
+            ))
+        );
+
+        // sync only returns once the stream breaks, so talk to the server by hand
+        let header = |line: &str| {
+            let mut stream = TcpStream::connect(&addr).unwrap();
+            writeln!(stream, "{}", line).unwrap();
+            let mut header = String::new();
+            BufReader::new(stream).read_line(&mut header).unwrap();
+            header
+        };
+        assert!(header("SYNC").starts_with("-Authentication required"));
+        assert!(header("AUTH a1").starts_with("SNAPSHOT "));
+
+        drop(primary);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,237 @@
+// RESP (Redis protocol) support for the TCP server
+//
+// A line starting with "*" is a RESP array of bulk strings, which is how redis
+// clients send commands. The arguments are mapped onto confidis commands:
+//   SET <question> <answer> [FROM <source>]   SET, replies +OK
+//   GET <question>                            GET ANSWER TO, replies the answer
+//   CONFIG SET <key> <value...>               CONFIGURE, replies +OK
+//   CLIENT SETNAME <source>                   the source of SETs without FROM
+//   AUTH [<username>] <token>                 authenticate, see auth.rs
+//   PING, ECHO, SELECT, COMMAND, QUIT         what redis clients expect
+// Anything else is joined with spaces, quoting arguments that need it, and
+// parsed with the text grammar, e.g. "BELIEVE s1" or "GET SOURCE s1", and
+// replied with the response text as a bulk string. Errors are replied as
+// "-ERR <message>". Since arguments arrive already split, answers may contain
+// spaces without quoting them, names can't contain any, see id.rs.
+//
+// Like redis, arrays of more than MAX_ARRAY_COUNT arguments and arguments
+// longer than MAX_BULK_LEN are a protocol error, which closes the connection.
+
+use crate::auth::AuthSession;
+use crate::command::{quote, Command, CommandResponse, CommandType};
+use crate::server::{read_line_limited, MAX_LINE_LEN};
+use crate::worker::GraphWorker;
+use std::io::{self, BufRead, ErrorKind, Read};
+
+pub(crate) const MAX_ARRAY_COUNT: usize = 1024 * 1024;
+pub(crate) const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+// Read the rest of a RESP array whose "*<count>" header line was already read.
+// Malformed or oversized arrays are an InvalidData error.
+pub fn read_array<R: BufRead>(reader: &mut R, header: &str) -> io::Result<Vec<String>> {
+    let count: usize = header[1..]
+        .trim()
+        .parse()
+        .map_err(|_| invalid_data(format!("Invalid RESP array header {}", header)))?;
+    if count > MAX_ARRAY_COUNT {
+        return Err(invalid_data(String::from(
+            "Protocol error: invalid multibulk length",
+        )));
+    }
+    // the count is only trusted as far as the arguments actually arrive
+    let mut args = Vec::with_capacity(count.min(64));
+    for _ in 0..count {
+        let mut line = String::new();
+        read_line_limited(reader, &mut line, MAX_LINE_LEN)?;
+        let len: usize = line
+            .trim_end()
+            .strip_prefix('$')
+            .and_then(|len| len.parse().ok())
+            .ok_or_else(|| invalid_data(format!("Expected a RESP bulk string, got {}", line)))?;
+        if len > MAX_BULK_LEN {
+            return Err(invalid_data(String::from(
+                "Protocol error: invalid bulk length",
+            )));
+        }
+        // the string is followed by \r\n
+        let mut data = Vec::new();
+        reader.take(len as u64 + 2).read_to_end(&mut data)?;
+        if data.len() < len + 2 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        data.truncate(len);
+        args.push(String::from_utf8(data).map_err(|e| invalid_data(e.to_string()))?);
+    }
+    Ok(args)
+}
+
+// The arguments as a line of the text grammar
+fn text_line(args: &[String]) -> String {
+    let items: Vec<_> = args.iter().map(|arg| quote(arg)).collect();
+    items.join(" ")
+}
+
+fn simple(s: &str) -> String {
+    format!("+{}\r\n", s)
+}
+
+pub(crate) fn error(msg: &str) -> String {
+    format!("-ERR {}\r\n", msg.replace(['\r', '\n'], " "))
+}
+
+fn bulk(s: Option<&str>) -> String {
+    match s {
+        Some(s) => format!("${}\r\n{}\r\n", s.len(), s),
+        None => String::from("$-1\r\n"),
+    }
+}
+
+// Per connection state of a RESP client
+#[derive(Default)]
+pub struct RespSession {
+    client_name: Option<String>,
+}
+
+impl RespSession {
+    // The encoded reply to args, and whether to close the connection after it
+    pub fn execute(
+        &mut self,
+        args: Vec<String>,
+        worker: &GraphWorker,
+        auth: &mut AuthSession,
+    ) -> (String, bool) {
+        if args.is_empty() {
+            return (error("Empty command"), false);
+        }
+        let name = args[0].to_ascii_uppercase();
+        let sub = args.get(1).map(|sub| sub.to_ascii_uppercase());
+        let reply = match (name.as_str(), sub.as_deref(), args.len()) {
+            ("QUIT", _, _) => return (simple("OK"), true),
+            ("PING", _, 1) => simple("PONG"),
+            ("PING", _, 2) | ("ECHO", _, 2) => bulk(Some(&args[1])),
+            ("SELECT", _, _) => simple("OK"),
+            ("COMMAND", _, _) => String::from("*0\r\n"),
+            ("CLIENT", Some("SETNAME"), 3) => {
+                self.client_name = Some(args[2].clone());
+                simple("OK")
+            }
+            ("CLIENT", Some("GETNAME"), 2) => bulk(self.client_name.as_deref()),
+            // redis 6 clients send a username too, tokens don't have one
+            ("AUTH", _, 2) | ("AUTH", _, 3) => match auth.authenticate(&args[args.len() - 1]) {
+                Ok(_) => simple("OK"),
+                Err(msg) => error(&msg),
+            },
+            ("SET", _, 3) | ("SET", _, 5) => match auth.authorize(CommandType::Set) {
+                Ok(()) => self.set(&args, worker),
+                Err(msg) => error(&msg),
+            },
+            ("GET", _, 2) => {
+                if let Err(msg) = auth.authorize(CommandType::GetAnswer) {
+                    return (error(&msg), false);
+                }
+                let cmd = Command {
+                    cmd: CommandType::GetAnswer,
+                    question: Some(args[1].clone().into()),
+                    ..Default::default()
+                };
+                match worker.execute(cmd) {
+                    Ok(CommandResponse::Answer { content, .. }) => bulk(Some(&content)),
+                    Ok(_) => bulk(None),
+                    Err(msg) => error(&msg),
+                }
+            }
+            ("CONFIG", Some("SET"), n) if n >= 4 => {
+                if let Err(msg) = auth.authorize(CommandType::Configure) {
+                    return (error(&msg), false);
+                }
+                let cmd = Command {
+                    cmd: CommandType::Configure,
+                    config_key: Some(args[2].clone().into()),
+                    config_val: Some(args[3..].join(" ").into()),
+                    ..Default::default()
+                };
+                match worker.execute(cmd) {
+                    Ok(_) => simple("OK"),
+                    Err(msg) => error(&msg),
+                }
+            }
+            _ => match Command::from(&text_line(&args)) {
+                Ok(cmd) => match auth
+                    .authorize(cmd.cmd)
+                    .and_then(|_| worker.execute(cmd.into_owned()))
+                {
+                    Ok(
+                        CommandResponse::Set
+                        | CommandResponse::Believe
+                        | CommandResponse::Configure { .. },
+                    ) => simple("OK"),
+                    Ok(response) => bulk(Some(&format!("{}", response))),
+                    Err(msg) => error(&msg),
+                },
+                Err(msg) => error(&format!("Invalid command: {}", msg)),
+            },
+        };
+        (reply, false)
+    }
+
+    fn set(&self, args: &[String], worker: &GraphWorker) -> String {
+        let source = if args.len() == 5 {
+            if !args[3].eq_ignore_ascii_case("FROM") {
+                return error("Syntax is SET <question> <answer> [FROM <source>]");
+            }
+            args[4].clone()
+        } else {
+            match self.client_name.as_ref() {
+                Some(name) => name.clone(),
+                None => return error("SET needs FROM <source> or a CLIENT SETNAME"),
+            }
+        };
+        let cmd = Command {
+            cmd: CommandType::Set,
+            question: Some(args[1].clone().into()),
+            answer: Some(args[2].clone().into()),
+            source: Some(source.into()),
+            ..Default::default()
+        };
+        match worker.execute(cmd) {
+            Ok(_) => simple("OK"),
+            Err(msg) => error(&msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_array() {
+        let read = |input: &str| {
+            let mut reader = input.as_bytes();
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            read_array(&mut reader, header.trim())
+        };
+        assert_eq!(
+            read("*2\r\n$3\r\nGET\r\n$5\r\na b c\r\n").unwrap(),
+            vec!["GET", "a b c"]
+        );
+        let error = |input: &str| read(input).unwrap_err().to_string();
+        assert_eq!(
+            error(&format!("*{}\r\n", MAX_ARRAY_COUNT + 1)),
+            "Protocol error: invalid multibulk length"
+        );
+        assert_eq!(
+            error(&format!("*1\r\n${}\r\n", MAX_BULK_LEN + 1)),
+            "Protocol error: invalid bulk length"
+        );
+        // a huge count or length that's within the limits allocates as the
+        // data arrives
+        assert!(read(&format!("*{}\r\n$1\r\na\r\n", MAX_ARRAY_COUNT)).is_err());
+        assert!(read(&format!("*1\r\n${}\r\nabc", MAX_BULK_LEN)).is_err());
+    }
+}
@@ -0,0 +1,567 @@
+// Per-question answer schemas
+//
+// A question can have a schema its answers must follow. A SET whose answer
+// (after normalization) violates it is rejected with a SchemaViolation error
+// and never stored, so malformed answers don't end up in clusters of their
+// own or drag down their sources:
+//
+//   SCHEMA q1 int min=0 max=120
+//
+// The schemas:
+//
+//   int [min=<n>] [max=<n>]    a whole number, within the bounds
+//   float [min=<x>] [max=<x>]  a finite number, within the bounds
+//   enum <value> [<value> ...] one of the values
+//   regex <pattern>            matching the whole pattern
+//   json <schema>              valid against a JSON Schema
+//
+// "none" removes the schema. Answers stored before the schema was changed are
+// kept as they were.
+//
+// JSON schemas check answers parsed as JSON, answers that aren't JSON are
+// strings, so "42" is a number and "Paris" a string. The supported keywords
+// are type, enum, const, minimum, maximum, exclusiveMinimum,
+// exclusiveMaximum, minLength, maxLength, pattern, items, minItems, maxItems,
+// properties, required and additionalProperties; annotations like title and
+// description are ignored, any other keyword is rejected rather than silently
+// not checked. SCHEMA takes the rest of its line as written, so a regex or
+// JSON schema can contain spaces, and enum values with spaces are quoted:
+//
+//   SCHEMA city enum "New York" Boston
+//   SCHEMA q2 json {"type": "string", "maxLength": 20}
+
+use crate::command::{quote, split_items};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum AnswerSchema {
+    Int { min: Option<i64>, max: Option<i64> },
+    Float { min: Option<f64>, max: Option<f64> },
+    Enum(Vec<String>),
+    // the pattern as given, anchored to match whole answers
+    Regex { pattern: String, regex: Regex },
+    Json { schema: Value, compiled: JsonSchema },
+}
+
+impl PartialEq for AnswerSchema {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_string() == other.to_string()
+    }
+}
+
+impl AnswerSchema {
+    // Ok if the answer follows the schema, otherwise why it doesn't
+    pub fn validate(&self, answer: &str) -> Result<(), String> {
+        match self {
+            AnswerSchema::Int { min, max } => {
+                let value: i64 = answer
+                    .parse()
+                    .map_err(|_| String::from("must be an integer"))?;
+                check_bounds(value, *min, *max)
+            }
+            AnswerSchema::Float { min, max } => match answer.parse::<f64>() {
+                Ok(value) if value.is_finite() => check_bounds(value, *min, *max),
+                _ => Err(String::from("must be a number")),
+            },
+            AnswerSchema::Enum(values) => {
+                if values.iter().any(|value| value == answer) {
+                    Ok(())
+                } else {
+                    Err(format!("must be one of {}", values.join(", ")))
+                }
+            }
+            AnswerSchema::Regex { pattern, regex } => {
+                if regex.is_match(answer) {
+                    Ok(())
+                } else {
+                    Err(format!("must match {}", pattern))
+                }
+            }
+            AnswerSchema::Json { compiled, .. } => {
+                let value = serde_json::from_str(answer)
+                    .unwrap_or_else(|_| Value::String(answer.to_string()));
+                compiled.validate(&value, "")
+            }
+        }
+    }
+}
+
+fn check_bounds<T: PartialOrd + fmt::Display>(
+    value: T,
+    min: Option<T>,
+    max: Option<T>,
+) -> Result<(), String> {
+    match (min, max) {
+        (Some(min), _) if value < min => Err(format!("must be at least {}", min)),
+        (_, Some(max)) if value > max => Err(format!("must be at most {}", max)),
+        _ => Ok(()),
+    }
+}
+
+impl fmt::Display for AnswerSchema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn bounds<T: fmt::Display>(min: &Option<T>, max: &Option<T>) -> String {
+            let mut text = String::new();
+            if let Some(min) = min {
+                text += &format!(" min={}", min);
+            }
+            if let Some(max) = max {
+                text += &format!(" max={}", max);
+            }
+            text
+        }
+        match self {
+            AnswerSchema::Int { min, max } => write!(f, "int{}", bounds(min, max)),
+            AnswerSchema::Float { min, max } => write!(f, "float{}", bounds(min, max)),
+            AnswerSchema::Enum(values) => {
+                let values: Vec<_> = values.iter().map(|value| quote(value)).collect();
+                write!(f, "enum {}", values.join(" "))
+            }
+            AnswerSchema::Regex { pattern, .. } => write!(f, "regex {}", pattern),
+            AnswerSchema::Json { schema, .. } => write!(f, "json {}", schema),
+        }
+    }
+}
+
+impl FromStr for AnswerSchema {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (kind, rest) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+        let rest = rest.trim();
+        match kind {
+            "int" => {
+                let (min, max) = parse_bounds(rest)?;
+                Ok(AnswerSchema::Int { min, max })
+            }
+            "float" => {
+                let (min, max) = parse_bounds::<f64>(rest)?;
+                if min.into_iter().chain(max).any(|bound| !bound.is_finite()) {
+                    return Err(String::from("float bounds must be finite"));
+                }
+                Ok(AnswerSchema::Float { min, max })
+            }
+            "enum" if !rest.is_empty() => Ok(AnswerSchema::Enum(
+                split_items(rest)
+                    .map_err(|e| e.to_string())?
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+            )),
+            "regex" if !rest.is_empty() => Ok(AnswerSchema::Regex {
+                pattern: rest.to_string(),
+                regex: Regex::new(&format!("^(?:{})$", rest))
+                    .map_err(|e| format!("invalid regex \"{}\": {}", rest, e))?,
+            }),
+            "json" if !rest.is_empty() => {
+                let schema: Value = serde_json::from_str(rest)
+                    .map_err(|e| format!("invalid JSON schema: {}", e))?;
+                Ok(AnswerSchema::Json {
+                    compiled: JsonSchema::compile(&schema)?,
+                    schema,
+                })
+            }
+            "enum" | "regex" | "json" => Err(format!("{} needs a value", kind)),
+            _ => Err(format!(
+                "unknown schema \"{}\". Try int, float, enum <value> ..., regex <pattern>, json <schema> or none",
+                kind
+            )),
+        }
+    }
+}
+
+// The min=<n> and max=<n> parameters of int and float
+fn parse_bounds<T: FromStr + PartialOrd>(params: &str) -> Result<(Option<T>, Option<T>), String> {
+    let (mut min, mut max) = (None, None);
+    for param in params.split_whitespace() {
+        let (name, value) = param.split_once('=').unwrap_or((param, ""));
+        let bound = match name {
+            "min" => &mut min,
+            "max" => &mut max,
+            _ => {
+                return Err(format!(
+                    "unknown parameter \"{}\", expected min or max",
+                    name
+                ))
+            }
+        };
+        *bound = Some(
+            value
+                .parse()
+                .map_err(|_| format!("invalid {} \"{}\"", name, value))?,
+        );
+    }
+    if let (Some(min), Some(max)) = (&min, &max) {
+        if min > max {
+            return Err(String::from("min must be at most max"));
+        }
+    }
+    Ok((min, max))
+}
+
+impl From<AnswerSchema> for String {
+    fn from(schema: AnswerSchema) -> String {
+        schema.to_string()
+    }
+}
+
+impl TryFrom<String> for AnswerSchema {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+// A JSON Schema compiled into the checks of its keywords
+#[derive(Debug, Clone)]
+pub struct JsonSchema(Vec<Keyword>);
+
+#[derive(Debug, Clone)]
+enum Keyword {
+    // the false schema, nothing is valid
+    Never,
+    Type(Vec<String>),
+    Enum(Vec<Value>),
+    Minimum(f64),
+    Maximum(f64),
+    ExclusiveMinimum(f64),
+    ExclusiveMaximum(f64),
+    MinLength(usize),
+    MaxLength(usize),
+    Pattern(Regex),
+    Items(JsonSchema),
+    MinItems(usize),
+    MaxItems(usize),
+    Properties(Vec<(String, JsonSchema)>),
+    Required(Vec<String>),
+    // the schema of properties not listed in properties
+    AdditionalProperties(Vec<String>, JsonSchema),
+}
+
+const JSON_TYPES: [&str; 7] = [
+    "string", "number", "integer", "boolean", "null", "array", "object",
+];
+
+// Keywords that describe rather than constrain
+const ANNOTATIONS: [&str; 7] = [
+    "$schema",
+    "$id",
+    "$comment",
+    "title",
+    "description",
+    "default",
+    "examples",
+];
+
+impl JsonSchema {
+    fn compile(schema: &Value) -> Result<JsonSchema, String> {
+        let object = match schema {
+            Value::Bool(true) => return Ok(JsonSchema(Vec::new())),
+            Value::Bool(false) => return Ok(JsonSchema(vec![Keyword::Never])),
+            Value::Object(object) => object,
+            _ => return Err(String::from("a JSON schema must be an object or a boolean")),
+        };
+        let mut keywords = Vec::new();
+        for (name, value) in object {
+            let invalid = || format!("invalid \"{}\" in JSON schema", name);
+            let number = || value.as_f64().ok_or_else(invalid);
+            let count = || value.as_u64().map(|n| n as usize).ok_or_else(invalid);
+            keywords.push(match name.as_str() {
+                "type" => {
+                    let types = match value {
+                        Value::String(name) => vec![name.clone()],
+                        Value::Array(names) => names
+                            .iter()
+                            .map(|name| name.as_str().map(String::from).ok_or_else(invalid))
+                            .collect::<Result<_, _>>()?,
+                        _ => return Err(invalid()),
+                    };
+                    if let Some(unknown) = types.iter().find(|t| !JSON_TYPES.contains(&t.as_str()))
+                    {
+                        return Err(format!("unknown type \"{}\" in JSON schema", unknown));
+                    }
+                    Keyword::Type(types)
+                }
+                "enum" => Keyword::Enum(value.as_array().ok_or_else(invalid)?.clone()),
+                "const" => Keyword::Enum(vec![value.clone()]),
+                "minimum" => Keyword::Minimum(number()?),
+                "maximum" => Keyword::Maximum(number()?),
+                "exclusiveMinimum" => Keyword::ExclusiveMinimum(number()?),
+                "exclusiveMaximum" => Keyword::ExclusiveMaximum(number()?),
+                "minLength" => Keyword::MinLength(count()?),
+                "maxLength" => Keyword::MaxLength(count()?),
+                "pattern" => {
+                    let pattern = value.as_str().ok_or_else(invalid)?;
+                    Keyword::Pattern(
+                        Regex::new(pattern)
+                            .map_err(|e| format!("invalid regex \"{}\": {}", pattern, e))?,
+                    )
+                }
+                "items" => Keyword::Items(JsonSchema::compile(value)?),
+                "minItems" => Keyword::MinItems(count()?),
+                "maxItems" => Keyword::MaxItems(count()?),
+                "properties" => Keyword::Properties(
+                    value
+                        .as_object()
+                        .ok_or_else(invalid)?
+                        .iter()
+                        .map(|(name, schema)| Ok((name.clone(), JsonSchema::compile(schema)?)))
+                        .collect::<Result<_, String>>()?,
+                ),
+                "required" => Keyword::Required(
+                    value
+                        .as_array()
+                        .ok_or_else(invalid)?
+                        .iter()
+                        .map(|name| name.as_str().map(String::from).ok_or_else(invalid))
+                        .collect::<Result<_, _>>()?,
+                ),
+                "additionalProperties" => {
+                    let known = object
+                        .get("properties")
+                        .and_then(Value::as_object)
+                        .map(|properties| properties.keys().cloned().collect())
+                        .unwrap_or_default();
+                    Keyword::AdditionalProperties(known, JsonSchema::compile(value)?)
+                }
+                _ if ANNOTATIONS.contains(&name.as_str()) => continue,
+                _ => return Err(format!("unsupported keyword \"{}\" in JSON schema", name)),
+            });
+        }
+        Ok(JsonSchema(keywords))
+    }
+
+    // path is the JSON pointer of value within the answer, "" for the answer
+    fn validate(&self, value: &Value, path: &str) -> Result<(), String> {
+        let fail = |reason: String| {
+            if path.is_empty() {
+                Err(reason)
+            } else {
+                Err(format!("{} {}", path, reason))
+            }
+        };
+        for keyword in &self.0 {
+            match keyword {
+                Keyword::Never => return fail(String::from("isn't allowed")),
+                Keyword::Type(types) => {
+                    if !types.iter().any(|name| is_type(value, name)) {
+                        return fail(format!("must be of type {}", types.join(" or ")));
+                    }
+                }
+                Keyword::Enum(values) => {
+                    if !values.iter().any(|allowed| json_eq(allowed, value)) {
+                        let values: Vec<String> = values.iter().map(Value::to_string).collect();
+                        return fail(format!("must be one of {}", values.join(", ")));
+                    }
+                }
+                Keyword::Minimum(min) => match value.as_f64() {
+                    Some(n) if n < *min => return fail(format!("must be at least {}", min)),
+                    _ => {}
+                },
+                Keyword::Maximum(max) => match value.as_f64() {
+                    Some(n) if n > *max => return fail(format!("must be at most {}", max)),
+                    _ => {}
+                },
+                Keyword::ExclusiveMinimum(min) => match value.as_f64() {
+                    Some(n) if n <= *min => return fail(format!("must be more than {}", min)),
+                    _ => {}
+                },
+                Keyword::ExclusiveMaximum(max) => match value.as_f64() {
+                    Some(n) if n >= *max => return fail(format!("must be less than {}", max)),
+                    _ => {}
+                },
+                Keyword::MinLength(min) => match value.as_str() {
+                    Some(s) if s.chars().count() < *min => {
+                        return fail(format!("must be at least {} characters long", min))
+                    }
+                    _ => {}
+                },
+                Keyword::MaxLength(max) => match value.as_str() {
+                    Some(s) if s.chars().count() > *max => {
+                        return fail(format!("must be at most {} characters long", max))
+                    }
+                    _ => {}
+                },
+                Keyword::Pattern(regex) => match value.as_str() {
+                    Some(s) if !regex.is_match(s) => {
+                        return fail(format!("must match {}", regex.as_str()))
+                    }
+                    _ => {}
+                },
+                Keyword::Items(schema) => {
+                    for (i, item) in value.as_array().into_iter().flatten().enumerate() {
+                        schema.validate(item, &format!("{}/{}", path, i))?;
+                    }
+                }
+                Keyword::MinItems(min) => match value.as_array() {
+                    Some(items) if items.len() < *min => {
+                        return fail(format!("must have at least {} items", min))
+                    }
+                    _ => {}
+                },
+                Keyword::MaxItems(max) => match value.as_array() {
+                    Some(items) if items.len() > *max => {
+                        return fail(format!("must have at most {} items", max))
+                    }
+                    _ => {}
+                },
+                Keyword::Properties(properties) => {
+                    if let Some(object) = value.as_object() {
+                        for (name, schema) in properties {
+                            if let Some(property) = object.get(name) {
+                                schema.validate(property, &format!("{}/{}", path, name))?;
+                            }
+                        }
+                    }
+                }
+                Keyword::Required(names) => {
+                    if let Some(object) = value.as_object() {
+                        if let Some(missing) = names.iter().find(|name| !object.contains_key(*name))
+                        {
+                            return fail(format!("must have property \"{}\"", missing));
+                        }
+                    }
+                }
+                Keyword::AdditionalProperties(known, schema) => {
+                    let additional = value
+                        .as_object()
+                        .into_iter()
+                        .flat_map(Map::iter)
+                        .filter(|(name, _)| !known.contains(name));
+                    for (name, property) in additional {
+                        schema.validate(property, &format!("{}/{}", path, name))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_type(value: &Value, name: &str) -> bool {
+    match name {
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => false,
+    }
+}
+
+// Equality as JSON Schema defines it, 1 and 1.0 are the same number
+fn json_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| json_eq(a, b))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(name, a)| b.get(name).is_some_and(|b| json_eq(a, b)))
+        }
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answer_schema() {
+        let schema: AnswerSchema = "int min=0 max=120".parse().unwrap();
+        assert_eq!(schema.validate("42"), Ok(()));
+        assert_eq!(schema.validate("4.2"), Err("must be an integer".into()));
+        assert_eq!(schema.validate("121"), Err("must be at most 120".into()));
+
+        let schema: AnswerSchema = "float min=-1.5".parse().unwrap();
+        assert_eq!(schema.validate("1e3"), Ok(()));
+        assert!(schema.validate("NaN").is_err());
+        assert_eq!(schema.validate("-2"), Err("must be at least -1.5".into()));
+
+        let schema: AnswerSchema = "enum red green blue".parse().unwrap();
+        assert_eq!(schema.validate("green"), Ok(()));
+        assert!(schema.validate("Green").is_err());
+        let schema: AnswerSchema = r#"enum "New York" Boston"#.parse().unwrap();
+        assert_eq!(schema.validate("New York"), Ok(()));
+        assert!(schema.validate("New").is_err());
+
+        // the whole answer has to match
+        let schema: AnswerSchema = r"regex [A-Z]{2}\d{3}".parse().unwrap();
+        assert_eq!(schema.validate("AB123"), Ok(()));
+        assert!(schema.validate("AB1234").is_err());
+
+        let schema: AnswerSchema = r#"json {"type":"object","required":["lat","lon"],"properties":{"lat":{"type":"number","minimum":-90,"maximum":90},"lon":{"type":"number"}},"additionalProperties":false}"#
+            .parse()
+            .unwrap();
+        assert_eq!(schema.validate(r#"{"lat":48.8,"lon":2.3}"#), Ok(()));
+        assert_eq!(
+            schema.validate(r#"{"lat":100,"lon":2.3}"#),
+            Err("/lat must be at most 90".into())
+        );
+        assert_eq!(
+            schema.validate(r#"{"lat":48.8}"#),
+            Err("must have property \"lon\"".into())
+        );
+        assert!(schema
+            .validate(r#"{"lat":48.8,"lon":2.3,"alt":35}"#)
+            .is_err());
+        assert!(schema.validate("Paris").is_err());
+
+        // answers that aren't JSON are strings
+        let schema: AnswerSchema = r#"json {"type":"string","pattern":"^[a-z]+$","maxLength":5}"#
+            .parse()
+            .unwrap();
+        assert_eq!(schema.validate("paris"), Ok(()));
+        assert!(schema.validate("london").is_err());
+        assert!(schema.validate("42").is_err());
+        let schema: AnswerSchema = r#"json {"type":"array","items":{"enum":[1,2]},"maxItems":2}"#
+            .parse()
+            .unwrap();
+        assert_eq!(schema.validate("[1,2.0]"), Ok(()));
+        assert_eq!(
+            schema.validate("[1,3]"),
+            Err("/1 must be one of 1, 2".into())
+        );
+
+        // The text form parses back to the same schema
+        for text in &[
+            "int",
+            "float min=0.5 max=1",
+            "enum a b",
+            r#"enum "New York" Boston"#,
+            r"regex \d+",
+            r#"json {"const":1}"#,
+        ] {
+            let schema: AnswerSchema = text.parse().unwrap();
+            assert_eq!(schema.to_string(), *text);
+            let json = serde_json::to_string(&schema).unwrap();
+            assert_eq!(serde_json::from_str::<AnswerSchema>(&json).unwrap(), schema);
+        }
+        for invalid in &[
+            "date",
+            "int min=a",
+            "int min=2 max=1",
+            "float max=inf",
+            "enum",
+            "regex (",
+            "json {",
+            r#"json {"format":"email"}"#,
+            r#"json {"type":"decimal"}"#,
+        ] {
+            assert!(invalid.parse::<AnswerSchema>().is_err(), "{}", invalid);
+        }
+    }
+}
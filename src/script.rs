@@ -0,0 +1,166 @@
+// Command scripts with expected output assertions
+//
+// A script is a list of commands, one per line. A line starting with "> "
+// asserts the output of the command before it, consecutive "> " lines are
+// joined for commands with multi-line output. Failed commands produce
+// "Err: <message>", so errors can be asserted too. Blank lines and lines
+// starting with "#" are ignored.
+//
+//   SET q1 a FROM s1
+//   SET q1 a FROM s2
+//   GET ANSWER TO q1
+//   > a (75.000%)
+
+use crate::command::Command;
+use crate::graph::Graph;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptFailure {
+    // 1-based line of the command
+    pub line: usize,
+    pub command: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ScriptReport {
+    pub commands: usize,
+    pub assertions: usize,
+    pub failures: Vec<ScriptFailure>,
+}
+
+impl ScriptReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for ScriptReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for failure in &self.failures {
+            writeln!(f, "line {}: {}", failure.line, failure.command)?;
+            for line in failure.expected.lines() {
+                writeln!(f, "  - {}", line)?;
+            }
+            for line in failure.actual.lines() {
+                writeln!(f, "  + {}", line)?;
+            }
+        }
+        write!(
+            f,
+            "{} commands, {} assertions, {} failed",
+            self.commands,
+            self.assertions,
+            self.failures.len()
+        )
+    }
+}
+
+// The command most recently executed and the assertions collected for it
+struct Pending {
+    line: usize,
+    command: String,
+    output: String,
+    expected: Vec<String>,
+}
+
+fn check(pending: Option<Pending>, report: &mut ScriptReport) {
+    if let Some(pending) = pending {
+        if pending.expected.is_empty() {
+            return;
+        }
+        report.assertions += 1;
+        let expected = pending.expected.join("\n");
+        if expected != pending.output {
+            report.failures.push(ScriptFailure {
+                line: pending.line,
+                command: pending.command,
+                expected,
+                actual: pending.output,
+            });
+        }
+    }
+}
+
+impl Graph {
+    // Execute a script against this graph. Only an unparseable command or an
+    // assertion without a command fails the run, failed assertions are
+    // collected in the report.
+    pub fn run_script(&mut self, script: &str) -> Result<ScriptReport, String> {
+        let mut report = ScriptReport::default();
+        let mut pending: Option<Pending> = None;
+        for (i, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(expected) = line.strip_prefix('>') {
+                let expected = expected.strip_prefix(' ').unwrap_or(expected);
+                match pending.as_mut() {
+                    Some(pending) => pending.expected.push(expected.to_string()),
+                    None => return Err(format!("Assertion on line {} has no command", i + 1)),
+                }
+                continue;
+            }
+
+            check(pending.take(), &mut report);
+            let cmd = Command::from(line)
+                .map_err(|msg| format!("Invalid command on line {}: {}", i + 1, msg))?;
+            let output = match self.execute_command(&cmd) {
+                Ok(response) => format!("{}", response),
+                Err(msg) => format!("Err: {}", msg),
+            };
+            report.commands += 1;
+            pending = Some(Pending {
+                line: i + 1,
+                command: line.to_string(),
+                output,
+                expected: Vec::new(),
+            });
+        }
+        check(pending, &mut report);
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_script() {
+        let script = "
+            # two sources agree
+            SET q1 a FROM s1
+            SET q1 a FROM s2
+            GET ANSWER TO q1
+            > a (75.000%)
+            GET SOURCE s1
+            > 0.123
+            TEST EQUALITY a a
+            >0.000
+        ";
+        let report = Graph::new().run_script(script).unwrap();
+        assert_eq!(report.commands, 5);
+        assert_eq!(report.assertions, 3);
+        assert_eq!(
+            report.failures,
+            vec![ScriptFailure {
+                line: 7,
+                command: String::from("GET SOURCE s1"),
+                expected: String::from("0.123"),
+                actual: String::from("0.688"),
+            }]
+        );
+        assert!(format!("{}", report).contains("  + 0.688"));
+
+        assert!(Graph::new().run_script("> a").is_err());
+        assert!(Graph::new()
+            .run_script("NOT A COMMAND")
+            .err()
+            .unwrap()
+            .contains("line 1"));
+    }
+}
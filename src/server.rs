@@ -0,0 +1,411 @@
+// TCP line protocol server
+//
+// Clients send commands in the text grammar, one per line, and get one line
+// back per command:
+//   +<response>   the command succeeded, e.g. "+a (75.000%)" or "+" for SET
+//   -<error>      the command failed or couldn't be parsed
+// Newlines inside a response (e.g. STATS) are sent as a literal "\n". QUIT
+// closes the connection, as does a line longer than MAX_LINE_LEN bytes, after
+// an error reply.
+//
+// A line starting with "{" is a JSON command envelope (see Command::from_json)
+// and is answered with the JSON serialized CommandResponse, or
+// {"error": "..."} if it failed. After CONFIGURE output_format json (or on a
+// server with set_output_format) commands in the text grammar are answered
+// that way too, see OutputFormat.
+//
+// A line starting with "*" starts a RESP array, so redis clients can talk to
+// the server too, see resp.rs.
+//
+// With tokens set (see set_tokens) clients authenticate with AUTH <token>,
+// answered with "+<role>", and can only run the commands their role allows,
+// see auth.rs.
+//
+// A server whose graph is a replica (see set_replica) rejects mutating
+// commands and serves reads only while the replica is up to date, see
+// replication.rs.
+//
+// Every connection gets its own thread, the graph is owned by a GraphWorker.
+
+use crate::auth::{AuthSession, Tokens};
+use crate::command::{Command, OutputFormat};
+use crate::graph::Graph;
+use crate::replication::ReplicaStatus;
+use crate::resp::{self, RespSession};
+use crate::worker::{GraphWorker, Reply};
+use log::{info, warn};
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+
+// The longest line a connection may send, so a client can't run the server
+// out of memory with a line that never ends
+pub(crate) const MAX_LINE_LEN: usize = 16 * 1024 * 1024;
+
+pub struct Server {
+    listener: TcpListener,
+    worker: GraphWorker,
+    output_format: OutputFormat,
+    tokens: Option<Arc<Tokens>>,
+    replica: Option<Arc<ReplicaStatus>>,
+}
+
+impl Server {
+    // make_graph runs on the worker thread, so the graph never crosses threads
+    pub fn bind<A, F>(addr: A, make_graph: F) -> Result<Server, String>
+    where
+        A: ToSocketAddrs,
+        F: FnOnce() -> Graph + Send + 'static,
+    {
+        let listener = TcpListener::bind(addr).map_err(|e| format!("Couldn't bind: {}", e))?;
+        Ok(Server {
+            listener,
+            worker: GraphWorker::spawn(make_graph),
+            output_format: OutputFormat::Text,
+            tokens: None,
+            replica: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.listener.local_addr().map_err(|e| e.to_string())
+    }
+
+    // The worker executing this server's commands, e.g. to share the graph
+    // with a WebSocketServer
+    pub fn worker(&self) -> GraphWorker {
+        self.worker.clone()
+    }
+
+    // The format connections answer text commands in until they CONFIGURE
+    // output_format
+    pub fn set_output_format(&mut self, output_format: OutputFormat) {
+        self.output_format = output_format;
+    }
+
+    // Require connections to authenticate with one of tokens, see auth.rs
+    pub fn set_tokens(&mut self, tokens: Tokens) {
+        self.tokens = Some(Arc::new(tokens));
+    }
+
+    // Serve the graph as a replica with status, see replication::Replica
+    pub fn set_replica(&mut self, replica: Arc<ReplicaStatus>) {
+        self.replica = Some(replica);
+    }
+
+    // Accept connections until the listener fails
+    pub fn run(self) -> Result<(), String> {
+        for stream in self.listener.incoming() {
+            let stream = stream.map_err(|e| format!("Couldn't accept connection: {}", e))?;
+            let worker = self.worker.clone();
+            let output_format = self.output_format;
+            let mut auth = AuthSession::new(self.tokens.clone());
+            if let Some(replica) = self.replica.as_ref() {
+                auth.set_replica(replica.clone());
+            }
+            thread::spawn(move || {
+                let peer = stream.peer_addr().ok();
+                if let Err(e) = handle_connection(stream, worker, output_format, auth) {
+                    warn!("Connection {:?} failed: {}", peer, e);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+pub fn format_reply(reply: &Reply) -> String {
+    match reply {
+        Ok(response) => format!("+{}", response).replace('\n', "\\n"),
+        Err(msg) => format!("-{}", msg).replace('\n', "\\n"),
+    }
+}
+
+pub fn format_json_reply(reply: &Reply) -> String {
+    OutputFormat::Json.format(reply)
+}
+
+// Execute cmd unless it switches the connection's output format, if the
+// connection is allowed to
+fn execute(
+    cmd: Command,
+    worker: &GraphWorker,
+    output_format: &mut OutputFormat,
+    auth: &AuthSession,
+) -> Reply {
+    match output_format.configure(&cmd) {
+        Some(reply) => reply.map_err(String::from),
+        None => {
+            auth.authorize(cmd.cmd)?;
+            worker.execute(cmd.into_owned())
+        }
+    }
+}
+
+// Read a line of at most max bytes into line, like BufRead::read_line. A longer
+// line is an InvalidData error.
+pub(crate) fn read_line_limited<R: BufRead>(
+    reader: &mut R,
+    line: &mut String,
+    max: usize,
+) -> io::Result<usize> {
+    let read = reader.by_ref().take(max as u64).read_line(line)?;
+    if read == max && !line.ends_with('\n') {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Line longer than {} bytes", max),
+        ));
+    }
+    Ok(read)
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    worker: GraphWorker,
+    mut output_format: OutputFormat,
+    mut auth: AuthSession,
+) -> std::io::Result<()> {
+    info!("Accepted connection from {:?}", stream.peer_addr());
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    let mut reader = BufReader::new(stream);
+    let mut session = RespSession::default();
+    loop {
+        let mut line = String::new();
+        match read_line_limited(&mut reader, &mut line, MAX_LINE_LEN) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                let reply = Err(e.to_string());
+                match output_format {
+                    OutputFormat::Text => writeln!(writer, "{}", format_reply(&reply))?,
+                    OutputFormat::Json => writeln!(writer, "{}", format_json_reply(&reply))?,
+                }
+                writer.flush()?;
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('*') {
+            let args = match resp::read_array(&mut reader, line) {
+                Ok(args) => args,
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    writer.write_all(resp::error(&e.to_string()).as_bytes())?;
+                    writer.flush()?;
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            let (reply, close) = session.execute(args, &worker, &mut auth);
+            writer.write_all(reply.as_bytes())?;
+            writer.flush()?;
+            if close {
+                break;
+            }
+            continue;
+        }
+        if line.eq_ignore_ascii_case("QUIT") {
+            break;
+        }
+        if let Some(token) = auth_token(line) {
+            let reply = match (auth.authenticate(token), output_format) {
+                (Ok(role), OutputFormat::Text) => format!("+{}", role),
+                (Ok(role), OutputFormat::Json) => {
+                    serde_json::json!({ "role": role.to_string() }).to_string()
+                }
+                (Err(msg), OutputFormat::Text) => format_reply(&Err(msg)),
+                (Err(msg), OutputFormat::Json) => format_json_reply(&Err(msg)),
+            };
+            writeln!(writer, "{}", reply)?;
+            writer.flush()?;
+            continue;
+        }
+        if line.starts_with('{') {
+            let reply = Command::from_json(line)
+                .map_err(String::from)
+                .and_then(|cmd| execute(cmd, &worker, &mut output_format, &auth));
+            writeln!(writer, "{}", format_json_reply(&reply))?;
+            writer.flush()?;
+            continue;
+        }
+        let reply = match Command::from(line) {
+            Ok(cmd) => execute(cmd, &worker, &mut output_format, &auth),
+            Err(msg) => Err(format!("Invalid command: {}", msg)),
+        };
+        match output_format {
+            OutputFormat::Text => writeln!(writer, "{}", format_reply(&reply))?,
+            OutputFormat::Json => writeln!(writer, "{}", format_json_reply(&reply))?,
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+// The token of an AUTH <token> line
+fn auth_token(line: &str) -> Option<&str> {
+    let (command, token) = line.split_once(char::is_whitespace)?;
+    Some(token.trim()).filter(|_| command.eq_ignore_ascii_case("AUTH"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_server_line_protocol() {
+        let server = Server::bind("127.0.0.1:0", Graph::new).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut request = |line: &str| {
+            writeln!(writer, "{}", line).unwrap();
+            let mut response = String::new();
+            reader.read_line(&mut response).unwrap();
+            response.trim_end().to_string()
+        };
+        assert_eq!(request("SET q1 a FROM s1"), "+");
+        assert_eq!(request("SET q1 a FROM s2"), "+");
+        assert_eq!(request("GET ANSWER TO q1"), "+a (75.000%)");
+        assert!(request("NOT A COMMAND").starts_with('-'));
+        assert!(request("STATS").contains("\\n"));
+        let response: serde_json::Value =
+            serde_json::from_str(&request(r#"{"cmd": "get_answer", "question": "q1"}"#)).unwrap();
+        assert_eq!(response["answer"], "a");
+        assert!(request(r#"{"cmd": "set", "question": "q1"}"#).contains("\"error\""));
+        assert_eq!(
+            request("CONFIGURE output_format json"),
+            "{\"cmd\":\"Configure\",\"previous\":\"text\"}"
+        );
+        let response: serde_json::Value =
+            serde_json::from_str(&request("GET ANSWER TO q1")).unwrap();
+        assert_eq!(response["answer"], "a");
+        assert!(request("NOT A COMMAND").contains("\"error\""));
+        assert_eq!(request("CONFIGURE output_format text"), "+json");
+
+        // redis clients send RESP arrays
+        let resp = |args: &[&str]| {
+            let mut out = format!("*{}\r\n", args.len());
+            for arg in args {
+                out += &format!("${}\r\n{}\r\n", arg.len(), arg);
+            }
+            out
+        };
+        let mut redis = TcpStream::connect(addr).unwrap();
+        let commands = [
+            resp(&["CLIENT", "SETNAME", "s3"]),
+            resp(&["SET", "q2", "a b"]),
+            resp(&["set", "q2", "a b", "FROM", "s4"]),
+            resp(&["GET", "q2"]),
+            resp(&["GET", "SOURCE", "s3"]),
+            resp(&["TEST", "EQUALITY", "a b", "a b"]),
+            resp(&["TEST", "EQUALITY", "a b", "a \"b\""]),
+            resp(&["NOPE"]),
+            resp(&["QUIT"]),
+        ];
+        redis.write_all(commands.concat().as_bytes()).unwrap();
+        let mut replies = String::new();
+        BufReader::new(redis).read_to_string(&mut replies).unwrap();
+        assert_eq!(
+            replies,
+            "+OK\r\n+OK\r\n+OK\r\n$3\r\na b\r\n$5\r\n0.688\r\n$5\r\n0.000\r\n$5\r\n1.000\r\n\
+             -ERR Invalid command: Invalid command starting token: NOPE\r\n+OK\r\n"
+        );
+
+        // oversized arrays are a protocol error that closes the connection
+        let mut redis = TcpStream::connect(addr).unwrap();
+        redis
+            .write_all(format!("*1\r\n${}\r\n", resp::MAX_BULK_LEN + 1).as_bytes())
+            .unwrap();
+        let mut replies = String::new();
+        BufReader::new(redis).read_to_string(&mut replies).unwrap();
+        assert_eq!(replies, "-ERR Protocol error: invalid bulk length\r\n");
+
+        // a second connection sees the same graph
+        let mut other = TcpStream::connect(addr).unwrap();
+        writeln!(other, "GET ANSWER TO q1\nQUIT").unwrap();
+        let mut response = String::new();
+        BufReader::new(other).read_line(&mut response).unwrap();
+        assert_eq!(response, "+a (75.000%)\n");
+    }
+
+    #[test]
+    fn test_read_line_limited() {
+        let mut reader = BufReader::new(&b"abc\nabcdefgh\nabcdefghi"[..]);
+        let mut line = String::new();
+        assert_eq!(read_line_limited(&mut reader, &mut line, 9).unwrap(), 4);
+        assert_eq!(line, "abc\n");
+        line.clear();
+        assert_eq!(read_line_limited(&mut reader, &mut line, 9).unwrap(), 9);
+        line.clear();
+        // no newline within 9 bytes
+        let e = read_line_limited(&mut reader, &mut line, 9).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        line.clear();
+        assert_eq!(read_line_limited(&mut reader, &mut line, 9).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_server_auth() {
+        let mut server = Server::bind("127.0.0.1:0", Graph::new).unwrap();
+        server.set_tokens(Tokens::parse("r1 read\nw1 write\na1 admin").unwrap());
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut request = |line: &str| {
+            writeln!(writer, "{}", line).unwrap();
+            let mut response = String::new();
+            reader.read_line(&mut response).unwrap();
+            response.trim_end().to_string()
+        };
+        assert_eq!(
+            request("GET ANSWER TO q1"),
+            "-Authentication required, send AUTH <token> first"
+        );
+        assert_eq!(request("AUTH nope"), "-Invalid token");
+        assert_eq!(request("AUTH w1"), "+write");
+        assert_eq!(request("SET q1 a FROM s1"), "+");
+        assert_eq!(
+            request("BELIEVE s1"),
+            "-Permission denied, Believe needs the admin role"
+        );
+        assert!(request(
+            r#"{"cmd": "configure", "config_key": "normalize", "config_val": "trim"}"#
+        )
+        .contains("Permission denied"));
+        assert_eq!(request("AUTH r1"), "+read");
+        assert!(request("SET q1 b FROM s2").starts_with("-Permission denied"));
+        assert_eq!(request("GET ANSWER TO q1"), "+a (50.000%)");
+        assert_eq!(request("AUTH a1"), "+admin");
+        assert_eq!(request("BELIEVE s1"), "+");
+
+        // redis clients AUTH with a password, or a username and password
+        let mut redis = TcpStream::connect(addr).unwrap();
+        redis
+            .write_all(
+                b"*2\r\n$3\r\nGET\r\n$2\r\nq1\r\n\
+                  *3\r\n$4\r\nAUTH\r\n$7\r\ndefault\r\n$2\r\nr1\r\n\
+                  *2\r\n$3\r\nGET\r\n$2\r\nq1\r\n\
+                  *2\r\n$7\r\nBELIEVE\r\n$2\r\ns2\r\n\
+                  *1\r\n$4\r\nQUIT\r\n",
+            )
+            .unwrap();
+        let mut replies = String::new();
+        BufReader::new(redis).read_to_string(&mut replies).unwrap();
+        assert_eq!(
+            replies,
+            "-ERR Authentication required, send AUTH <token> first\r\n+OK\r\n$1\r\na\r\n\
+             -ERR Permission denied, Believe needs the admin role\r\n+OK\r\n"
+        );
+    }
+}
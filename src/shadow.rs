@@ -0,0 +1,356 @@
+// Shadow evaluation of a second comparison method
+//
+// Changing comparison_method, e.g. a numeric max_distance, changes which
+// answers agree and so every source's quality, and can't be tried on a fork
+// for long. With shadow_comparison_method set, every question recomputed from
+// then on is also clustered with the shadow method, with the same source
+// qualities, and the two results compared:
+//
+//   CONFIGURE shadow_comparison_method numeric max_distance=0.2
+//   GET SHADOW
+//
+// GET SHADOW reports how many of the compared questions the shadow method
+// would have answered differently or clustered differently, the mean change
+// of confidence and the first questions whose answer diverged. The shadow
+// method never changes answers, weights or qualities, and only questions
+// recomputed since it was configured are compared. Questions with a declared
+// type aren't compared, their answers don't use comparison_method, see TYPE.
+// The comparisons aren't persisted, the shadow method is, as part of the
+// configuration.
+
+use crate::cluster::DistanceCache;
+use crate::command::AnswerContent;
+use crate::config::ConfigValue;
+use crate::equalifier::{Equalifier, EqualifierConfig};
+use crate::error::ConfidisError;
+use crate::graph::{AnswerClustersWithConfidences, Graph};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+// Divergent answers GET SHADOW lists
+const MAX_EXAMPLES: usize = 10;
+
+pub(crate) struct Shadow<A> {
+    equalifier: Arc<dyn Equalifier<A>>,
+    distance_cache: DistanceCache<A>,
+    // the latest comparison of each question, by question name
+    comparisons: HashMap<String, ShadowComparison<A>>,
+}
+
+// A copy starts with an empty distance cache, like a fork
+impl<A: Clone> Clone for Shadow<A> {
+    fn clone(&self) -> Self {
+        Shadow {
+            equalifier: self.equalifier.clone(),
+            distance_cache: DistanceCache::default(),
+            comparisons: self.comparisons.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ShadowComparison<A> {
+    answer: A,
+    confidence: f64,
+    shadow_answer: A,
+    shadow_confidence: f64,
+    // the shadow's most confident cluster doesn't hold the answer
+    answer_diverged: bool,
+    // the answers were grouped into different clusters
+    clusters_diverged: bool,
+}
+
+impl<A> Shadow<A> {
+    pub(crate) fn new(equalifier: Box<dyn Equalifier<A>>) -> Shadow<A> {
+        Shadow {
+            equalifier: equalifier.into(),
+            distance_cache: DistanceCache::default(),
+            comparisons: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn config(&self) -> EqualifierConfig {
+        self.equalifier.config()
+    }
+
+    pub(crate) fn invalidate_distances(&mut self) {
+        self.distance_cache.invalidate();
+    }
+}
+
+// GET SHADOW
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShadowStats {
+    // the shadow comparison method in the form CONFIGURE takes
+    pub comparison_method: String,
+    // questions compared
+    pub questions: usize,
+    pub answers_diverged: usize,
+    pub clusters_diverged: usize,
+    // mean of the shadow's confidence minus the answer's
+    pub mean_confidence_delta: f64,
+    // the first questions whose answer diverged, by question name
+    pub examples: Vec<ShadowExample>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShadowExample {
+    pub question: String,
+    pub answer: String,
+    pub confidence: f64,
+    pub shadow_answer: String,
+    pub shadow_confidence: f64,
+}
+
+// Clusters as a comparable partition of answer indices
+fn partition(clusters: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut partition: Vec<Vec<usize>> = clusters
+        .iter()
+        .map(|members| {
+            let mut members = members.clone();
+            members.sort_unstable();
+            members
+        })
+        .collect();
+    partition.sort_unstable();
+    partition
+}
+
+impl<A: AnswerContent> Graph<A> {
+    // Evaluate equalifier alongside the comparison method, None to stop. The
+    // comparisons start over.
+    pub fn set_shadow_equalifier(&mut self, equalifier: Option<Box<dyn Equalifier<A>>>) {
+        self.shadow = equalifier.map(Shadow::new);
+        self.config.shadow_comparison_method = self.shadow.as_ref().map(Shadow::config);
+    }
+
+    // Compare the question's clusters under the shadow comparison method with
+    // computed, its clusters under the comparison method
+    pub(crate) fn compare_with_shadow(
+        &mut self,
+        question_name: &str,
+        computed: &AnswerClustersWithConfidences,
+    ) -> Result<(), ConfidisError> {
+        let shadow = match self.shadow.as_ref() {
+            Some(shadow) => shadow,
+            None => return Ok(()),
+        };
+        if self
+            .questions
+            .get(question_name)
+            .is_some_and(|question| question.question_type.is_some())
+        {
+            return Ok(());
+        }
+        let shadowed = self.clusters_with_confidence(
+            question_name,
+            shadow.equalifier.as_ref(),
+            &shadow.distance_cache,
+        )?;
+        let question = match self.questions.get(question_name) {
+            Some(question) => question,
+            None => return Ok(()),
+        };
+        let comparison = match (
+            computed.clusters.get(computed.correct_cluster),
+            shadowed.clusters.get(shadowed.correct_cluster),
+        ) {
+            (Some(members), Some(shadow_members)) => ShadowComparison {
+                answer: question.answers[members[0]].content.clone(),
+                confidence: computed.cluster_confidences[computed.correct_cluster],
+                shadow_answer: question.answers[shadow_members[0]].content.clone(),
+                shadow_confidence: shadowed.cluster_confidences[shadowed.correct_cluster],
+                answer_diverged: !shadow_members.contains(&members[0]),
+                clusters_diverged: partition(&computed.clusters) != partition(&shadowed.clusters),
+            },
+            // every answer is from an excluded source
+            _ => return Ok(()),
+        };
+        if let Some(shadow) = self.shadow.as_mut() {
+            shadow
+                .comparisons
+                .insert(question_name.to_string(), comparison);
+        }
+        Ok(())
+    }
+}
+
+impl Graph {
+    // GET SHADOW, how the shadow comparison method's answers compare for the
+    // questions recomputed since it was configured
+    pub fn shadow_stats(&self) -> Result<ShadowStats, ConfidisError> {
+        let shadow = self.shadow.as_ref().ok_or_else(|| {
+            ConfidisError::NotImplemented(String::from("No shadow comparison method is configured"))
+        })?;
+        // questions that were since made honeypots aren't clustered
+        let mut comparisons: Vec<(&String, &ShadowComparison<String>)> = shadow
+            .comparisons
+            .iter()
+            .filter(|(question_name, _)| {
+                self.questions
+                    .get(*question_name)
+                    .is_some_and(|question| question.honeypot.is_none())
+            })
+            .collect();
+        comparisons.sort_unstable_by_key(|(question_name, _)| *question_name);
+
+        let questions = comparisons.len();
+        let delta: f64 = comparisons
+            .iter()
+            .map(|(_, comparison)| comparison.shadow_confidence - comparison.confidence)
+            .sum();
+        Ok(ShadowStats {
+            comparison_method: ConfigValue::ComparisonMethod(shadow.config()).to_string(),
+            questions,
+            answers_diverged: comparisons
+                .iter()
+                .filter(|(_, comparison)| comparison.answer_diverged)
+                .count(),
+            clusters_diverged: comparisons
+                .iter()
+                .filter(|(_, comparison)| comparison.clusters_diverged)
+                .count(),
+            mean_confidence_delta: if questions > 0 {
+                delta / questions as f64
+            } else {
+                0.0
+            },
+            examples: comparisons
+                .iter()
+                .filter(|(_, comparison)| comparison.answer_diverged)
+                .take(MAX_EXAMPLES)
+                .map(|(question_name, comparison)| ShadowExample {
+                    question: question_name.to_string(),
+                    answer: comparison.answer.clone(),
+                    confidence: comparison.confidence,
+                    shadow_answer: comparison.shadow_answer.clone(),
+                    shadow_confidence: comparison.shadow_confidence,
+                })
+                .collect(),
+        })
+    }
+}
+
+impl fmt::Display for ShadowStats {
+    // e.g.
+    //   shadow comparison method: numeric max_distance=0.2
+    //   questions compared: 20
+    //   answers diverged: 1 (5.000%)
+    //   clusters diverged: 3 (15.000%)
+    //   mean confidence change: +0.012
+    //   q7: 1.0 (90.000%) -> 1.1 (95.000%)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let share = |count: usize| {
+            if self.questions > 0 {
+                count as f64 / self.questions as f64 * 100.
+            } else {
+                0.0
+            }
+        };
+        write!(
+            f,
+            "shadow comparison method: {}\nquestions compared: {}\nanswers diverged: {} ({:.3}%)\nclusters diverged: {} ({:.3}%)\nmean confidence change: {:+.3}",
+            self.comparison_method,
+            self.questions,
+            self.answers_diverged,
+            share(self.answers_diverged),
+            self.clusters_diverged,
+            share(self.clusters_diverged),
+            self.mean_confidence_delta
+        )?;
+        for example in &self.examples {
+            write!(
+                f,
+                "\n{}: {} ({:.3}%) -> {} ({:.3}%)",
+                example.question,
+                example.answer,
+                example.confidence * 100.,
+                example.shadow_answer,
+                example.shadow_confidence * 100.
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::{Command, CommandResponse};
+    use crate::graph::Graph;
+
+    #[test]
+    fn test_shadow_comparison_method() {
+        let mut g = Graph::new();
+        let mut run = |line: &str| g.execute_command(&Command::from(line).unwrap());
+        assert!(run("GET SHADOW").is_err());
+        run("CONFIGURE shadow_comparison_method numeric max_distance=0.5").unwrap();
+        // 1.0 and 1.2 only agree under the shadow method
+        let lines = [
+            "SET q1 1.0 FROM s1",
+            "SET q1 1.2 FROM s2",
+            "SET q1 1.2 FROM s3",
+            "SET q2 5 FROM s1",
+            "SET q2 5 FROM s2",
+            "SET q3 1.0 FROM s1",
+            "SET q3 3.0 FROM s2",
+        ];
+        for line in &lines {
+            run(line).unwrap();
+        }
+        let stats = match run("GET SHADOW").unwrap() {
+            CommandResponse::Shadow(stats) => stats,
+            response => panic!("unexpected response {:?}", response),
+        };
+        assert_eq!(stats.comparison_method, "numeric max_distance=0.5");
+        assert_eq!(stats.questions, 3);
+        assert_eq!(stats.clusters_diverged, 1);
+        assert_eq!(stats.answers_diverged, 0);
+        assert!(stats.mean_confidence_delta > 0.0);
+        assert!(stats.examples.is_empty());
+
+        // the shadow doesn't change answers or qualities
+        let mut exact = Graph::new();
+        for line in &lines {
+            exact
+                .execute_command(&Command::from(line).unwrap())
+                .unwrap();
+        }
+        assert_eq!(
+            g.compute_answer("q1").unwrap(),
+            exact.compute_answer("q1").unwrap()
+        );
+        assert_eq!(
+            g.source("s1").unwrap().quality,
+            exact.source("s1").unwrap().quality
+        );
+
+        // three close answers outweigh two equal ones under the shadow method
+        let mut run = |line: &str| g.execute_command(&Command::from(line).unwrap());
+        for line in &[
+            "SET q4 1.0 FROM s4",
+            "SET q4 1.2 FROM s5",
+            "SET q4 1.4 FROM s6",
+            "SET q4 5 FROM s7",
+            "SET q4 5 FROM s8",
+        ] {
+            run(line).unwrap();
+        }
+        let stats = match run("GET SHADOW").unwrap() {
+            CommandResponse::Shadow(stats) => stats,
+            response => panic!("unexpected response {:?}", response),
+        };
+        assert_eq!(stats.answers_diverged, 1);
+        assert_eq!(stats.examples[0].question, "q4");
+        assert!(stats.to_string().contains("\nq4: 5 (75.000%) -> 1.0 ("));
+
+        assert_eq!(
+            run("CONFIGURE shadow_comparison_method none").unwrap(),
+            CommandResponse::Configure {
+                previous: String::from("numeric max_distance=0.5")
+            }
+        );
+        assert!(run("GET SHADOW").is_err());
+    }
+}
@@ -0,0 +1,439 @@
+// Questions partitioned across graphs
+//
+// A graph recomputes one question at a time, so it can't use more than one
+// core, and all of its questions live in one map. A ShardedGraph splits its
+// questions across N graphs (shards) by the XXH64 hash of their name, and
+// set_many adds each shard's part of a batch on a thread of its own:
+//
+//   let mut g = ShardedGraph::new(8);
+//   g.set_many(&[("q1", "a", "s1"), ("q2", "b", "s1")])?;
+//   g.execute_command(&Command::from("GET ANSWER TO q1")?)?;
+//
+// A source's answers land in many shards, and each shard judges them with its
+// own copy of the source's quality. The copies are merged every
+// merge_interval mutations (1000 by default, see set_merge_interval) and by
+// merge_sources: the evidence every shard gathered about a source since the
+// previous merge is pooled, weighed by the strength it added, and each shard
+// continues from the pooled quality and strength. Between merges a shard
+// doesn't see what the others learned about a source, so confidences can
+// differ slightly from a single graph's. GET SOURCE reports the quality the
+// next merge would give.
+//
+// Commands about a question (SET, GET ANSWER TO, EXPLAIN, EXCLUDE, SCHEMA,
+// TYPE, ...) run in the question's shard. CONFIGURE and BELIEVE run in every
+// shard, and REBUILD re-estimates the qualities in every shard and merges
+// them. STATS, MGET, COMPARE SOURCES, GET AUDIT, GET SHADOW, UNDO and REDO
+// aren't supported. The shards have no journal.
+
+use crate::command::{Command, CommandResponse, CommandType};
+use crate::config::GraphConfig;
+use crate::error::ConfidisError;
+use crate::graph::{AnswerResult, Graph, SourceStats};
+use crate::id::{QuestionId, SourceId};
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use twox_hash::XxHash64;
+
+const DEFAULT_MERGE_INTERVAL: usize = 1000;
+
+pub struct ShardedGraph {
+    shards: Vec<Graph>,
+    // every source's (quality, strength, discount) as of the last merge, which
+    // each shard continued from
+    merged: HashMap<String, (f64, f64, f64)>,
+    merge_interval: usize,
+    mutations_since_merge: usize,
+}
+
+// A shard per core
+impl Default for ShardedGraph {
+    fn default() -> Self {
+        ShardedGraph::new(thread::available_parallelism().map_or(1, NonZeroUsize::get))
+    }
+}
+
+// The source's (quality, strength, discount) in g, None if g doesn't know it
+fn state_in(g: &Graph, source_name: &str) -> Option<(f64, f64, f64)> {
+    g.source(source_name)
+        .map(|source| (source.quality, source.strength, source.discount))
+}
+
+// Run f with each shard and its item, on a thread per shard if there's more
+// than one. Returns the first error.
+fn for_each_shard<T, F>(shards: &mut [Graph], items: Vec<T>, f: F) -> Result<(), ConfidisError>
+where
+    T: Send,
+    F: Fn(&mut Graph, T) -> Result<(), ConfidisError> + Sync,
+{
+    let mut work: Vec<(&mut Graph, T)> = shards.iter_mut().zip(items).collect();
+    if work.len() <= 1 {
+        return work.pop().map_or(Ok(()), |(shard, item)| f(shard, item));
+    }
+    let f = &f;
+    let results: Vec<Result<(), ConfidisError>> = thread::scope(|scope| {
+        let threads: Vec<_> = work
+            .into_iter()
+            .map(|(shard, item)| scope.spawn(move || f(shard, item)))
+            .collect();
+        threads
+            .into_iter()
+            .map(|thread| {
+                thread
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            })
+            .collect()
+    });
+    results.into_iter().collect()
+}
+
+impl ShardedGraph {
+    // Panics if shard_count is 0
+    pub fn new(shard_count: usize) -> ShardedGraph {
+        ShardedGraph::new_with_config(shard_count, GraphConfig::default())
+    }
+
+    pub fn new_with_config(shard_count: usize, config: GraphConfig) -> ShardedGraph {
+        assert!(shard_count > 0, "a ShardedGraph needs at least one shard");
+        ShardedGraph {
+            shards: (0..shard_count)
+                .map(|_| Graph::new_with_config(config.clone()))
+                .collect(),
+            merged: HashMap::new(),
+            merge_interval: DEFAULT_MERGE_INTERVAL,
+            mutations_since_merge: 0,
+        }
+    }
+
+    // Merge source qualities every mutations mutations, 0 only merges them
+    // when merge_sources is called
+    pub fn set_merge_interval(&mut self, mutations: usize) {
+        self.merge_interval = mutations;
+    }
+
+    pub fn shards(&self) -> &[Graph] {
+        &self.shards
+    }
+
+    // The index of the shard holding the question
+    pub fn shard_index(&self, question_name: &str) -> usize {
+        (XxHash64::oneshot(0, question_name.as_bytes()) % self.shards.len() as u64) as usize
+    }
+
+    pub fn shard(&self, question_name: &str) -> &Graph {
+        &self.shards[self.shard_index(question_name)]
+    }
+
+    pub fn execute_command(&mut self, cmd: &Command) -> Result<CommandResponse, ConfidisError> {
+        cmd.validate()?;
+        let response = match cmd.cmd {
+            CommandType::Set
+            | CommandType::GetAnswer
+            | CommandType::GetAnswers
+            | CommandType::Explain
+            | CommandType::GetHistory
+            | CommandType::DebugClusters
+            | CommandType::Exclude
+            | CommandType::Honeypot
+            | CommandType::Schema
+            | CommandType::Type => {
+                let shard = self.shard_index(cmd.field("question")?);
+                self.shards[shard].execute_command(cmd)?
+            }
+            CommandType::GetSource => {
+                let SourceStats { quality, strength } = self.source_stats(cmd.field("source")?);
+                CommandResponse::Source { quality, strength }
+            }
+            CommandType::TestEquality => self.shards[0].execute_command(cmd)?,
+            CommandType::Configure => self.execute_in_every_shard(cmd)?,
+            CommandType::Believe => {
+                // Every shard ends up with the believed quality, the other
+                // sources stay merged
+                self.merge_sources();
+                let response = self.execute_in_every_shard(cmd)?;
+                let source_name = cmd.field("source")?;
+                if let Some(state) = state_in(&self.shards[0], source_name) {
+                    self.merged.insert(source_name.to_string(), state);
+                }
+                return Ok(response);
+            }
+            CommandType::Rebuild => {
+                let count = self.shards.len();
+                for_each_shard(&mut self.shards, vec![(); count], |shard, ()| {
+                    shard.rebuild_qualities()
+                })?;
+                // Every shard started over from the initial qualities
+                self.merged.clear();
+                self.merge_sources();
+                return Ok(CommandResponse::Rebuild);
+            }
+            _ => {
+                return Err(ConfidisError::NotImplemented(format!(
+                    "{:?} isn't supported by a ShardedGraph",
+                    cmd.cmd
+                )))
+            }
+        };
+        if !cmd.cmd.is_read_only() {
+            self.mutated(1);
+        }
+        Ok(response)
+    }
+
+    // Run cmd in the first shard, then in the others if it succeeded
+    fn execute_in_every_shard(&mut self, cmd: &Command) -> Result<CommandResponse, ConfidisError> {
+        let (first, others) = self.shards.split_at_mut(1);
+        let response = first[0].execute_command(cmd)?;
+        for shard in others {
+            shard.execute_command(cmd)?;
+        }
+        Ok(response)
+    }
+
+    // Add (question, answer, source) entries, each shard's on its own thread.
+    // Each shard adds its entries like Graph::set_many, all or nothing, but
+    // when a shard rejects its entries the other shards may have added theirs.
+    pub fn set_many(&mut self, entries: &[(&str, &str, &str)]) -> Result<(), ConfidisError> {
+        let mut batches: Vec<Vec<(&str, &str, &str)>> = vec![Vec::new(); self.shards.len()];
+        for &entry in entries {
+            batches[self.shard_index(entry.0)].push(entry);
+        }
+        // only the batches shards added count towards the merge interval
+        let added = AtomicUsize::new(0);
+        let result = for_each_shard(&mut self.shards, batches, |shard, batch| {
+            if batch.is_empty() {
+                return Ok(());
+            }
+            shard.set_many(&batch)?;
+            added.fetch_add(batch.len(), Ordering::Relaxed);
+            Ok(())
+        });
+        self.mutated(added.into_inner());
+        result
+    }
+
+    pub fn get_answer(&self, question: &QuestionId) -> Result<AnswerResult, ConfidisError> {
+        self.shard(question.as_str()).get_answer(question)
+    }
+
+    // The source's quality and strength as the next merge would give them
+    pub fn get_source(&self, source: &SourceId) -> SourceStats {
+        self.source_stats(source.as_str())
+    }
+
+    // A shard that continued from base holds base's effects and the ones it
+    // added, all scaled down by its discount, see added_effect in graph.rs.
+    // Scaled back to base's discount, each shard's strength and correct weight
+    // less base's are what it added, and pooling them gives the quality a
+    // single graph that saw all of their questions would have, instead of
+    // adding up each shard's change of quality, which grows with the number of
+    // shards. Shards that didn't change the source are left out, so when only
+    // one did, it's taken as is.
+    fn source_state(&self, source_name: &str) -> (f64, f64, f64) {
+        let config = self.shards[0].config();
+        let base = self.merged.get(source_name).copied().unwrap_or((
+            config.default_source_quality,
+            config.initial_source_strength,
+            0.0,
+        ));
+        let changed: Vec<(f64, f64, f64)> = self
+            .shards
+            .iter()
+            .filter_map(|g| state_in(g, source_name))
+            .filter(|&state| state != base)
+            .collect();
+        match changed[..] {
+            [] => return base,
+            [state] => return state,
+            _ => {}
+        }
+        let (base_quality, base_strength, base_discount) = base;
+        let mut strength = base_strength;
+        let mut correct = base_quality * base_strength;
+        for (quality_in_shard, strength_in_shard, discount_in_shard) in changed {
+            let scale = (discount_in_shard - base_discount).exp();
+            strength += strength_in_shard * scale - base_strength;
+            correct += quality_in_shard * strength_in_shard * scale - base_quality * base_strength;
+        }
+        if strength <= 0.0 {
+            return base;
+        }
+        let quality = (correct / strength).clamp(0.0, 1.0);
+        if strength > config.maximum_strength {
+            let discount = base_discount + (strength / config.maximum_strength).ln();
+            (quality, config.maximum_strength, discount)
+        } else {
+            (quality, strength, base_discount)
+        }
+    }
+
+    fn source_stats(&self, source_name: &str) -> SourceStats {
+        let (quality, strength, _) = self.source_state(source_name);
+        SourceStats { quality, strength }
+    }
+
+    fn mutated(&mut self, count: usize) {
+        self.mutations_since_merge += count;
+        if self.merge_interval > 0 && self.mutations_since_merge >= self.merge_interval {
+            self.merge_sources();
+        }
+    }
+
+    // Pool the evidence each shard gathered about its sources since the last
+    // merge and continue every shard from it, see source_stats
+    pub fn merge_sources(&mut self) {
+        self.mutations_since_merge = 0;
+        let mut source_names: Vec<String> = Vec::new();
+        for shard in &self.shards {
+            source_names.extend(shard.sources().map(|source| source.name.to_string()));
+        }
+        source_names.sort_unstable();
+        source_names.dedup();
+        for source_name in source_names {
+            let state = self.source_state(&source_name);
+            for shard in &mut self.shards {
+                shard.merge_source(&source_name, state);
+            }
+            self.merged.insert(source_name, state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sharded_graph() {
+        let mut sharded = ShardedGraph::new(4);
+        sharded.set_merge_interval(0);
+        let mut single = Graph::new();
+        let questions: Vec<String> = (0..40).map(|i| format!("q{}", i)).collect();
+        let mut entries: Vec<(&str, &str, &str)> = Vec::new();
+        for question in &questions {
+            entries.extend([
+                (question.as_str(), "a", "s1"),
+                (question.as_str(), "a", "s2"),
+            ]);
+            entries.push((question.as_str(), "b", "s3"));
+        }
+        sharded.set_many(&entries).unwrap();
+        single.set_many(&entries).unwrap();
+        assert!(sharded
+            .shards()
+            .iter()
+            .all(|shard| shard.questions().count() > 0));
+        assert_eq!(
+            sharded
+                .shards()
+                .iter()
+                .map(|shard| shard.questions().count())
+                .sum::<usize>(),
+            40
+        );
+
+        // each shard only learned from its own questions until the merge
+        let s3 = SourceId::new("s3").unwrap();
+        let before_merge = sharded.get_source(&s3);
+        assert!(sharded
+            .shards()
+            .iter()
+            .all(|shard| shard.get_source(&s3).quality > before_merge.quality));
+        sharded.merge_sources();
+        for shard in sharded.shards() {
+            assert_eq!(shard.get_source(&s3), before_merge);
+        }
+        assert!(before_merge.quality < single.get_source(&s3).quality + 0.1);
+
+        let q7 = QuestionId::new("q7").unwrap();
+        assert_eq!(
+            sharded.get_answer(&q7).unwrap().answer,
+            single.get_answer(&q7).unwrap().answer
+        );
+        let run = |g: &mut ShardedGraph, line: &str| {
+            g.execute_command(&Command::from(line).unwrap())
+                .map(|response| response.to_string())
+        };
+        assert!(run(&mut sharded, "GET ANSWER TO q7")
+            .unwrap()
+            .starts_with("a ("));
+
+        // BELIEVE and CONFIGURE apply to every shard
+        run(&mut sharded, "BELIEVE s3").unwrap();
+        run(&mut sharded, "CONFIGURE min_confidence 0.2").unwrap();
+        for shard in sharded.shards() {
+            assert_eq!(shard.get_source(&s3).quality, 0.999);
+            assert_eq!(shard.config().min_confidence, 0.2);
+        }
+        assert_eq!(sharded.get_source(&s3).quality, 0.999);
+        run(&mut sharded, "REBUILD").unwrap();
+        assert_eq!(sharded.get_source(&s3), before_merge);
+
+        assert!(run(&mut sharded, "STATS").is_err());
+    }
+
+    #[test]
+    fn test_merge_matches_single_graph() {
+        // s1 and s2 agree, s3 is right on every third question only
+        let questions: Vec<String> = (0..64).map(|i| format!("q{}", i)).collect();
+        let mut entries: Vec<(&str, &str, &str)> = Vec::new();
+        for (i, question) in questions.iter().enumerate() {
+            let s3_answer = if i % 3 == 0 { "a" } else { "b" };
+            entries.extend([
+                (question.as_str(), "a", "s1"),
+                (question.as_str(), "a", "s2"),
+                (question.as_str(), s3_answer, "s3"),
+            ]);
+        }
+        let mut single = Graph::new();
+        single.set_many(&entries).unwrap();
+
+        // Between merges a shard judges with what it learned alone, so they
+        // merge every 8 questions like a busy graph would
+        for shard_count in [1, 2, 4, 8, 16] {
+            let mut sharded = ShardedGraph::new(shard_count);
+            sharded.set_merge_interval(0);
+            for chunk in entries.chunks(24) {
+                sharded.set_many(chunk).unwrap();
+                sharded.merge_sources();
+            }
+            for source in &["s1", "s2", "s3"] {
+                let source = SourceId::new(*source).unwrap();
+                let expected = single.get_source(&source);
+                let merged = sharded.get_source(&source);
+                // past maximum_strength a single graph scales each question's
+                // effect down by every later one, a shard only by its own, so
+                // the pooled quality weighs recent questions a little less
+                assert!(
+                    (merged.quality - expected.quality).abs() < 0.01,
+                    "{} shards: {} has {} instead of {}",
+                    shard_count,
+                    source,
+                    merged.quality,
+                    expected.quality
+                );
+                // shards weigh questions by the confidence they reached
+                // knowing less, but never more than the single graph did
+                assert!(merged.strength <= expected.strength + 1e-9);
+                assert!(merged.strength > 0.8 * expected.strength);
+                if shard_count == 1 {
+                    assert_eq!(merged, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejected_entries_dont_count_towards_merges() {
+        let mut g = ShardedGraph::new(1);
+        g.set_merge_interval(3);
+        g.execute_command(&Command::from("SCHEMA q1 int").unwrap())
+            .unwrap();
+        g.set_many(&[("q2", "a", "s1")]).unwrap();
+        assert!(g.set_many(&[("q1", "a", "s1"), ("q1", "b", "s2")]).is_err());
+        // SCHEMA and the first SET
+        assert_eq!(g.mutations_since_merge, 2);
+    }
+}
@@ -0,0 +1,72 @@
+use crate::command::{Command, CommandResponse};
+use crate::error::ConfidisError;
+use crate::graph::Graph;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// A Graph behind a RwLock. Read-only commands (GET ANSWER, GET ANSWERS, MGET,
+// GET SOURCE, TEST EQUALITY, STATS) only take a shared lock so they can run
+// concurrently, while SET, BELIEVE and CONFIGURE are serialized.
+pub struct SharedGraph {
+    graph: RwLock<Graph>,
+}
+
+impl Default for SharedGraph {
+    fn default() -> Self {
+        SharedGraph::new(Graph::new())
+    }
+}
+
+impl SharedGraph {
+    pub fn new(graph: Graph) -> Self {
+        SharedGraph {
+            graph: RwLock::new(graph),
+        }
+    }
+
+    pub fn execute_command(&self, cmd: &Command) -> Result<CommandResponse, ConfidisError> {
+        if cmd.cmd.is_read_only() {
+            self.read().execute_read_command(cmd)
+        } else {
+            self.write().execute_command(cmd)
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, Graph> {
+        self.graph.read().unwrap()
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, Graph> {
+        self.graph.write().unwrap()
+    }
+
+    pub fn into_inner(self) -> Graph {
+        self.graph.into_inner().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_graph_reads_share_lock() {
+        let g = SharedGraph::default();
+        for line in &["SET q1 a FROM s1", "SET q1 a FROM s2", "SET q1 b FROM s3"] {
+            g.execute_command(&Command::from(line).unwrap()).unwrap();
+        }
+
+        // Commands can still be read while another reader holds the lock
+        let reader = g.read();
+        let output = g
+            .execute_command(&Command::from("GET ANSWER TO q1").unwrap())
+            .unwrap();
+        assert!(matches!(output, CommandResponse::Answer { content, .. } if content == "a"));
+        let output = g
+            .execute_command(&Command::from("GET SOURCE unknown").unwrap())
+            .unwrap();
+        assert!(matches!(output, CommandResponse::Source { quality, .. } if quality == 0.5));
+        drop(reader);
+
+        assert_eq!(g.into_inner().memory_stats().source_count, 3);
+    }
+}
@@ -0,0 +1,332 @@
+// Synthetic data for measuring how well a graph recovers the truth
+//
+// A Simulation generates sources with known qualities, questions with known
+// true answers and the answers the sources submit under an ErrorModel, feeds
+// the submissions to a graph with SET in a shuffled order, and compares the
+// graph's answers and source qualities with the truth. Run it with different
+// GraphConfigs to tune e.g. log_weight_factor or maximum_strength:
+//
+//   let report = Simulation {
+//       config: GraphConfig { log_weight_factor: 5.0, ..Default::default() },
+//       ..Default::default()
+//   }
+//   .run()?;
+//   println!("{}", report);
+//
+// Simulations are seeded, the same Simulation always produces the same data.
+
+use crate::config::GraphConfig;
+use crate::error::ConfidisError;
+use crate::graph::Graph;
+use crate::id::{QuestionId, SourceId};
+use std::collections::HashMap;
+use std::fmt;
+
+// How sources answer incorrectly
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorModel {
+    // A source answers correctly with the probability of its quality, otherwise
+    // with one of wrong_answers wrong answers picked at random. With one wrong
+    // answer, every wrong source agrees (colluding sources).
+    Uniform {
+        wrong_answers: usize,
+    },
+    // Like Uniform with wrong_answers answers, except that a fraction of the
+    // sources answer correctly until turn_after of the submissions were made
+    // and then always give the same wrong answer, the "start good, turn bad"
+    // attack maximum_strength defends against
+    StartGoodTurnBad {
+        wrong_answers: usize,
+        fraction_of_sources: f64,
+        turn_after: f64,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    pub seed: u64,
+    pub source_count: usize,
+    pub question_count: usize,
+    // number of distinct sources answering each question
+    pub answers_per_question: usize,
+    // source qualities are drawn uniformly from this range
+    pub min_quality: f64,
+    pub max_quality: f64,
+    pub error_model: ErrorModel,
+    pub config: GraphConfig,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Simulation {
+            seed: 1,
+            source_count: 20,
+            question_count: 500,
+            answers_per_question: 5,
+            min_quality: 0.5,
+            max_quality: 0.95,
+            error_model: ErrorModel::Uniform { wrong_answers: 3 },
+            config: GraphConfig::default(),
+        }
+    }
+}
+
+// The generated data, see Simulation::generate
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedData {
+    // (source, quality it was generated with)
+    pub sources: Vec<(String, f64)>,
+    // (question, true answer)
+    pub questions: Vec<(String, String)>,
+    // (question, answer, source) in the order they're submitted
+    pub submissions: Vec<(String, String, String)>,
+}
+
+impl SimulatedData {
+    // The fraction of each source's submissions that were correct, the quality
+    // a graph should recover. Sources without submissions are left out.
+    pub fn observed_qualities(&self) -> Vec<(String, f64)> {
+        let truth: HashMap<&str, &str> = self
+            .questions
+            .iter()
+            .map(|(q, a)| (q.as_str(), a.as_str()))
+            .collect();
+        self.sources
+            .iter()
+            .filter_map(|(source, _)| {
+                let answers: Vec<bool> = self
+                    .submissions
+                    .iter()
+                    .filter(|(_, _, s)| s == source)
+                    .map(|(q, a, _)| truth[q.as_str()] == a)
+                    .collect();
+                if answers.is_empty() {
+                    return None;
+                }
+                let correct = answers.iter().filter(|&&correct| correct).count();
+                Some((source.clone(), correct as f64 / answers.len() as f64))
+            })
+            .collect()
+    }
+}
+
+// How well a graph recovered the truth of a simulation
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationReport {
+    // fraction of questions whose answer is the true answer
+    pub answer_accuracy: f64,
+    // mean confidence of the questions' answers
+    pub mean_confidence: f64,
+    // mean absolute difference between each source's quality in the graph and
+    // the fraction of its answers that were correct
+    pub quality_mean_absolute_error: f64,
+    // Pearson correlation of the same, None if either doesn't vary
+    pub quality_correlation: Option<f64>,
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "answer accuracy: {:.3}", self.answer_accuracy)?;
+        writeln!(f, "mean confidence: {:.3}", self.mean_confidence)?;
+        writeln!(
+            f,
+            "quality mean absolute error: {:.3}",
+            self.quality_mean_absolute_error
+        )?;
+        match self.quality_correlation {
+            Some(correlation) => write!(f, "quality correlation: {:.3}", correlation),
+            None => write!(f, "quality correlation: None"),
+        }
+    }
+}
+
+// SplitMix64, small and seedable so simulations are reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    // uniform in 0..n
+    fn below(&mut self, n: usize) -> usize {
+        ((self.next_f64() * n as f64) as usize).min(n.saturating_sub(1))
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+fn pearson(pairs: &[(f64, f64)]) -> Option<f64> {
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|p| p.1).sum::<f64>() / n;
+    let (mut cov, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for (x, y) in pairs {
+        cov += (x - mean_x) * (y - mean_y);
+        var_x += (x - mean_x).powi(2);
+        var_y += (y - mean_y).powi(2);
+    }
+    if var_x == 0.0 || var_y == 0.0 {
+        return None;
+    }
+    Some(cov / (var_x * var_y).sqrt())
+}
+
+impl Simulation {
+    pub fn generate(&self) -> SimulatedData {
+        let mut rng = Rng(self.seed);
+        let sources: Vec<(String, f64)> = (0..self.source_count)
+            .map(|i| {
+                let quality =
+                    self.min_quality + (self.max_quality - self.min_quality) * rng.next_f64();
+                (format!("s{}", i), quality)
+            })
+            .collect();
+        let questions: Vec<(String, String)> = (0..self.question_count)
+            .map(|i| (format!("q{}", i), String::from("true")))
+            .collect();
+        let (wrong_answers, turning_sources, turn_after) = match self.error_model {
+            ErrorModel::Uniform { wrong_answers } => (wrong_answers, 0, 1.0),
+            ErrorModel::StartGoodTurnBad {
+                wrong_answers,
+                fraction_of_sources,
+                turn_after,
+            } => (
+                wrong_answers,
+                (fraction_of_sources * self.source_count as f64).round() as usize,
+                turn_after,
+            ),
+        };
+        let wrong_answer = |rng: &mut Rng| format!("wrong{}", rng.below(wrong_answers.max(1)));
+
+        // Which distinct sources answer each question, in shuffled order
+        let mut picks: Vec<(usize, usize)> = Vec::new();
+        let mut source_indices: Vec<usize> = (0..self.source_count).collect();
+        for question_index in 0..self.question_count {
+            rng.shuffle(&mut source_indices);
+            for &source_index in source_indices.iter().take(self.answers_per_question) {
+                picks.push((question_index, source_index));
+            }
+        }
+        rng.shuffle(&mut picks);
+
+        let turn_at = (turn_after * picks.len() as f64) as usize;
+        let submissions = picks
+            .iter()
+            .enumerate()
+            .map(|(i, &(question_index, source_index))| {
+                let (question, truth) = &questions[question_index];
+                let (source, quality) = &sources[source_index];
+                let answer = if source_index < turning_sources {
+                    if i < turn_at {
+                        truth.clone()
+                    } else {
+                        String::from("wrong0")
+                    }
+                } else if rng.next_f64() < *quality {
+                    truth.clone()
+                } else {
+                    wrong_answer(&mut rng)
+                };
+                (question.clone(), answer, source.clone())
+            })
+            .collect();
+        SimulatedData {
+            sources,
+            questions,
+            submissions,
+        }
+    }
+
+    // Generate the data, SET every submission on a new graph with the
+    // simulation's config and compare the result with the truth
+    pub fn run(&self) -> Result<SimulationReport, ConfidisError> {
+        let data = self.generate();
+        let mut g = Graph::new_with_config(self.config.clone());
+        for (question, answer, source) in &data.submissions {
+            g.set_answer(
+                &QuestionId::new(question.as_str())?,
+                answer,
+                &SourceId::new(source.as_str())?,
+            )?;
+        }
+        Ok(self.evaluate(&g, &data))
+    }
+
+    fn evaluate(&self, g: &Graph, data: &SimulatedData) -> SimulationReport {
+        let (mut correct, mut confidence) = (0, 0.0);
+        for (question, truth) in &data.questions {
+            if let Ok((answer, answer_confidence)) = g.compute_answer(question) {
+                if &answer == truth {
+                    correct += 1;
+                }
+                confidence += answer_confidence;
+            }
+        }
+        let qualities: Vec<(f64, f64)> = data
+            .observed_qualities()
+            .into_iter()
+            .filter_map(|(source, observed)| g.source(&source).map(|s| (s.quality, observed)))
+            .collect();
+        let question_count = data.questions.len().max(1) as f64;
+        SimulationReport {
+            answer_accuracy: correct as f64 / question_count,
+            mean_confidence: confidence / question_count,
+            quality_mean_absolute_error: qualities
+                .iter()
+                .map(|(estimated, observed)| (estimated - observed).abs())
+                .sum::<f64>()
+                / qualities.len().max(1) as f64,
+            quality_correlation: pearson(&qualities),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulation() {
+        let simulation = Simulation {
+            question_count: 200,
+            ..Default::default()
+        };
+        assert_eq!(simulation.generate(), simulation.generate());
+        assert_eq!(
+            simulation.generate().submissions.len(),
+            200 * simulation.answers_per_question
+        );
+
+        let report = simulation.run().unwrap();
+        assert!(report.answer_accuracy > 0.8, "{}", report);
+        assert!(report.quality_correlation.unwrap() > 0.5, "{}", report);
+
+        // Sources that turn bad drag down accuracy once they do
+        let attacked = Simulation {
+            question_count: 200,
+            error_model: ErrorModel::StartGoodTurnBad {
+                wrong_answers: 3,
+                fraction_of_sources: 0.5,
+                turn_after: 0.5,
+            },
+            ..Default::default()
+        }
+        .run()
+        .unwrap();
+        assert!(attacked.answer_accuracy < report.answer_accuracy);
+    }
+}
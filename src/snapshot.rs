@@ -0,0 +1,484 @@
+// Binary snapshots of a Graph
+//
+// Layout:
+//   magic   8 bytes  b"CONFIDIS"
+//   version u16 (little endian), SNAPSHOT_VERSION when written
+//   body    the bincode encoded graph state
+//
+// Snapshots written by a newer format version are rejected instead of being
+// misread. The settings are stored as JSON text, so settings added later load
+// with their defaults without a new format version.
+//
+// A SnapshotPolicy makes a graph snapshot itself into a directory every N
+// mutations and/or every T seconds. Once a snapshot is safely on disk the
+// graph's journal is truncated, since the snapshot covers every record in it.
+// Graph::recover restores the latest snapshot and replays the journal on top.
+//
+// An encrypted snapshot is ENCRYPTED_SNAPSHOT_MAGIC followed by a plain
+// snapshot sealed with the key and the magic as associated data, so an
+// encrypted journal record can't pass for a snapshot, see encryption.rs. A
+// SnapshotPolicy with a key writes encrypted snapshots, and
+// Graph::recover_encrypted reads them.
+
+use crate::encryption::EncryptionKey;
+use crate::graph::Graph;
+use crate::journal::now_millis;
+use log::{info, warn};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"CONFIDIS";
+pub const SNAPSHOT_VERSION: u16 = 1;
+pub const ENCRYPTED_SNAPSHOT_MAGIC: &[u8; 8] = b"CONFIENC";
+
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const SNAPSHOT_EXTENSION: &str = ".bin";
+
+#[derive(Debug, Clone)]
+pub struct SnapshotPolicy {
+    pub directory: PathBuf,
+
+    // Snapshot after this many mutating commands (SET, set_many, BELIEVE ...)
+    pub every_mutations: Option<u64>,
+
+    // Snapshot on the first mutation after this much time has passed
+    pub every: Option<Duration>,
+
+    // How many snapshots to keep, older ones are deleted
+    pub retain: usize,
+
+    // Encrypt the snapshots with this key
+    pub key: Option<EncryptionKey>,
+}
+
+impl SnapshotPolicy {
+    pub fn new<P: AsRef<Path>>(directory: P) -> SnapshotPolicy {
+        SnapshotPolicy {
+            directory: directory.as_ref().to_path_buf(),
+            every_mutations: Some(1000),
+            every: None,
+            retain: 3,
+            key: None,
+        }
+    }
+}
+
+pub(crate) struct SnapshotSchedule {
+    policy: SnapshotPolicy,
+    mutations: u64,
+    last_snapshot: Instant,
+}
+
+// Snapshot files in directory, oldest first
+pub fn list_snapshots<P: AsRef<Path>>(directory: P) -> Result<Vec<PathBuf>, String> {
+    let directory = directory.as_ref();
+    let mut snapshots: Vec<PathBuf> = fs::read_dir(directory)
+        .map_err(|e| format!("Couldn't read {}: {}", directory.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    name.starts_with(SNAPSHOT_PREFIX) && name.ends_with(SNAPSHOT_EXTENSION)
+                })
+        })
+        .collect();
+    // Names embed a zero padded timestamp, so they sort chronologically
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+// When a snapshot file was written, from its name
+fn snapshot_timestamp(path: &Path) -> Option<u64> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix(SNAPSHOT_PREFIX)?
+        .strip_suffix(SNAPSHOT_EXTENSION)?
+        .parse()
+        .ok()
+}
+
+impl Graph {
+    // The snapshots the snapshot policy has kept with when they were taken,
+    // oldest first, none without a snapshot policy
+    pub(crate) fn snapshot_key(&self) -> Option<&EncryptionKey> {
+        self.snapshot_schedule.as_ref()?.policy.key.as_ref()
+    }
+
+    pub(crate) fn snapshot_history(&self) -> Result<Vec<(u64, PathBuf)>, String> {
+        let schedule = match self.snapshot_schedule.as_ref() {
+            Some(schedule) => schedule,
+            None => return Ok(Vec::new()),
+        };
+        Ok(list_snapshots(&schedule.policy.directory)?
+            .into_iter()
+            .filter_map(|path| Some((snapshot_timestamp(&path)?, path)))
+            .collect())
+    }
+
+    pub fn save_snapshot<W: Write>(&self, writer: W) -> Result<(), String> {
+        let mut writer = BufWriter::new(writer);
+        writer
+            .write_all(SNAPSHOT_MAGIC)
+            .and_then(|_| writer.write_all(&SNAPSHOT_VERSION.to_le_bytes()))
+            .map_err(|e| format!("Couldn't write snapshot header: {}", e))?;
+        bincode::serialize_into(&mut writer, self)
+            .map_err(|e| format!("Couldn't write snapshot: {}", e))?;
+        writer
+            .flush()
+            .map_err(|e| format!("Couldn't write snapshot: {}", e))
+    }
+
+    pub fn load_snapshot<R: Read>(reader: R) -> Result<Graph, String> {
+        let mut reader = BufReader::new(reader);
+        let mut magic = [0_u8; 8];
+        let mut version = [0_u8; 2];
+        reader
+            .read_exact(&mut magic)
+            .and_then(|_| reader.read_exact(&mut version))
+            .map_err(|e| format!("Couldn't read snapshot header: {}", e))?;
+        if &magic == ENCRYPTED_SNAPSHOT_MAGIC {
+            return Err("Snapshot is encrypted, load it with its key".into());
+        }
+        if &magic != SNAPSHOT_MAGIC {
+            return Err("Not a confidis snapshot (bad magic header)".into());
+        }
+        let version = u16::from_le_bytes(version);
+        if version > SNAPSHOT_VERSION {
+            return Err(format!(
+                "Snapshot format version {} is newer than the supported version {}",
+                version, SNAPSHOT_VERSION
+            ));
+        }
+        bincode::deserialize_from(reader).map_err(|e| format!("Couldn't read snapshot: {}", e))
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn save_encrypted_snapshot<W: Write>(
+        &self,
+        writer: W,
+        key: &EncryptionKey,
+    ) -> Result<(), String> {
+        self.save_snapshot_with_key(writer, Some(key))
+    }
+
+    // Plain snapshots load too
+    #[cfg(feature = "encryption")]
+    pub fn load_encrypted_snapshot<R: Read>(
+        reader: R,
+        key: &EncryptionKey,
+    ) -> Result<Graph, String> {
+        Graph::load_snapshot_with_key(reader, Some(key))
+    }
+
+    pub(crate) fn save_snapshot_with_key<W: Write>(
+        &self,
+        mut writer: W,
+        key: Option<&EncryptionKey>,
+    ) -> Result<(), String> {
+        let key = match key {
+            Some(key) => key,
+            None => return self.save_snapshot(writer),
+        };
+        let mut plain = Vec::new();
+        self.save_snapshot(&mut plain)?;
+        writer
+            .write_all(ENCRYPTED_SNAPSHOT_MAGIC)
+            .and_then(|_| writer.write_all(&key.seal(&plain, ENCRYPTED_SNAPSHOT_MAGIC)))
+            .and_then(|_| writer.flush())
+            .map_err(|e| format!("Couldn't write snapshot: {}", e))
+    }
+
+    pub(crate) fn load_snapshot_with_key<R: Read>(
+        mut reader: R,
+        key: Option<&EncryptionKey>,
+    ) -> Result<Graph, String> {
+        let key = match key {
+            Some(key) => key,
+            None => return Graph::load_snapshot(reader),
+        };
+        let mut magic = [0_u8; 8];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|e| format!("Couldn't read snapshot header: {}", e))?;
+        if &magic != ENCRYPTED_SNAPSHOT_MAGIC {
+            return Graph::load_snapshot((&magic[..]).chain(reader));
+        }
+        let mut sealed = Vec::new();
+        reader
+            .read_to_end(&mut sealed)
+            .map_err(|e| format!("Couldn't read snapshot: {}", e))?;
+        let plain = key
+            .open(&sealed, ENCRYPTED_SNAPSHOT_MAGIC)
+            .map_err(|e| format!("Couldn't read encrypted snapshot: {}", e))?;
+        Graph::load_snapshot(&plain[..])
+    }
+
+    pub fn set_snapshot_policy(&mut self, policy: SnapshotPolicy) -> Result<(), String> {
+        fs::create_dir_all(&policy.directory)
+            .map_err(|e| format!("Couldn't create {}: {}", policy.directory.display(), e))?;
+        self.snapshot_schedule = Some(SnapshotSchedule {
+            policy,
+            mutations: 0,
+            last_snapshot: Instant::now(),
+        });
+        Ok(())
+    }
+
+    pub fn take_snapshot_policy(&mut self) -> Option<SnapshotPolicy> {
+        self.snapshot_schedule
+            .take()
+            .map(|schedule| schedule.policy)
+    }
+
+    // Write a snapshot into the policy's directory now, truncate the journal and
+    // apply retention. Returns the path of the new snapshot.
+    pub fn snapshot_now(&mut self) -> Result<PathBuf, String> {
+        let policy = match self.snapshot_schedule.as_ref() {
+            Some(schedule) => schedule.policy.clone(),
+            None => return Err("No snapshot policy set".into()),
+        };
+        let path = self.write_snapshot_file(&policy.directory, policy.key.as_ref())?;
+        if let Some(journal) = self.journal_mut() {
+            journal.truncate()?;
+        }
+        // Recovery starts from the snapshot, which can't be undone
+        self.clear_undo();
+        if let Some(schedule) = self.snapshot_schedule.as_mut() {
+            schedule.mutations = 0;
+            schedule.last_snapshot = Instant::now();
+        }
+
+        let snapshots = list_snapshots(&policy.directory)?;
+        let expired = snapshots.len().saturating_sub(policy.retain.max(1));
+        for old in &snapshots[..expired] {
+            if let Err(e) = fs::remove_file(old) {
+                warn!("Couldn't remove old snapshot {}: {}", old.display(), e);
+            }
+        }
+        Ok(path)
+    }
+
+    // Called after every mutation
+    pub(crate) fn snapshot_if_due(&mut self) {
+        let due = match self.snapshot_schedule.as_mut() {
+            Some(schedule) => {
+                schedule.mutations += 1;
+                let policy = &schedule.policy;
+                policy
+                    .every_mutations
+                    .is_some_and(|n| schedule.mutations >= n)
+                    || policy
+                        .every
+                        .is_some_and(|every| schedule.last_snapshot.elapsed() >= every)
+            }
+            None => false,
+        };
+        // A graph can't be serialized mid bulk load, the snapshot is taken on the
+        // first mutation after it finishes
+        if due && !self.is_bulk_loading() {
+            match self.snapshot_now() {
+                Ok(path) => info!("Wrote snapshot {}", path.display()),
+                Err(msg) => warn!("Automatic snapshot failed: {}", msg),
+            }
+        }
+    }
+
+    // Write to a temporary file and rename it, so a crash never leaves a partial
+    // snapshot that looks complete
+    fn write_snapshot_file(
+        &self,
+        directory: &Path,
+        key: Option<&EncryptionKey>,
+    ) -> Result<PathBuf, String> {
+        let mut timestamp = now_millis();
+        let mut path;
+        loop {
+            path = directory.join(format!(
+                "{}{:020}{}",
+                SNAPSHOT_PREFIX, timestamp, SNAPSHOT_EXTENSION
+            ));
+            if !path.exists() {
+                break;
+            }
+            timestamp += 1;
+        }
+        let tmp_path = path.with_extension("tmp");
+        let file = File::create(&tmp_path)
+            .map_err(|e| format!("Couldn't create {}: {}", tmp_path.display(), e))?;
+        self.save_snapshot_with_key(&file, key)?;
+        file.sync_all()
+            .and_then(|_| fs::rename(&tmp_path, &path))
+            .map_err(|e| format!("Couldn't write snapshot {}: {}", path.display(), e))?;
+        Ok(path)
+    }
+
+    // Restore the latest snapshot in directory (if any) and replay the journal
+    // (if it exists) on top of it
+    pub fn recover<P: AsRef<Path>, J: AsRef<Path>>(
+        directory: P,
+        journal_path: Option<J>,
+    ) -> Result<Graph, String> {
+        Graph::recover_with_key(directory.as_ref(), journal_path, None)
+    }
+
+    // Graph::recover with snapshots and a journal encrypted with key
+    #[cfg(feature = "encryption")]
+    pub fn recover_encrypted<P: AsRef<Path>, J: AsRef<Path>>(
+        directory: P,
+        journal_path: Option<J>,
+        key: &EncryptionKey,
+    ) -> Result<Graph, String> {
+        Graph::recover_with_key(directory.as_ref(), journal_path, Some(key))
+    }
+
+    fn recover_with_key<J: AsRef<Path>>(
+        directory: &Path,
+        journal_path: Option<J>,
+        key: Option<&EncryptionKey>,
+    ) -> Result<Graph, String> {
+        let mut g = match list_snapshots(directory) {
+            Ok(snapshots) => match snapshots.last() {
+                Some(latest) => {
+                    let file = File::open(latest)
+                        .map_err(|e| format!("Couldn't open {}: {}", latest.display(), e))?;
+                    Graph::load_snapshot_with_key(file, key)?
+                }
+                None => Graph::new(),
+            },
+            Err(_) if !directory.exists() => Graph::new(),
+            Err(msg) => return Err(msg),
+        };
+        if let Some(journal_path) = journal_path {
+            if journal_path.as_ref().exists() {
+                g.apply_journal_with_key(journal_path.as_ref(), key)?;
+            }
+        }
+        Ok(g)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let mut g = Graph::new();
+        for line in &[
+            "CONFIGURE comparison_method numeric_vec allowed_difference=1 vec_length=2 diff_fn=l2",
+            "SET q1 1,2 FROM s1",
+            "SET q1 1,2.1 FROM s2",
+            "SET q1 5,5 FROM s3",
+            "BELIEVE s2",
+        ] {
+            g.execute_command(&Command::from(line).unwrap()).unwrap();
+        }
+
+        let mut bytes: Vec<u8> = Vec::new();
+        g.save_snapshot(&mut bytes).unwrap();
+        assert_eq!(&bytes[..8], SNAPSHOT_MAGIC);
+
+        let mut restored = Graph::load_snapshot(&bytes[..]).unwrap();
+        assert_eq!(restored.equalifier_config(), g.equalifier_config());
+        for line in &["GET ANSWER TO q1", "GET SOURCE s1", "GET SOURCE s3"] {
+            let cmd = Command::from(line).unwrap();
+            assert_eq!(
+                format!("{}", restored.execute_command(&cmd).unwrap()),
+                format!("{}", g.execute_command(&cmd).unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn test_periodic_snapshots() {
+        use crate::journal::Journal;
+
+        let directory =
+            std::env::temp_dir().join(format!("confidis-snapshots-{}", std::process::id()));
+        let journal_path = directory.join("journal.log");
+        let _ = fs::remove_dir_all(&directory);
+
+        let mut g = Graph::new();
+        g.set_snapshot_policy(SnapshotPolicy {
+            every_mutations: Some(2),
+            retain: 2,
+            ..SnapshotPolicy::new(&directory)
+        })
+        .unwrap();
+        g.set_journal(Journal::open(&journal_path).unwrap());
+        for i in 0..7 {
+            let line = format!("SET q{} a FROM s{}", i % 3, i % 2);
+            g.execute_command(&Command::from(&line).unwrap()).unwrap();
+        }
+        g.execute_command(&Command::from("GET ANSWER TO q1").unwrap())
+            .unwrap();
+
+        // 3 snapshots were taken, the oldest was deleted
+        assert_eq!(list_snapshots(&directory).unwrap().len(), 2);
+        // only the 7th SET happened after the last snapshot
+        assert_eq!(Journal::read(&journal_path).unwrap().len(), 1);
+
+        let mut restored = Graph::recover(&directory, Some(&journal_path)).unwrap();
+        for line in &["GET ANSWER TO q0", "GET ANSWER TO q2", "GET SOURCE s1"] {
+            let cmd = Command::from(line).unwrap();
+            assert_eq!(
+                format!("{}", restored.execute_command(&cmd).unwrap()),
+                format!("{}", g.execute_command(&cmd).unwrap())
+            );
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_header_checks() {
+        let mut bytes: Vec<u8> = Vec::new();
+        Graph::new().save_snapshot(&mut bytes).unwrap();
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(Graph::load_snapshot(&bad_magic[..])
+            .err()
+            .unwrap()
+            .contains("magic"));
+
+        let mut future_version = bytes.clone();
+        future_version[8..10].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+        assert!(Graph::load_snapshot(&future_version[..])
+            .err()
+            .unwrap()
+            .contains("newer"));
+
+        assert!(Graph::load_snapshot(&bytes[..4]).is_err());
+        assert!(Graph::load_snapshot(&bytes[..]).is_ok());
+    }
+
+    #[test]
+    fn test_load_snapshot_without_later_settings() {
+        let mut g = Graph::new();
+        g.set_many(&[("q1", "a", "s1"), ("q1", "a", "s2"), ("q1", "b", "s3")])
+            .unwrap();
+
+        // The settings are JSON text, settings missing from it load with
+        // their defaults. bincode concatenates the fields of the state.
+        let mut config = serde_json::to_value(g.config()).unwrap();
+        config.as_object_mut().unwrap().remove("rate_limit_window");
+        let mut bytes = SNAPSHOT_MAGIC.to_vec();
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        bytes.extend(bincode::serialize(&config.to_string()).unwrap());
+        bytes.extend(
+            bincode::serialize(&(g.equalifier_config(), &g.sources, &g.questions)).unwrap(),
+        );
+
+        let mut restored = Graph::load_snapshot(&bytes[..]).unwrap();
+        assert_eq!(restored.config(), g.config());
+        let cmd = Command::from("GET ANSWER TO q1").unwrap();
+        assert_eq!(
+            format!("{}", restored.execute_command(&cmd).unwrap()),
+            format!("{}", g.execute_command(&cmd).unwrap())
+        );
+    }
+}
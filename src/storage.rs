@@ -0,0 +1,560 @@
+// Pluggable persistence for graphs
+//
+// A Storage keeps sources, questions and answers outside of a Graph. StoredGraph
+// pairs a Graph with a Storage: sources and the configuration are loaded when it
+// is opened, questions (and their answers) are only loaded when a command
+// touches them and can be evicted from memory again, and every mutation is
+// written through to the storage. Which Storage is used decides the tradeoff
+// between durability and speed, the Graph itself doesn't change.
+
+#[cfg(feature = "sled")]
+pub mod sled;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+use crate::command::{Answer, Command, CommandResponse, CommandType};
+use crate::config::{DuplicateAnswers, GraphConfig};
+use crate::equalifier::EqualifierConfig;
+use crate::error::ConfidisError;
+use crate::graph::{Graph, Question, Source};
+use log::warn;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+pub trait Storage {
+    fn get_meta(&self, key: &str) -> Result<Option<String>, String>;
+    fn put_meta(&mut self, key: &str, value: &str) -> Result<(), String>;
+
+    // Every stored source, used to populate the graph when it's opened
+    fn sources(&self) -> Result<Vec<Source>, String>;
+    fn get_source(&self, source_name: &str) -> Result<Option<Source>, String>;
+    fn put_source(&mut self, source: &Source) -> Result<(), String>;
+
+    // Questions are stored without their answers, see get_answers
+    fn get_question(&self, question_name: &str) -> Result<Option<Question>, String>;
+    fn put_question(&mut self, question: &Question) -> Result<(), String>;
+    // Every stored question, used to load them all for REBUILD
+    fn question_names(&self) -> Result<Vec<String>, String>;
+
+    fn get_answers(&self, question_name: &str) -> Result<Vec<Answer>, String>;
+    fn answer_count(&self, question_name: &str) -> Result<usize, String>;
+    // Answers are appended, answers[0] is stored at first_position
+    fn append_answers(
+        &mut self,
+        question_name: &str,
+        first_position: usize,
+        answers: &[Answer],
+    ) -> Result<(), String>;
+    // Replace every stored answer of the question, used once answers were
+    // removed (by duplicate_answers replace)
+    fn put_answers(&mut self, question_name: &str, answers: &[Answer]) -> Result<(), String>;
+
+    // Writes between begin and commit are applied atomically if the storage
+    // supports it
+    fn begin(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    fn commit(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    fn rollback(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Keeps everything in HashMaps, nothing survives the process
+#[derive(Default)]
+pub struct MemoryStorage {
+    meta: HashMap<String, String>,
+    sources: HashMap<String, Source>,
+    questions: HashMap<String, Question>,
+    answers: HashMap<String, Vec<Answer>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get_meta(&self, key: &str) -> Result<Option<String>, String> {
+        Ok(self.meta.get(key).cloned())
+    }
+
+    fn put_meta(&mut self, key: &str, value: &str) -> Result<(), String> {
+        self.meta.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn sources(&self) -> Result<Vec<Source>, String> {
+        Ok(self.sources.values().cloned().collect())
+    }
+
+    fn get_source(&self, source_name: &str) -> Result<Option<Source>, String> {
+        Ok(self.sources.get(source_name).cloned())
+    }
+
+    fn put_source(&mut self, source: &Source) -> Result<(), String> {
+        self.sources.insert(source.name.clone(), source.clone());
+        Ok(())
+    }
+
+    fn get_question(&self, question_name: &str) -> Result<Option<Question>, String> {
+        Ok(self.questions.get(question_name).cloned())
+    }
+
+    fn put_question(&mut self, question: &Question) -> Result<(), String> {
+        self.questions.insert(
+            question.name.clone(),
+            Question {
+                name: question.name.clone(),
+                correct_answers: question.correct_answers.clone(),
+                weight: question.weight,
+                confidence: question.confidence,
+                answers: Vec::new(),
+                answered_at: question.answered_at,
+                excluded_sources: question.excluded_sources.clone(),
+                honeypot: question.honeypot.clone(),
+                schema: question.schema.clone(),
+                question_type: question.question_type.clone(),
+            },
+        );
+        Ok(())
+    }
+
+    fn question_names(&self) -> Result<Vec<String>, String> {
+        Ok(self.questions.keys().cloned().collect())
+    }
+
+    fn get_answers(&self, question_name: &str) -> Result<Vec<Answer>, String> {
+        Ok(self.answers.get(question_name).cloned().unwrap_or_default())
+    }
+
+    fn answer_count(&self, question_name: &str) -> Result<usize, String> {
+        Ok(self.answers.get(question_name).map_or(0, |a| a.len()))
+    }
+
+    fn append_answers(
+        &mut self,
+        question_name: &str,
+        first_position: usize,
+        answers: &[Answer],
+    ) -> Result<(), String> {
+        let stored = self.answers.entry(question_name.to_string()).or_default();
+        stored.truncate(first_position);
+        stored.extend_from_slice(answers);
+        Ok(())
+    }
+
+    fn put_answers(&mut self, question_name: &str, answers: &[Answer]) -> Result<(), String> {
+        self.answers
+            .insert(question_name.to_string(), answers.to_vec());
+        Ok(())
+    }
+}
+
+pub struct StoredGraph<S: Storage> {
+    graph: Graph,
+    storage: S,
+
+    // Questions currently in memory, oldest first
+    loaded_questions: VecDeque<String>,
+    loaded_question_set: HashSet<String>,
+
+    // Evict the oldest questions once more than this many are in memory
+    max_loaded_questions: usize,
+}
+
+impl<S: Storage> StoredGraph<S> {
+    pub fn new(storage: S) -> Result<StoredGraph<S>, String> {
+        let config: GraphConfig = match storage.get_meta("config")? {
+            Some(json) => serde_json::from_str(&json).map_err(|e| e.to_string())?,
+            None => GraphConfig::default(),
+        };
+        let mut graph = Graph::new_with_config(config);
+        if let Some(json) = storage.get_meta("equalifier")? {
+            let equalifier_config: EqualifierConfig =
+                serde_json::from_str(&json).map_err(|e| e.to_string())?;
+            if let Some(equalifier) = equalifier_config.build() {
+                graph.set_equalifier(equalifier);
+            }
+        }
+        for source in storage.sources()? {
+            graph.insert_source(source);
+        }
+
+        Ok(StoredGraph {
+            graph,
+            storage,
+            loaded_questions: VecDeque::new(),
+            loaded_question_set: HashSet::new(),
+            max_loaded_questions: usize::MAX,
+        })
+    }
+
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    pub fn set_max_loaded_questions(&mut self, max_loaded_questions: usize) {
+        self.max_loaded_questions = max_loaded_questions;
+        self.evict_questions(max_loaded_questions);
+    }
+
+    pub fn loaded_question_count(&self) -> usize {
+        self.loaded_questions.len()
+    }
+
+    // Drop the oldest loaded questions from memory until at most keep remain.
+    // They are already persisted, so nothing is lost.
+    pub fn evict_questions(&mut self, keep: usize) {
+        while self.loaded_questions.len() > keep {
+            let question_name = self.loaded_questions.pop_front().unwrap();
+            self.loaded_question_set.remove(&question_name);
+            self.graph.take_question(&question_name);
+        }
+    }
+
+    // Errors of the storage are ConfidisError::Storage, those of the command
+    // are the graph's
+    pub fn execute_command(&mut self, cmd: &Command) -> Result<CommandResponse, ConfidisError> {
+        // Restoring questions behind the storage's back could resurrect
+        // evicted questions with stale answers
+        if matches!(cmd.cmd, CommandType::Undo | CommandType::Redo) {
+            return Err(ConfidisError::NotImplemented(format!(
+                "\"{}\" isn't supported by stored graphs",
+                cmd
+            )));
+        }
+        if let Some(question_name) = cmd.question.as_ref() {
+            self.load_question(question_name)
+                .map_err(ConfidisError::Storage)?;
+        }
+        // MGET reads several questions
+        for question_name in cmd.questions.iter().flatten() {
+            self.load_question(question_name)
+                .map_err(ConfidisError::Storage)?;
+        }
+        let replaced = match (cmd.cmd, &cmd.question, &cmd.source) {
+            (CommandType::Set, Some(question_name), Some(source_name)) => {
+                self.replaced_questions(&[(question_name.as_ref(), source_name.as_ref())])
+            }
+            _ => HashSet::new(),
+        };
+        // REBUILD recomputes every question and changes every source
+        let rebuilt = match cmd.cmd {
+            CommandType::Rebuild => {
+                let question_names = self
+                    .storage
+                    .question_names()
+                    .map_err(ConfidisError::Storage)?;
+                for question_name in &question_names {
+                    self.load_question(question_name)
+                        .map_err(ConfidisError::Storage)?;
+                }
+                question_names
+            }
+            _ => Vec::new(),
+        };
+        let response = self.graph.execute_command(cmd)?;
+        match cmd.cmd {
+            CommandType::Set
+            | CommandType::Exclude
+            | CommandType::Honeypot
+            | CommandType::Schema
+            | CommandType::Type => self
+                .persist_questions(&[cmd.field("question")?], &replaced)
+                .map_err(ConfidisError::Storage)?,
+            CommandType::Believe | CommandType::GetSource => self
+                .persist_sources(&[cmd.field("source")?])
+                .map_err(ConfidisError::Storage)?,
+            CommandType::Configure => self.persist_config().map_err(ConfidisError::Storage)?,
+            CommandType::Rebuild => {
+                let question_names: Vec<&str> = rebuilt.iter().map(String::as_str).collect();
+                self.persist_questions(&question_names, &replaced)
+                    .and_then(|_| {
+                        self.write(|storage, graph| {
+                            write_sources(storage, graph, graph.sources().map(|source| source.name))
+                        })
+                    })
+                    .map_err(ConfidisError::Storage)?;
+            }
+            _ => {}
+        }
+        self.evict_questions(self.max_loaded_questions);
+        Ok(response)
+    }
+
+    pub fn set_many(&mut self, entries: &[(&str, &str, &str)]) -> Result<(), ConfidisError> {
+        let mut question_names: Vec<&str> = Vec::new();
+        let mut seen: HashSet<&str> = HashSet::new();
+        for (question_name, _, _) in entries {
+            if seen.insert(question_name) {
+                self.load_question(question_name)
+                    .map_err(ConfidisError::Storage)?;
+                question_names.push(question_name);
+            }
+        }
+        let answered: Vec<(&str, &str)> = entries
+            .iter()
+            .map(|(question_name, _, source_name)| (*question_name, *source_name))
+            .collect();
+        let replaced = self.replaced_questions(&answered);
+        self.graph.set_many(entries)?;
+        self.persist_questions(&question_names, &replaced)
+            .map_err(ConfidisError::Storage)?;
+        self.evict_questions(self.max_loaded_questions);
+        Ok(())
+    }
+
+    fn load_question(&mut self, question_name: &str) -> Result<(), String> {
+        if self.graph.has_question(question_name) {
+            return Ok(());
+        }
+        let mut question = match self.storage.get_question(question_name)? {
+            Some(question) => question,
+            None => return Ok(()),
+        };
+        question.answers = self.storage.get_answers(question_name)?;
+        question.rehash(self.graph.config().answer_hash);
+        self.graph.insert_question(question);
+        self.track_question(question_name);
+        Ok(())
+    }
+
+    // The questions whose earlier answers from the source duplicate_answers
+    // replace removes when it answers again. Call before answering.
+    fn replaced_questions(&self, answered: &[(&str, &str)]) -> HashSet<String> {
+        if self.graph.config().duplicate_answers != DuplicateAnswers::Replace {
+            return HashSet::new();
+        }
+        answered
+            .iter()
+            .filter(|(question_name, source_name)| {
+                self.graph.question(question_name).is_some_and(|question| {
+                    question
+                        .answers
+                        .iter()
+                        .any(|answer| answer.source == *source_name)
+                })
+            })
+            .map(|(question_name, _)| question_name.to_string())
+            .collect()
+    }
+
+    fn track_question(&mut self, question_name: &str) {
+        if self.loaded_question_set.insert(question_name.to_string()) {
+            self.loaded_questions.push_back(question_name.to_string());
+        }
+    }
+
+    // Run writes in a single storage transaction
+    fn write<F>(&mut self, f: F) -> Result<(), String>
+    where
+        F: FnOnce(&mut S, &Graph) -> Result<(), String>,
+    {
+        self.storage.begin()?;
+        match f(&mut self.storage, &self.graph) {
+            Ok(()) => self.storage.commit(),
+            Err(e) => {
+                self.storage.rollback()?;
+                Err(e)
+            }
+        }
+    }
+
+    // Write the questions, their answers and every source that answered them.
+    // New answers are appended, the answers of replaced questions rewritten.
+    fn persist_questions(
+        &mut self,
+        question_names: &[&str],
+        replaced: &HashSet<String>,
+    ) -> Result<(), String> {
+        for question_name in question_names {
+            if self.graph.has_question(question_name) {
+                self.track_question(question_name);
+            }
+        }
+        self.write(|storage, graph| {
+            let mut source_names: HashSet<&str> = HashSet::new();
+            for question_name in question_names {
+                let question = match graph.question(question_name) {
+                    Some(question) => question,
+                    None => continue,
+                };
+                storage.put_question(question)?;
+                if replaced.contains(&question.name) {
+                    storage.put_answers(&question.name, &question.answers)?;
+                } else {
+                    let stored = storage.answer_count(&question.name)?;
+                    if stored < question.answers.len() {
+                        storage.append_answers(
+                            &question.name,
+                            stored,
+                            &question.answers[stored..],
+                        )?;
+                    }
+                }
+                for answer in &question.answers {
+                    source_names.insert(&answer.source);
+                }
+            }
+            write_sources(storage, graph, source_names.into_iter())
+        })
+    }
+
+    fn persist_sources(&mut self, source_names: &[&str]) -> Result<(), String> {
+        self.write(|storage, graph| write_sources(storage, graph, source_names.iter().copied()))
+    }
+
+    fn persist_config(&mut self) -> Result<(), String> {
+        self.write(|storage, graph| {
+            storage.put_meta("config", &serde_json::to_string(graph.config()).unwrap())?;
+            match graph.equalifier_config() {
+                EqualifierConfig::Custom => {
+                    warn!("Custom equalifiers can't be persisted");
+                    Ok(())
+                }
+                equalifier_config => storage.put_meta(
+                    "equalifier",
+                    &serde_json::to_string(&equalifier_config).unwrap(),
+                ),
+            }
+        })
+    }
+}
+
+impl StoredGraph<MemoryStorage> {
+    pub fn in_memory() -> StoredGraph<MemoryStorage> {
+        StoredGraph::new(MemoryStorage::new()).unwrap()
+    }
+}
+
+fn write_sources<'a, S: Storage>(
+    storage: &mut S,
+    graph: &Graph,
+    source_names: impl Iterator<Item = &'a str>,
+) -> Result<(), String> {
+    for source_name in source_names {
+        if let Some(source) = graph.source(source_name) {
+            storage.put_source(source)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run<S: Storage>(g: &mut StoredGraph<S>, line: &str) -> String {
+        format!(
+            "{}",
+            g.execute_command(&Command::from(line).unwrap()).unwrap()
+        )
+    }
+
+    #[test]
+    fn test_stored_graph_evicts_questions() {
+        let mut g = StoredGraph::in_memory();
+        let mut in_memory = Graph::new();
+        g.set_max_loaded_questions(1);
+        for line in &["SET q1 a FROM s1", "SET q1 a FROM s2", "SET q2 b FROM s1"] {
+            run(&mut g, line);
+            in_memory
+                .execute_command(&Command::from(line).unwrap())
+                .unwrap();
+        }
+        assert_eq!(g.loaded_question_count(), 1);
+        assert!(!g.graph().has_question("q1"));
+
+        // q1 is transparently reloaded
+        let cmd = Command::from("GET ANSWER TO q1").unwrap();
+        assert_eq!(
+            run(&mut g, "GET ANSWER TO q1"),
+            format!("{}", in_memory.execute_command(&cmd).unwrap())
+        );
+        assert!(g.graph().has_question("q1"));
+        run(&mut g, "SET q1 c FROM s3");
+        assert_eq!(g.graph().question("q1").unwrap().answers.len(), 3);
+        assert_eq!(g.storage().answer_count("q1").unwrap(), 3);
+
+        // REBUILD loads the evicted questions and persists every source
+        in_memory
+            .execute_command(&Command::from("SET q1 c FROM s3").unwrap())
+            .unwrap();
+        let cmd = Command::from("REBUILD").unwrap();
+        in_memory.execute_command(&cmd).unwrap();
+        g.execute_command(&cmd).unwrap();
+        assert_eq!(g.loaded_question_count(), 1);
+        for source_name in ["s1", "s2", "s3"] {
+            let source = g.storage().get_source(source_name).unwrap().unwrap();
+            assert_eq!(
+                source.quality,
+                in_memory.source(source_name).unwrap().quality
+            );
+        }
+    }
+
+    #[test]
+    fn test_stored_graph_errors() {
+        let mut g = StoredGraph::in_memory();
+        assert!(matches!(
+            g.execute_command(&Command::from("CONFIGURE maximum_strength 0").unwrap()),
+            Err(ConfidisError::InvalidConfig { .. })
+        ));
+        assert!(matches!(
+            g.execute_command(&Command::from("UNDO").unwrap()),
+            Err(ConfidisError::NotImplemented(_))
+        ));
+        g.graph.begin_bulk_load();
+        g.set_many(&[("q1", "a", "s1")]).unwrap();
+        assert_eq!(
+            g.execute_command(&Command::from("GET ANSWER TO q1").unwrap()),
+            Err(ConfidisError::BulkLoadInProgress)
+        );
+    }
+
+    #[test]
+    fn test_stored_graph_mget_loads_questions() {
+        let mut g = StoredGraph::in_memory();
+        for line in &["SET q1 a FROM s1", "SET q1 a FROM s2", "SET q2 b FROM s1"] {
+            run(&mut g, line);
+        }
+        let expected = run(&mut g, "MGET q1 q2 q3");
+        g.evict_questions(0);
+        assert_eq!(run(&mut g, "MGET q1 q2 q3"), expected);
+        assert_eq!(
+            expected,
+            "q1: a (75.000%)\nq2: b (68.790%)\nq3: None (0.000%)"
+        );
+    }
+
+    #[test]
+    fn test_stored_graph_persists_replaced_answers() {
+        let mut g = StoredGraph::in_memory();
+        run(&mut g, "CONFIGURE duplicate_answers replace");
+        run(&mut g, "SET q1 a FROM s1");
+        run(&mut g, "SET q1 c FROM s2");
+        run(&mut g, "SET q1 b FROM s1");
+        let answer = run(&mut g, "GET ANSWER TO q1");
+        g.evict_questions(0);
+        assert_eq!(run(&mut g, "GET ANSWER TO q1"), answer);
+        let stored: Vec<String> = g
+            .storage()
+            .get_answers("q1")
+            .unwrap()
+            .into_iter()
+            .map(|answer| answer.content)
+            .collect();
+        assert_eq!(stored, ["c", "b"]);
+
+        g.evict_questions(0);
+        g.set_many(&[("q1", "d", "s2"), ("q1", "e", "s3")]).unwrap();
+        assert_eq!(g.storage().answer_count("q1").unwrap(), 3);
+    }
+}
@@ -0,0 +1,278 @@
+// Storage backed by the sled embedded key/value store
+//
+// Faster than SQLite for write heavy workloads. Writes are made durable when a
+// command commits, but unlike SQLite they aren't applied atomically, a crash
+// mid-command can persist part of its writes.
+
+use super::{Storage, StoredGraph};
+use crate::command::Answer;
+use crate::graph::{Question, Source};
+use crate::question_type::QuestionType;
+use crate::schema::AnswerSchema;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+fn sled_err(e: ::sled::Error) -> String {
+    format!("sled error: {}", e)
+}
+
+fn bincode_err(e: bincode::Error) -> String {
+    format!("Couldn't decode stored value: {}", e)
+}
+
+// A question without its answers, which are stored separately so they can be
+// appended without rewriting the question
+#[derive(Serialize, Deserialize)]
+struct QuestionRecord {
+    correct_answers: Vec<usize>,
+    weight: f64,
+    confidence: f64,
+    answered_at: u64,
+    excluded_sources: Vec<String>,
+    honeypot: Option<String>,
+    schema: Option<AnswerSchema>,
+    question_type: Option<QuestionType>,
+}
+
+pub struct SledStorage {
+    db: ::sled::Db,
+    meta: ::sled::Tree,
+    sources: ::sled::Tree,
+    questions: ::sled::Tree,
+    answers: ::sled::Tree,
+}
+
+// A graph persisted to sled that loads questions lazily
+pub type SledGraph = StoredGraph<SledStorage>;
+
+impl SledStorage {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SledStorage, String> {
+        SledStorage::from_db(::sled::open(path).map_err(sled_err)?)
+    }
+
+    // A database that is deleted when dropped
+    pub fn temporary() -> Result<SledStorage, String> {
+        SledStorage::from_db(
+            ::sled::Config::new()
+                .temporary(true)
+                .open()
+                .map_err(sled_err)?,
+        )
+    }
+
+    pub fn from_db(db: ::sled::Db) -> Result<SledStorage, String> {
+        Ok(SledStorage {
+            meta: db.open_tree("meta").map_err(sled_err)?,
+            sources: db.open_tree("sources").map_err(sled_err)?,
+            questions: db.open_tree("questions").map_err(sled_err)?,
+            answers: db.open_tree("answers").map_err(sled_err)?,
+            db,
+        })
+    }
+}
+
+impl StoredGraph<SledStorage> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SledGraph, String> {
+        StoredGraph::new(SledStorage::open(path)?)
+    }
+}
+
+// Answer keys are the length prefixed question name followed by the big endian
+// position, so a question's answers are contiguous and ordered
+fn answer_prefix(question_name: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(4 + question_name.len() + 8);
+    key.extend_from_slice(&(question_name.len() as u32).to_be_bytes());
+    key.extend_from_slice(question_name.as_bytes());
+    key
+}
+
+fn answer_key(question_name: &str, position: usize) -> Vec<u8> {
+    let mut key = answer_prefix(question_name);
+    key.extend_from_slice(&(position as u64).to_be_bytes());
+    key
+}
+
+impl Storage for SledStorage {
+    fn get_meta(&self, key: &str) -> Result<Option<String>, String> {
+        match self.meta.get(key).map_err(sled_err)? {
+            Some(value) => Ok(Some(
+                String::from_utf8(value.to_vec()).map_err(|e| e.to_string())?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    fn put_meta(&mut self, key: &str, value: &str) -> Result<(), String> {
+        self.meta.insert(key, value.as_bytes()).map_err(sled_err)?;
+        Ok(())
+    }
+
+    fn sources(&self) -> Result<Vec<Source>, String> {
+        let mut sources = Vec::new();
+        for entry in self.sources.iter() {
+            let (_, value) = entry.map_err(sled_err)?;
+            sources.push(bincode::deserialize(&value).map_err(bincode_err)?);
+        }
+        Ok(sources)
+    }
+
+    fn get_source(&self, source_name: &str) -> Result<Option<Source>, String> {
+        match self.sources.get(source_name).map_err(sled_err)? {
+            Some(value) => Ok(Some(bincode::deserialize(&value).map_err(bincode_err)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn put_source(&mut self, source: &Source) -> Result<(), String> {
+        self.sources
+            .insert(
+                source.name.as_str(),
+                bincode::serialize(source).map_err(bincode_err)?,
+            )
+            .map_err(sled_err)?;
+        Ok(())
+    }
+
+    fn get_question(&self, question_name: &str) -> Result<Option<Question>, String> {
+        let value = match self.questions.get(question_name).map_err(sled_err)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let record: QuestionRecord = bincode::deserialize(&value).map_err(bincode_err)?;
+        Ok(Some(Question {
+            name: question_name.to_string(),
+            correct_answers: record.correct_answers,
+            weight: record.weight,
+            confidence: record.confidence,
+            answers: Vec::new(),
+            answered_at: record.answered_at,
+            excluded_sources: record.excluded_sources,
+            honeypot: record.honeypot,
+            schema: record.schema,
+            question_type: record.question_type,
+        }))
+    }
+
+    fn put_question(&mut self, question: &Question) -> Result<(), String> {
+        let record = QuestionRecord {
+            correct_answers: question.correct_answers.clone(),
+            weight: question.weight,
+            confidence: question.confidence,
+            answered_at: question.answered_at,
+            excluded_sources: question.excluded_sources.clone(),
+            honeypot: question.honeypot.clone(),
+            schema: question.schema.clone(),
+            question_type: question.question_type.clone(),
+        };
+        self.questions
+            .insert(
+                question.name.as_str(),
+                bincode::serialize(&record).map_err(bincode_err)?,
+            )
+            .map_err(sled_err)?;
+        Ok(())
+    }
+
+    fn question_names(&self) -> Result<Vec<String>, String> {
+        let mut question_names = Vec::new();
+        for key in self.questions.iter().keys() {
+            let key = key.map_err(sled_err)?;
+            question_names.push(String::from_utf8(key.to_vec()).map_err(|e| e.to_string())?);
+        }
+        Ok(question_names)
+    }
+
+    fn get_answers(&self, question_name: &str) -> Result<Vec<Answer>, String> {
+        let mut answers = Vec::new();
+        for entry in self.answers.scan_prefix(answer_prefix(question_name)) {
+            let (_, value) = entry.map_err(sled_err)?;
+            answers.push(bincode::deserialize(&value).map_err(bincode_err)?);
+        }
+        Ok(answers)
+    }
+
+    fn answer_count(&self, question_name: &str) -> Result<usize, String> {
+        Ok(self
+            .answers
+            .scan_prefix(answer_prefix(question_name))
+            .count())
+    }
+
+    fn append_answers(
+        &mut self,
+        question_name: &str,
+        first_position: usize,
+        answers: &[Answer],
+    ) -> Result<(), String> {
+        let mut batch = ::sled::Batch::default();
+        for (i, answer) in answers.iter().enumerate() {
+            batch.insert(
+                answer_key(question_name, first_position + i),
+                bincode::serialize(answer).map_err(bincode_err)?,
+            );
+        }
+        self.answers.apply_batch(batch).map_err(sled_err)
+    }
+
+    fn put_answers(&mut self, question_name: &str, answers: &[Answer]) -> Result<(), String> {
+        let mut batch = ::sled::Batch::default();
+        for key in self
+            .answers
+            .scan_prefix(answer_prefix(question_name))
+            .keys()
+        {
+            batch.remove(key.map_err(sled_err)?);
+        }
+        for (i, answer) in answers.iter().enumerate() {
+            batch.insert(
+                answer_key(question_name, i),
+                bincode::serialize(answer).map_err(bincode_err)?,
+            );
+        }
+        self.answers.apply_batch(batch).map_err(sled_err)
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        self.db.flush().map_err(sled_err)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+
+    fn run(g: &mut SledGraph, line: &str) -> String {
+        format!(
+            "{}",
+            g.execute_command(&Command::from(line).unwrap()).unwrap()
+        )
+    }
+
+    #[test]
+    fn test_sled_graph_survives_reopen() {
+        let db = ::sled::Config::new().temporary(true).open().unwrap();
+
+        let expected = {
+            let mut g = SledGraph::new(SledStorage::from_db(db.clone()).unwrap()).unwrap();
+            run(&mut g, "SET q1 a FROM s1");
+            run(&mut g, "SET q1 a FROM s2");
+            g.set_many(&[("q2", "b", "s1"), ("q2", "b", "s3"), ("q1", "c", "s3")])
+                .unwrap();
+            run(&mut g, "BELIEVE s2");
+            [
+                run(&mut g, "GET ANSWER TO q1"),
+                run(&mut g, "GET ANSWERS TO q2"),
+                run(&mut g, "GET SOURCE s3"),
+            ]
+        };
+
+        let mut g = SledGraph::new(SledStorage::from_db(db).unwrap()).unwrap();
+        assert_eq!(g.loaded_question_count(), 0);
+        assert_eq!(run(&mut g, "GET ANSWER TO q1"), expected[0]);
+        assert_eq!(run(&mut g, "GET ANSWERS TO q2"), expected[1]);
+        assert_eq!(run(&mut g, "GET SOURCE s3"), expected[2]);
+        assert_eq!(g.storage().answer_count("q1").unwrap(), 3);
+    }
+}
@@ -0,0 +1,366 @@
+// Storage backed by SQLite
+//
+// Durable and transactional: everything written by a single command is
+// committed together, so a crash never leaves a half-applied SET behind.
+
+use super::{Storage, StoredGraph};
+use crate::command::Answer;
+use crate::graph::{Question, Source};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sources (
+    name TEXT PRIMARY KEY,
+    quality REAL NOT NULL,
+    strength REAL NOT NULL,
+    evidence TEXT NOT NULL DEFAULT '{}',
+    discount REAL NOT NULL DEFAULT 0,
+    complete_ledger INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS questions (
+    name TEXT PRIMARY KEY,
+    weight REAL NOT NULL,
+    confidence REAL NOT NULL,
+    correct_answers TEXT NOT NULL,
+    answered_at INTEGER NOT NULL DEFAULT 0,
+    excluded_sources TEXT NOT NULL DEFAULT '[]',
+    honeypot TEXT,
+    schema TEXT,
+    question_type TEXT
+);
+CREATE TABLE IF NOT EXISTS answers (
+    question TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    source TEXT NOT NULL,
+    PRIMARY KEY (question, position)
+);
+";
+
+fn sql_err(e: rusqlite::Error) -> String {
+    format!("SQLite error: {}", e)
+}
+
+pub struct SqliteStorage {
+    conn: Connection,
+}
+
+// A graph persisted to SQLite that loads questions lazily
+pub type SqliteGraph = StoredGraph<SqliteStorage>;
+
+impl SqliteStorage {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteStorage, String> {
+        SqliteStorage::from_connection(Connection::open(path).map_err(sql_err)?)
+    }
+
+    pub fn open_in_memory() -> Result<SqliteStorage, String> {
+        SqliteStorage::from_connection(Connection::open_in_memory().map_err(sql_err)?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<SqliteStorage, String> {
+        conn.execute_batch(SCHEMA).map_err(sql_err)?;
+        Ok(SqliteStorage { conn })
+    }
+}
+
+impl StoredGraph<SqliteStorage> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteGraph, String> {
+        StoredGraph::new(SqliteStorage::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<SqliteGraph, String> {
+        StoredGraph::new(SqliteStorage::open_in_memory()?)
+    }
+}
+
+fn source_from_row(row: &rusqlite::Row) -> rusqlite::Result<Source> {
+    let evidence: String = row.get(3)?;
+    Ok(Source {
+        name: row.get(0)?,
+        quality: row.get(1)?,
+        strength: row.get(2)?,
+        evidence: serde_json::from_str(&evidence).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Text, Box::new(e))
+        })?,
+        discount: row.get(4)?,
+        complete_ledger: row.get(5)?,
+    })
+}
+
+impl Storage for SqliteStorage {
+    fn get_meta(&self, key: &str) -> Result<Option<String>, String> {
+        self.conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(sql_err)
+    }
+
+    fn put_meta(&mut self, key: &str, value: &str) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+                params![key, value],
+            )
+            .map(|_| ())
+            .map_err(sql_err)
+    }
+
+    fn sources(&self) -> Result<Vec<Source>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT name, quality, strength, evidence, discount, complete_ledger FROM sources",
+            )
+            .map_err(sql_err)?;
+        let sources = stmt
+            .query_map([], source_from_row)
+            .map_err(sql_err)?
+            .collect::<Result<Vec<Source>, _>>()
+            .map_err(sql_err)?;
+        Ok(sources)
+    }
+
+    fn get_source(&self, source_name: &str) -> Result<Option<Source>, String> {
+        self.conn
+            .query_row(
+                "SELECT name, quality, strength, evidence, discount, complete_ledger FROM sources WHERE name = ?1",
+                params![source_name],
+                source_from_row,
+            )
+            .optional()
+            .map_err(sql_err)
+    }
+
+    fn put_source(&mut self, source: &Source) -> Result<(), String> {
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO sources
+                 (name, quality, strength, evidence, discount, complete_ledger)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )
+            .map_err(sql_err)?
+            .execute(params![
+                source.name,
+                source.quality,
+                source.strength,
+                serde_json::to_string(&source.evidence).unwrap(),
+                source.discount,
+                source.complete_ledger
+            ])
+            .map(|_| ())
+            .map_err(sql_err)
+    }
+
+    fn get_question(&self, question_name: &str) -> Result<Option<Question>, String> {
+        // the JSON columns are parsed once the row is read
+        let row = self
+            .conn
+            .query_row(
+                "SELECT weight, confidence, correct_answers, answered_at, excluded_sources, honeypot,
+                 schema, question_type FROM questions WHERE name = ?1",
+                params![question_name],
+                |row| {
+                    let question = Question {
+                        name: question_name.to_string(),
+                        weight: row.get(0)?,
+                        confidence: row.get(1)?,
+                        answered_at: row.get::<_, i64>(3)? as u64,
+                        honeypot: row.get(5)?,
+                        ..Default::default()
+                    };
+                    Ok((
+                        question,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(4)?,
+                        row.get::<_, Option<String>>(6)?,
+                        row.get::<_, Option<String>>(7)?,
+                    ))
+                },
+            )
+            .optional()
+            .map_err(sql_err)?;
+        match row {
+            Some((mut question, correct_answers, excluded_sources, schema, question_type)) => {
+                question.correct_answers =
+                    serde_json::from_str(&correct_answers).map_err(|e| e.to_string())?;
+                question.excluded_sources =
+                    serde_json::from_str(&excluded_sources).map_err(|e| e.to_string())?;
+                question.schema = schema.map(|schema| schema.parse()).transpose()?;
+                question.question_type = question_type
+                    .map(|question_type| question_type.parse())
+                    .transpose()?;
+                Ok(Some(question))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn put_question(&mut self, question: &Question) -> Result<(), String> {
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO questions
+                 (name, weight, confidence, correct_answers, answered_at, excluded_sources, honeypot,
+                  schema, question_type)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )
+            .map_err(sql_err)?
+            .execute(params![
+                question.name,
+                question.weight,
+                question.confidence,
+                serde_json::to_string(&question.correct_answers).unwrap(),
+                question.answered_at as i64,
+                serde_json::to_string(&question.excluded_sources).unwrap(),
+                question.honeypot,
+                question.schema.as_ref().map(ToString::to_string),
+                question.question_type.as_ref().map(ToString::to_string)
+            ])
+            .map(|_| ())
+            .map_err(sql_err)
+    }
+
+    fn question_names(&self) -> Result<Vec<String>, String> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name FROM questions")
+            .map_err(sql_err)?;
+        let question_names = stmt
+            .query_map([], |row| row.get(0))
+            .map_err(sql_err)?
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(sql_err)?;
+        Ok(question_names)
+    }
+
+    fn get_answers(&self, question_name: &str) -> Result<Vec<Answer>, String> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT content, source FROM answers WHERE question = ?1 ORDER BY position",
+            )
+            .map_err(sql_err)?;
+        let answers = stmt
+            .query_map(params![question_name], |row| {
+                Ok(Answer::new(row.get(0)?, row.get(1)?))
+            })
+            .map_err(sql_err)?
+            .collect::<Result<Vec<Answer>, _>>()
+            .map_err(sql_err)?;
+        Ok(answers)
+    }
+
+    fn answer_count(&self, question_name: &str) -> Result<usize, String> {
+        let count: i64 = self
+            .conn
+            .query_row(
+                "SELECT COUNT(*) FROM answers WHERE question = ?1",
+                params![question_name],
+                |row| row.get(0),
+            )
+            .map_err(sql_err)?;
+        Ok(count as usize)
+    }
+
+    fn append_answers(
+        &mut self,
+        question_name: &str,
+        first_position: usize,
+        answers: &[Answer],
+    ) -> Result<(), String> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO answers (question, position, content, source)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )
+            .map_err(sql_err)?;
+        for (i, answer) in answers.iter().enumerate() {
+            stmt.execute(params![
+                question_name,
+                (first_position + i) as i64,
+                answer.content,
+                answer.source
+            ])
+            .map_err(sql_err)?;
+        }
+        Ok(())
+    }
+
+    fn put_answers(&mut self, question_name: &str, answers: &[Answer]) -> Result<(), String> {
+        self.conn
+            .execute(
+                "DELETE FROM answers WHERE question = ?1",
+                params![question_name],
+            )
+            .map_err(sql_err)?;
+        self.append_answers(question_name, 0, answers)
+    }
+
+    fn begin(&mut self) -> Result<(), String> {
+        self.conn.execute_batch("BEGIN").map_err(sql_err)
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        self.conn.execute_batch("COMMIT").map_err(sql_err)
+    }
+
+    fn rollback(&mut self) -> Result<(), String> {
+        self.conn.execute_batch("ROLLBACK").map_err(sql_err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+    use crate::equalifier::EqualifierConfig;
+    use std::fs;
+
+    fn run(g: &mut SqliteGraph, line: &str) -> String {
+        format!(
+            "{}",
+            g.execute_command(&Command::from(line).unwrap()).unwrap()
+        )
+    }
+
+    #[test]
+    fn test_sqlite_graph_survives_reopen() {
+        let path = std::env::temp_dir().join(format!("confidis-{}.sqlite", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let expected = {
+            let mut g = SqliteGraph::open(&path).unwrap();
+            run(&mut g, "CONFIGURE comparison_method numeric max_distance=1");
+            run(&mut g, "SET q1 1 FROM s1");
+            run(&mut g, "SET q1 1.2 FROM s2");
+            g.set_many(&[("q2", "5", "s1"), ("q2", "5", "s3"), ("q1", "9", "s3")])
+                .unwrap();
+            run(&mut g, "BELIEVE s2");
+            [
+                run(&mut g, "GET ANSWER TO q1"),
+                run(&mut g, "GET ANSWERS TO q2"),
+                run(&mut g, "GET SOURCE s3"),
+            ]
+        };
+
+        let mut g = SqliteGraph::open(&path).unwrap();
+        assert_eq!(g.loaded_question_count(), 0);
+        assert_eq!(run(&mut g, "GET ANSWER TO q1"), expected[0]);
+        assert_eq!(run(&mut g, "GET ANSWERS TO q2"), expected[1]);
+        assert_eq!(run(&mut g, "GET SOURCE s3"), expected[2]);
+        assert_eq!(
+            g.graph().equalifier_config(),
+            EqualifierConfig::Numeric { max_distance: 1.0 }
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
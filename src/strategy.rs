@@ -0,0 +1,392 @@
+// A/B comparison of aggregation strategies over the same command stream
+//
+// compare_strategies runs a script of commands through one graph per
+// strategy and reports on how many questions their answers disagree, and
+// where a script line "TRUTH <question> <answer>" gives the true answer, how
+// accurate each strategy was:
+//
+//   let comparison = compare_strategies(
+//       &std::fs::read_to_string("commands.txt")?,
+//       &Strategy::Incremental(GraphConfig::default()),
+//       &Strategy::ExpectationMaximization { iterations: 50 },
+//   )?;
+//
+// Incremental is the graph itself with a config, so two configs can be
+// compared too. MajorityVote and ExpectationMaximization decide from the
+// clusters the graph forms of each question's answers, with its comparison
+// method, normalization and excluded sources, but ignore source qualities.
+// Graphs run in deterministic mode so that the strategies break ties alike,
+// and honeypots report their known answer under every strategy. `confidis
+// --compare-strategies <a> <b> <file>` prints the same report.
+
+use crate::command::{Answer, Command};
+use crate::config::GraphConfig;
+use crate::error::ConfidisError;
+use crate::graph::Graph;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+const DEFAULT_EM_ITERATIONS: usize = 50;
+
+// Disagreeing questions the report prints
+const MAX_LISTED_DISAGREEMENTS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Strategy {
+    // The graph's incremental source quality estimation with the config
+    Incremental(GraphConfig),
+    // The cluster with the most answers, every source counts the same
+    MajorityVote,
+    // One-coin Dawid-Skene: each source is correct with its own probability,
+    // answers and qualities are estimated from each other in turn, starting
+    // from the majority vote
+    ExpectationMaximization { iterations: usize },
+}
+
+impl Strategy {
+    // The name --compare-strategies takes
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Strategy::Incremental(_) => "incremental",
+            Strategy::MajorityVote => "majority_vote",
+            Strategy::ExpectationMaximization { .. } => "em",
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = ConfidisError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "incremental" => Ok(Strategy::Incremental(GraphConfig::default())),
+            "majority_vote" => Ok(Strategy::MajorityVote),
+            "em" => Ok(Strategy::ExpectationMaximization {
+                iterations: DEFAULT_EM_ITERATIONS,
+            }),
+            _ => Err(ConfidisError::ParseError(format!(
+                "Unknown strategy \"{}\", try incremental, majority_vote or em",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StrategyComparison {
+    // the names of the two strategies
+    pub strategies: [String; 2],
+    pub questions: usize,
+    // the questions the strategies answered differently, sorted
+    pub disagreements: Vec<String>,
+    // questions with a TRUTH line
+    pub labelled: usize,
+    // the fraction of labelled questions each strategy answered correctly,
+    // None without labelled questions
+    pub accuracy: [Option<f64>; 2],
+}
+
+impl StrategyComparison {
+    pub fn disagreement_rate(&self) -> f64 {
+        self.disagreements.len() as f64 / self.questions.max(1) as f64
+    }
+}
+
+// The commands of a script and the truth of its TRUTH lines
+struct Stream<'a> {
+    commands: Vec<Command<'a>>,
+    truth: BTreeMap<&'a str, &'a str>,
+}
+
+fn parse_stream(script: &str) -> Result<Stream<'_>, ConfidisError> {
+    let mut stream = Stream {
+        commands: Vec::new(),
+        truth: BTreeMap::new(),
+    };
+    for (i, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let items: Vec<&str> = line.split_whitespace().collect();
+        if items[0] == "TRUTH" {
+            match items[..] {
+                [_, question, answer] => stream.truth.insert(question, answer),
+                _ => {
+                    return Err(ConfidisError::ParseError(format!(
+                        "Invalid TRUTH on line {}, syntax is TRUTH <question> <answer>",
+                        i + 1
+                    )))
+                }
+            };
+            continue;
+        }
+        let cmd = Command::from(line).map_err(|e| {
+            ConfidisError::ParseError(format!("Invalid command on line {}: {}", i + 1, e))
+        })?;
+        stream.commands.push(cmd);
+    }
+    Ok(stream)
+}
+
+// Run the stream's commands and decide every question's answer with the
+// strategy, returning the graph the answers were decided from
+fn run_strategy(
+    strategy: &Strategy,
+    stream: &Stream,
+) -> Result<(Graph, BTreeMap<String, String>), ConfidisError> {
+    let mut g = match strategy {
+        Strategy::Incremental(config) => Graph::new_with_config(config.clone()),
+        _ => Graph::new(),
+    };
+    g.set_deterministic(true);
+    for cmd in &stream.commands {
+        g.execute_command(cmd)?;
+    }
+    let mut answers = BTreeMap::new();
+    // the questions left to decide, each cluster's answer and its sources
+    let mut undecided: Vec<(String, Vec<String>)> = Vec::new();
+    let mut clusters: Vec<Vec<Vec<String>>> = Vec::new();
+    for (name, question) in &g.questions {
+        if let Some(known) = &question.honeypot {
+            answers.insert(name.clone(), known.clone());
+            continue;
+        }
+        if matches!(strategy, Strategy::Incremental(_)) {
+            answers.insert(name.clone(), g.compute_answer(name)?.0);
+            continue;
+        }
+        let members = g.compute_answer_clusters_with_confidence(name)?.clusters;
+        let contents = members
+            .iter()
+            .map(|members| question.answers[members[0]].content.clone())
+            .collect();
+        undecided.push((name.clone(), contents));
+        clusters.push(
+            members
+                .iter()
+                .map(|members| {
+                    members
+                        .iter()
+                        .map(|&i| question.answers[i].source.clone())
+                        .collect()
+                })
+                .collect(),
+        );
+    }
+    let scores = match strategy {
+        Strategy::ExpectationMaximization { iterations } => {
+            expectation_maximization(&clusters, *iterations)
+        }
+        _ => majority_vote(&clusters),
+    };
+    for ((name, contents), scores) in undecided.into_iter().zip(scores) {
+        let answer = match argmax(&scores) {
+            Some(best) => contents[best].clone(),
+            // every answer is from an excluded source, or none was given
+            None => String::from("None"),
+        };
+        answers.insert(name, answer);
+    }
+    Ok((g, answers))
+}
+
+// The index of the highest score, the first one of equal scores
+fn argmax(scores: &[f64]) -> Option<usize> {
+    (0..scores.len()).reduce(|best, i| if scores[i] > scores[best] { i } else { best })
+}
+
+// Each cluster's share of its question's answers
+fn majority_vote(clusters: &[Vec<Vec<String>>]) -> Vec<Vec<f64>> {
+    clusters
+        .iter()
+        .map(|question| {
+            let total: usize = question.iter().map(Vec::len).sum();
+            question
+                .iter()
+                .map(|members| members.len() as f64 / total.max(1) as f64)
+                .collect()
+        })
+        .collect()
+}
+
+// The probability of each cluster being correct. A source's quality is the
+// expected share of its answers that are correct, smoothed with one correct
+// and one wrong answer so it stays between 0 and 1, and a cluster is correct
+// with a probability proportional to the product of its sources' odds
+// quality / (1 - quality).
+fn expectation_maximization(clusters: &[Vec<Vec<String>>], iterations: usize) -> Vec<Vec<f64>> {
+    let mut posteriors = majority_vote(clusters);
+    for _ in 0..iterations {
+        let mut counts: HashMap<&str, (f64, f64)> = HashMap::new();
+        for (question, posterior) in clusters.iter().zip(&posteriors) {
+            for (members, &p) in question.iter().zip(posterior) {
+                for source in members {
+                    let (correct, total) = counts.entry(source).or_insert((1.0, 2.0));
+                    *correct += p;
+                    *total += 1.0;
+                }
+            }
+        }
+        for (question, posterior) in clusters.iter().zip(posteriors.iter_mut()) {
+            let log_odds: Vec<f64> = question
+                .iter()
+                .map(|members| {
+                    members
+                        .iter()
+                        .map(|source| {
+                            let (correct, total) = counts[source.as_str()];
+                            let quality = correct / total;
+                            (quality / (1.0 - quality)).ln()
+                        })
+                        .sum()
+                })
+                .collect();
+            let max = log_odds.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            let weights: Vec<f64> = log_odds.iter().map(|l| (l - max).exp()).collect();
+            let sum: f64 = weights.iter().sum();
+            *posterior = weights.iter().map(|w| w / sum).collect();
+        }
+    }
+    posteriors
+}
+
+// Whether the answer is the question's true answer under the graph's
+// normalization and the question's comparison method
+fn is_correct(g: &Graph, question: &str, answer: &str, truth: &str) -> bool {
+    let truth = g.config.normalize.apply(truth).into_owned();
+    g.answer_distance(
+        question,
+        &Answer::new(answer.to_string(), String::new()),
+        &Answer::new(truth, String::new()),
+    ) < 1.0
+}
+
+// Run the script's commands under both strategies and compare their answers
+pub fn compare_strategies(
+    script: &str,
+    a: &Strategy,
+    b: &Strategy,
+) -> Result<StrategyComparison, ConfidisError> {
+    let stream = parse_stream(script)?;
+    let (graph_a, answers_a) = run_strategy(a, &stream)?;
+    let (graph_b, answers_b) = run_strategy(b, &stream)?;
+
+    let disagreements = answers_a
+        .iter()
+        .filter(|(name, answer)| answers_b.get(*name) != Some(answer))
+        .map(|(name, _)| name.clone())
+        .collect();
+    let labelled: Vec<(&str, &str)> = stream
+        .truth
+        .iter()
+        .filter(|(question, _)| answers_a.contains_key(**question))
+        .map(|(&question, &truth)| (question, truth))
+        .collect();
+    let accuracy = |g: &Graph, answers: &BTreeMap<String, String>| {
+        if labelled.is_empty() {
+            return None;
+        }
+        let correct = labelled
+            .iter()
+            .filter(|(question, truth)| is_correct(g, question, &answers[*question], truth))
+            .count();
+        Some(correct as f64 / labelled.len() as f64)
+    };
+    Ok(StrategyComparison {
+        strategies: [a.as_str().to_string(), b.as_str().to_string()],
+        questions: answers_a.len(),
+        disagreements,
+        labelled: labelled.len(),
+        accuracy: [
+            accuracy(&graph_a, &answers_a),
+            accuracy(&graph_b, &answers_b),
+        ],
+    })
+}
+
+impl fmt::Display for StrategyComparison {
+    // e.g.
+    //   incremental vs em
+    //   questions: 500
+    //   disagreements: 12 (2.400%)
+    //   accuracy on 100 labelled questions: 0.950 vs 0.930
+    //   disagreed on: q17 q52 q90 ...
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} vs {}", self.strategies[0], self.strategies[1])?;
+        writeln!(f, "questions: {}", self.questions)?;
+        write!(
+            f,
+            "disagreements: {} ({:.3}%)",
+            self.disagreements.len(),
+            self.disagreement_rate() * 100.
+        )?;
+        if let [Some(a), Some(b)] = self.accuracy {
+            write!(
+                f,
+                "\naccuracy on {} labelled questions: {:.3} vs {:.3}",
+                self.labelled, a, b
+            )?;
+        }
+        if !self.disagreements.is_empty() {
+            let listed =
+                &self.disagreements[..self.disagreements.len().min(MAX_LISTED_DISAGREEMENTS)];
+            write!(f, "\ndisagreed on: {}", listed.join(" "))?;
+            if self.disagreements.len() > listed.len() {
+                write!(f, " ...")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_strategies() {
+        // s1 and s2 are reliable, s3, s4 and s5 always agree on a wrong answer
+        let mut script = String::new();
+        for i in 0..20 {
+            script += &format!("SET q{} right{} FROM s1\n", i, i);
+            script += &format!("SET q{} right{} FROM s2\n", i, i);
+            if i < 5 {
+                script += &format!("TRUTH q{} right{}\n", i, i);
+            }
+        }
+        for i in 0..5 {
+            for source in ["s3", "s4", "s5"] {
+                script += &format!("SET q{} wrong FROM {}\n", i, source);
+            }
+        }
+        // s1 and s2 answer the last question alone, disagreeing
+        script += "SET q20 a FROM s1\nSET q20 b FROM s3\nSET q20 b FROM s4\n";
+
+        let incremental = Strategy::Incremental(GraphConfig::default());
+        let comparison =
+            compare_strategies(&script, &incremental, &Strategy::MajorityVote).unwrap();
+        assert_eq!(comparison.questions, 21);
+        assert_eq!(comparison.labelled, 5);
+        // the majority is wrong wherever s3, s4 and s5 answered
+        assert_eq!(comparison.accuracy[1], Some(0.0));
+        assert!(comparison.accuracy[0].unwrap() > 0.0);
+        assert!(comparison.disagreements.contains(&String::from("q0")));
+
+        let em = "em".parse::<Strategy>().unwrap();
+        let comparison = compare_strategies(&script, &Strategy::MajorityVote, &em).unwrap();
+        assert_eq!(comparison.accuracy[0], Some(0.0));
+        assert_eq!(comparison.accuracy[1], Some(1.0));
+        assert!(comparison
+            .to_string()
+            .contains("accuracy on 5 labelled questions: 0.000 vs 1.000"));
+
+        // the same strategy always agrees with itself
+        let comparison = compare_strategies(&script, &em, &em).unwrap();
+        assert!(comparison.disagreements.is_empty());
+        assert!(compare_strategies("TRUTH q1", &em, &em).is_err());
+        assert!("median".parse::<Strategy>().is_err());
+    }
+}
@@ -0,0 +1,118 @@
+// OpenTelemetry tracing of commands
+//
+// Servers record a span per executed command, named after the command type
+// (e.g. "confidis GetAnswer") with confidis.question, confidis.source and
+// confidis.config_key attributes where the command has them. Failed commands
+// get an error status. Spans go to the global tracer provider, init_otlp
+// installs one exporting over OTLP/HTTP.
+
+use crate::command::{Command, CommandResponse};
+use opentelemetry::global::{self, BoxedSpan};
+use opentelemetry::trace::{Span, Status, Tracer};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+
+// Export spans in batches to an OTLP/HTTP collector, e.g.
+// "http://localhost:4318/v1/traces". Keep the returned provider and shut it
+// down before exiting to flush the last batch.
+pub fn init_otlp(endpoint: &str) -> Result<SdkTracerProvider, String> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| format!("Couldn't create OTLP exporter: {}", e))?;
+    let provider = SdkTracerProvider::builder()
+        .with_resource(Resource::builder().with_service_name("confidis").build())
+        .with_batch_exporter(exporter)
+        .build();
+    global::set_tracer_provider(provider.clone());
+    Ok(provider)
+}
+
+pub fn command_span(cmd: &Command) -> BoxedSpan {
+    let mut span = global::tracer("confidis").start(format!("confidis {:?}", cmd.cmd));
+    let attributes = [
+        ("confidis.question", &cmd.question),
+        ("confidis.source", &cmd.source),
+        ("confidis.config_key", &cmd.config_key),
+    ];
+    for (key, value) in attributes.iter() {
+        if let Some(value) = value {
+            span.set_attribute(KeyValue::new(*key, value.to_string()));
+        }
+    }
+    span
+}
+
+pub fn end_command_span(mut span: BoxedSpan, result: &Result<CommandResponse, String>) {
+    match result {
+        Ok(
+            CommandResponse::Answer { confidence, .. }
+            | CommandResponse::InsufficientEvidence { confidence, .. }
+            | CommandResponse::Unknown { confidence, .. },
+        ) => {
+            span.set_attribute(KeyValue::new("confidis.confidence", *confidence));
+        }
+        Ok(_) => {}
+        Err(msg) => span.set_status(Status::error(msg.clone())),
+    }
+    span.end();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::Graph;
+    use opentelemetry_sdk::error::OTelSdkResult;
+    use opentelemetry_sdk::trace::{SpanData, SpanExporter};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Default)]
+    struct CapturingExporter {
+        spans: Arc<Mutex<Vec<SpanData>>>,
+    }
+
+    impl SpanExporter for CapturingExporter {
+        async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+            self.spans.lock().unwrap().extend(batch);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_command_spans() {
+        let exporter = CapturingExporter::default();
+        let spans = exporter.spans.clone();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter)
+            .build();
+        global::set_tracer_provider(provider.clone());
+
+        let mut g = Graph::new();
+        for line in &["SET q1 a FROM s1", "GET ANSWER TO q1", "CONFIGURE nope 1"] {
+            let cmd = Command::from(line).unwrap();
+            let span = command_span(&cmd);
+            end_command_span(span, &g.execute_command(&cmd).map_err(String::from));
+        }
+        provider.force_flush().unwrap();
+
+        let spans = spans.lock().unwrap();
+        let names: Vec<&str> = spans.iter().map(|span| span.name.as_ref()).collect();
+        assert_eq!(
+            names,
+            vec!["confidis Set", "confidis GetAnswer", "confidis Configure"]
+        );
+        let attribute = |span: &SpanData, key: &str| {
+            span.attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.to_string())
+        };
+        assert_eq!(attribute(&spans[0], "confidis.source").unwrap(), "s1");
+        assert_eq!(attribute(&spans[1], "confidis.question").unwrap(), "q1");
+        assert!(attribute(&spans[1], "confidis.confidence").is_some());
+        assert!(matches!(spans[2].status, Status::Error { .. }));
+    }
+}
@@ -0,0 +1,122 @@
+// Undo and redo of mutating commands
+//
+// With undo_depth set, every mutating command and set_many batch remembers
+// the state it is about to change: the questions it touches, the sources that
+// answered them and, for CONFIGURE, the configuration and comparison methods.
+// UNDO puts that state back and keeps the state it replaced for REDO, so the
+// graph is restored exactly rather than by an approximate inverse:
+//
+//   CONFIGURE undo_depth 100
+//   UNDO 3
+//   REDO
+//
+// Any other mutation forgets what REDO could redo. UNDO and REDO are
+// journaled like other commands and replay the same way, as the journal also
+// holds the mutations they take back. Snapshots, journal compaction, bulk
+// loads and calibration start the stacks over, as a replay from the snapshot
+// couldn't take back what came before it. The audit log and answer history
+// are logs, an undone change stays in them.
+
+use crate::config::GraphConfig;
+use crate::equalifier::Equalifier;
+use crate::graph::{Question, Source};
+use crate::shadow::Shadow;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+// The state a mutation is about to change, None for a question or source it
+// creates
+pub(crate) struct UndoEntry<A> {
+    // the mutation, e.g. SET q1 a FROM s1
+    pub(crate) description: String,
+    pub(crate) questions: Vec<(String, Option<Arc<Question<A>>>)>,
+    pub(crate) sources: Vec<(String, Option<Source>)>,
+    pub(crate) config: Option<ConfigState<A>>,
+}
+
+// The configuration with the comparison method and shadow comparison method
+pub(crate) type ConfigState<A> = (GraphConfig, Arc<dyn Equalifier<A>>, Option<Shadow<A>>);
+
+pub(crate) struct UndoStack<A> {
+    // oldest first
+    undo: VecDeque<UndoEntry<A>>,
+    // most recently undone last
+    redo: Vec<UndoEntry<A>>,
+}
+
+impl<A> Default for UndoStack<A> {
+    fn default() -> Self {
+        UndoStack {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+        }
+    }
+}
+
+impl<A> UndoStack<A> {
+    // Remember a new mutation, forgetting the oldest beyond depth and
+    // everything REDO could redo
+    pub(crate) fn push(&mut self, entry: UndoEntry<A>, depth: usize) {
+        self.redo.clear();
+        self.push_redone(entry, depth);
+    }
+
+    // Remember a mutation REDO applied again, keeping the rest of the redo stack
+    pub(crate) fn push_redone(&mut self, entry: UndoEntry<A>, depth: usize) {
+        self.undo.push_back(entry);
+        while self.undo.len() > depth {
+            self.undo.pop_front();
+        }
+    }
+
+    pub(crate) fn pop_undo(&mut self) -> Option<UndoEntry<A>> {
+        self.undo.pop_back()
+    }
+
+    pub(crate) fn push_undone(&mut self, entry: UndoEntry<A>) {
+        self.redo.push(entry);
+    }
+
+    pub(crate) fn pop_redo(&mut self) -> Option<UndoEntry<A>> {
+        self.redo.pop()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(description: &str) -> UndoEntry<String> {
+        UndoEntry {
+            description: description.to_string(),
+            questions: Vec::new(),
+            sources: Vec::new(),
+            config: None,
+        }
+    }
+
+    #[test]
+    fn test_undo_stack_is_bounded() {
+        let mut stack = UndoStack::default();
+        for description in ["a", "b", "c"] {
+            stack.push(entry(description), 2);
+        }
+        let undone = stack.pop_undo().unwrap();
+        assert_eq!(undone.description, "c");
+        stack.push_undone(undone);
+        let redone = stack.pop_redo().unwrap();
+        stack.push_redone(redone, 2);
+        assert_eq!(stack.pop_undo().unwrap().description, "c");
+        assert_eq!(stack.pop_undo().unwrap().description, "b");
+        assert!(stack.pop_undo().is_none());
+
+        stack.push_undone(entry("b"));
+        stack.push(entry("d"), 2);
+        assert!(stack.pop_redo().is_none());
+    }
+}
@@ -0,0 +1,191 @@
+// WebSocket streaming of answer changes
+//
+// Clients subscribe by sending {"subscribe": ["prefix", ...]} text messages
+// and then receive an AnswerChange as JSON, e.g.
+//   {"question": "q1", "answer": "a", "confidence": 0.9}
+// whenever the answer or confidence of a question starting with one of their
+// prefixes changes. Runs next to a TCP or HTTP server, sharing its GraphWorker.
+//
+// With tokens set (see set_tokens) clients first send {"auth": "<token>"},
+// answered with {"auth": "<role>"}, and subscribing needs the read role, see
+// auth.rs.
+
+use crate::auth::{AuthSession, Tokens};
+use crate::command::CommandType;
+use crate::worker::GraphWorker;
+use log::{info, warn};
+use serde::Deserialize;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tungstenite::{Error, Message};
+
+// How long a connection waits for a client message before checking for
+// answer changes to send
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ClientMessage {
+    Auth { auth: String },
+    Subscribe { subscribe: Vec<String> },
+}
+
+pub struct WebSocketServer {
+    listener: TcpListener,
+    worker: GraphWorker,
+    tokens: Option<Arc<Tokens>>,
+}
+
+impl WebSocketServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A, worker: GraphWorker) -> Result<WebSocketServer, String> {
+        let listener = TcpListener::bind(addr).map_err(|e| format!("Couldn't bind: {}", e))?;
+        Ok(WebSocketServer {
+            listener,
+            worker,
+            tokens: None,
+        })
+    }
+
+    // Require clients to authenticate with one of tokens, see auth.rs
+    pub fn set_tokens(&mut self, tokens: Tokens) {
+        self.tokens = Some(Arc::new(tokens));
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.listener.local_addr().map_err(|e| e.to_string())
+    }
+
+    // Accept connections until the listener fails
+    pub fn run(self) -> Result<(), String> {
+        for stream in self.listener.incoming() {
+            let stream = stream.map_err(|e| format!("Couldn't accept connection: {}", e))?;
+            let worker = self.worker.clone();
+            let auth = AuthSession::new(self.tokens.clone());
+            thread::spawn(move || {
+                let peer = stream.peer_addr().ok();
+                if let Err(e) = handle_connection(stream, worker, auth) {
+                    warn!("WebSocket connection {:?} failed: {}", peer, e);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    worker: GraphWorker,
+    mut auth: AuthSession,
+) -> Result<(), String> {
+    info!(
+        "Accepted WebSocket connection from {:?}",
+        stream.peer_addr()
+    );
+    let mut ws = tungstenite::accept(stream).map_err(|e| format!("Handshake failed: {}", e))?;
+    ws.get_ref()
+        .set_read_timeout(Some(POLL_INTERVAL))
+        .map_err(|e| e.to_string())?;
+    let subscription = worker.subscribe(Vec::new());
+    loop {
+        match ws.read() {
+            Ok(Message::Text(text)) => {
+                let reply = match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(ClientMessage::Auth { auth: token }) => auth
+                        .authenticate(&token)
+                        .map(|role| Some(serde_json::json!({ "auth": role.to_string() }))),
+                    Ok(ClientMessage::Subscribe { subscribe }) => auth
+                        .authorize(CommandType::GetAnswer)
+                        .map(|_| subscription.add_prefixes(subscribe))
+                        .map(|_| None),
+                    Err(e) => Err(e.to_string()),
+                };
+                let reply = match reply {
+                    Ok(reply) => reply,
+                    Err(msg) => Some(serde_json::json!({ "error": msg })),
+                };
+                if let Some(reply) = reply {
+                    ws.send(Message::text(reply.to_string()))
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+            Ok(Message::Close(_)) | Err(Error::ConnectionClosed) => return Ok(()),
+            Ok(_) => {}
+            Err(Error::Io(e))
+                if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+            Err(e) => return Err(e.to_string()),
+        }
+        for change in subscription.changes.try_iter() {
+            ws.send(Message::text(serde_json::to_string(&change).unwrap()))
+                .map_err(|e| e.to_string())?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+    use crate::graph::Graph;
+    use crate::worker::AnswerChange;
+
+    #[test]
+    fn test_websocket_streams_answer_changes() {
+        let worker = GraphWorker::spawn(Graph::new);
+        let server = WebSocketServer::bind("127.0.0.1:0", worker.clone()).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let (mut ws, _) = tungstenite::connect(format!("ws://{}", addr)).unwrap();
+        ws.send(Message::text("{\"subscribe\": [\"q\"]}")).unwrap();
+        // wait for the subscription to be registered before mutating
+        thread::sleep(POLL_INTERVAL * 4);
+
+        for line in &["SET x a FROM s1", "SET q1 a FROM s1"] {
+            worker.execute(Command::from(line).unwrap()).unwrap();
+        }
+        let change: AnswerChange = match ws.read().unwrap() {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            message => panic!("unexpected message {:?}", message),
+        };
+        assert_eq!(change.question, "q1");
+        assert_eq!(change.answer, "a");
+    }
+
+    #[test]
+    fn test_websocket_auth() {
+        let worker = GraphWorker::spawn(Graph::new);
+        let mut server = WebSocketServer::bind("127.0.0.1:0", worker.clone()).unwrap();
+        server.set_tokens(Tokens::parse("r1 read\n").unwrap());
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let (mut ws, _) = tungstenite::connect(format!("ws://{}", addr)).unwrap();
+        let mut reply = |text: &str| -> serde_json::Value {
+            ws.send(Message::text(text)).unwrap();
+            match ws.read().unwrap() {
+                Message::Text(text) => serde_json::from_str(&text).unwrap(),
+                message => panic!("unexpected message {:?}", message),
+            }
+        };
+        assert!(reply("{\"subscribe\": [\"q\"]}")["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Authentication required"));
+        assert_eq!(reply("{\"auth\": \"x\"}")["error"], "Invalid token");
+        assert_eq!(reply("{\"auth\": \"r1\"}")["auth"], "read");
+        ws.send(Message::text("{\"subscribe\": [\"q\"]}")).unwrap();
+        thread::sleep(POLL_INTERVAL * 4);
+
+        worker
+            .execute(Command::from("SET q1 a FROM s1").unwrap())
+            .unwrap();
+        let change: AnswerChange = match ws.read().unwrap() {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            message => panic!("unexpected message {:?}", message),
+        };
+        assert_eq!(change.question, "q1");
+    }
+}
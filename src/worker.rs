@@ -0,0 +1,283 @@
+// A graph owned by a dedicated thread
+//
+// Servers hand commands to the worker through a channel and wait for the
+// reply, so connections on any number of threads share one graph without the
+// graph itself having to be shared between threads. Commands are executed in
+// the order they arrive.
+//
+// Subscribers register question name prefixes and receive an AnswerChange
+// whenever a mutating command changes the answer or confidence GET ANSWER
+// would report for a matching question.
+//
+// The worker keeps Metrics for the commands it executes, see metrics_text, and
+// with the otel feature records a span for each, see telemetry.rs.
+
+use crate::command::{Command, CommandResponse};
+use crate::graph::Graph;
+use crate::metrics::Metrics;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::Instant;
+
+pub type Reply = Result<CommandResponse, String>;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnswerChange {
+    pub question: String,
+    pub answer: String,
+    pub confidence: f64,
+}
+
+enum Request {
+    Execute(Box<Command<'static>>, Sender<Reply>),
+    // Adds prefixes to the subscriber with this id, creating it if needed
+    Subscribe(u64, Vec<String>, Sender<AnswerChange>),
+    Unsubscribe(u64),
+    // Replies with the Prometheus text of the worker's metrics
+    RenderMetrics(Sender<String>),
+    // Runs a closure with the graph, see with_graph
+    Run(Box<dyn FnOnce(&mut Graph) + Send>),
+}
+
+struct Subscriber {
+    prefixes: Vec<String>,
+    changes: Sender<AnswerChange>,
+}
+
+static NEXT_SUBSCRIBER_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone)]
+pub struct GraphWorker {
+    requests: Sender<Request>,
+}
+
+// Receives the answer changes for the prefixes passed to subscribe,
+// unsubscribes when dropped
+pub struct Subscription {
+    id: u64,
+    requests: Sender<Request>,
+    changes_tx: Sender<AnswerChange>,
+    pub changes: Receiver<AnswerChange>,
+}
+
+impl Subscription {
+    pub fn add_prefixes(&self, prefixes: Vec<String>) {
+        let _ = self.requests.send(Request::Subscribe(
+            self.id,
+            prefixes,
+            self.changes_tx.clone(),
+        ));
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let _ = self.requests.send(Request::Unsubscribe(self.id));
+    }
+}
+
+impl GraphWorker {
+    // make_graph runs on the worker thread, so the graph never crosses threads
+    pub fn spawn<F>(make_graph: F) -> GraphWorker
+    where
+        F: FnOnce() -> Graph + Send + 'static,
+    {
+        let (requests, incoming) = channel::<Request>();
+        thread::spawn(move || {
+            let mut g = make_graph();
+            let mut subscribers: HashMap<u64, Subscriber> = HashMap::new();
+            let metrics = Metrics::new();
+            for request in incoming {
+                match request {
+                    Request::Execute(cmd, reply) => {
+                        #[cfg(feature = "otel")]
+                        let span = crate::telemetry::command_span(&cmd);
+                        let start = Instant::now();
+                        let result = execute_and_notify(&mut g, &cmd, &mut subscribers);
+                        metrics.record_command(&cmd.cmd, start.elapsed(), result.is_ok());
+                        #[cfg(feature = "otel")]
+                        crate::telemetry::end_command_span(span, &result);
+                        // The requester may have gone away, nothing to do then
+                        let _ = reply.send(result);
+                    }
+                    Request::Subscribe(id, prefixes, changes) => {
+                        subscribers
+                            .entry(id)
+                            .or_insert(Subscriber {
+                                prefixes: Vec::new(),
+                                changes,
+                            })
+                            .prefixes
+                            .extend(prefixes);
+                    }
+                    Request::Unsubscribe(id) => {
+                        subscribers.remove(&id);
+                    }
+                    Request::RenderMetrics(reply) => {
+                        metrics.observe_graph(&g);
+                        let _ = reply.send(metrics.render());
+                    }
+                    Request::Run(f) => f(&mut g),
+                }
+            }
+        });
+        GraphWorker { requests }
+    }
+
+    pub fn execute(&self, cmd: Command<'static>) -> Reply {
+        let (reply_tx, reply_rx) = channel::<Reply>();
+        self.requests
+            .send(Request::Execute(Box::new(cmd), reply_tx))
+            .map_err(|_| String::from("Graph worker has stopped"))?;
+        reply_rx
+            .recv()
+            .unwrap_or_else(|_| Err(String::from("Graph worker has stopped")))
+    }
+
+    // Run f on the worker thread with exclusive access to the graph. Changes f
+    // makes aren't reported to subscribers or counted in the metrics.
+    pub fn with_graph<F, R>(&self, f: F) -> Result<R, String>
+    where
+        F: FnOnce(&mut Graph) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (reply_tx, reply_rx) = channel::<R>();
+        let run = move |g: &mut Graph| {
+            let _ = reply_tx.send(f(g));
+        };
+        self.requests
+            .send(Request::Run(Box::new(run)))
+            .map_err(|_| String::from("Graph worker has stopped"))?;
+        reply_rx
+            .recv()
+            .map_err(|_| String::from("Graph worker has stopped"))
+    }
+
+    // Metrics of the commands executed so far and the graph's current size
+    pub fn metrics_text(&self) -> Result<String, String> {
+        let (reply_tx, reply_rx) = channel::<String>();
+        self.requests
+            .send(Request::RenderMetrics(reply_tx))
+            .map_err(|_| String::from("Graph worker has stopped"))?;
+        reply_rx
+            .recv()
+            .map_err(|_| String::from("Graph worker has stopped"))
+    }
+
+    pub fn subscribe(&self, prefixes: Vec<String>) -> Subscription {
+        let (changes_tx, changes) = channel::<AnswerChange>();
+        let subscription = Subscription {
+            id: NEXT_SUBSCRIBER_ID.fetch_add(1, Ordering::Relaxed),
+            requests: self.requests.clone(),
+            changes_tx,
+            changes,
+        };
+        subscription.add_prefixes(prefixes);
+        subscription
+    }
+}
+
+// Answers of the subscribed questions cmd could change, see
+// Graph::affected_questions
+fn watched_answers(
+    g: &Graph,
+    cmd: &Command,
+    subscribers: &HashMap<u64, Subscriber>,
+) -> HashMap<String, (String, f64)> {
+    let mut answers = HashMap::new();
+    for question_name in g.affected_questions(cmd) {
+        let subscribed = subscribers.values().any(|subscriber| {
+            subscriber
+                .prefixes
+                .iter()
+                .any(|prefix| question_name.starts_with(prefix.as_str()))
+        });
+        if !subscribed {
+            continue;
+        }
+        if let Ok(answer) = g.compute_answer(question_name) {
+            answers.insert(question_name.to_string(), answer);
+        }
+    }
+    answers
+}
+
+fn execute_and_notify(
+    g: &mut Graph,
+    cmd: &Command,
+    subscribers: &mut HashMap<u64, Subscriber>,
+) -> Reply {
+    if subscribers.is_empty() || cmd.cmd.is_read_only() {
+        return g.execute_command(cmd).map_err(String::from);
+    }
+    let before = watched_answers(g, cmd, subscribers);
+    let result = g.execute_command(cmd);
+    let after = watched_answers(g, cmd, subscribers);
+
+    let mut gone: Vec<u64> = Vec::new();
+    for (question_name, (answer, confidence)) in after {
+        if before.get(&question_name) == Some(&(answer.clone(), confidence)) {
+            continue;
+        }
+        let change = AnswerChange {
+            question: question_name,
+            answer,
+            confidence,
+        };
+        for (id, subscriber) in subscribers.iter() {
+            let matches = subscriber
+                .prefixes
+                .iter()
+                .any(|prefix| change.question.starts_with(prefix.as_str()));
+            if matches && subscriber.changes.send(change.clone()).is_err() {
+                gone.push(*id);
+            }
+        }
+    }
+    for id in gone {
+        subscribers.remove(&id);
+    }
+    result.map_err(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_worker_notifies_subscribers() {
+        let worker = GraphWorker::spawn(Graph::new);
+        let run = |line: &str| {
+            worker
+                .execute(Command::from(line).unwrap().into_owned())
+                .unwrap()
+        };
+        run("SET other a FROM s1");
+        let subscription = worker.subscribe(vec![String::from("q")]);
+
+        run("SET q1 a FROM s1");
+        let change = subscription.changes.recv().unwrap();
+        assert_eq!(change.question, "q1");
+        assert_eq!(change.answer, "a");
+
+        // s1's quality changes with the second answer to q1, which changes q2
+        run("SET q2 b FROM s1");
+        assert_eq!(subscription.changes.recv().unwrap().question, "q2");
+        run("SET q1 a FROM s2");
+        let mut changed: Vec<String> = subscription
+            .changes
+            .try_iter()
+            .map(|change| change.question)
+            .collect();
+        changed.sort();
+        assert_eq!(changed, vec!["q1", "q2"]);
+
+        // read-only commands and unmatched questions don't notify
+        run("GET ANSWER TO q1");
+        run("SET other b FROM s3");
+        assert!(subscription.changes.try_recv().is_err());
+    }
+}
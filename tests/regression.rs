@@ -1,7 +1,33 @@
 extern crate confidis;
 
-use confidis::command::Command;
 use confidis::graph::Graph;
+use std::fs;
+use std::path::Path;
 
+// Run every tests/scripts/*.txt against a new graph, see confidis::script for
+// the format. Add a script there to cover the behavior of a new comparison
+// method or setting without writing Rust.
 #[test]
-fn test_regression_1() {}
+fn test_scripts() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scripts");
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .expect("Couldn't read tests/scripts")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let mut failed = Vec::new();
+    for path in &paths {
+        let script = fs::read_to_string(path).unwrap();
+        let name = path.file_name().unwrap().to_string_lossy();
+        match Graph::new().run_script(&script) {
+            Ok(report) if report.assertions == 0 => failed.push(format!("{}: no assertions", name)),
+            Ok(report) if !report.passed() => failed.push(format!("{}:\n{}", name, report)),
+            Ok(_) => {}
+            Err(msg) => failed.push(format!("{}: {}", name, msg)),
+        }
+    }
+    assert!(failed.is_empty(), "\n{}", failed.join("\n\n"));
+}